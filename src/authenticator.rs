@@ -1,4 +1,587 @@
+//! SASL authentication mechanisms for use with
+//! [`Client::authenticate`](../client/struct.Client.html#method.authenticate).
+
+use base64;
+use getrandom;
+use std::cell::{Cell, RefCell};
+
+use super::digest::Algorithm;
+use super::error::{Error, ParseError, Result};
+
 /// This will allow plugable authentication mechanisms.
+///
+/// `process` is called with each challenge the server sends, already base64-decoded (and empty
+/// if the server sent none), and returns the response to send back, which is then base64-encoded
+/// by the client.
 pub trait Authenticator {
-    fn process(&self, String) -> String;
+    /// The type of the response to a challenge, such as `String` or `Vec<u8>`.
+    type Response: AsRef<[u8]>;
+
+    /// Compute the response to the server's `challenge`.
+    fn process(&self, challenge: &[u8]) -> Self::Response;
+
+    /// Respond to a continuation request that is not base64, such as a human-readable prompt or
+    /// error message, which some servers send instead of a challenge. The response is
+    /// base64-encoded by the client, as with `process`.
+    ///
+    /// By default, no response is given, so that the exchange is cancelled and `authenticate`
+    /// fails with `Error::Parse(ParseError::Authentication(text))`.
+    fn process_text(&self, text: &str) -> Option<Self::Response> {
+        let _ = text;
+        None
+    }
+
+    /// Called after each call to `process` (or `process_text`). If this returns an error, for
+    /// example because the server sent a challenge that the mechanism cannot accept, the exchange
+    /// is cancelled and `authenticate` fails with that error.
+    fn failure(&self) -> Option<Error> {
+        None
+    }
+
+    /// Called once the server has accepted the authentication, with the additional data that it
+    /// sent along with its tagged `OK` (as the single argument of a response code, such as
+    /// `[SASL dj1ybUY5...]`), if any, already base64-decoded.
+    ///
+    /// A mechanism that authenticates the server as well should return an error unless the
+    /// server has proven itself by now, so that a server (or an attacker in between) cannot skip
+    /// that step by accepting right away. `authenticate` then fails with that error. By default,
+    /// this succeeds.
+    fn finished(&self, data: Option<&[u8]>) -> Result<()> {
+        let _ = data;
+        Ok(())
+    }
+}
+
+/// The flavors of OAuth 2.0 bearer token authentication supported by [`OAuth2`](struct.OAuth2.html).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OAuth2Mechanism {
+    /// Google's and Microsoft's `XOAUTH2` mechanism.
+    XOAuth2,
+    /// The standard `OAUTHBEARER` mechanism from [RFC 7628](https://tools.ietf.org/html/rfc7628).
+    OAuthBearer,
+}
+
+/// Authenticate with an OAuth 2.0 access token, as required by e.g. Gmail and Outlook.
+///
+/// If the server rejects the token, it sends a base64-encoded JSON error as a continuation
+/// request that must be acknowledged with an empty response before it sends its tagged `NO`.
+/// `OAuth2` does this, so that `authenticate` then fails with the server's `NO` response.
+///
+/// ```rust,no_run
+/// # extern crate imap;
+/// # extern crate native_tls;
+/// # use imap::authenticator::OAuth2;
+/// # use native_tls::TlsConnector;
+/// # fn main() {
+/// # let ssl_connector = TlsConnector::builder().build().unwrap();
+/// let client = imap::client::secure_connect(
+///     ("imap.gmail.com", 993),
+///     "imap.gmail.com",
+///     &ssl_connector).unwrap();
+///
+/// let auth = OAuth2::xoauth2("somebody@gmail.com", "<access token>");
+/// let session = client.authenticate(auth.mechanism(), auth);
+/// # }
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OAuth2 {
+    mechanism: OAuth2Mechanism,
+    user: String,
+    access_token: String,
+}
+
+impl OAuth2 {
+    /// Authenticate `user` with `access_token` using `XOAUTH2`.
+    pub fn xoauth2(user: &str, access_token: &str) -> Self {
+        OAuth2::new(OAuth2Mechanism::XOAuth2, user, access_token)
+    }
+
+    /// Authenticate `user` with `access_token` using `OAUTHBEARER`.
+    pub fn oauthbearer(user: &str, access_token: &str) -> Self {
+        OAuth2::new(OAuth2Mechanism::OAuthBearer, user, access_token)
+    }
+
+    fn new(mechanism: OAuth2Mechanism, user: &str, access_token: &str) -> Self {
+        OAuth2 {
+            mechanism,
+            user: user.to_string(),
+            access_token: access_token.to_string(),
+        }
+    }
+
+    /// The name of the SASL mechanism to pass to `authenticate`.
+    pub fn mechanism(&self) -> &'static str {
+        match self.mechanism {
+            OAuth2Mechanism::XOAuth2 => "XOAUTH2",
+            OAuth2Mechanism::OAuthBearer => "OAUTHBEARER",
+        }
+    }
+
+    // The initial client response.
+    fn payload(&self) -> String {
+        match self.mechanism {
+            OAuth2Mechanism::XOAuth2 => format!(
+                "user={}\x01auth=Bearer {}\x01\x01",
+                self.user, self.access_token
+            ),
+            OAuth2Mechanism::OAuthBearer => format!(
+                "n,a={},\x01auth=Bearer {}\x01\x01",
+                self.user.replace('=', "=3D").replace(',', "=2C"),
+                self.access_token
+            ),
+        }
+    }
+}
+
+impl Authenticator for OAuth2 {
+    type Response = String;
+    fn process(&self, challenge: &[u8]) -> String {
+        if challenge.is_empty() {
+            self.payload()
+        } else {
+            // an error report; the server expects an empty response before failing the command
+            String::new()
+        }
+    }
+}
+
+/// Authenticate with a username and password using the `PLAIN` SASL mechanism ([RFC
+/// 4616](https://tools.ietf.org/html/rfc4616)).
+///
+/// This works with servers that refuse the `LOGIN` command (and advertise `LOGINDISABLED`), but
+/// still accept passwords through `AUTHENTICATE`. As with `LOGIN`, the password is sent in the
+/// clear, so this should only be used over an encrypted connection.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Plain {
+    user: String,
+    password: String,
+}
+
+impl Plain {
+    /// Authenticate `user` with `password`.
+    pub fn new(user: &str, password: &str) -> Self {
+        Plain {
+            user: user.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// The name of the SASL mechanism to pass to `authenticate`.
+    pub fn mechanism(&self) -> &'static str {
+        "PLAIN"
+    }
+}
+
+impl Authenticator for Plain {
+    type Response = String;
+    fn process(&self, _: &[u8]) -> String {
+        format!("\0{}\0{}", self.user, self.password)
+    }
+}
+
+/// Authenticate with a username and password using the obsolete, but widely deployed, `LOGIN`
+/// SASL mechanism, in which the server asks for the username and the password in turn.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Login {
+    user: String,
+    password: String,
+    step: Cell<u8>,
+}
+
+impl Login {
+    /// Authenticate `user` with `password`.
+    pub fn new(user: &str, password: &str) -> Self {
+        Login {
+            user: user.to_string(),
+            password: password.to_string(),
+            step: Cell::new(0),
+        }
+    }
+
+    /// The name of the SASL mechanism to pass to `authenticate`.
+    pub fn mechanism(&self) -> &'static str {
+        "LOGIN"
+    }
+}
+
+impl Authenticator for Login {
+    type Response = String;
+    fn process(&self, _: &[u8]) -> String {
+        let step = self.step.get();
+        self.step.set(step.saturating_add(1));
+        match step {
+            0 => self.user.clone(),
+            1 => self.password.clone(),
+            _ => String::new(),
+        }
+    }
+}
+
+/// The highest iteration count that `Scram` accepts from a server. Each iteration costs the
+/// client an HMAC, so a server could otherwise keep it busy for hours; real servers use a few
+/// thousand.
+pub const SCRAM_MAX_ITERATIONS: u32 = 1_000_000;
+
+/// Authenticate with a username and password using `SCRAM-SHA-1` or `SCRAM-SHA-256` ([RFC
+/// 5802](https://tools.ietf.org/html/rfc5802), [RFC 7677](https://tools.ietf.org/html/rfc7677)).
+///
+/// Unlike `PLAIN` and `LOGIN`, this never sends the password to the server, and the server has to
+/// prove that it knows the password as well. If it fails to, `authenticate` fails with
+/// `Error::ServerSignatureMismatch`.
+///
+/// The server's proof may come as a last challenge, or along with its tagged `OK`. If it
+/// accepts the authentication without sending one, `authenticate` fails with
+/// `Error::ServerSignatureMismatch` as well. A challenge with more than
+/// [`SCRAM_MAX_ITERATIONS`] iterations is rejected as invalid.
+///
+/// The username and password are used as given, without the SASLprep normalization that the
+/// RFCs call for, which only makes a difference for some non-ASCII passwords.
+///
+/// ```rust,no_run
+/// # extern crate imap;
+/// # extern crate native_tls;
+/// # use imap::authenticator::Scram;
+/// # use native_tls::TlsConnector;
+/// # fn main() {
+/// # let ssl_connector = TlsConnector::builder().build().unwrap();
+/// let client = imap::client::secure_connect(
+///     ("imap.example.org", 993),
+///     "imap.example.org",
+///     &ssl_connector).unwrap();
+///
+/// let auth = Scram::sha256("user", "pass");
+/// let session = client.authenticate(auth.mechanism(), auth);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Scram {
+    algorithm: Algorithm,
+    user: String,
+    password: String,
+    nonce: String,
+    state: RefCell<ScramState>,
+}
+
+#[derive(Clone, Debug)]
+enum ScramState {
+    Initial,
+    // the client-first message (without the GS2 header) has been sent
+    First { client_first: String },
+    // the client-final message has been sent, and the server is expected to prove itself
+    Final { server_signature: Vec<u8> },
+    Done,
+    Failed(ScramFailure),
+}
+
+#[derive(Clone, Debug)]
+enum ScramFailure {
+    Invalid(String),
+    Mismatch,
+}
+
+impl Scram {
+    /// Authenticate `user` with `password` using `SCRAM-SHA-1`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system cannot provide random bytes for the client's nonce.
+    pub fn sha1(user: &str, password: &str) -> Self {
+        Scram::new(Algorithm::Sha1, user, password, &random_nonce())
+    }
+
+    /// Authenticate `user` with `password` using `SCRAM-SHA-256`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system cannot provide random bytes for the client's nonce.
+    pub fn sha256(user: &str, password: &str) -> Self {
+        Scram::new(Algorithm::Sha256, user, password, &random_nonce())
+    }
+
+    fn new(algorithm: Algorithm, user: &str, password: &str, nonce: &str) -> Self {
+        Scram {
+            algorithm,
+            user: user.to_string(),
+            password: password.to_string(),
+            nonce: nonce.to_string(),
+            state: RefCell::new(ScramState::Initial),
+        }
+    }
+
+    /// The name of the SASL mechanism to pass to `authenticate`.
+    pub fn mechanism(&self) -> &'static str {
+        match self.algorithm {
+            Algorithm::Sha1 => "SCRAM-SHA-1",
+            Algorithm::Sha256 => "SCRAM-SHA-256",
+        }
+    }
+
+    // Compute the client-final message from the server-first message, and the signature that
+    // the server must answer with. An iteration count above `SCRAM_MAX_ITERATIONS` is rejected
+    // like any other invalid challenge.
+    fn client_final(&self, client_first: &str, server_first: &str) -> Option<(String, Vec<u8>)> {
+        let mut nonce = None;
+        let mut salt = None;
+        let mut iterations = None;
+        for attr in server_first.split(',') {
+            match attr.split_at(attr.find('=')?) {
+                ("r", value) => nonce = Some(&value[1..]),
+                ("s", value) => salt = Some(base64::decode(&value[1..]).ok()?),
+                ("i", value) => iterations = Some(value[1..].parse::<u32>().ok()?),
+                // a mandatory extension that we do not support
+                ("m", _) => return None,
+                _ => {}
+            }
+        }
+        let nonce = nonce?;
+        if !nonce.starts_with(&self.nonce) || nonce.len() == self.nonce.len() {
+            return None;
+        }
+        let iterations = iterations.filter(|&i| i > 0 && i <= SCRAM_MAX_ITERATIONS)?;
+
+        let h = self.algorithm;
+        let salted = h.pbkdf2(self.password.as_bytes(), &salt?, iterations);
+        let client_key = h.hmac(&salted, b"Client Key");
+        let server_key = h.hmac(&salted, b"Server Key");
+
+        // `biws` is the base64 encoding of the GS2 header `n,,`
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!("{},{},{}", client_first, server_first, without_proof);
+        let client_signature = h.hmac(&h.hash(&client_key), auth_message.as_bytes());
+        let proof: Vec<u8> = client_key
+            .iter()
+            .zip(&client_signature)
+            .map(|(k, s)| k ^ s)
+            .collect();
+        let server_signature = h.hmac(&server_key, auth_message.as_bytes());
+
+        Some((
+            format!("{},p={}", without_proof, base64::encode(&proof)),
+            server_signature,
+        ))
+    }
+}
+
+impl Authenticator for Scram {
+    type Response = String;
+    fn process(&self, challenge: &[u8]) -> String {
+        let mut state = self.state.borrow_mut();
+        let challenge = String::from_utf8_lossy(challenge);
+        let (response, next) = match *state {
+            ScramState::Initial => {
+                let user = self.user.replace('=', "=3D").replace(',', "=2C");
+                let client_first = format!("n={},r={}", user, self.nonce);
+                (
+                    format!("n,,{}", client_first),
+                    ScramState::First { client_first },
+                )
+            }
+            ScramState::First { ref client_first } => {
+                match self.client_final(client_first, &challenge) {
+                    Some((client_final, server_signature)) => {
+                        (client_final, ScramState::Final { server_signature })
+                    }
+                    None => (
+                        String::new(),
+                        ScramState::Failed(ScramFailure::Invalid(challenge.into_owned())),
+                    ),
+                }
+            }
+            ScramState::Final {
+                ref server_signature,
+            } => {
+                let verifier = challenge
+                    .split(',')
+                    .find(|attr| attr.starts_with("v="))
+                    .and_then(|v| base64::decode(&v[2..]).ok());
+                if verifier.as_ref() == Some(server_signature) {
+                    (String::new(), ScramState::Done)
+                } else {
+                    (String::new(), ScramState::Failed(ScramFailure::Mismatch))
+                }
+            }
+            ScramState::Done | ScramState::Failed(_) => return String::new(),
+        };
+        *state = next;
+        response
+    }
+
+    fn failure(&self) -> Option<Error> {
+        match *self.state.borrow() {
+            ScramState::Failed(ScramFailure::Invalid(ref challenge)) => Some(Error::Parse(
+                ParseError::Authentication(challenge.clone()),
+            )),
+            ScramState::Failed(ScramFailure::Mismatch) => Some(Error::ServerSignatureMismatch),
+            _ => None,
+        }
+    }
+
+    fn finished(&self, data: Option<&[u8]>) -> Result<()> {
+        let waiting = matches!(*self.state.borrow(), ScramState::Final { .. });
+        if let (true, Some(data)) = (waiting, data) {
+            self.process(data);
+        }
+        if let Some(e) = self.failure() {
+            return Err(e);
+        }
+        match *self.state.borrow() {
+            ScramState::Done => Ok(()),
+            // the server accepted without proving that it knows the password
+            _ => Err(Error::ServerSignatureMismatch),
+        }
+    }
+}
+
+// A client nonce for SCRAM, from the operating system's secure random number generator.
+fn random_nonce() -> String {
+    let mut bytes = [0; 24];
+    getrandom::getrandom(&mut bytes).expect("no random bytes for the SCRAM nonce");
+    base64::encode(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn oauth2_payload() {
+        let auth = OAuth2::xoauth2("someone@example.com", "ya29.token");
+        assert_eq!(auth.mechanism(), "XOAUTH2");
+        assert_eq!(
+            auth.process(b""),
+            "user=someone@example.com\x01auth=Bearer ya29.token\x01\x01"
+        );
+
+        let auth = OAuth2::oauthbearer("a=b,c@example.com", "ya29.token");
+        assert_eq!(auth.mechanism(), "OAUTHBEARER");
+        assert_eq!(
+            auth.process(b""),
+            "n,a=a=3Db=2Cc@example.com,\x01auth=Bearer ya29.token\x01\x01"
+        );
+
+        assert_eq!(auth.process(b"{\"status\":\"401\"}"), "");
+    }
+
+    #[test]
+    fn plain_payload() {
+        let auth = Plain::new("user", "pass");
+        assert_eq!(auth.mechanism(), "PLAIN");
+        assert_eq!(auth.process(b""), "\0user\0pass");
+    }
+
+    // The example exchanges from RFC 5802 and RFC 7677.
+    #[test]
+    fn scram_steps() {
+        let auth = Scram::new(Algorithm::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+        assert_eq!(auth.mechanism(), "SCRAM-SHA-1");
+        assert_eq!(auth.process(b""), "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL");
+        assert_eq!(
+            auth.process(b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096"),
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        assert_eq!(auth.process(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ="), "");
+        assert!(auth.failure().is_none());
+
+        let auth = Scram::new(Algorithm::Sha256, "user", "pencil", "rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(auth.mechanism(), "SCRAM-SHA-256");
+        assert_eq!(auth.process(b""), "n,,n=user,r=rOprNGfwEbeRWgbNEkqO");
+        assert_eq!(
+            auth.process(
+                b"r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+                  s=W22ZaJ0SNY7soEsUEjb6gQ==,i=4096"
+            ),
+            "c=biws,r=rOprNGfwEbeRWgbNEkqO%hvYDpWUa2RaTCAfuxFIlj)hNlF$k0,\
+             p=dHzbZapWIk4jUhN+Ute9ytag9zjfMHgsqmmiz7AndVQ="
+        );
+        assert_eq!(
+            auth.process(b"v=6rriTRBi23WpRR/wtup+mMhUZUn/dB5nLTJRsjl95G4="),
+            ""
+        );
+        assert!(auth.failure().is_none());
+    }
+
+    #[test]
+    fn scram_failures() {
+        let auth = Scram::new(Algorithm::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+        auth.process(b"");
+        auth.process(b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096");
+        auth.process(b"v=AAAApqV8S7suAoZWja4dJRkFsKQ=");
+        match auth.failure() {
+            Some(Error::ServerSignatureMismatch) => {}
+            r => panic!("expected ServerSignatureMismatch, got {:?}", r),
+        }
+
+        // the server's nonce must extend ours
+        let auth = Scram::new(Algorithm::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+        auth.process(b"");
+        auth.process(b"r=3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096");
+        match auth.failure() {
+            Some(Error::Parse(ParseError::Authentication(_))) => {}
+            r => panic!("expected an authentication parse error, got {:?}", r),
+        }
+
+        // the server may not make us compute for hours
+        let auth = Scram::new(Algorithm::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+        auth.process(b"");
+        auth.process(
+            b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4294967295",
+        );
+        match auth.failure() {
+            Some(Error::Parse(ParseError::Authentication(_))) => {}
+            r => panic!("expected an authentication parse error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn scram_finished() {
+        let exchange = |auth: &Scram| {
+            auth.process(b"");
+            auth.process(b"r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096");
+        };
+        let new = || Scram::new(Algorithm::Sha1, "user", "pencil", "fyko+d2lbbFgONRv9qkxdawL");
+
+        let auth = new();
+        exchange(&auth);
+        auth.process(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ=");
+        assert!(auth.finished(None).is_ok());
+
+        // the proof may come with the tagged OK
+        let auth = new();
+        exchange(&auth);
+        assert!(auth.finished(Some(b"v=rmF9pqV8S7suAoZWja4dJRkFsKQ=")).is_ok());
+
+        // but it may not be left out
+        let auth = new();
+        exchange(&auth);
+        match auth.finished(None) {
+            Err(Error::ServerSignatureMismatch) => {}
+            r => panic!("expected ServerSignatureMismatch, got {:?}", r),
+        }
+        let auth = new();
+        exchange(&auth);
+        match auth.finished(Some(b"v=AAAApqV8S7suAoZWja4dJRkFsKQ=")) {
+            Err(Error::ServerSignatureMismatch) => {}
+            r => panic!("expected ServerSignatureMismatch, got {:?}", r),
+        }
+        assert!(new().finished(None).is_err());
+
+        assert!(Plain::new("user", "pass").finished(None).is_ok());
+    }
+
+    #[test]
+    fn scram_nonce() {
+        let a = Scram::sha256("a,b=c", "pass");
+        let b = Scram::sha256("a,b=c", "pass");
+        assert_ne!(a.nonce, b.nonce);
+        assert!(!a.nonce.contains(','));
+        assert_eq!(a.process(b""), format!("n,,n=a=2Cb=3Dc,r={}", a.nonce));
+    }
+
+    #[test]
+    fn login_steps() {
+        let auth = Login::new("user", "pass");
+        assert_eq!(auth.mechanism(), "LOGIN");
+        assert_eq!(auth.process(b"Username:"), "user");
+        assert_eq!(auth.process(b"Password:"), "pass");
+    }
 }
@@ -0,0 +1,145 @@
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+
+// The default size of the read buffer, as with `std::io::BufReader`.
+pub(crate) const DEFAULT_CAPACITY: usize = 8 * 1024;
+
+// A stream with buffered reads, much like `bufstream::BufStream`, except that the bytes that have
+// been read from the underlying stream but not yet consumed can be recovered. We need those when
+// switching the underlying stream to another protocol layer (such as `COMPRESS=DEFLATE`), and when
+// resizing the buffer.
+//
+// Writes are not buffered; we always write whole lines (or literals) at once anyway.
+pub(crate) struct BufStream<T: Read + Write> {
+    inner: T,
+    buf: Vec<u8>,
+    // the bytes that have been read but not yet consumed are `buf[pos..filled]`
+    pos: usize,
+    filled: usize,
+}
+
+impl<T: Read + Write> BufStream<T> {
+    pub(crate) fn new(inner: T) -> Self {
+        BufStream::with_capacity(DEFAULT_CAPACITY, inner)
+    }
+
+    pub(crate) fn with_capacity(capacity: usize, inner: T) -> Self {
+        BufStream {
+            inner,
+            buf: vec![0; capacity.max(1)],
+            pos: 0,
+            filled: 0,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    // Resize the read buffer, keeping the bytes in it that have not been consumed yet (so it may
+    // stay larger than `capacity` until they are).
+    pub(crate) fn set_capacity(&mut self, capacity: usize) {
+        let unread = &self.buf[self.pos..self.filled];
+        let mut buf = vec![0; capacity.max(unread.len()).max(1)];
+        buf[..unread.len()].copy_from_slice(unread);
+        self.filled = unread.len();
+        self.pos = 0;
+        self.buf = buf;
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    // Return the underlying stream, discarding any unconsumed buffered input.
+    pub(crate) fn into_inner(self) -> T {
+        self.inner
+    }
+
+    // Return the underlying stream along with the bytes that have been read from it but not yet
+    // consumed.
+    pub(crate) fn into_parts(self) -> (T, Vec<u8>) {
+        let unread = self.buf[self.pos..self.filled].to_vec();
+        (self.inner, unread)
+    }
+}
+
+impl<T: Read + Write + fmt::Debug> fmt::Debug for BufStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BufStream")
+            .field("inner", &self.inner)
+            .field("buffered", &(self.filled - self.pos))
+            .field("capacity", &self.buf.len())
+            .finish()
+    }
+}
+
+impl<T: Read + Write> Read for BufStream<T> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // like `BufReader`, skip the buffer for reads that are at least as large as it
+        if self.pos == self.filled && out.len() >= self.buf.len() {
+            return self.inner.read(out);
+        }
+        let n = {
+            let available = self.fill_buf()?;
+            let n = available.len().min(out.len());
+            out[..n].copy_from_slice(&available[..n]);
+            n
+        };
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl<T: Read + Write> BufRead for BufStream<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos == self.filled {
+            self.filled = self.inner.read(&mut self.buf)?;
+            self.pos = 0;
+        }
+        Ok(&self.buf[self.pos..self.filled])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos = (self.pos + amt).min(self.filled);
+    }
+}
+
+impl<T: Read + Write> Write for BufStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock_stream::MockStream;
+    use super::*;
+
+    #[test]
+    fn set_capacity() {
+        let mut stream = BufStream::with_capacity(4, MockStream::new(b"a1 OK\r\nrest".to_vec()));
+        let mut line = Vec::new();
+        stream.read_until(b'\n', &mut line).unwrap();
+        assert_eq!(line, b"a1 OK\r\n");
+        stream.read_until(b's', &mut line).unwrap();
+
+        // the byte that was read but not yet consumed is kept
+        stream.set_capacity(1024);
+        assert_eq!(stream.capacity(), 1024);
+        let mut rest = [0; 1];
+        stream.read_exact(&mut rest).unwrap();
+        assert_eq!(&rest, b"t");
+
+        let (_, unread) = BufStream::new(MockStream::new(Vec::new())).into_parts();
+        assert!(unread.is_empty());
+    }
+}
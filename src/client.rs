@@ -1,39 +1,140 @@
-use bufstream::BufStream;
+use base64;
 use native_tls::{TlsConnector, TlsStream};
 use nom::IResult;
 use std::io::{self, Read, Write};
 use std::net::{TcpStream, ToSocketAddrs};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::ops::{Deref,DerefMut};
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::fmt;
+use std::hint;
+use std::mem;
+use std::collections::{vec_deque, HashMap, HashSet, VecDeque};
+use std::sync::mpsc;
 
 use super::authenticator::Authenticator;
-use super::error::{Error, ParseError, Result, ValidateError};
+use super::buf_stream::BufStream;
+use super::compress::DeflateStream;
+use super::error::{Error, ParseError, ResponseCode, ResponseText, Result, ValidateError};
+use super::socks5;
 use super::parse::{
-    parse_authenticate_response, parse_capabilities, parse_fetches, parse_mailbox, parse_names, parse_ids,
+    parse_annotations, parse_authenticate_response, AuthContinuation, parse_capabilities, parse_fetches, parse_fetches_lenient, parse_mailbox, parse_modified,
+    parse_enabled, parse_expunge, parse_expunge_vanished, parse_names, parse_ids, parse_noop, parse_qresync, parse_quota_root, parse_quotas, parse_response_text, parse_untagged, split_response_code,
 };
+use super::tls::TlsConfig;
 use super::types::*;
+use super::utf7;
 
 static TAG_PREFIX: &'static str = "a";
 const INITIAL_TAG: u32 = 0;
+
+// The capabilities under which servers offer GETANNOTATION and SETANNOTATION.
+const ANNOTATE_CAPABILITIES: &[&str] = &["ANNOTATEMORE", "ANNOTATE-EXPERIMENT-1", "ANNOTATE"];
+// How many of the messages found by `Session::search_fetch` are fetched per command.
+const SEARCH_FETCH_BATCH_SIZE: usize = 500;
 const CR: u8 = 0x0d;
 const LF: u8 = 0x0a;
 
+// A string argument, quoted, or as a literal if it contains line breaks (see `ImapArg`).
 macro_rules! quote {
     ($x:expr) => {
-        format!("\"{}\"", $x.replace(r"\", r"\\").replace("\"", "\\\""))
+        ImapArg::string(&$x[..]).to_string()
     };
 }
 
+// Quote a string argument that may not be sent as a literal, such as a password.
 fn validate_str(value: &str) -> Result<String> {
-    let quoted = quote!(value);
-    if quoted.find('\n').is_some() {
+    if value.find('\n').is_some() {
         return Err(Error::Validate(ValidateError('\n')));
     }
-    if quoted.find('\r').is_some() {
+    if value.find('\r').is_some() {
         return Err(Error::Validate(ValidateError('\r')));
     }
-    Ok(quoted)
+    Ok(quote!(value))
+}
+
+// The error for a tagged NO response, which is `Error::Code` if it carries a known response code.
+fn no_response(text: ResponseText) -> Error {
+    match text.response_code() {
+        Some(code) => Error::Code(code, text),
+        None => Error::No(text),
+    }
+}
+
+// Map a `[TOOBIG]` NO response (RFC 4469/7889) to `Error::MessageTooBig`.
+fn too_big_or(e: Error) -> Error {
+    match e {
+        Error::No(ref text) if text.has_code("TOOBIG") => Error::MessageTooBig,
+        e => e,
+    }
+}
+
+// Whether the data item of a STORE query suppresses the untagged FETCH responses.
+fn is_silent_store(query: &str) -> bool {
+    query
+        .split_whitespace()
+        .next()
+        .map(|item| item.to_uppercase().ends_with(".SILENT"))
+        .unwrap_or(false)
+}
+
+// The name of a command as reported to the timing callback, such as `FETCH` or `UID FETCH`.
+fn command_name(command: &str) -> String {
+    let mut words = command.split_whitespace().map(str::to_uppercase);
+    match words.next() {
+        Some(ref uid) if uid == "UID" => format!("UID {}", words.next().unwrap_or_default()),
+        verb => verb.unwrap_or_default(),
+    }
+}
+
+// Whether a command can be sent again after it failed without risk of doing the same thing
+// twice. `APPEND` and `COPY` may have stored the messages even if we did not get to see the
+// server's `OK`.
+fn is_idempotent(command: &str) -> bool {
+    let mut words = command.split_whitespace().map(str::to_uppercase);
+    let verb = match words.next() {
+        Some(ref uid) if uid == "UID" => words.next(),
+        verb => verb,
+    };
+    !matches!(verb.as_ref().map(|v| &v[..]), Some("APPEND") | Some("COPY"))
+}
+
+// Collect the messages of `iter`, calling `progress` with each one as it arrives.
+fn collect_with_progress<T, F>(mut iter: FetchIter<'_, T>, mut progress: F) -> Result<Vec<Fetch>>
+where
+    T: Read + Write,
+    F: FnMut(&Fetch, usize, u64),
+{
+    let mut fetches = Vec::new();
+    while let Some(fetch) = iter.next() {
+        let fetch = fetch?;
+        progress(&fetch, fetches.len() + 1, iter.bytes_read());
+        fetches.push(fetch);
+    }
+    Ok(fetches)
+}
+
+// The name of a mailbox as a key to match `STATUS` responses with; `INBOX` is case-insensitive.
+fn status_key(name: &str) -> String {
+    if name.eq_ignore_ascii_case("INBOX") {
+        "INBOX".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+fn parse_store_response(
+    lines: Vec<u8>,
+    silent: bool,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<Fetch>> {
+    if silent {
+        // any FETCH responses we got are unilateral updates, not the result of this STORE
+        parse_noop(&lines, unsolicited);
+        parse_fetches(Vec::new(), unsolicited)
+    } else {
+        parse_fetches(lines, unsolicited)
+    }
 }
 
 /// An authenticated IMAP session providing the usual IMAP commands. This type is what you get from
@@ -44,6 +145,40 @@ fn validate_str(value: &str) -> Result<String> {
 #[derive(Debug)]
 pub struct Session<T: Read + Write> {
     conn: Connection<T>,
+    unsolicited_responses: VecDeque<UnsolicitedResponse>,
+    mailbox: Option<Mailbox>,
+    setup: Setup,
+    // whether a search result was saved with `search_save` in the selected mailbox
+    saved_search: bool,
+}
+
+// What `Session::reconnect` needs to set up a new connection like the current one.
+#[derive(Clone, Default)]
+struct Setup {
+    // the username and password given to `login_reconnectable`
+    credentials: Option<(String, String)>,
+    // the name of the selected mailbox, and whether it was examined rather than selected
+    selected: Option<(String, bool)>,
+}
+
+impl Drop for Setup {
+    fn drop(&mut self) {
+        // overwrite the password rather than leave it behind in freed memory
+        if let Some((_, ref mut password)) = self.credentials {
+            let mut bytes = mem::take(password).into_bytes();
+            bytes.iter_mut().for_each(|b| *b = 0);
+            hint::black_box(&bytes);
+        }
+    }
+}
+
+impl fmt::Debug for Setup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Setup")
+            .field("username", &self.credentials.as_ref().map(|c| &c.0))
+            .field("selected", &self.selected)
+            .finish()
+    }
 }
 
 /// An (unauthenticated) handle to talk to an IMAP server. This is what you get when first
@@ -54,16 +189,82 @@ pub struct Session<T: Read + Write> {
 /// primitives type.
 #[derive(Debug)]
 pub struct Client<T: Read + Write> {
-    conn: Connection<T>,
+    pub(crate) conn: Connection<T>,
 }
 
 /// The underlying primitives type. Both `Client`(unauthenticated) and `Session`(after succesful
 /// login) use a `Connection` internally for the TCP stream primitives.
 #[derive(Debug)]
 pub struct Connection<T: Read + Write> {
-    stream: BufStream<T>,
+    pub(crate) stream: BufStream<T>,
     tag: u32,
+    /// Print every line sent to and received from the server to standard output, with the
+    /// credentials of `LOGIN` and `AUTHENTICATE` redacted.
+    ///
+    /// Regardless of this flag, the same lines are logged at the `trace` level through the
+    /// [`log`](https://docs.rs/log) crate, so that they can be routed to any logger.
     pub debug: bool,
+    poisoned: bool,
+    // the most recent `* CAPABILITY` response, or an equivalent one made up from the
+    // `[CAPABILITY ...]` response code of an `OK` response
+    capabilities: Option<Box<[u8]>>,
+    // what is rarely needed, boxed to keep the `(Error, Client)` of a failed login small
+    details: Box<Details>,
+    // whether `UTF8=ACCEPT` has been enabled, so that mailbox names are sent as UTF-8
+    utf8_accept: bool,
+}
+
+#[derive(Debug)]
+struct Details {
+    greeting: Option<Greeting>,
+    // the tagged `OK` response to the last command that succeeded
+    last_ok: Option<ResponseText>,
+    bye: Option<String>,
+    // each command is tagged with this prefix followed by the value of `Connection::tag`
+    tag_prefix: String,
+    // see `Connection::set_lenient`
+    lenient: bool,
+    // whether a command that should not be repeated blindly was sent (see `Session::with_retry`)
+    sent_non_idempotent: bool,
+    // see `Connection::set_timing_callback`
+    timing: Option<Timing>,
+    // when the last command was sent (see `Connection::idle_time`)
+    last_command: Option<Instant>,
+    // whether QRESYNC was enabled, so that expunged messages are reported with `* VANISHED`
+    qresync: bool,
+}
+
+type TimingCallback = Box<dyn FnMut(&str, Duration) + Send>;
+
+// The callback that is told how long each command took, and the commands that are still waiting
+// for their tagged response: their tags, names, and when they were sent.
+struct Timing {
+    callback: TimingCallback,
+    pending: Vec<(String, String, Instant)>,
+}
+
+impl fmt::Debug for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Timing")
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
+
+impl Default for Details {
+    fn default() -> Self {
+        Details {
+            greeting: None,
+            last_ok: None,
+            bye: None,
+            tag_prefix: TAG_PREFIX.to_string(),
+            lenient: false,
+            sent_non_idempotent: false,
+            timing: None,
+            last_command: None,
+            qresync: false,
+        }
+    }
 }
 
 // `Deref` instances are so we can make use of the same underlying primitives in `Client` and
@@ -122,20 +323,41 @@ pub trait SetReadTimeout {
     ///
     /// See also `std::net::TcpStream::set_read_timeout`.
     fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()>;
+
+    /// The current read timeout, so that it can be restored after an operation that needs a
+    /// different one.
+    ///
+    /// The default implementation returns `None`.
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(None)
+    }
+
+    /// Set the timeout for subsequent writes to the given one, or remove it if `timeout` is
+    /// `None`.
+    ///
+    /// The default implementation does nothing, for transports that do not support write
+    /// timeouts.
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let _ = timeout;
+        Ok(())
+    }
 }
 
 impl<'a, T: Read + Write + 'a> IdleHandle<'a, T> {
     fn new(session: &'a mut Session<T>) -> Result<Self> {
         let mut h = IdleHandle {
             session,
-            keepalive: Duration::from_secs(29 * 60),
-            done: false,
+            keepalive: Duration::from_secs(28 * 60),
+            // there is nothing to terminate until the server accepts the IDLE
+            done: true,
         };
-        h.init()?;
+        let lines = h.init()?;
+        h.queue(lines)?;
         Ok(h)
     }
 
-    fn init(&mut self) -> Result<()> {
+    // Start the IDLE, returning any untagged responses the server sent before accepting it.
+    fn init(&mut self) -> Result<Vec<u8>> {
         // https://tools.ietf.org/html/rfc2177
         //
         // The IDLE command takes no arguments.
@@ -145,40 +367,72 @@ impl<'a, T: Read + Write + 'a> IdleHandle<'a, T> {
         //
         //   a) if there's an error, or
         //   b) *after* we send DONE
-        let mut v = Vec::new();
-        self.session.readline(&mut v)?;
-        if v.starts_with(b"+") {
-            self.done = false;
-            return Ok(());
-        }
+        let mut untagged = Vec::new();
+        loop {
+            let mut v = Vec::new();
+            self.session.read_untagged(&mut v)?;
+            if v.starts_with(b"+") {
+                self.done = false;
+                return Ok(untagged);
+            }
+            if v.starts_with(b"* ") {
+                untagged.extend(v);
+                continue;
+            }
 
-        self.session.read_response_onto(&mut v)?;
-        // We should *only* get a continuation on an error (i.e., it gives BAD or NO).
-        unreachable!();
+            self.session.read_response_onto(&mut v)?;
+            // We should *only* get a continuation on an error (i.e., it gives BAD or NO).
+            unreachable!();
+        }
     }
 
-    fn terminate(&mut self) -> Result<()> {
+    // End the IDLE, returning any untagged responses the server sent before acknowledging it.
+    fn terminate(&mut self) -> Result<Vec<u8>> {
         if !self.done {
             self.done = true;
             self.session.write_line(b"DONE")?;
-            self.session.read_response().map(|_| ())
+            self.session.read_response()
         } else {
-            Ok(())
+            Ok(Vec::new())
         }
     }
 
+    // Parse the unilateral responses in `lines`, keeping the session's view of the selected
+    // mailbox up to date, and return them as events.
+    fn events(&mut self, lines: Vec<u8>) -> Result<Vec<UnsolicitedResponse>> {
+        let before = self.session.unsolicited_responses.len();
+        self.session.parse_response(lines, |lines, unsolicited| {
+            parse_noop(&lines, unsolicited);
+            Ok(())
+        })?;
+        Ok(self.session.unsolicited_responses.drain(before..).collect())
+    }
+
+    // Queue the unilateral responses in `lines` on the session.
+    fn queue(&mut self, lines: Vec<u8>) -> Result<()> {
+        self.session.parse_response(lines, |lines, unsolicited| {
+            parse_noop(&lines, unsolicited);
+            Ok(())
+        })
+    }
+
+    // Like `terminate`, but queues the responses sent before the acknowledgement on the session.
+    fn terminate_queued(&mut self) -> Result<()> {
+        let lines = self.terminate()?;
+        self.queue(lines)
+    }
+
     /// Internal helper that doesn't consume self.
     ///
     /// This is necessary so that we can keep using the inner `Session` in `wait_keepalive`.
     fn wait_inner(&mut self) -> Result<()> {
         let mut v = Vec::new();
         match self.session.readline(&mut v).map(|_| ()) {
-            Err(Error::Io(ref e))
-                if e.kind() == io::ErrorKind::TimedOut || e.kind() == io::ErrorKind::WouldBlock =>
-            {
+            Err(Error::Timeout) => {
                 // we need to refresh the IDLE connection
-                self.terminate()?;
-                self.init()?;
+                self.terminate_queued()?;
+                let lines = self.init()?;
+                self.queue(lines)?;
                 self.wait_inner()
             }
             r => r,
@@ -186,15 +440,20 @@ impl<'a, T: Read + Write + 'a> IdleHandle<'a, T> {
     }
 
     /// Block until the selected mailbox changes.
+    ///
+    /// If a read timeout is configured (see `Connection::set_read_timeout`), the IDLE is
+    /// refreshed whenever it expires, so waiting is not cut short by it.
     pub fn wait(mut self) -> Result<()> {
         self.wait_inner()
     }
 }
 
 impl<'a, T: SetReadTimeout + Read + Write + 'a> IdleHandle<'a, T> {
-    /// Set the keep-alive interval to use when `wait_keepalive` is called.
+    /// Set the keep-alive interval to use when `wait_keepalive`, `wait_with_callback`, or
+    /// `wait_with_channel` is called.
     ///
-    /// The interval defaults to 29 minutes as dictated by RFC 2177.
+    /// The interval defaults to 28 minutes, which keeps us clear of the 29 minutes after which
+    /// RFC 2177 allows the server to log us off.
     pub fn set_keepalive(&mut self, interval: Duration) {
         self.keepalive = interval;
     }
@@ -203,8 +462,8 @@ impl<'a, T: SetReadTimeout + Read + Write + 'a> IdleHandle<'a, T> {
     ///
     /// This method differs from `IdleHandle::wait` in that it will periodically refresh the IDLE
     /// connection, to prevent the server from timing out our connection. The keepalive interval is
-    /// set to 29 minutes by default, as dictated by RFC 2177, but can be changed using
-    /// `set_keepalive`.
+    /// set to 28 minutes by default, to stay within the 29 minutes dictated by RFC 2177, but can
+    /// be changed using `set_keepalive`.
     ///
     /// This is the recommended method to use for waiting.
     pub fn wait_keepalive(self) -> Result<()> {
@@ -221,20 +480,232 @@ impl<'a, T: SetReadTimeout + Read + Write + 'a> IdleHandle<'a, T> {
 
     /// Block until the selected mailbox changes, or until the given amount of time has expired.
     pub fn wait_timeout(mut self, timeout: Duration) -> Result<()> {
+        let read_timeout = self.session.stream.get_ref().read_timeout()?;
         self.session
             .stream
             .get_mut()
             .set_read_timeout(Some(timeout))?;
         let res = self.wait_inner();
-        self.session.stream.get_mut().set_read_timeout(None).is_ok();
+        let _ = self.session.stream.get_mut().set_read_timeout(read_timeout);
         res
     }
+
+    /// Block until the server reports changes to the selected mailbox, or until the given amount
+    /// of time has expired, and return those changes.
+    ///
+    /// The IDLE is terminated (by sending `DONE`) before returning, and any changes the server
+    /// reports before acknowledging that are included as well. The returned list is empty if the
+    /// timeout expired without any changes. Untagged `OK` responses, which some servers send
+    /// periodically while idling (e.g. `* OK Still here`), do not end the wait.
+    pub fn wait_with_timeout(mut self, timeout: Duration) -> Result<Vec<UnsolicitedResponse>> {
+        let read_timeout = self.session.stream.get_ref().read_timeout()?;
+        let deadline = Instant::now() + timeout;
+        let mut events = Vec::new();
+        let res = loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break Ok(());
+            }
+            if let Err(e) = self
+                .session
+                .stream
+                .get_mut()
+                .set_read_timeout(Some(deadline - now))
+            {
+                break Err(e);
+            }
+
+            let mut line = Vec::new();
+            match self.session.read_untagged(&mut line) {
+                Err(Error::Timeout) => break Ok(()),
+                Err(e) => break Err(e),
+                Ok(()) => match self.events(line) {
+                    Ok(ref new) if new.is_empty() => {}
+                    Ok(new) => {
+                        events.extend(new);
+                        break Ok(());
+                    }
+                    Err(e) => break Err(e),
+                },
+            }
+        };
+        let _ = self.session.stream.get_mut().set_read_timeout(read_timeout);
+        res?;
+
+        let lines = self.terminate()?;
+        events.extend(self.events(lines)?);
+        Ok(events)
+    }
+
+    /// Call `callback` with every change to the selected mailbox that the server reports, until it
+    /// returns `false`.
+    ///
+    /// The IDLE is refreshed every keepalive interval (see `set_keepalive`). Changes reported
+    /// after the callback asked to stop, but before the server acknowledged the end of the IDLE,
+    /// are not lost: they are queued on the session, and can be taken with
+    /// [`Session::unsolicited_responses`](struct.Session.html#method.unsolicited_responses).
+    pub fn wait_with_callback<F>(mut self, mut callback: F) -> Result<()>
+    where
+        F: FnMut(UnsolicitedResponse) -> bool,
+    {
+        let read_timeout = self.session.stream.get_ref().read_timeout()?;
+        let res = self.callback_inner(&mut callback);
+        let _ = self.session.stream.get_mut().set_read_timeout(read_timeout);
+        res?;
+        self.terminate_queued()
+    }
+
+    /// Send every change to the selected mailbox that the server reports to `sender`, until the
+    /// receiving end hangs up.
+    ///
+    /// Like `wait_with_callback`, the IDLE is refreshed every keepalive interval, and this is
+    /// meant for staying connected indefinitely, e.g. on a thread of its own. Changes reported
+    /// while the IDLE is being refreshed are sent like any other. That the receiver has hung up is
+    /// only noticed when the next change is sent; anything reported after that is queued on the
+    /// session.
+    pub fn wait_with_channel(self, sender: mpsc::Sender<UnsolicitedResponse>) -> Result<()> {
+        self.wait_with_callback(|event| sender.send(event).is_ok())
+    }
+
+    fn callback_inner<F>(&mut self, callback: &mut F) -> Result<()>
+    where
+        F: FnMut(UnsolicitedResponse) -> bool,
+    {
+        let mut refresh_at = Instant::now() + self.keepalive;
+        loop {
+            let now = Instant::now();
+            let events = if now >= refresh_at {
+                // Refresh the IDLE. Anything the server reports between our DONE and its
+                // acceptance of the new IDLE arrives along with the responses to those.
+                let keepalive = self.keepalive;
+                self.session
+                    .stream
+                    .get_mut()
+                    .set_read_timeout(Some(keepalive))?;
+                let mut lines = self.terminate()?;
+                lines.extend(self.init()?);
+                refresh_at = Instant::now() + keepalive;
+                self.events(lines)?
+            } else {
+                // Keepalives like `* OK Still here` must not postpone the refresh, so only wait
+                // for what is left of the interval.
+                self.session
+                    .stream
+                    .get_mut()
+                    .set_read_timeout(Some(refresh_at - now))?;
+                let mut line = Vec::new();
+                match self.session.read_untagged(&mut line) {
+                    Err(Error::Timeout) => continue,
+                    Err(e) => return Err(e),
+                    Ok(()) => self.events(line)?,
+                }
+            };
+
+            let mut events = events.into_iter();
+            for event in events.by_ref() {
+                if !callback(event) {
+                    self.session.unsolicited_responses.extend(events);
+                    return Ok(());
+                }
+            }
+        }
+    }
 }
 
 impl<'a, T: Read + Write + 'a> Drop for IdleHandle<'a, T> {
     fn drop(&mut self) {
         // we don't want to panic here if we can't terminate the Idle
-        self.terminate().is_ok();
+        self.terminate_queued().is_ok();
+    }
+}
+
+/// An iterator over the messages returned by `Session::fetch_iter`, which reads and parses one
+/// `FETCH` response at a time, as it arrives.
+///
+/// Unilateral responses that arrive in between are queued as usual (see
+/// `Session::unsolicited_responses`). The iterator ends at the tagged response, or after the first
+/// error reading from the server. If it is dropped early, the rest of the response is read and
+/// discarded (apart from unilateral responses), so that the session can be used again.
+#[derive(Debug)]
+pub struct FetchIter<'a, T: Read + Write + 'a> {
+    session: &'a mut Session<T>,
+    done: bool,
+    bytes_read: u64,
+}
+
+impl<'a, T: Read + Write + 'a> FetchIter<'a, T> {
+    fn new(session: &'a mut Session<T>, command: &str) -> Result<Self> {
+        session.run_command(command)?;
+        Ok(FetchIter {
+            session,
+            done: false,
+            bytes_read: 0,
+        })
+    }
+
+    /// The number of bytes of untagged responses read so far, including literals.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    // Read the next response, which is `None` once the tagged response has been read.
+    fn read_next(&mut self) -> Result<Option<Vec<u8>>> {
+        let mut lines = Vec::new();
+        if let Err(e) = self.session.read_untagged(&mut lines) {
+            // the state of the session is unknown if the rest of the response never arrives
+            self.session.poisoned = true;
+            return Err(e);
+        }
+        if lines.starts_with(b"* ") {
+            self.bytes_read += lines.len() as u64;
+            return Ok(Some(lines));
+        }
+        self.session.read_tagged_response_onto(&mut lines)?;
+        Ok(None)
+    }
+}
+
+impl<'a, T: Read + Write + 'a> Iterator for FetchIter<'a, T> {
+    type Item = Result<Fetch>;
+
+    fn next(&mut self) -> Option<Result<Fetch>> {
+        while !self.done {
+            let lines = match self.read_next() {
+                Ok(Some(lines)) => lines,
+                Ok(None) => {
+                    self.done = true;
+                    return None;
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            };
+            // anything but a `FETCH` is queued as an unsolicited response
+            match self.session.parse_response(lines, parse_fetches) {
+                Ok(fetches) => {
+                    if let Some(fetch) = fetches.into_owned().pop() {
+                        return Some(Ok(fetch));
+                    }
+                }
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T: Read + Write + 'a> Drop for FetchIter<'a, T> {
+    fn drop(&mut self) {
+        // skip the remaining messages, but still queue any unilateral responses
+        while !self.done {
+            match self.read_next() {
+                Ok(Some(lines)) => {
+                    let _ = self.session.parse_response(lines, parse_fetches);
+                }
+                Ok(None) | Err(_) => self.done = true,
+            }
+        }
     }
 }
 
@@ -242,6 +713,14 @@ impl<'a> SetReadTimeout for TcpStream {
     fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
         TcpStream::set_read_timeout(self, timeout).map_err(Error::Io)
     }
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        TcpStream::read_timeout(self).map_err(Error::Io)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        TcpStream::set_write_timeout(self, timeout).map_err(Error::Io)
+    }
 }
 
 impl<'a> SetReadTimeout for TlsStream<TcpStream> {
@@ -250,6 +729,16 @@ impl<'a> SetReadTimeout for TlsStream<TcpStream> {
             .set_read_timeout(timeout)
             .map_err(Error::Io)
     }
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.get_ref().read_timeout().map_err(Error::Io)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.get_ref()
+            .set_write_timeout(timeout)
+            .map_err(Error::Io)
+    }
 }
 
 /// Creates a new client. The usual IMAP commands are part of the [`Session`](struct.Session.html)
@@ -270,12 +759,7 @@ impl<'a> SetReadTimeout for TlsStream<TcpStream> {
 /// ```
 pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Client<TcpStream>> {
     match TcpStream::connect(addr) {
-        Ok(stream) => {
-            let mut socket = Client::new(stream);
-
-            socket.read_greeting()?;
-            Ok(socket)
-        }
+        Ok(stream) => Client::from_stream(stream),
         Err(e) => Err(Error::Io(e)),
     }
 }
@@ -307,20 +791,208 @@ pub fn secure_connect<A: ToSocketAddrs>(
                 Ok(s) => s,
                 Err(e) => return Err(Error::TlsHandshake(e)),
             };
-            let mut socket = Client::new(ssl_stream);
-
-            socket.read_greeting()?;
-            Ok(socket)
+            Client::from_stream(ssl_stream)
         }
         Err(e) => Err(Error::Io(e)),
     }
 }
 
+/// Like [`secure_connect`](fn.secure_connect.html), but with the TLS settings of `config`, such
+/// as additional root certificates or a client certificate. See the [`tls`](../tls/index.html)
+/// module.
+pub fn secure_connect_with_config<A: ToSocketAddrs>(
+    addr: A,
+    domain: &str,
+    config: &TlsConfig,
+) -> Result<Client<TlsStream<TcpStream>>> {
+    let stream = TcpStream::connect(addr)?;
+    Client::from_stream(config.handshake(domain, stream)?)
+}
+
+/// Connect to the IMAP server at `target` (a host name or IP address, and a port, usually 993)
+/// through the SOCKS5 proxy at `proxy`, and secure the connection with TLS.
+///
+/// The proxy is asked to connect to `target` by name, so the name is resolved by the proxy and not
+/// locally. The TLS handshake is made with the IMAP server at the end of the tunnel: the host of
+/// `target` (or the `server_name` of `tls`, if set) is used for SNI and to verify the server's
+/// certificate, never the proxy's address. Proxies that require authentication are not supported.
+///
+/// ```rust,no_run
+/// # use imap::tls::TlsConfig;
+/// let client = imap::client::connect_via_socks5(
+///     ("127.0.0.1", 1080),
+///     ("imap.example.org", 993),
+///     &TlsConfig::new()).unwrap();
+/// ```
+pub fn connect_via_socks5<A: ToSocketAddrs>(
+    proxy: A,
+    target: (&str, u16),
+    tls: &TlsConfig,
+) -> Result<Client<TlsStream<TcpStream>>> {
+    let (host, port) = target;
+    // an IPv6 literal may be given in brackets, as in a URL
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let mut stream = TcpStream::connect(proxy)?;
+    socks5::connect(&mut stream, host, port)?;
+    Client::from_stream(tls.handshake(host, stream)?)
+}
+
+/// A stream that may or may not have been upgraded to TLS, as returned by
+/// [`connect_auto`](fn.connect_auto.html).
+#[derive(Debug)]
+pub enum MaybeTls<P = TcpStream, S = TlsStream<TcpStream>> {
+    /// A plaintext stream.
+    Plain(P),
+    /// A stream secured with TLS.
+    Tls(S),
+}
+
+impl<P: Read, S: Read> Read for MaybeTls<P, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            MaybeTls::Plain(ref mut s) => s.read(buf),
+            MaybeTls::Tls(ref mut s) => s.read(buf),
+        }
+    }
+}
+
+impl<P: SetReadTimeout, S: SetReadTimeout> SetReadTimeout for MaybeTls<P, S> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match *self {
+            MaybeTls::Plain(ref mut s) => s.set_read_timeout(timeout),
+            MaybeTls::Tls(ref mut s) => s.set_read_timeout(timeout),
+        }
+    }
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        match *self {
+            MaybeTls::Plain(ref s) => s.read_timeout(),
+            MaybeTls::Tls(ref s) => s.read_timeout(),
+        }
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match *self {
+            MaybeTls::Plain(ref mut s) => s.set_write_timeout(timeout),
+            MaybeTls::Tls(ref mut s) => s.set_write_timeout(timeout),
+        }
+    }
+}
+
+impl<P: Write, S: Write> Write for MaybeTls<P, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            MaybeTls::Plain(ref mut s) => s.write(buf),
+            MaybeTls::Tls(ref mut s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            MaybeTls::Plain(ref mut s) => s.flush(),
+            MaybeTls::Tls(ref mut s) => s.flush(),
+        }
+    }
+}
+
+/// Connect to a server without TLS (usually on port 143), upgrade the connection with `STARTTLS`
+/// if the server supports it, and log in.
+///
+/// The connection is only left unencrypted if the server does not offer `STARTTLS`, and it
+/// allows logging in without it (i.e. it does not advertise `LOGINDISABLED`). Otherwise, this
+/// fails with `Error::MissingCapability`.
+///
+/// ```rust,no_run
+/// # extern crate native_tls;
+/// # extern crate imap;
+/// # use native_tls::TlsConnector;
+/// # fn main() {
+/// let ssl_connector = TlsConnector::builder().build().unwrap();
+/// let session = imap::client::connect_auto(
+///     ("imap.example.org", 143),
+///     "imap.example.org",
+///     &ssl_connector,
+///     "user",
+///     "pass").unwrap();
+/// # }
+/// ```
+pub fn connect_auto<A: ToSocketAddrs>(
+    addr: A,
+    domain: &str,
+    ssl_connector: &TlsConnector,
+    username: &str,
+    password: &str,
+) -> Result<Session<MaybeTls>> {
+    let mut client = Client::new(MaybeTls::Plain(TcpStream::connect(addr)?));
+    client.read_greeting()?;
+    login_auto(
+        client,
+        |stream| TlsConnector::connect(ssl_connector, domain, stream).map_err(Error::TlsHandshake),
+        username,
+        password,
+    )
+}
+
+// The protocol side of `connect_auto`, with the TLS handshake left to `secure`.
+fn login_auto<P, S, F>(
+    mut client: Client<MaybeTls<P, S>>,
+    secure: F,
+    username: &str,
+    password: &str,
+) -> Result<Session<MaybeTls<P, S>>>
+where
+    P: Read + Write,
+    S: Read + Write,
+    F: FnOnce(P) -> Result<S>,
+{
+    if client.has_capability("STARTTLS")? {
+        client = client.starttls_with(|stream| match stream {
+            MaybeTls::Plain(stream) => Ok(MaybeTls::Tls(secure(stream)?)),
+            tls => Ok(tls),
+        })?;
+    } else if client.has_capability("LOGINDISABLED")? {
+        return Err(Error::MissingCapability("STARTTLS".to_string()));
+    }
+
+    client.login(username, password).map_err(|(e, _)| e)
+}
 
 impl Client<TcpStream> {
+    /// Upgrade a plaintext connection (usually on port 143) to TLS with `STARTTLS`.
+    ///
+    /// Unlike `secure`, this fails with `Error::MissingCapability` (before sending anything) if
+    /// the server does not advertise `STARTTLS`. After the handshake, the capabilities are fetched
+    /// again, since the server may offer different ones over TLS, and those it announced before
+    /// cannot be trusted.
+    ///
+    /// Use the domain parameter for SNI and hostname verification.
+    ///
+    /// ```rust,no_run
+    /// # extern crate native_tls;
+    /// # extern crate imap;
+    /// # use native_tls::TlsConnector;
+    /// # fn main() {
+    /// let client = imap::client::connect(("imap.example.org", 143)).unwrap();
+    /// let ssl_connector = TlsConnector::builder().build().unwrap();
+    /// let tls_client = client.starttls("imap.example.org", &ssl_connector).unwrap();
+    /// # }
+    /// ```
+    pub fn starttls(
+        self,
+        domain: &str,
+        ssl_connector: &TlsConnector,
+    ) -> Result<Client<TlsStream<TcpStream>>> {
+        self.starttls_with(|stream| {
+            TlsConnector::connect(ssl_connector, domain, stream).map_err(Error::TlsHandshake)
+        })
+    }
+
     /// This will upgrade a regular TCP connection to use SSL.
     ///
     /// Use the domain parameter for openssl's SNI and hostname verification.
+    ///
+    /// This does not check that the server supports `STARTTLS`, nor does it refresh the
+    /// capabilities afterwards; `starttls` does both.
     pub fn secure(
         mut self,
         domain: &str,
@@ -328,7 +1000,7 @@ impl Client<TcpStream> {
     ) -> Result<Client<TlsStream<TcpStream>>> {
         // TODO This needs to be tested
         self.run_command_and_check_ok("STARTTLS")?;
-        TlsConnector::connect(ssl_connector, domain, self.conn.stream.into_inner()?)
+        TlsConnector::connect(ssl_connector, domain, self.conn.stream.into_inner())
             .map(Client::new)
             .map_err(Error::TlsHandshake)
     }
@@ -352,57 +1024,186 @@ macro_rules! ok_or_unauth_client_err {
 
 impl<T: Read + Write> Client<T> {
     /// Creates a new client with the underlying stream.
+    ///
+    /// The stream can be any already-connected transport, such as a socket going through a
+    /// proxy, or an SSH tunnel. The server's greeting is left unread, so this is for streams whose
+    /// greeting has already been consumed; use `from_stream` otherwise.
     pub fn new(stream: T) -> Client<T> {
         Client {
             conn: Connection {
                 stream: BufStream::new(stream),
                 tag: INITIAL_TAG,
                 debug: false,
+                poisoned: false,
+                capabilities: None,
+                details: Box::default(),
+                utf8_accept: false,
             },
         }
     }
 
+    /// Turn this client into a `Session` without logging in, if the server greeted with
+    /// `* PREAUTH` (see `Connection::greeting`). Otherwise, the client is given back.
+    ///
+    /// A preauthenticated connection is already in the authenticated state, so `login` and
+    /// `authenticate` fail with `Error::AlreadyAuthenticated` without sending anything.
+    ///
+    /// ```rust,no_run
+    /// # use imap::client::Client;
+    /// # use std::net::TcpStream;
+    /// let stream = TcpStream::connect(("imap.example.org", 143)).unwrap();
+    /// let session = match Client::from_stream(stream).unwrap().into_preauthenticated_session() {
+    ///     Ok(session) => session,
+    ///     Err(client) => client.login("user", "pass").map_err(|(e, _)| e).unwrap(),
+    /// };
+    /// ```
+    pub fn into_preauthenticated_session(self) -> ::std::result::Result<Session<T>, Client<T>> {
+        if self.greeting().is_some_and(Greeting::is_preauth) {
+            Ok(Session::new(self.conn))
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Creates a new client with an already-connected stream (see `new`), and reads the server's
+    /// greeting from it.
+    ///
+    /// Fails with `Error::Bye` if the server turns the connection away.
+    pub fn from_stream(stream: T) -> Result<Client<T>> {
+        let mut client = Client::new(stream);
+        client.read_greeting()?;
+        Ok(client)
+    }
+
+    // The protocol side of `starttls`, with the TLS handshake left to `secure`.
+    fn starttls_with<S, F>(mut self, secure: F) -> Result<Client<S>>
+    where
+        S: Read + Write,
+        F: FnOnce(T) -> Result<S>,
+    {
+        if !self.has_capability("STARTTLS")? {
+            return Err(Error::MissingCapability("STARTTLS".to_string()));
+        }
+        self.run_command_and_check_ok("STARTTLS")?;
+
+        // anything the server sent after its OK was sent in the clear, so we drop it
+        let (debug, lenient) = (self.conn.debug, self.conn.details.lenient);
+        let greeting = self.conn.details.greeting.take();
+        let capacity = self.conn.stream.capacity();
+        let mut client = Client::new(secure(self.conn.stream.into_inner())?);
+        client.debug = debug;
+        client.details.lenient = lenient;
+        client.stream.set_capacity(capacity);
+        client.details.greeting = greeting;
+        client.capabilities()?;
+        Ok(client)
+    }
+
     /// Authenticate will authenticate with the server, using the authenticator given.
+    ///
+    /// `auth_type` is the SASL mechanism, such as `PLAIN`. The
+    /// [`authenticator`](../authenticator/index.html) module provides authenticators for `PLAIN`
+    /// and `LOGIN`, which can be used with servers that disable the `LOGIN` command, for
+    /// `SCRAM-SHA-1` and `SCRAM-SHA-256`, which do not send the password, and for OAuth 2.0.
     pub fn authenticate<A: Authenticator> (
         mut self,
         auth_type: &str,
         authenticator: A,
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        ok_or_unauth_client_err!(self.check_not_preauth(), self);
+        self.conn.capabilities = None;
         ok_or_unauth_client_err!(self.run_command(&format!("AUTHENTICATE {}", auth_type)), self);
         self.do_auth_handshake(&authenticator)
     }
 
-    /// This func does the handshake process once the authenticate command is made.
-    fn do_auth_handshake<A: Authenticator>(
-        mut self,
-        authenticator: &A
-    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
-        // TODO Clean up this code
-        loop {
-            let mut line = Vec::new();
+    // Logging in is a protocol error once the server has greeted with `* PREAUTH`.
+    fn check_not_preauth(&self) -> Result<()> {
+        if self.greeting().is_some_and(Greeting::is_preauth) {
+            Err(Error::AlreadyAuthenticated)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Logging in is pointless, and gives the password away, if the server has said
+    // `LOGINDISABLED` (typically before `STARTTLS`). Only capabilities we already know of are
+    // checked, so that this does not cost a round trip.
+    fn check_login_enabled(&self) -> Result<()> {
+        let disabled = self
+            .conn
+            .capabilities
+            .as_ref()
+            .and_then(|lines| parse_capabilities(lines.to_vec()).ok())
+            .is_some_and(|caps| caps.iter().any(|c| c.eq_ignore_ascii_case("LOGINDISABLED")));
+        if disabled {
+            Err(Error::LoginDisabled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// This func does the handshake process once the authenticate command is made.
+    fn do_auth_handshake<A: Authenticator>(
+        mut self,
+        authenticator: &A
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        // TODO Clean up this code
+        loop {
+            let mut line = Vec::new();
             // explicit match blocks neccessary to convert error to tuple and not bind self too
             // early (see also comment on `login`)
             ok_or_unauth_client_err!(self.readline(&mut line), self);
 
             if line.starts_with(b"+") {
-                let data = ok_or_unauth_client_err!(
-                    parse_authenticate_response(String::from_utf8(line).unwrap()), self);
-                let auth_response = authenticator.process(data);
+                let continuation =
+                    ok_or_unauth_client_err!(parse_authenticate_response(&line), self);
+                let response = match continuation {
+                    AuthContinuation::Challenge(challenge) => authenticator.process(&challenge),
+                    AuthContinuation::Text(text) => match authenticator.process_text(&text) {
+                        Some(response) => response,
+                        None => {
+                            return self.cancel_auth(Error::Parse(ParseError::Authentication(text)))
+                        }
+                    },
+                };
+                if let Some(e) = authenticator.failure() {
+                    return self.cancel_auth(e);
+                }
 
-                ok_or_unauth_client_err!(self.write_line(auth_response.into_bytes().as_slice()), self);
+                let auth_response = base64::encode(response.as_ref());
+                ok_or_unauth_client_err!(
+                    self.write_line_as(auth_response.as_bytes(), b"<redacted>"),
+                    self
+                );
             } else {
                 ok_or_unauth_client_err!(self.read_response_onto(&mut line), self);
-                return Ok(Session { conn: self.conn });
+                let data = self.last_ok_response().and_then(sasl_data);
+                if let Err(e) = authenticator.finished(data.as_deref()) {
+                    return Err((e, self));
+                }
+                return Ok(Session::new(self.conn));
             }
         }
     }
 
+    // Cancel an `AUTHENTICATE` exchange; the server then fails the command with BAD, and we fail
+    // with `e`.
+    fn cancel_auth(mut self, e: Error) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        ok_or_unauth_client_err!(self.write_line(b"*"), self);
+        let mut response = Vec::new();
+        let _ = self.read_response_onto(&mut response);
+        Err((e, self))
+    }
+
     /// Log in to the IMAP server. Upon success a [`Session`](struct.Session.html) instance is
     /// returned; on error the original `Client` instance is returned in addition to the error.
     /// This is because `login` takes ownership of `self`, so in order to try again (e.g. after
     /// prompting the user for credetials), ownership of the original `Client` needs to be
     /// transferred back to the caller.
     ///
+    /// If the server is known to advertise `LOGINDISABLED` (from its greeting, or an earlier call
+    /// to `capabilities`), this fails with `Error::LoginDisabled` without sending the password.
+    ///
     /// ```rust,no_run
     /// # extern crate imap;
     /// # extern crate native_tls;
@@ -432,16 +1233,156 @@ impl<T: Read + Write> Client<T> {
         username: &str,
         password: &str
     ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        ok_or_unauth_client_err!(self.check_not_preauth(), self);
+        ok_or_unauth_client_err!(self.check_login_enabled(), self);
         let u = ok_or_unauth_client_err!(validate_str(username), self);
         let p = ok_or_unauth_client_err!(validate_str(password), self);
+        // the server's capabilities change once we are authenticated
+        self.conn.capabilities = None;
         ok_or_unauth_client_err!(self.run_command_and_check_ok(&format!("LOGIN {} {}", u, p)), self);
+        Ok(Session::new(self.conn))
+    }
 
-        Ok(Session { conn: self.conn })
+    /// Like [`login`](#method.login), but the session keeps `username` and `password`, so that
+    /// `Session::reconnect` can log in again with them. They are kept in memory for as long as
+    /// the session lives, and overwritten when it is dropped.
+    pub fn login_reconnectable(
+        self,
+        username: &str,
+        password: &str,
+    ) -> ::std::result::Result<Session<T>, (Error, Client<T>)> {
+        let mut session = self.login(username, password)?;
+        session.setup.credentials = Some((username.to_string(), password.to_string()));
+        Ok(session)
     }
 }
 
 
 impl <T: Read + Write> Session<T> {
+    // not public, just to avoid duplicating the field initialization
+    pub(crate) fn new(conn: Connection<T>) -> Self {
+        Session {
+            conn,
+            unsolicited_responses: VecDeque::new(),
+            mailbox: None,
+            setup: Setup::default(),
+            saved_search: false,
+        }
+    }
+
+    /// Creates a session with an already-connected stream that is already authenticated, such as
+    /// a tunnel to a server that was started for a logged-in user (and greeted with `* PREAUTH`).
+    ///
+    /// Nothing is read from or written to the stream, so its greeting must already have been
+    /// consumed. This is also handy for testing against canned server responses.
+    pub fn from_stream(stream: T) -> Session<T> {
+        Session::new(Client::new(stream).conn)
+    }
+
+    /// Set up a new connection in place of this one, e.g. after it was lost, and restore its
+    /// state: log in again with the username and password given to `login_reconnectable` (unless
+    /// the server greets the new connection with `* PREAUTH`), and select (or examine) the
+    /// mailbox that was selected.
+    ///
+    /// `connect` creates the new connection, as in `|| imap::client::connect(...)`. Unsolicited
+    /// responses that have not been taken yet are kept.
+    ///
+    /// If the UIDVALIDITY of the mailbox has changed since it was last selected, the UIDs that
+    /// the caller knows are no longer valid, and this fails with `Error::UidValidityChanged`;
+    /// the session is reconnected and the mailbox selected nonetheless, so that the caller can
+    /// resynchronize it. Fails with `Error::NoCredentials` if the session was not set up with
+    /// `login_reconnectable` and the new connection is not preauthenticated.
+    pub fn reconnect<F>(&mut self, connect: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<Client<T>>,
+    {
+        let mut client = connect()?;
+        client.debug = self.debug;
+        client.details.lenient = self.details.lenient;
+        client.details.tag_prefix = self.details.tag_prefix.clone();
+        let session = match client.into_preauthenticated_session() {
+            Ok(session) => session,
+            Err(client) => match self.setup.credentials {
+                Some((ref username, ref password)) => {
+                    client.login(username, password).map_err(|(e, _)| e)?
+                }
+                None => return Err(Error::NoCredentials),
+            },
+        };
+        self.conn = session.conn;
+
+        let uid_validity = self.mailbox.take().and_then(|mailbox| mailbox.uid_validity);
+        let mailbox = match self.setup.selected.clone() {
+            Some((ref name, true)) => self.examine(name)?,
+            Some((ref name, false)) => self.select(name)?,
+            None => return Ok(()),
+        };
+        match uid_validity {
+            Some(old) => check_uid_validity(old, &mailbox),
+            None => Ok(()),
+        }
+    }
+
+    /// Take the responses that the server has sent unilaterally (such as `* 3 EXISTS` when new
+    /// mail arrives), in the order in which they were received.
+    ///
+    /// These are collected while processing the responses to other commands, and kept until
+    /// taken with this method.
+    pub fn unsolicited_responses(&mut self) -> vec_deque::Drain<'_, UnsolicitedResponse> {
+        self.unsolicited_responses.drain(..)
+    }
+
+    /// The currently selected mailbox, as reported by the last `select` or `examine`.
+    ///
+    /// The message counts and flags are kept up to date with the unilateral `EXISTS`, `RECENT`,
+    /// `EXPUNGE`, and `FLAGS` responses that the server has sent since.
+    pub fn selected_mailbox(&self) -> Option<&Mailbox> {
+        self.mailbox.as_ref()
+    }
+
+    // Parse a `LIST` or `LSUB` response, decoding the names from modified UTF-7 unless the server
+    // sends them as UTF-8.
+    fn parse_names(&mut self, lines: Vec<u8>) -> ZeroCopyResult<Vec<Name>> {
+        let decode_utf7 = !self.utf8_accept;
+        let lenient = self.details.lenient;
+        self.parse_response(lines, |lines, unsolicited| {
+            parse_names(lines, unsolicited, decode_utf7, lenient)
+        })
+    }
+
+    // Parse the response to `FETCH` or `UID FETCH`, skipping what cannot be parsed if `lenient`.
+    fn parse_fetch_response(&mut self, lines: Vec<u8>) -> ZeroCopyResult<Vec<Fetch>> {
+        if self.details.lenient {
+            self.parse_response(lines, parse_fetches_lenient)
+        } else {
+            self.parse_response(lines, parse_fetches)
+        }
+    }
+
+    // Parse the response to a command, queueing any unilateral responses, and keeping the cached
+    // state of the selected mailbox in sync with them.
+    fn parse_response<R, F>(&mut self, lines: Vec<u8>, parse: F) -> Result<R>
+    where
+        F: FnOnce(Vec<u8>, &mut VecDeque<UnsolicitedResponse>) -> Result<R>,
+    {
+        let before = self.unsolicited_responses.len();
+        let result = parse(lines, &mut self.unsolicited_responses);
+        if let Some(ref mut mailbox) = self.mailbox {
+            for response in self.unsolicited_responses.iter().skip(before) {
+                match *response {
+                    UnsolicitedResponse::Exists(n) => mailbox.exists = n,
+                    UnsolicitedResponse::Recent(n) => mailbox.recent = n,
+                    UnsolicitedResponse::Expunge(_) => {
+                        mailbox.exists = mailbox.exists.saturating_sub(1)
+                    }
+                    UnsolicitedResponse::Flags(ref flags) => mailbox.flags = flags.clone(),
+                    UnsolicitedResponse::Fetch(_) | UnsolicitedResponse::Status(_) => {}
+                }
+            }
+        }
+        result
+    }
+
     /// Selects a mailbox
     ///
     /// Note that the server *is* allowed to unilaterally send things to the client for messages in
@@ -450,24 +1391,265 @@ impl <T: Read + Write> Session<T> {
     /// [`Connection::run_command_and_read_response`], you *may* see additional untagged `RECENT`,
     /// `EXISTS`, `FETCH`, and `EXPUNGE` responses!
     pub fn select(&mut self, mailbox_name: &str) -> Result<Mailbox> {
-        self.run_command_and_read_response(&format!("SELECT {}", validate_str(mailbox_name)?))
-            .and_then(|lines| parse_mailbox(&lines[..]))
+        self.deselect();
+        let mailbox = self
+            .run_command_and_read_response(&format!("SELECT {}", self.quote_mailbox(mailbox_name)))
+            .and_then(|lines| parse_mailbox(&lines[..]))?;
+        Ok(self.note_selected(mailbox, mailbox_name, false))
+    }
+
+    /// Select a mailbox whose messages the caller has cached by UID, and check that those UIDs
+    /// are still valid, i.e. that the mailbox's UIDVALIDITY is still `uid_validity`.
+    ///
+    /// If it is not, this fails with `Error::UidValidityChanged(uid_validity, actual)`, and the
+    /// cache must be discarded ([RFC 3501](https://tools.ietf.org/html/rfc3501#section-2.3.1.1)).
+    /// The mailbox is selected either way.
+    pub fn select_expecting(&mut self, mailbox_name: &str, uid_validity: u32) -> Result<Mailbox> {
+        let mailbox = self.select(mailbox_name)?;
+        check_uid_validity(uid_validity, &mailbox)?;
+        Ok(mailbox)
+    }
+
+    /// Select a mailbox, and have the server report what changed in it since the client last
+    /// synchronized it, as defined by QRESYNC ([RFC
+    /// 7162](https://tools.ietf.org/html/rfc7162#section-3.2.5)).
+    ///
+    /// `uid_validity` and `mod_seq` are the `uid_validity` and `highest_mod_seq` of the mailbox
+    /// as last seen, and `known_uids` (such as `1:500`) optionally limits the report to the
+    /// messages the client knows about. If the mailbox's UIDVALIDITY has changed, nothing is
+    /// reported and a full resync is needed. QRESYNC must have been enabled first, with
+    /// `enable(&["QRESYNC"])`.
+    pub fn select_qresync(
+        &mut self,
+        mailbox_name: &str,
+        uid_validity: u32,
+        mod_seq: u64,
+        known_uids: Option<&str>,
+    ) -> Result<Resync> {
+        self.deselect();
+        let known_uids = known_uids.map(|uids| format!(" {}", uids)).unwrap_or_default();
+        let mut resync = self
+            .run_command_and_read_response(&format!(
+                "SELECT {} (QRESYNC ({} {}{}))",
+                self.quote_mailbox(mailbox_name),
+                uid_validity,
+                mod_seq,
+                known_uids
+            )).and_then(|lines| self.parse_response(lines, parse_qresync))?;
+        resync.mailbox = self.note_selected(resync.mailbox, mailbox_name, false);
+        Ok(resync)
     }
 
     /// Examine is identical to Select, but the selected mailbox is identified as read-only
+    /// (`Mailbox::read_only`).
+    ///
+    /// Examining a mailbox leaves its state on the server alone: messages keep their `\Recent`
+    /// flag, and fetching message bodies does not set `\Seen`. This makes it the right choice
+    /// for scanning or indexing a mailbox.
     pub fn examine(&mut self, mailbox_name: &str) -> Result<Mailbox> {
-        self.run_command_and_read_response(&format!("EXAMINE {}", validate_str(mailbox_name)?))
-            .and_then(|lines| parse_mailbox(&lines[..]))
+        self.deselect();
+        let mailbox = self
+            .run_command_and_read_response(&format!("EXAMINE {}", self.quote_mailbox(mailbox_name)))
+            .and_then(|lines| parse_mailbox(&lines[..]))?;
+        Ok(self.note_selected(mailbox, mailbox_name, true))
+    }
+
+    // Remember the mailbox that was just selected, which is read-only if it was examined or the
+    // server said `[READ-ONLY]` in its tagged response.
+    fn note_selected(&mut self, mut mailbox: Mailbox, name: &str, examined: bool) -> Mailbox {
+        let read_only = self
+            .last_ok_response()
+            .is_some_and(|text| text.response_code() == Some(ResponseCode::ReadOnly));
+        mailbox.read_only = examined || read_only;
+        self.setup.selected = Some((name.to_string(), examined));
+        self.mailbox = Some(mailbox.clone());
+        mailbox
     }
 
     /// Fetch retreives data associated with a set of messages in the mailbox.
     ///
+    /// The query may also be one of the macros `FAST`, `ALL`, or `FULL`, whose data items are
+    /// then all available on the returned `Fetch`es (see `FetchQuery::full`).
+    ///
     /// Note that the server *is* allowed to unilaterally include `FETCH` responses for other
     /// messages in the selected mailbox whose status has changed. See the note on [unilateral
     /// server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
+    ///
+    /// The query is sent as given, so by default, fetching any `BODY[...]` section (or `RFC822`)
+    /// marks the message as `\Seen`, even if other sections in the same query use
+    /// `BODY.PEEK[...]`. Use [`fetch_peek`](#method.fetch_peek) to leave the flags alone.
+    /// [`FetchQuery`](../struct.FetchQuery.html) can be used to build queries that are checked
+    /// for this.
     pub fn fetch(&mut self, sequence_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.check_saved_search(sequence_set)?;
         self.run_command_and_read_response(&format!("FETCH {} {}", sequence_set, query))
-            .and_then(parse_fetches)
+            .and_then(|lines| self.parse_fetch_response(lines))
+    }
+
+    /// Like [`fetch`](#method.fetch), but returns an iterator that parses each message as soon as
+    /// it arrives, instead of reading the whole response first. Each message is copied out of its
+    /// own response (see `ZeroCopy::into_owned`), so only one is held in memory at a time.
+    ///
+    /// ```no_run
+    /// # use imap::client::Session;
+    /// # use std::net::TcpStream;
+    /// # let mut session = Session::from_stream(TcpStream::connect("imap.example.org:143").unwrap());
+    /// for fetch in session.fetch_iter("1:*", "(UID RFC822.SIZE)").unwrap() {
+    ///     let fetch = fetch.unwrap();
+    ///     println!("message {} has UID {:?}", fetch.message, fetch.uid);
+    /// }
+    /// ```
+    pub fn fetch_iter(&mut self, sequence_set: &str, query: &str) -> Result<FetchIter<'_, T>> {
+        self.check_saved_search(sequence_set)?;
+        FetchIter::new(self, &format!("FETCH {} {}", sequence_set, query))
+    }
+
+    /// Like [`fetch_iter`](#method.fetch_iter), but for UIDs, as with
+    /// [`uid_fetch`](#method.uid_fetch).
+    pub fn uid_fetch_iter(&mut self, uid_set: &str, query: &str) -> Result<FetchIter<'_, T>> {
+        self.check_saved_search(uid_set)?;
+        FetchIter::new(self, &format!("UID FETCH {} {}", uid_set, query))
+    }
+
+    /// Like [`fetch`](#method.fetch), but calls `progress` as each message arrives, e.g. to
+    /// update a progress bar during a large download. It is passed the message, the number of
+    /// messages received so far (including this one), and the number of bytes of the response
+    /// read so far.
+    ///
+    /// Unlike [`fetch_iter`](#method.fetch_iter), the messages are also collected and returned
+    /// once the response is complete. They are copied out of the response buffers (see
+    /// `ZeroCopy::into_owned`).
+    ///
+    /// ```no_run
+    /// # use imap::client::Session;
+    /// # use std::net::TcpStream;
+    /// # let mut session = Session::from_stream(TcpStream::connect("imap.example.org:143").unwrap());
+    /// let fetches = session.fetch_with_progress("1:*", "RFC822", |_, count, bytes| {
+    ///     println!("{} messages, {} bytes", count, bytes);
+    /// }).unwrap();
+    /// ```
+    pub fn fetch_with_progress<F>(
+        &mut self,
+        sequence_set: &str,
+        query: &str,
+        progress: F,
+    ) -> Result<Vec<Fetch>>
+    where
+        F: FnMut(&Fetch, usize, u64),
+    {
+        let iter = self.fetch_iter(sequence_set, query)?;
+        collect_with_progress(iter, progress)
+    }
+
+    /// Like [`fetch_with_progress`](#method.fetch_with_progress), but for UIDs, as with
+    /// [`uid_fetch`](#method.uid_fetch).
+    pub fn uid_fetch_with_progress<F>(
+        &mut self,
+        uid_set: &str,
+        query: &str,
+        progress: F,
+    ) -> Result<Vec<Fetch>>
+    where
+        F: FnMut(&Fetch, usize, u64),
+    {
+        let iter = self.uid_fetch_iter(uid_set, query)?;
+        collect_with_progress(iter, progress)
+    }
+
+    /// Like [`fetch`](#method.fetch), but without marking the messages as `\Seen`.
+    ///
+    /// Every `BODY[...]` item of the query is sent as `BODY.PEEK[...]` instead, and `RFC822` and
+    /// `RFC822.TEXT` as `BODY.PEEK[]` and `BODY.PEEK[TEXT]`. Note that the data of the latter two
+    /// is thus returned as body sections, through `Fetch::body` and `Fetch::section`.
+    pub fn fetch_peek(&mut self, sequence_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.fetch(sequence_set, &peek_query(query))
+    }
+
+    /// Like [`fetch`](#method.fetch), but for large sequence sets: the set is split into batches
+    /// of at most `batch_size` messages, which are fetched one after the other.
+    ///
+    /// The results are copied out of the response buffers (see `ZeroCopy::into_owned`) and
+    /// concatenated in the order of the batches. Messages named more than once in the set are
+    /// only fetched once, and a message the server returns more than once only appears once.
+    /// `*` stands for the number of messages in the selected mailbox, as last reported by the
+    /// server, so open-ended ranges (`n:*`) are split as well. With `uid_fetch_batched`, where
+    /// `*` is the highest UID, they are fetched in one last batch instead.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `batch_size` is zero.
+    pub fn fetch_batched(
+        &mut self,
+        sequence_set: &str,
+        query: &str,
+        batch_size: usize,
+    ) -> Result<Vec<Fetch>> {
+        let mut set: SequenceSet = sequence_set.parse()?;
+        if let Some(mailbox) = self.selected_mailbox() {
+            if mailbox.exists == 0 {
+                return Ok(Vec::new());
+            }
+            set = set.resolve(mailbox.exists);
+        }
+        self.in_batches(set, query, batch_size, Self::fetch)
+    }
+
+    /// Like [`uid_fetch`](#method.uid_fetch), but in batches. See
+    /// [`fetch_batched`](#method.fetch_batched).
+    pub fn uid_fetch_batched(
+        &mut self,
+        uid_set: &str,
+        query: &str,
+        batch_size: usize,
+    ) -> Result<Vec<Fetch>> {
+        self.in_batches(uid_set.parse()?, query, batch_size, Self::uid_fetch)
+    }
+
+    fn in_batches<F>(
+        &mut self,
+        set: SequenceSet,
+        query: &str,
+        batch_size: usize,
+        mut fetch: F,
+    ) -> Result<Vec<Fetch>>
+    where
+        F: FnMut(&mut Self, &str, &str) -> ZeroCopyResult<Vec<Fetch>>,
+    {
+        let mut fetches = Vec::new();
+        let mut seen = HashSet::new();
+        for batch in set.split(batch_size) {
+            let batch = fetch(self, &batch.to_string(), query)?.into_owned();
+            fetches.extend(batch.into_iter().filter(|f| seen.insert(f.message)));
+        }
+        Ok(fetches)
+    }
+
+    /// Fetch only the given header fields (e.g. `Subject` and `From`) of a set of messages.
+    ///
+    /// This uses `BODY.PEEK[HEADER.FIELDS (...)]`, so the messages are not marked as `\Seen`. The
+    /// fields are sent in upper case, and the result is stored under the section specifier as the
+    /// server returns it, which can be looked up with `Fetch::section`:
+    ///
+    /// ```no_run
+    /// # use imap::client::Session;
+    /// # fn example<T: ::std::io::Read + ::std::io::Write>(session: &mut Session<T>) {
+    /// let fetches = session.fetch_header_fields("1:5", &["Subject", "From", "Date"]).unwrap();
+    /// for fetch in fetches.iter() {
+    ///     let header = fetch.section("HEADER.FIELDS (SUBJECT FROM DATE)");
+    /// }
+    /// # }
+    /// ```
+    pub fn fetch_header_fields(
+        &mut self,
+        sequence_set: &str,
+        fields: &[&str],
+    ) -> ZeroCopyResult<Vec<Fetch>> {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|f| ImapArg::new(&f.to_uppercase()).to_string())
+            .collect();
+        let query = format!("(BODY.PEEK[HEADER.FIELDS ({})])", fields.join(" "));
+        self.fetch(sequence_set, &query)
     }
 
     /// Fetch retreives data associated with a set of messages by UID in the mailbox.
@@ -476,68 +1658,476 @@ impl <T: Read + Write> Session<T> {
     /// messages in the selected mailbox whose status has changed. See the note on [unilateral
     /// server responses in RFC 3501](https://tools.ietf.org/html/rfc3501#section-7).
     pub fn uid_fetch(&mut self, uid_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.check_saved_search(uid_set)?;
         self.run_command_and_read_response(&format!("UID FETCH {} {}", uid_set, query))
-            .and_then(parse_fetches)
+            .and_then(|lines| self.parse_fetch_response(lines))
+    }
+
+    /// Like [`uid_fetch`](#method.uid_fetch), but without marking the messages as `\Seen`. See
+    /// [`fetch_peek`](#method.fetch_peek).
+    pub fn uid_fetch_peek(&mut self, uid_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.uid_fetch(uid_set, &peek_query(query))
+    }
+
+    /// Like [`uid_fetch`](#method.uid_fetch), but for a list of UIDs, returning the messages
+    /// keyed by their UID.
+    ///
+    /// `UID` is added to the query if it does not ask for it already. Unsolicited responses about
+    /// other messages are left out. Fails with `Error::MessageNotFound` if the server does not
+    /// return one of the requested UIDs, e.g. because the message has been expunged.
+    pub fn uid_fetch_map(&mut self, uids: &[u32], query: &str) -> Result<HashMap<u32, Fetch>> {
+        let mut map = HashMap::new();
+        if uids.is_empty() {
+            return Ok(map);
+        }
+
+        let set = SequenceSet::from(uids);
+        let fetches = self.uid_fetch(&set.to_string(), &with_uid(query)?)?.into_owned();
+        let requested: HashSet<u32> = uids.iter().cloned().collect();
+        for fetch in fetches {
+            if let Some(uid) = fetch.uid.filter(|uid| requested.contains(uid)) {
+                map.insert(uid, fetch);
+            }
+        }
+        match uids.iter().find(|uid| !map.contains_key(uid)) {
+            Some(&uid) => Err(Error::MessageNotFound(uid)),
+            None => Ok(map),
+        }
+    }
+
+    /// Look up the current message sequence numbers of the messages with the given UIDs, with a
+    /// single `UID FETCH <uids> (UID)`. This is handy for commands that are cheaper with sequence
+    /// numbers when it is UIDs that are kept track of.
+    ///
+    /// The result maps each UID to its sequence number. UIDs of messages that no longer exist
+    /// (e.g. because they have been expunged) are left out, as are unsolicited responses about
+    /// other messages. Note that sequence numbers change when messages are expunged.
+    pub fn resolve_uids(&mut self, uids: &[u32]) -> Result<HashMap<u32, u32>> {
+        let mut map = HashMap::new();
+        if uids.is_empty() {
+            return Ok(map);
+        }
+
+        let set = SequenceSet::from(uids);
+        let fetches = self.uid_fetch(&set.to_string(), "(UID)")?;
+        let requested: HashSet<u32> = uids.iter().cloned().collect();
+        for fetch in fetches.iter() {
+            if let Some(uid) = fetch.uid.filter(|uid| requested.contains(uid)) {
+                map.insert(uid, fetch.message);
+            }
+        }
+        Ok(map)
+    }
+
+    /// Fetch the messages whose UID is greater than `last_uid`, i.e. those that arrived since the
+    /// message with that UID was seen, which is how a mailbox is typically synced.
+    ///
+    /// This issues `UID FETCH <last_uid + 1>:* <query>`, adding `UID` to the query if it does not
+    /// ask for it already. Since `N:*` always includes the message with the highest UID, even if
+    /// that UID is less than `N`, the server returns the last message when there are no new ones;
+    /// such messages (and unsolicited responses about older messages) are left out of the
+    /// result.
+    pub fn fetch_since_uid(&mut self, last_uid: u32, query: &str) -> Result<Vec<Fetch>> {
+        let first = match last_uid.checked_add(1) {
+            Some(first) => first,
+            None => return Ok(Vec::new()),
+        };
+        let fetches = self
+            .uid_fetch(&format!("{}:*", first), &with_uid(query)?)?
+            .into_owned();
+        Ok(fetches
+            .into_iter()
+            .filter(|fetch| fetch.uid.is_some_and(|uid| uid > last_uid))
+            .collect())
+    }
+
+    /// Fetch everything about the message with the given UID in one go: its flags, internal
+    /// date, size, and full content, without marking it as `\Seen`. This is handy for archiving
+    /// messages.
+    ///
+    /// The content is available through `Fetch::body`. Fails with `Error::MessageNotFound` if
+    /// there is no message with that UID.
+    pub fn fetch_full(&mut self, uid: u32) -> Result<Fetch> {
+        let fetches = self
+            .uid_fetch(&uid.to_string(), "(UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[])")?
+            .into_owned();
+        // the server may also send unsolicited updates of other messages
+        fetches
+            .into_iter()
+            .find(|fetch| fetch.uid == Some(uid))
+            .ok_or(Error::MessageNotFound(uid))
     }
 
-    /// Noop always succeeds, and it does nothing.
-    pub fn noop(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("NOOP")
+    /// Fetch `length` bytes of a body section (e.g. `1.2`, or the empty string for the whole
+    /// message) of the message with the given UID, starting at byte `offset`. This can be used to
+    /// download large messages or attachments in chunks, or to resume an interrupted download.
+    ///
+    /// `BODY.PEEK` is used, so the message is not marked as `\Seen`. The data is returned through
+    /// `Fetch::body` (and `Fetch::section`), and the offset it starts at through `Fetch::origin`.
+    /// If the section ends before `offset + length`, the server returns fewer bytes than
+    /// requested (possibly none); this is not an error.
+    pub fn fetch_partial(
+        &mut self,
+        uid: u32,
+        section: &str,
+        offset: u32,
+        length: u32,
+    ) -> ZeroCopyResult<Vec<Fetch>> {
+        self.uid_fetch(
+            &uid.to_string(),
+            &format!("(BODY.PEEK[{}]<{}.{}>)", section, offset, length),
+        )
+    }
+
+    /// Ask the server for the updates it has not reported yet, and return them. `NOOP` does
+    /// nothing else, and always succeeds.
+    ///
+    /// This is the simplest way to poll for changes: the server answers with e.g. `* 5 EXISTS`
+    /// for new messages, `* 2 EXPUNGE` for removed ones, and `* 3 FETCH (FLAGS (...))` for flag
+    /// changes. The updates are applied to `selected_mailbox` as usual, but are returned rather
+    /// than added to `unsolicited_responses` (which still holds those that came with earlier
+    /// commands).
+    pub fn noop(&mut self) -> Result<Vec<UnsolicitedResponse>> {
+        let before = self.unsolicited_responses.len();
+        self.run_command_and_check_ok("NOOP")?;
+        Ok(self.unsolicited_responses.drain(before..).collect())
+    }
+
+    /// Send a `NOOP` if no command has been sent for at least `interval` (or none at all), so
+    /// that a connection that sits unused, e.g. in a pool, is not logged out by the server for
+    /// inactivity. Returns the updates that came with the `NOOP` (see `noop`), or `None` if it
+    /// was not sent.
+    ///
+    /// Servers must not log out a client that is inactive for less than 30 minutes, but many
+    /// do after a shorter time, or drop idle connections at a firewall; an interval of a few
+    /// minutes is a safe choice. A session cannot be shared with a background thread, so this
+    /// is meant to be called periodically by the owner of the session, such as whenever a
+    /// connection is taken from the pool.
+    pub fn keepalive(&mut self, interval: Duration) -> Result<Option<Vec<UnsolicitedResponse>>> {
+        if self.idle_time().is_some_and(|idle| idle < interval) {
+            return Ok(None);
+        }
+        self.noop().map(Some)
     }
 
     /// Logout informs the server that the client is done with the connection.
+    ///
+    /// The server answers with `* BYE`, whose text is then available from `bye_response`, and
+    /// the tagged `OK`; a server that hangs up right after the `BYE` is not an error. Some servers
+    /// only commit pending expunges on a proper logout. No further commands can be sent, and the
+    /// socket is closed once the session is dropped.
     pub fn logout(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("LOGOUT")
+        self.run_command("LOGOUT")?;
+        let mut lines = Vec::new();
+        let result = self.read_response_onto(&mut lines);
+        self.details.bye = bye_text(&lines);
+        self.poisoned = true;
+        match result {
+            Ok(()) | Err(Error::Bye(..)) => Ok(()),
+            Err(e) => Err(e),
+        }
     }
 
     /// Create creates a mailbox with the given name.
+    ///
+    /// If a mailbox with that name already exists, servers that support [RFC
+    /// 5530](https://tools.ietf.org/html/rfc5530) fail with `[ALREADYEXISTS]`, which is returned
+    /// as `Error::Code(ResponseCode::AlreadyExists, _)`.
     pub fn create(&mut self, mailbox_name: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("CREATE {}", validate_str(mailbox_name)?))
+        self.run_command_and_check_ok(&format!("CREATE {}", self.quote_mailbox(mailbox_name)))
     }
 
     /// Delete permanently removes the mailbox with the given name.
+    ///
+    /// If there is no such mailbox, servers that support RFC 5530 fail with `[NONEXISTENT]`,
+    /// which is returned as `Error::Code(ResponseCode::NonExistent, _)`.
     pub fn delete(&mut self, mailbox_name: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("DELETE {}", validate_str(mailbox_name)?))
+        self.run_command_and_check_ok(&format!("DELETE {}", self.quote_mailbox(mailbox_name)))
     }
 
     /// Rename changes the name of a mailbox.
+    ///
+    /// Fails with `Error::Code(ResponseCode::NonExistent, _)` or
+    /// `Error::Code(ResponseCode::AlreadyExists, _)` if the current name does not exist or the
+    /// new one does (see `create` and `delete`).
+    ///
+    /// Renaming `INBOX` is special: it moves all the messages in it to a new mailbox with the
+    /// given name, and leaves `INBOX` empty rather than removing it (see [section 6.3.5 of RFC
+    /// 3501](https://tools.ietf.org/html/rfc3501#section-6.3.5)).
     pub fn rename(&mut self, current_mailbox_name: &str, new_mailbox_name: &str) -> Result<()> {
         self.run_command_and_check_ok(&format!(
             "RENAME {} {}",
-            quote!(current_mailbox_name),
-            quote!(new_mailbox_name)
+            self.quote_mailbox(current_mailbox_name),
+            self.quote_mailbox(new_mailbox_name)
         ))
     }
 
     /// Subscribe adds the specified mailbox name to the server's set of "active" or "subscribed"
-    /// mailboxes as returned by the LSUB command.
+    /// mailboxes as returned by the LSUB command. See `unsubscribe`.
     pub fn subscribe(&mut self, mailbox: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("SUBSCRIBE {}", quote!(mailbox)))
+        self.run_command_and_check_ok(&format!("SUBSCRIBE {}", self.quote_mailbox(mailbox)))
     }
 
-    /// Unsubscribe removes the specified mailbox name from the server's set of
-    /// "active" or "subscribed mailboxes as returned by the LSUB command.
+    /// Unsubscribe removes the specified mailbox name from the server's set of "active" or
+    /// "subscribed" mailboxes as returned by the LSUB command.
+    ///
+    /// Like all mailbox names, the name is quoted (and encoded in modified UTF-7 if need be), so
+    /// names with spaces such as `Sent Items` work as is. The mailbox need not exist anymore.
     pub fn unsubscribe(&mut self, mailbox: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("UNSUBSCRIBE {}", quote!(mailbox)))
+        self.run_command_and_check_ok(&format!("UNSUBSCRIBE {}", self.quote_mailbox(mailbox)))
     }
 
-    /// Capability requests a listing of capabilities that the server supports.
-    pub fn capabilities(&mut self) -> ZeroCopyResult<Capabilities> {
-        self.run_command_and_read_response("CAPABILITY")
-            .and_then(parse_capabilities)
+    /// Enable the given extensions with `ENABLE` ([RFC 5161](https://tools.ietf.org/html/rfc5161)),
+    /// and return those that the server reports as enabled.
+    ///
+    /// Enabling `UTF8=ACCEPT` ([RFC 6855](https://tools.ietf.org/html/rfc6855)) makes mailbox
+    /// names and search criteria plain UTF-8. From then on, mailbox names passed to this session
+    /// are sent as UTF-8 instead of being encoded in modified UTF-7, and the names returned by
+    /// `list` and friends are no longer decoded from it (so a name like `Tom &- Jerry` is taken
+    /// literally rather than decoded twice).
+    pub fn enable(&mut self, capabilities: &[&str]) -> Result<Vec<String>> {
+        let enabled = self
+            .run_command_and_read_response(&format!("ENABLE {}", capabilities.join(" ")))
+            .and_then(|lines| {
+                self.parse_response(lines, |lines, unsolicited| {
+                    Ok(parse_enabled(&lines, unsolicited))
+                })
+            })?;
+        if enabled.iter().any(|c| c.eq_ignore_ascii_case("UTF8=ACCEPT")) {
+            self.utf8_accept = true;
+        }
+        if enabled.iter().any(|c| c.eq_ignore_ascii_case("QRESYNC")) {
+            self.details.qresync = true;
+        }
+        Ok(enabled)
+    }
+
+    /// Enable `COMPRESS=DEFLATE` ([RFC 4978](https://tools.ietf.org/html/rfc4978)), and return
+    /// a session over a stream that deflates all subsequent traffic in both directions.
+    ///
+    /// Fails with `Error::MissingCapability` if the server does not advertise
+    /// `COMPRESS=DEFLATE`. Compression starts right after the server's tagged `OK`; any bytes the
+    /// server sent after it, and that have already been read, are inflated as well. If
+    /// compression could not be enabled (e.g. the server said `NO` because it is already
+    /// active), the error comes with the uncompressed session, which can still be used.
+    #[allow(clippy::result_large_err)] // like `login`, which gives the client back
+    pub fn compress(
+        mut self,
+    ) -> ::std::result::Result<Session<DeflateStream<T>>, (Error, Session<T>)> {
+        ok_or_unauth_client_err!(self.require_capability(&["COMPRESS=DEFLATE"]), self);
+        ok_or_unauth_client_err!(self.run_command_and_check_ok("COMPRESS DEFLATE"), self);
+
+        let Session {
+            conn,
+            unsolicited_responses,
+            mailbox,
+            setup,
+            saved_search,
+        } = self;
+        let capacity = conn.stream.capacity();
+        let (inner, unread) = conn.stream.into_parts();
+        Ok(Session {
+            conn: Connection {
+                stream: BufStream::with_capacity(capacity, DeflateStream::new(inner, unread)),
+                tag: conn.tag,
+                debug: conn.debug,
+                poisoned: conn.poisoned,
+                capabilities: conn.capabilities,
+                details: conn.details,
+                utf8_accept: conn.utf8_accept,
+            },
+            unsolicited_responses,
+            mailbox,
+            setup,
+            saved_search,
+        })
+    }
+
+    // Fail with `Error::MissingCapability` unless the server advertises one of `capabilities`.
+    fn require_capability(&mut self, capabilities: &[&str]) -> Result<()> {
+        for capability in capabilities {
+            if self.has_capability(capability)? {
+                return Ok(());
+            }
+        }
+        Err(Error::MissingCapability(capabilities.join(" or ")))
+    }
+
+    /// Get the usage and limits of a quota root with `GETQUOTA` ([RFC
+    /// 2087](https://tools.ietf.org/html/rfc2087)). Quota roots can be found with
+    /// `get_quota_root`.
+    ///
+    /// Fails with `Error::MissingCapability` unless the server advertises `QUOTA`.
+    pub fn get_quota(&mut self, quota_root: &str) -> ZeroCopyResult<Vec<Quota>> {
+        self.require_capability(&["QUOTA"])?;
+        self.run_command_and_read_response(&format!("GETQUOTA {}", quote!(quota_root)))
+            .and_then(|lines| self.parse_response(lines, parse_quotas))
+    }
+
+    /// Change the limits of a quota root with `SETQUOTA` (see `get_quota`), e.g. `&[("STORAGE",
+    /// 512)]`. Resources that are left out are no longer limited. This is usually reserved to
+    /// administrators.
+    ///
+    /// Returns the updated usage and limits of the root, if the server reports them (as it
+    /// should). A server that refuses the change for lack of rights fails with
+    /// `Error::Code(ResponseCode::NoPerm, _)`.
+    pub fn set_quota(&mut self, quota_root: &str, limits: &[(&str, u64)]) -> Result<Option<Quota>> {
+        self.require_capability(&["QUOTA"])?;
+        let limits: Vec<String> = limits
+            .iter()
+            .map(|(resource, limit)| format!("{} {}", resource, limit))
+            .collect();
+        let quotas = self
+            .run_command_and_read_response(&format!(
+                "SETQUOTA {} ({})",
+                quote!(quota_root),
+                limits.join(" ")
+            )).and_then(|lines| self.parse_response(lines, parse_quotas))?
+            .into_owned();
+        Ok(quotas.into_iter().find(|quota| quota.root() == quota_root))
+    }
+
+    /// Get the quota roots of a mailbox, along with their usage and limits, with `GETQUOTAROOT`
+    /// (see `get_quota`).
+    pub fn get_quota_root(&mut self, mailbox_name: &str) -> Result<(Vec<String>, Vec<Quota>)> {
+        self.require_capability(&["QUOTA"])?;
+        self.run_command_and_read_response(&format!(
+            "GETQUOTAROOT {}",
+            self.quote_mailbox(mailbox_name)
+        )).and_then(|lines| self.parse_response(lines, parse_quota_root))
+    }
+
+    /// Get the values of the given `attributes` (such as `value.priv`) of an annotation `entry`
+    /// (such as `/comment`) on a mailbox, using `GETANNOTATION` from the ANNOTATEMORE draft that
+    /// preceded [RFC 5257](https://tools.ietf.org/html/rfc5257).
+    ///
+    /// `entry` and the attributes may contain the wildcards `*` and `%`. Fails with
+    /// `Error::MissingCapability` unless the server advertises `ANNOTATEMORE` or one of the
+    /// `ANNOTATE` capabilities.
+    pub fn get_annotation(
+        &mut self,
+        mailbox_name: &str,
+        entry: &str,
+        attributes: &[&str],
+    ) -> ZeroCopyResult<Vec<Annotation>> {
+        self.require_capability(ANNOTATE_CAPABILITIES)?;
+        let attributes: Vec<_> = attributes.iter().map(|a| quote!(a)).collect();
+        self.run_command_and_read_response(&format!(
+            "GETANNOTATION {} {} ({})",
+            self.quote_mailbox(mailbox_name),
+            quote!(entry),
+            attributes.join(" ")
+        )).and_then(|lines| self.parse_response(lines, parse_annotations))
+    }
+
+    /// Set (or, with a value of `None`, remove) attributes of an annotation `entry` on a mailbox
+    /// using `SETANNOTATION`. See `get_annotation`.
+    pub fn set_annotation(
+        &mut self,
+        mailbox_name: &str,
+        entry: &str,
+        values: &[(&str, Option<&str>)],
+    ) -> Result<()> {
+        self.require_capability(ANNOTATE_CAPABILITIES)?;
+        let mut items = Vec::new();
+        for &(attribute, value) in values {
+            items.push(quote!(attribute));
+            items.push(match value {
+                Some(value) => quote!(value),
+                None => "NIL".to_string(),
+            });
+        }
+        self.run_command_and_check_ok(&format!(
+            "SETANNOTATION {} {} ({})",
+            self.quote_mailbox(mailbox_name),
+            quote!(entry),
+            items.join(" ")
+        ))
+    }
+
+    /// The largest message (in octets) the server will accept through `append`, as advertised
+    /// by the `APPENDLIMIT` capability ([RFC 7889](https://tools.ietf.org/html/rfc7889)).
+    ///
+    /// This is only known once the server has announced its capabilities, or they have been
+    /// requested with `capabilities`, and is `None` if the server does not advertise a global
+    /// limit.
+    pub fn append_limit(&self) -> Option<u64> {
+        let lines = self.conn.capabilities.as_ref()?.to_vec();
+        parse_capabilities(lines).ok()?.append_limit()
     }
 
     /// Expunge permanently removes all messages that have the \Deleted flag set from the currently
-    /// selected mailbox.
-    pub fn expunge(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("EXPUNGE")
+    /// selected mailbox, and returns the message sequence numbers of the removed messages.
+    ///
+    /// The numbers are those the server reports, in order, and each is relative to the numbering
+    /// *after* the messages reported before it were removed. Removing messages 3, 4, and 7 out of
+    /// 10 is thus reported as `[3, 3, 5]`, which can be applied one by one to a local model of
+    /// the mailbox without any further adjustment.
+    pub fn expunge(&mut self) -> Result<Vec<u32>> {
+        self.expunge_with("EXPUNGE")
     }
 
     /// Permanently removes all messages that have both the \Deleted flag set and have a UID that is
-    /// included in the specified message set.
+    /// included in the specified message set, and returns the UIDs of the removed messages, in
+    /// the order in which they were removed. Unlike `expunge`, this leaves alone messages that
+    /// another client has just marked as `\Deleted`.
+    ///
+    /// Once QRESYNC has been enabled (see `enable`), the server reports the removed messages by
+    /// UID with `* VANISHED`, and those UIDs are returned as they are. Otherwise, the server
+    /// reports them by sequence number, and their UIDs are looked up with a `UID FETCH`
+    /// beforehand, so the result is only a best effort: a message that is expunged by another
+    /// client between the two commands is left out. Fails with `Error::MissingCapability` unless
+    /// the server advertises `UIDPLUS`.
     /// The UID EXPUNGE command is defined in [RFC 4315 - "Internet Message Access Protocol (IMAP) - UIDPLUS extension"](https://tools.ietf.org/html/rfc4315#section-2.1).
-    pub fn uid_expunge(&mut self, uid_set: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("UID EXPUNGE {}", uid_set))
+    pub fn uid_expunge(&mut self, uid_set: &str) -> Result<Vec<u32>> {
+        self.require_capability(&["UIDPLUS"])?;
+        if self.details.qresync {
+            let lines = self.run_command_and_read_response(&format!("UID EXPUNGE {}", uid_set))?;
+            let vanished = self.parse_response(lines, |lines, unsolicited| {
+                parse_expunge_vanished(&lines, unsolicited)
+            })?;
+            let uids: Vec<u32> = vanished
+                .ranges()
+                .into_iter()
+                .flat_map(|(start, end)| start..=end.unwrap_or(start))
+                .collect();
+            if let Some(ref mut mailbox) = self.mailbox {
+                mailbox.exists = mailbox.exists.saturating_sub(uids.len() as u32);
+            }
+            return Ok(uids);
+        }
+
+        let mut messages: Vec<(u32, u32)> = self
+            .uid_fetch(uid_set, "UID")?
+            .iter()
+            .filter_map(|fetch| fetch.uid.map(|uid| (fetch.message, uid)))
+            .collect();
+        let expunged = self.expunge_with(&format!("UID EXPUNGE {}", uid_set))?;
+
+        let mut uids = Vec::new();
+        for n in expunged {
+            if let Some(i) = messages.iter().position(|&(message, _)| message == n) {
+                uids.push(messages.remove(i).1);
+            }
+            // every expunge renumbers the messages after it
+            for message in &mut messages {
+                if message.0 > n {
+                    message.0 -= 1;
+                }
+            }
+        }
+        Ok(uids)
+    }
+
+    fn expunge_with(&mut self, command: &str) -> Result<Vec<u32>> {
+        let lines = self.run_command_and_read_response(command)?;
+        let expunged = self.parse_response(lines, |lines, unsolicited| {
+            Ok(parse_expunge(&lines, unsolicited))
+        })?;
+        if let Some(ref mut mailbox) = self.mailbox {
+            mailbox.exists = mailbox.exists.saturating_sub(expunged.len() as u32);
+        }
+        Ok(expunged)
     }
 
     /// Check requests a checkpoint of the currently selected mailbox.
@@ -547,28 +2137,195 @@ impl <T: Read + Write> Session<T> {
 
     /// Close permanently removes all messages that have the \Deleted flag set from the currently
     /// selected mailbox, and returns to the authenticated state from the selected state.
+    ///
+    /// The server does not report which messages were removed, or whether any were. To find
+    /// out, call `expunge` (which returns them) and then `unselect`. Afterwards,
+    /// `selected_mailbox` returns `None`.
     pub fn close(&mut self) -> Result<()> {
-        self.run_command_and_check_ok("CLOSE")
+        self.run_command_and_check_ok("CLOSE")?;
+        self.deselect();
+        Ok(())
+    }
+
+    /// Leave the selected mailbox, like `close`, but without removing the messages that have the
+    /// \Deleted flag set, using `UNSELECT` ([RFC 3691](https://tools.ietf.org/html/rfc3691)).
+    ///
+    /// Fails with `Error::MissingCapability` unless the server advertises `UNSELECT`.
+    pub fn unselect(&mut self) -> Result<()> {
+        self.require_capability(&["UNSELECT"])?;
+        self.run_command_and_check_ok("UNSELECT")?;
+        self.deselect();
+        Ok(())
+    }
+
+    fn deselect(&mut self) {
+        self.mailbox = None;
+        self.setup.selected = None;
+        self.saved_search = false;
     }
 
     /// Store alters data associated with a message in the mailbox.
+    ///
+    /// `query` is the data item and its value, such as `+FLAGS (\Deleted)` to add flags,
+    /// `-FLAGS (\Seen)` to remove them, or `FLAGS (\Seen)` to replace them. The server replies
+    /// with the resulting flags of each affected message, which are returned as `Fetch`es. If the
+    /// data item has the `.SILENT` suffix (e.g. `+FLAGS.SILENT (\Deleted)`), the server does not
+    /// report the new flags and an empty `Vec` is returned.
     pub fn store(&mut self, sequence_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.check_saved_search(sequence_set)?;
+        let silent = is_silent_store(query);
         self.run_command_and_read_response(&format!("STORE {} {}", sequence_set, query))
-            .and_then(parse_fetches)
+            .and_then(|lines| {
+                self.parse_response(lines, |lines, unsolicited| {
+                    parse_store_response(lines, silent, unsolicited)
+                })
+            })
     }
 
+    /// Like `store`, but identifies messages by UID rather than by sequence number.
     pub fn uid_store(&mut self, uid_set: &str, query: &str) -> ZeroCopyResult<Vec<Fetch>> {
+        self.check_saved_search(uid_set)?;
+        let silent = is_silent_store(query);
         self.run_command_and_read_response(&format!("UID STORE {} {}", uid_set, query))
-            .and_then(parse_fetches)
+            .and_then(|lines| {
+                self.parse_response(lines, |lines, unsolicited| {
+                    parse_store_response(lines, silent, unsolicited)
+                })
+            })
+    }
+
+    /// Change the Gmail labels of a set of messages, on a server with the `X-GM-EXT-1`
+    /// capability. Like the flags with `store`, `item` is `+X-GM-LABELS` to add `labels`,
+    /// `-X-GM-LABELS` to remove them, or `X-GM-LABELS` to replace them (optionally with the
+    /// `.SILENT` suffix). The labels are encoded like mailbox names; system labels such as
+    /// `\Important` or `\Starred` are sent as they are. The resulting labels of each message
+    /// are returned in its `Fetch` (see `Fetch::gmail_labels`).
+    ///
+    /// Fails with `Error::MissingCapability` unless the server advertises `X-GM-EXT-1`.
+    pub fn store_gmail_labels(
+        &mut self,
+        sequence_set: &str,
+        item: &str,
+        labels: &[&str],
+    ) -> ZeroCopyResult<Vec<Fetch>> {
+        self.require_capability(&["X-GM-EXT-1"])?;
+        let query = format!("{} {}", item, self.gmail_labels(labels));
+        self.store(sequence_set, &query)
+    }
+
+    /// Like `store_gmail_labels`, but identifies messages by UID rather than by sequence number.
+    pub fn uid_store_gmail_labels(
+        &mut self,
+        uid_set: &str,
+        item: &str,
+        labels: &[&str],
+    ) -> ZeroCopyResult<Vec<Fetch>> {
+        self.require_capability(&["X-GM-EXT-1"])?;
+        let query = format!("{} {}", item, self.gmail_labels(labels));
+        self.uid_store(uid_set, &query)
+    }
+
+    // The parenthesized list of `labels` for `X-GM-LABELS`.
+    fn gmail_labels(&self, labels: &[&str]) -> String {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|label| {
+                let system = label.len() > 1
+                    && label.starts_with('\\')
+                    && label[1..].chars().all(|c| c.is_ascii_alphanumeric());
+                if system {
+                    label.to_string()
+                } else {
+                    self.quote_mailbox(label)
+                }
+            }).collect();
+        format!("({})", labels.join(" "))
+    }
+
+    /// Change the flags of the message with the given UID from `current` (e.g. its
+    /// `Fetch::flags`) to `desired`, by adding and removing only the flags that differ.
+    ///
+    /// Unlike replacing the flags with `FLAGS (...)`, this leaves alone any flags that other
+    /// clients have set or cleared since `current` was fetched, unless they are among those
+    /// changed here. Flags are compared case-insensitively, and `\Recent`, which cannot be
+    /// stored, is ignored. Nothing is sent if the flags are already as desired.
+    pub fn set_flags_diff<C, D>(&mut self, uid: u32, current: &[C], desired: &[D]) -> Result<()>
+    where
+        C: AsRef<str>,
+        D: AsRef<str>,
+    {
+        fn missing<'a, A: AsRef<str>, B: AsRef<str>>(from: &'a [A], other: &[B]) -> Vec<&'a str> {
+            let mut flags: Vec<&str> = Vec::new();
+            for flag in from.iter().map(AsRef::as_ref) {
+                let known = |f: &str| f.eq_ignore_ascii_case(flag);
+                if !flag.eq_ignore_ascii_case("\\Recent")
+                    && !other.iter().any(|f| known(f.as_ref()))
+                    && !flags.iter().any(|f| known(f))
+                {
+                    flags.push(flag);
+                }
+            }
+            flags
+        }
+
+        let add = missing(desired, current);
+        if !add.is_empty() {
+            self.uid_store(&uid.to_string(), &format!("+FLAGS.SILENT ({})", add.join(" ")))?;
+        }
+        let remove = missing(current, desired);
+        if !remove.is_empty() {
+            self.uid_store(&uid.to_string(), &format!("-FLAGS.SILENT ({})", remove.join(" ")))?;
+        }
+        Ok(())
+    }
+
+    /// Conditionally store flags on the messages in `uid_set`, as defined by CONDSTORE ([RFC
+    /// 7162](https://tools.ietf.org/html/rfc7162#section-3.1.3)).
+    ///
+    /// The store (e.g. `+FLAGS (\Seen)`) is only applied to messages whose mod-sequence is not
+    /// greater than `mod_seq`. The returned set contains the UIDs of the messages that were *not*
+    /// updated because they were modified concurrently (reported by the server through the
+    /// `[MODIFIED ...]` response code); it is empty if the store applied to all messages. The
+    /// `FETCH` responses for the messages that were updated are queued as unsolicited responses.
+    ///
+    /// Fails with `Error::MissingCapability` unless the server advertises `CONDSTORE`.
+    pub fn uid_store_unchanged_since(
+        &mut self,
+        uid_set: &str,
+        mod_seq: u64,
+        query: &str,
+    ) -> Result<SequenceSet> {
+        self.require_capability(&["CONDSTORE"])?;
+        self.check_saved_search(uid_set)?;
+        let command = format!("UID STORE {} (UNCHANGEDSINCE {}) {}", uid_set, mod_seq, query);
+        match self.run_command_and_read_response(&command) {
+            Ok(lines) => {
+                self.parse_response(lines, |lines, unsolicited| {
+                    parse_noop(&lines, unsolicited);
+                    Ok(())
+                })?;
+                match self.last_ok_response() {
+                    Some(text) => Ok(parse_modified(text)?.unwrap_or_default()),
+                    None => Ok(SequenceSet::default()),
+                }
+            }
+            Err(Error::No(text)) => match parse_modified(&text)? {
+                Some(modified) => Ok(modified),
+                None => Err(Error::No(text)),
+            },
+            Err(e) => Err(e),
+        }
     }
 
     /// Copy copies the specified message to the end of the specified destination mailbox.
     pub fn copy(&mut self, sequence_set: &str, mailbox_name: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("COPY {} {}", sequence_set, mailbox_name))
+        self.check_saved_search(sequence_set)?;
+        self.run_command_and_check_ok(&format!("COPY {} {}", sequence_set, self.quote_mailbox(mailbox_name)))
     }
 
     pub fn uid_copy(&mut self, uid_set: &str, mailbox_name: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("UID COPY {} {}", uid_set, mailbox_name))
+        self.check_saved_search(uid_set)?;
+        self.run_command_and_check_ok(&format!("UID COPY {} {}", uid_set, self.quote_mailbox(mailbox_name)))
     }
 
     /// Moves each message in the sequence into the destination mailbox. This function is
@@ -576,17 +2333,19 @@ impl <T: Read + Write> Session<T> {
     /// The MOVE command is defined in [RFC 6851 - "Internet Message Access Protocol (IMAP)
     /// - MOVE Extension"](https://tools.ietf.org/html/rfc6851#section-3).
     pub fn mv(&mut self, sequence_set: &str, mailbox_name: &str) -> Result<()> {
-        self.run_command_and_check_ok(&format!("MOVE {} {}", sequence_set, validate_str(mailbox_name)?))
+        self.check_saved_search(sequence_set)?;
+        self.run_command_and_check_ok(&format!("MOVE {} {}", sequence_set, self.quote_mailbox(mailbox_name)))
     }
 
     /// Moves each message in the uid set into the destination mailbox.
     /// The UID MOVE command is defined in [RFC 6851 - "Internet Message Access Protocol (IMAP)
     /// - MOVE Extension"](https://tools.ietf.org/html/rfc6851#section-3).
     pub fn uid_move(&mut self, uid_set: &str, mailbox_name: &str) -> Result<()> {
+        self.check_saved_search(uid_set)?;
         self.run_command_and_check_ok(&format!(
             "UID MOVE {} {}",
             uid_set,
-            validate_str(mailbox_name)?
+            self.quote_mailbox(mailbox_name)
         ))
     }
 
@@ -599,78 +2358,503 @@ impl <T: Read + Write> Session<T> {
     ) -> ZeroCopyResult<Vec<Name>> {
         self.run_command_and_read_response(&format!(
             "LIST {} {}",
-            quote!(reference_name),
+            self.quote_mailbox(reference_name),
             mailbox_search_pattern
-        )).and_then(parse_names)
+        )).and_then(|lines| self.parse_names(lines))
     }
 
-    /// The LSUB command returns a subset of names from the set of names
-    /// that the user has declared as being "active" or "subscribed".
-    pub fn lsub(
-        &mut self,
+    /// List the mailboxes that match any of several patterns, such as `INBOX` and `INBOX.*`.
+    /// Patterns are sent as they are, as with `list`.
+    ///
+    /// If the server supports `LIST-EXTENDED` ([RFC 5258](https://tools.ietf.org/html/rfc5258)),
+    /// this is a single `LIST "" (INBOX INBOX.*)` command. Otherwise, one `LIST` command is sent
+    /// per pattern, and a mailbox that matches several of them is returned only once.
+    pub fn list_patterns(&mut self, reference_name: &str, patterns: &[&str]) -> Result<Vec<Name>> {
+        if self.has_capability("LIST-EXTENDED")? {
+            let names = self
+                .run_command_and_read_response(&format!(
+                    "LIST {} ({})",
+                    self.quote_mailbox(reference_name),
+                    patterns.join(" ")
+                )).and_then(|lines| self.parse_names(lines))?;
+            return Ok(names.into_owned());
+        }
+
+        let mut seen = HashSet::new();
+        let mut names = Vec::new();
+        for pattern in patterns {
+            for name in self.list(reference_name, pattern)?.into_owned() {
+                if seen.insert(name.name().to_string()) {
+                    names.push(name);
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// The extended LIST command defined in [RFC 5258](https://tools.ietf.org/html/rfc5258),
+    /// which takes selection options such as `SUBSCRIBED` and `RECURSIVEMATCH`, and return
+    /// options such as `SUBSCRIBED` and `CHILDREN`. Either list of options may be empty.
+    ///
+    /// For example, `list_extended(&["SUBSCRIBED", "RECURSIVEMATCH"], "", "*", &[])` returns all
+    /// subscribed mailboxes, as well as any mailbox that is not itself subscribed but has
+    /// subscribed descendants. The latter can be recognized through
+    /// [`Name::child_info`](../struct.Name.html#method.child_info).
+    ///
+    /// `list_extended(&[], "", "*", &["SUBSCRIBED", "CHILDREN"])` returns all mailboxes, with
+    /// the `\Subscribed` and `\HasChildren` or `\HasNoChildren` attributes telling which are
+    /// subscribed and which have children, in place of separate `list` and `lsub` calls.
+    pub fn list_extended(
+        &mut self,
+        selection_options: &[&str],
+        reference_name: &str,
+        mailbox_search_pattern: &str,
+        return_options: &[&str],
+    ) -> ZeroCopyResult<Vec<Name>> {
+        let mut command = String::from("LIST ");
+        if !selection_options.is_empty() {
+            command.push_str(&format!("({}) ", selection_options.join(" ")));
+        }
+        command.push_str(&format!(
+            "{} {}",
+            self.quote_mailbox(reference_name),
+            mailbox_search_pattern
+        ));
+        if !return_options.is_empty() {
+            command.push_str(&format!(" RETURN ({})", return_options.join(" ")));
+        }
+        self.run_command_and_read_response(&command)
+            .and_then(|lines| self.parse_names(lines))
+    }
+
+    /// List only the mailboxes with a special use, such as the ones for sent messages or drafts,
+    /// using the `SPECIAL-USE` selection option of [RFC
+    /// 6154](https://tools.ietf.org/html/rfc6154). The use of each mailbox is given by
+    /// [`Name::special_use`](../struct.Name.html#method.special_use).
+    ///
+    /// The server must support both `LIST-EXTENDED` and `SPECIAL-USE`. Otherwise, `list` returns
+    /// the special-use attributes of all mailboxes if the server supports `SPECIAL-USE` alone.
+    pub fn list_special_use(
+        &mut self,
+        reference_name: &str,
+        mailbox_search_pattern: &str,
+    ) -> ZeroCopyResult<Vec<Name>> {
+        self.list_extended(&["SPECIAL-USE"], reference_name, mailbox_search_pattern, &[])
+    }
+
+    /// The LSUB command returns a subset of names from the set of names
+    /// that the user has declared as being "active" or "subscribed".
+    ///
+    /// The attributes are returned as the server sent them. In particular, if `%` matches a
+    /// mailbox that is not subscribed itself but has a subscribed child, the server returns it
+    /// with `\Noselect`, even though the mailbox may well be selectable (see [section 6.3.9 of
+    /// RFC 3501](https://tools.ietf.org/html/rfc3501#section-6.3.9)). `list_extended` with the
+    /// `SUBSCRIBED` and `RECURSIVEMATCH` options reports this case unambiguously.
+    pub fn lsub(
+        &mut self,
         reference_name: &str,
         mailbox_search_pattern: &str,
     ) -> ZeroCopyResult<Vec<Name>> {
         self.run_command_and_read_response(&format!(
             "LSUB {} {}",
-            quote!(reference_name),
+            self.quote_mailbox(reference_name),
             mailbox_search_pattern
-        )).and_then(parse_names)
+        )).and_then(|lines| self.parse_names(lines))
     }
 
     /// The STATUS command requests the status of the indicated mailbox.
+    ///
+    /// The items asked for in `status_data_items`, such as `(MESSAGES UNSEEN)`, are filled in
+    /// the returned `Mailbox`: `MESSAGES` as `exists`, and `UNSEEN` as `unseen_count`.
+    ///
+    /// On servers that support CONDSTORE, `HIGHESTMODSEQ` is filled in as `highest_mod_seq`.
+    /// Since it changes whenever anything in the mailbox does, `(UIDVALIDITY HIGHESTMODSEQ)` is a
+    /// cheap way to find out whether a mailbox needs to be synchronized, without selecting it.
     pub fn status(&mut self, mailbox_name: &str, status_data_items: &str) -> Result<Mailbox> {
         self.run_command_and_read_response(&format!(
             "STATUS {} {}",
-            validate_str(mailbox_name)?,
+            self.quote_mailbox(mailbox_name),
             status_data_items
         )).and_then(|lines| parse_mailbox(&lines[..]))
     }
 
+    /// The number of unread messages (the `UNSEEN` item of `STATUS`) in each of `mailboxes`, keyed
+    /// by the names as given.
+    ///
+    /// The `STATUS` commands are sent together (see [`pipeline`](#method.pipeline)), so this takes
+    /// a single round trip. Mailboxes for which the server fails the command, such as those with
+    /// the `\Noselect` attribute, or ones that do not exist, are left out with a logged warning.
+    pub fn unread_counts(&mut self, mailboxes: &[&str]) -> Result<HashMap<String, u32>> {
+        let commands: Vec<String> = mailboxes
+            .iter()
+            .map(|m| format!("STATUS {} (UNSEEN)", self.quote_mailbox(m)))
+            .collect();
+        let commands: Vec<&str> = commands.iter().map(|c| &c[..]).collect();
+        let results = self.pipeline(&commands)?;
+
+        let mut unseen = HashMap::new();
+        for (mailbox, result) in mailboxes.iter().zip(results) {
+            let lines = match result {
+                Ok(lines) => lines,
+                Err(Error::No(ref text)) | Err(Error::Code(_, ref text)) => {
+                    warn!("skipping mailbox {:?}: {}", mailbox, text);
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            // the untagged responses may come with the command of another mailbox
+            let statuses = self.parse_response(lines, |lines, unsolicited| {
+                let mut responses = VecDeque::new();
+                parse_noop(&lines, &mut responses);
+                let mut statuses = Vec::new();
+                for response in responses {
+                    match response {
+                        UnsolicitedResponse::Status(status) => statuses.push(status),
+                        response => unsolicited.push_back(response),
+                    }
+                }
+                Ok(statuses)
+            })?;
+            for status in statuses {
+                if let Some(n) = status.unseen {
+                    unseen.insert(status_key(&status.mailbox), n);
+                }
+            }
+        }
+
+        let mut counts = HashMap::new();
+        for mailbox in mailboxes {
+            let name = if self.utf8_accept {
+                mailbox.to_string()
+            } else {
+                utf7::encode(mailbox)
+            };
+            if let Some(&n) = unseen.get(&status_key(&name)) {
+                counts.insert(mailbox.to_string(), n);
+            }
+        }
+        Ok(counts)
+    }
+
     /// Returns a handle that can be used to block until the state of the currently selected
-    /// mailbox changes.
+    /// mailbox changes, or that of another mailbox the server was asked to report on with
+    /// `notify`.
     pub fn idle(&mut self) -> Result<IdleHandle<T>> {
         IdleHandle::new(self)
     }
 
+    /// Ask the server to report the given events, on the selected mailbox and others, with
+    /// `NOTIFY SET` ([RFC 5465](https://tools.ietf.org/html/rfc5465)). This replaces any earlier
+    /// request.
+    ///
+    /// The server then reports changes to other mailboxes as `UnsolicitedResponse::Status`, and
+    /// changes to the selected mailbox as usual (e.g. as `UnsolicitedResponse::Exists`). These
+    /// arrive along with the responses to other commands, and can be waited for with `idle`,
+    /// just like changes to the selected mailbox. The `STATUS` responses asked for with
+    /// `NotifySpec::status` are queued right away (see `unsolicited_responses`).
+    ///
+    /// Fails with `Error::MissingCapability` unless the server advertises `NOTIFY`.
+    pub fn notify(&mut self, spec: &NotifySpec) -> Result<()> {
+        self.require_capability(&["NOTIFY"])?;
+        let arguments = spec.arguments(|mailbox| self.quote_mailbox(mailbox));
+        self.run_command_and_check_ok(&format!("NOTIFY SET {}", arguments))
+    }
+
+    /// Stop all notifications requested with `notify`, with `NOTIFY NONE`.
+    pub fn notify_none(&mut self) -> Result<()> {
+        self.require_capability(&["NOTIFY"])?;
+        self.run_command_and_check_ok("NOTIFY NONE")
+    }
+
     /// The APPEND command adds a mail to a mailbox.
+    ///
+    /// If the server has advertised an `APPENDLIMIT` (see `append_limit`), messages larger than
+    /// the limit are rejected with `Error::MessageTooBig` without being sent. The same error is
+    /// returned if the server refuses the message with a `[TOOBIG]` response code.
+    ///
+    /// If the server is known to support `LITERAL+` (or `LITERAL-`, for messages of up to 4096
+    /// octets), the message is sent right away, rather than after the server has asked for it.
     pub fn append(&mut self, folder: &str, content: &[u8]) -> Result<()> {
-        self.run_command(&format!("APPEND \"{}\" {{{}}}", folder, content.len()))?;
-        let mut v = Vec::new();
-        self.readline(&mut v)?;
-        if !v.starts_with(b"+") {
-            return Err(Error::Append);
+        if let Some(limit) = self.append_limit() {
+            if content.len() as u64 > limit {
+                return Err(Error::MessageTooBig);
+            }
+        }
+
+        let non_sync = self.non_sync_literal(content.len());
+        self.run_command(&format!(
+            "APPEND {} {}",
+            self.quote_mailbox(folder),
+            literal_announcement(content.len(), non_sync)
+        ))?;
+        if !non_sync {
+            let mut v = Vec::new();
+            self.readline(&mut v)?;
+            if !v.starts_with(b"+") {
+                // the server may refuse the literal outright with a tagged response
+                return match self.read_response_onto(&mut v) {
+                    Err(e) => Err(too_big_or(e)),
+                    Ok(()) => Err(Error::Append),
+                };
+            }
         }
         self.stream.write_all(content)?;
         self.stream.write_all(b"\r\n")?;
         self.stream.flush()?;
-        self.read_response().map(|_| ())
+        self.read_response().map(|_| ()).map_err(too_big_or)
+    }
+
+    /// Like `append`, but reads the message from `content`, for messages that are generated on
+    /// the fly.
+    ///
+    /// IMAP needs the length of a message before it is sent (even with `LITERAL+`), so the message
+    /// is read into memory first. If the server has advertised an `APPENDLIMIT`, reading stops as
+    /// soon as the message exceeds it, and this fails with `Error::MessageTooBig`.
+    pub fn append_stream<R: Read>(&mut self, folder: &str, content: R) -> Result<()> {
+        let mut buf = Vec::new();
+        match self.append_limit() {
+            Some(limit) => {
+                content.take(limit.saturating_add(1)).read_to_end(&mut buf)?;
+            }
+            None => {
+                let mut content = content;
+                content.read_to_end(&mut buf)?;
+            }
+        }
+        self.append(folder, &buf)
+    }
+
+    /// Append several messages to a mailbox in a single `APPEND` command, as defined by
+    /// MULTIAPPEND ([RFC 3502](https://tools.ietf.org/html/rfc3502)), which saves a round trip
+    /// per message when uploading many of them. Either all messages are appended, or none are.
+    ///
+    /// If the server supports `UIDPLUS`, the UIDs of the new messages are returned, in the order
+    /// of `messages`. Fails with `Error::MissingCapability` unless the server advertises
+    /// `MULTIAPPEND`. Messages larger than the `APPENDLIMIT` and literals are handled as for
+    /// `append`. If the server's `[APPENDUID ...]` does not list one UID per message, this fails
+    /// with a parse error, although the messages were appended.
+    pub fn append_multiple(
+        &mut self,
+        folder: &str,
+        messages: &[AppendMessage],
+    ) -> Result<Option<AppendUid>> {
+        self.require_capability(&["MULTIAPPEND"])?;
+        if messages.is_empty() {
+            return Ok(None);
+        }
+        if let Some(limit) = self.append_limit() {
+            if messages.iter().any(|m| m.content().len() as u64 > limit) {
+                return Err(Error::MessageTooBig);
+            }
+        }
+
+        // each message's flags and date follow the literal of the one before it
+        let mut lines = vec![format!("APPEND {} ", self.quote_mailbox(folder))];
+        for (i, message) in messages.iter().enumerate() {
+            if i > 0 {
+                lines.push(" ".to_string());
+            }
+            lines.last_mut().unwrap().push_str(&message.arguments());
+        }
+        lines.push(String::new());
+        let literals: Vec<&[u8]> = messages.iter().map(|m| m.content()).collect();
+        self.run_command_with_literals(lines, &literals)
+            .map_err(too_big_or)?;
+        match self.last_ok_response() {
+            Some(text) if text.has_code("APPENDUID") => match text.append_uid(messages.len()) {
+                Some(uids) => Ok(Some(uids)),
+                None => Err(Error::Parse(ParseError::Invalid(text.line().as_bytes().to_vec()))),
+            },
+            _ => Ok(None),
+        }
     }
 
     /// Searches the mailbox for messages that match the given criteria and returns
-    /// the list of message sequence numbers of those messages.
-    pub fn search(&mut self, query: &str) -> ZeroCopyResult<HashSet<u32>> {
+    /// the list of message sequence numbers of those messages (see `SearchResult`).
+    ///
+    /// The criteria are either a raw string such as `"FROM alice UNSEEN"`, which is sent as is, or
+    /// a [`SearchQuery`](../struct.SearchQuery.html) built from `SearchKey`s, which takes care of
+    /// quoting and date formatting.
+    ///
+    /// If the criteria contain non-ASCII characters, they are sent as UTF-8, and `CHARSET UTF-8`
+    /// is added unless the criteria already name a charset or `UTF8=ACCEPT` is enabled.
+    ///
+    /// Fails with `Error::MissingCapability` if the query uses `SearchKey::Older` or
+    /// `SearchKey::Younger` but the server does not advertise `WITHIN`.
+    pub fn search<Q: Into<SearchQuery>>(&mut self, query: Q) -> Result<SearchResult> {
+        let query = query.into();
+        if query.needs_within() {
+            self.require_capability(&["WITHIN"])?;
+        }
+        let query = self.search_criteria(query.as_str()).into_owned();
         self.run_command_and_read_response(&format!("SEARCH {}", query))
-            .and_then(parse_ids)
+            .and_then(|lines| self.parse_response(lines, parse_ids))
+            .map(|ids| SearchResult::new(ids.into_owned(), false))
     }
 
     /// Searches the mailbox for messages that match the given criteria and returns
-    /// the list of unique identifier numbers of those messages.
-    pub fn uid_search(&mut self, query: &str) -> ZeroCopyResult<HashSet<u32>> {
-        eprint!("{}", format!("UID SEARCH {}", query));
+    /// the list of unique identifier numbers of those messages (see `SearchResult`).
+    ///
+    /// The criteria and non-ASCII characters in them are handled as for `search`.
+    pub fn uid_search<Q: Into<SearchQuery>>(&mut self, query: Q) -> Result<SearchResult> {
+        let query = query.into();
+        if query.needs_within() {
+            self.require_capability(&["WITHIN"])?;
+        }
+        let query = self.search_criteria(query.as_str()).into_owned();
         self.run_command_and_read_response(&format!("UID SEARCH {}", query))
-            .and_then(parse_ids)
+            .and_then(|lines| self.parse_response(lines, parse_ids))
+            .map(|ids| SearchResult::new(ids.into_owned(), true))
+    }
+
+    /// Searches the mailbox like `search`, but has the server remember the matching messages
+    /// instead of returning them, using `SEARCH RETURN (SAVE)` ([RFC
+    /// 5182](https://tools.ietf.org/html/rfc5182)). The saved result can then be referred to
+    /// with the set `$` in `fetch`, `store`, `copy`, `mv`, and their UID variants, without sending
+    /// the messages' numbers back and forth.
+    ///
+    /// The result is kept until the next search is saved, or until another mailbox is selected
+    /// or the mailbox is closed. Using `$` before a result was saved fails with
+    /// `Error::NoSavedSearch`. Fails with `Error::MissingCapability` unless the server
+    /// advertises `SEARCHRES`.
+    pub fn search_save<Q: Into<SearchQuery>>(&mut self, query: Q) -> Result<()> {
+        self.search_save_command("SEARCH", query.into())
+    }
+
+    /// Like `search_save`, but with `UID SEARCH`. The saved result is the same either way, and
+    /// `$` refers to the messages' UIDs in UID commands and to their sequence numbers otherwise.
+    pub fn uid_search_save<Q: Into<SearchQuery>>(&mut self, query: Q) -> Result<()> {
+        self.search_save_command("UID SEARCH", query.into())
+    }
+
+    fn search_save_command(&mut self, command: &str, query: SearchQuery) -> Result<()> {
+        self.require_capability(&["SEARCHRES"])?;
+        if query.needs_within() {
+            self.require_capability(&["WITHIN"])?;
+        }
+        let query = self.search_criteria(query.as_str()).into_owned();
+        self.run_command_and_check_ok(&format!("{} RETURN (SAVE) {}", command, query))?;
+        self.saved_search = true;
+        Ok(())
+    }
+
+    // Fail with `Error::NoSavedSearch` if `set` refers to a saved search result that there is
+    // none of.
+    fn check_saved_search(&self, set: &str) -> Result<()> {
+        if set.trim() == "$" && !self.saved_search {
+            return Err(Error::NoSavedSearch);
+        }
+        Ok(())
+    }
+
+    /// Like `search`, but with the given charset (such as `UTF-8` or `ISO-8859-1`) for the strings
+    /// in the criteria, which is sent as `SEARCH CHARSET <charset> ...`.
+    ///
+    /// Strings with non-ASCII characters (quoted or not) cannot be sent as quoted strings, so they
+    /// are sent as literals instead, waiting for the server's continuation before each one, unless
+    /// the server is known to support `LITERAL+` or `LITERAL-` (see `Session::append`).
+    ///
+    /// If the server does not support the charset, the search fails with
+    /// `Error::Code(ResponseCode::BadCharset, text)`, and `text.bad_charsets()` gives the charsets
+    /// that the server does support (if it listed them), so that the search can be retried.
+    pub fn search_charset<Q: Into<SearchQuery>>(
+        &mut self,
+        charset: &str,
+        query: Q,
+    ) -> Result<SearchResult> {
+        let query = query.into();
+        if query.needs_within() {
+            self.require_capability(&["WITHIN"])?;
+        }
+        let (mut lines, literals) = literal_terms(query.as_str());
+        lines[0] = format!("SEARCH CHARSET {} {}", charset, lines[0]);
+        self.run_command_with_literals(lines, &literals)
+            .and_then(|lines| self.parse_response(lines, parse_ids))
+            .map(|ids| SearchResult::new(ids.into_owned(), false))
+    }
+
+    /// Search for the messages that match `query` (as with `uid_search`), and fetch `fetch_query`
+    /// (e.g. `(FLAGS ENVELOPE)`) for them, in the order of their UIDs.
+    ///
+    /// The messages are fetched by UID, so that messages expunged in between do not get in the
+    /// way, in batches of a few hundred (see `uid_fetch_batched`). `UID` is added to the fetch
+    /// query if it does not ask for it already, and unsolicited responses about other messages
+    /// are left out. If no messages match, nothing is fetched.
+    pub fn search_fetch<Q: Into<SearchQuery>>(
+        &mut self,
+        query: Q,
+        fetch_query: &str,
+    ) -> Result<Vec<Fetch>> {
+        let found = self.uid_search(query)?;
+        if found.is_empty() {
+            return Ok(Vec::new());
+        }
+        let set = SequenceSet::from(&found.to_vec()[..]);
+        let fetches = self.uid_fetch_batched(
+            &set.to_string(),
+            &with_uid(fetch_query)?,
+            SEARCH_FETCH_BATCH_SIZE,
+        )?;
+        Ok(fetches
+            .into_iter()
+            .filter(|fetch| fetch.uid.is_some_and(|uid| found.ids().contains(&uid)))
+            .collect())
     }
 
     // these are only here because they are public interface, the rest is in `Connection`
     /// Runs a command and checks if it returns OK.
+    ///
+    /// Any unilateral responses the server sent along are queued (see `unsolicited_responses`).
     pub fn run_command_and_check_ok(&mut self, command: &str) -> Result<()> {
-        self.run_command_and_read_response(command).map(|_| ())
+        let lines = self.run_command_and_read_response(command)?;
+        self.parse_response(lines, |lines, unsolicited| {
+            parse_noop(&lines, unsolicited);
+            Ok(())
+        })
     }
 
-    /// Runs any command passed to it.
-    pub fn run_command(&mut self, untagged_command: &str) -> Result<()> {
-        self.conn.run_command(untagged_command)
+    /// Run a raw IMAP command, and parse the untagged responses to it with imap-proto. This is
+    /// meant for extensions that this crate does not support yet.
+    ///
+    /// All untagged responses are returned, including unilateral ones (such as `* 3 EXISTS`),
+    /// which are thus not queued in `unsolicited_responses`. If the server answers with `NO` or
+    /// `BAD`, this fails with the corresponding error; otherwise, the tagged `OK` response is
+    /// available from `last_ok_response`. If imap-proto cannot parse a response, this fails with
+    /// `Error::Parse`, and `run_command_and_read_response` has to be used instead.
+    pub fn run_command_and_parse(&mut self, command: &str) -> ZeroCopyResult<UntaggedResponses> {
+        self.run_command_and_read_response(command)
+            .and_then(parse_untagged)
+    }
+
+    /// Runs any command passed to it, and returns the tag it was sent with (e.g. `a4`), which the
+    /// server's tagged response to it will carry.
+    pub fn run_command(&mut self, untagged_command: &str) -> Result<String> {
+        self.conn.run_command(untagged_command)?;
+        Ok(self.conn.last_tag().unwrap_or_default())
+    }
+
+    /// Send several independent commands at once, and only then read the responses to them, to
+    /// save a round trip per command (e.g. when asking for the `STATUS` of many mailboxes). The
+    /// result for each command is the untagged data that came with it, as returned by
+    /// `run_command_and_read_response`, or the error that the server failed it with.
+    ///
+    /// The server may answer the commands in any order; each tagged response is matched to its
+    /// command, and the untagged responses are given to the command whose tagged response
+    /// follows them. That is how servers that handle one command at a time answer, but servers
+    /// that run them concurrently may mix up the untagged responses of different commands.
+    /// As per [RFC 3501](https://tools.ietf.org/html/rfc3501#section-5.5), the caller must also
+    /// not mix commands whose outcome depends on each other, such as a `FETCH` by sequence
+    /// number with a `STORE` or `EXPUNGE`.
+    ///
+    /// Commands that change the state of the connection (`LOGIN`, `AUTHENTICATE`, `STARTTLS`,
+    /// `COMPRESS`, `SELECT`, `EXAMINE`, `CLOSE`, `UNSELECT`, `ENABLE`, and `LOGOUT`) or wait for
+    /// the server's go-ahead (`IDLE`, `APPEND`, and commands with literals) are rejected with
+    /// `Error::NotPipelinable` before anything is sent.
+    pub fn pipeline(&mut self, commands: &[&str]) -> Result<Vec<Result<Vec<u8>>>> {
+        if let Some(command) = commands.iter().find(|c| !can_pipeline(c)) {
+            return Err(Error::NotPipelinable(command.to_string()));
+        }
+        self.conn.run_pipeline(commands)
     }
 
     /// Run a raw IMAP command and read back its response.
@@ -684,27 +2868,348 @@ impl <T: Read + Write> Session<T> {
     }
 }
 
+impl<T: Read + Write + SetReadTimeout> Connection<T> {
+    /// Set how long to wait for data from the server before failing with `Error::Timeout`, or
+    /// wait forever if `timeout` is `None` (the default).
+    ///
+    /// This guards against hung servers. Since the state of the session is unknown after a
+    /// command times out, it can no longer be used, and the caller should reconnect to retry.
+    ///
+    /// IDLE, where long waits are expected, uses its own timeouts (see
+    /// [`IdleHandle`](struct.IdleHandle.html)), and restores this one when it is done, as long as
+    /// the transport can report its timeout (see `SetReadTimeout::read_timeout`).
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.get_mut().set_read_timeout(timeout)
+    }
+
+    /// Set how long writing a command to the server may block before failing with
+    /// `Error::Timeout`, or block indefinitely if `timeout` is `None` (the default).
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.get_mut().set_write_timeout(timeout)
+    }
+}
+
 impl <T: Read + Write> Connection<T> {
+    /// Capability requests a listing of capabilities that the server supports.
+    ///
+    /// Servers often announce their capabilities unasked, in the greeting or in the response to
+    /// logging in (as in `OK [CAPABILITY IMAP4rev1 ...] Logged in`). The most recently announced
+    /// capabilities are remembered, and returned here without sending a `CAPABILITY` command.
+    pub fn capabilities(&mut self) -> ZeroCopyResult<Capabilities> {
+        if let Some(ref lines) = self.capabilities {
+            return parse_capabilities(lines.to_vec());
+        }
+        let lines = self.run_command_and_read_response("CAPABILITY")?;
+        let caps = parse_capabilities(lines.clone())?;
+        self.capabilities = Some(lines.into_boxed_slice());
+        Ok(caps)
+    }
+
+    /// Returns true if the server advertises the given capability (compared case-insensitively),
+    /// such as `MOVE` or `UIDPLUS`.
+    ///
+    /// This uses the capabilities remembered by `capabilities`, and only asks the server for them
+    /// if they are not yet known. They are forgotten when logging in, since the server may then
+    /// advertise different ones.
+    pub fn has_capability(&mut self, capability: &str) -> Result<bool> {
+        let caps = self.capabilities()?;
+        let has = caps.iter().any(|c| c.eq_ignore_ascii_case(capability));
+        Ok(has)
+    }
+
+    // Read the server greeting. Some proxies send informational lines of their own before it,
+    // so blank lines and untagged responses that are not a greeting (`* OK`, `* PREAUTH`, or
+    // `* BYE`) are skipped. Anything else means that this is not an IMAP server, or that a
+    // middlebox got in the way.
     fn read_greeting(&mut self) -> Result<()> {
-        let mut v = Vec::new();
-        self.readline(&mut v)?;
-        Ok(())
+        loop {
+            let mut v = Vec::new();
+            self.readline(&mut v)?;
+            let line = String::from_utf8_lossy(&v);
+            let line = line.trim_end();
+            if line.is_empty() {
+                continue;
+            }
+            let mut words = line.splitn(3, ' ');
+            if words.next() != Some("*") {
+                let line = v.strip_suffix(b"\r\n").unwrap_or(&v);
+                return Err(Error::UnexpectedGreeting(line.escape_ascii().to_string()));
+            }
+            let status = words.next().unwrap_or("").to_uppercase();
+            let information = words.next().unwrap_or("");
+            let status = match &*status {
+                "OK" => GreetingStatus::Ok,
+                "PREAUTH" => GreetingStatus::PreAuth,
+                "BYE" => return Err(Error::bye(information)),
+                _ => continue,
+            };
+            self.note_capabilities(information);
+            self.details.greeting = Some(Greeting {
+                status,
+                text: parse_response_text(&v),
+            });
+            return Ok(());
+        }
+    }
+
+    // Remember the capabilities announced in the text of an `OK` response, if any.
+    fn note_capabilities(&mut self, information: &str) {
+        if let Some((code, caps)) = split_response_code(information) {
+            if code.eq_ignore_ascii_case("CAPABILITY") {
+                self.capabilities = Some(format!("* CAPABILITY {}\r\n", caps).into_bytes().into_boxed_slice());
+            }
+        }
     }
 
     fn run_command_and_check_ok(&mut self, command: &str) -> Result<()> {
         self.run_command_and_read_response(command).map(|_| ())
     }
 
+    // Quote a mailbox name for use as a command argument, encoding it in modified UTF-7 unless
+    // `UTF8=ACCEPT` is enabled. Encoded names only consist of printable characters, so they can
+    // always be quoted; other names are sent as literals if they contain line breaks.
+    fn quote_mailbox(&self, name: &str) -> String {
+        if self.utf8_accept {
+            quote!(name)
+        } else {
+            quote!(utf7::encode(name))
+        }
+    }
+
+    // Search criteria with non-ASCII characters need `CHARSET UTF-8`, unless `UTF8=ACCEPT` is
+    // enabled, in which case UTF-8 is the default.
+    fn search_criteria<'q>(&self, query: &'q str) -> Cow<'q, str> {
+        if self.utf8_accept
+            || query.is_ascii()
+            || query
+                .as_bytes()
+                .get(..8)
+                .is_some_and(|p| p.eq_ignore_ascii_case(b"CHARSET "))
+        {
+            Cow::Borrowed(query)
+        } else {
+            Cow::Owned(format!("CHARSET UTF-8 {}", query))
+        }
+    }
+
+    /// The greeting the server sent when we connected, which tells whether the connection is
+    /// already authenticated (`PREAUTH`), and usually names the server software. `None` if the
+    /// greeting was not read by this crate (see `Client::new`).
+    pub fn greeting(&self) -> Option<&Greeting> {
+        self.details.greeting.as_ref()
+    }
+
+    /// The tagged `OK` response to the last command that succeeded, such as `a2 OK [READ-WRITE]
+    /// SELECT completed`. Its `response_code` tells apart the various ways in which a command
+    /// may succeed, and an `[ALERT]` in it should be shown to the user.
+    pub fn last_ok_response(&self) -> Option<&ResponseText> {
+        self.details.last_ok.as_ref()
+    }
+
+    /// The text of the `* BYE` response the server sent when logging out (see `Session::logout`),
+    /// if any.
+    pub fn bye_response(&self) -> Option<&str> {
+        self.details.bye.as_deref()
+    }
+
+    /// Accept slightly malformed responses from nonconforming servers where possible, such as a
+    /// `* LIST` response without a space before its flags, and skip the untagged responses that
+    /// cannot be parsed (logging a warning), instead of failing the whole command with
+    /// `Error::Parse`. This applies to the responses of `list`, `lsub`, `fetch` and `uid_fetch`.
+    ///
+    /// This is off by default, so that malformed responses are noticed.
+    pub fn set_lenient(&mut self, lenient: bool) {
+        self.details.lenient = lenient;
+    }
+
+    /// Read from the server in chunks of up to `size` bytes (8 KiB by default). A larger buffer
+    /// takes fewer system calls to receive large responses, such as the message bodies of a
+    /// big `FETCH`, which can make a difference on fast links. Literals that are larger than the
+    /// buffer are read straight into the response, without being copied through the buffer.
+    ///
+    /// Any data that has already been read is kept.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn set_read_buffer_size(&mut self, size: usize) {
+        assert!(size > 0, "the read buffer cannot be empty");
+        self.stream.set_capacity(size);
+    }
+
+    /// The size of the chunks read from the server (see `set_read_buffer_size`).
+    pub fn read_buffer_size(&self) -> usize {
+        self.stream.capacity()
+    }
+
+    /// Use `prefix` instead of `a` for the tags of the commands that follow, so that they are
+    /// tagged `<prefix>1`, `<prefix>2`, and so on (the counter is not reset). This can help tell
+    /// apart the traffic of several connections in a log.
+    ///
+    /// The prefix may not contain characters that are not allowed in tags, such as spaces,
+    /// parentheses, quotes, or `+`.
+    pub fn set_tag_prefix(&mut self, prefix: &str) -> Result<()> {
+        if let Some(c) = prefix
+            .chars()
+            .find(|&c| !c.is_ascii_graphic() || "(){%*\"\\]+".contains(c))
+        {
+            return Err(Error::Validate(ValidateError(c)));
+        }
+        self.details.tag_prefix = prefix.to_string();
+        Ok(())
+    }
+
+    /// Call `callback` with the name of each command that completes, such as `FETCH` or `UID
+    /// SEARCH`, and the time from sending it until the server's tagged response arrived,
+    /// whether the command succeeded or not. This helps find out which commands are slow on a
+    /// given server, e.g. when profiling a synchronization job.
+    ///
+    /// The time includes any round trips within the command, such as for the continuation
+    /// requests of `APPEND`, and for pipelined commands (see `Session::pipeline`), the time
+    /// spent waiting for the responses to the commands before them.
+    pub fn set_timing_callback<F>(&mut self, callback: F)
+    where
+        F: FnMut(&str, Duration) + Send + 'static,
+    {
+        self.details.timing = Some(Timing {
+            callback: Box::new(callback),
+            pending: Vec::new(),
+        });
+    }
+
+    /// Stop timing commands, as started with `set_timing_callback`.
+    pub fn clear_timing_callback(&mut self) {
+        self.details.timing = None;
+    }
+
+    /// How long ago the last command was sent on this connection, or `None` if none was sent
+    /// yet. See `Session::keepalive`.
+    pub fn idle_time(&self) -> Option<Duration> {
+        self.details.last_command.map(|sent| sent.elapsed())
+    }
+
+    // Report the time the command with the given tag took, now that its tagged response arrived.
+    fn note_completed(&mut self, tag: &str) {
+        if let Some(ref mut timing) = self.details.timing {
+            if let Some(i) = timing.pending.iter().position(|p| p.0 == tag) {
+                let (_, name, sent) = timing.pending.remove(i);
+                (timing.callback)(&name, sent.elapsed());
+            }
+        }
+    }
+
+    /// The tag of the last command sent, such as `a4`, or `None` if none was sent yet.
+    pub fn last_tag(&self) -> Option<String> {
+        if self.tag == INITIAL_TAG {
+            None
+        } else {
+            Some(format!("{}{}", self.details.tag_prefix, self.tag))
+        }
+    }
+
+    /// Returns true if the connection was closed in the middle of a response, or after logging
+    /// out. The state of the session is then unknown, and all further commands fail with
+    /// `Error::ConnectionClosed`.
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned
+    }
+
     fn run_command(&mut self, untagged_command: &str) -> Result<()> {
+        if self.poisoned {
+            return Err(Error::ConnectionClosed(
+                "session is unusable after an incomplete response or logout".to_string(),
+            ));
+        }
+        // a line break would end the command early; literals are split off before this
+        if let Some(c) = untagged_command.chars().find(|&c| c == '\r' || c == '\n') {
+            return Err(Error::Validate(ValidateError(c)));
+        }
+        if !is_idempotent(untagged_command) {
+            self.details.sent_non_idempotent = true;
+        }
         let command = self.create_command(untagged_command);
+        self.details.last_command = Some(Instant::now());
+        if let Some(ref mut timing) = self.details.timing {
+            let tag = format!("{}{}", self.details.tag_prefix, self.tag);
+            timing.pending.push((tag, command_name(untagged_command), Instant::now()));
+        }
         self.write_line(command.into_bytes().as_slice())
     }
 
+    pub(crate) fn sent_non_idempotent(&self) -> bool {
+        self.details.sent_non_idempotent
+    }
+
+    pub(crate) fn reset_non_idempotent(&mut self) {
+        self.details.sent_non_idempotent = false;
+    }
+
     fn run_command_and_read_response(&mut self, untagged_command: &str) -> Result<Vec<u8>> {
+        if untagged_command.contains("}\r\n") {
+            let (lines, literals) = split_literals(untagged_command);
+            return self.run_command_with_literals(lines, &literals);
+        }
         self.run_command(untagged_command)?;
         self.read_response()
     }
 
+    // Run a command with literal arguments, each of which is sent after the line before it. The
+    // announcement of each literal is appended to that line; the literal is then sent once the
+    // server has asked for it, or right away if it may be sent as a non-synchronizing literal.
+    fn run_command_with_literals<L: AsRef<[u8]>>(
+        &mut self,
+        lines: Vec<String>,
+        literals: &[L],
+    ) -> Result<Vec<u8>> {
+        let mut untagged = Vec::new();
+        for (i, mut line) in lines.into_iter().enumerate() {
+            let non_sync = literals
+                .get(i)
+                .is_some_and(|literal| self.non_sync_literal(literal.as_ref().len()));
+            if let Some(literal) = literals.get(i) {
+                line.push_str(&literal_announcement(literal.as_ref().len(), non_sync));
+            }
+            if i == 0 {
+                self.run_command(&line)?;
+            } else {
+                self.stream.write_all(literals[i - 1].as_ref())?;
+                self.write_line(line.as_bytes())?;
+            }
+            if i == literals.len() || non_sync {
+                continue;
+            }
+
+            // wait for the continuation, keeping any untagged responses that come first
+            loop {
+                let mut v = Vec::new();
+                self.readline(&mut v)?;
+                if v.starts_with(b"+") {
+                    break;
+                } else if v.starts_with(b"* ") {
+                    untagged.extend(v);
+                } else {
+                    // the server refused the literal with a tagged response
+                    self.read_response_onto(&mut v)?;
+                    return Ok(untagged);
+                }
+            }
+        }
+        untagged.extend(self.read_response()?);
+        Ok(untagged)
+    }
+
+    // Whether a literal of `len` octets may be sent without waiting for the server to ask for it,
+    // as the server is known to support `LITERAL+` or `LITERAL-` (see `Capabilities`). The
+    // capabilities are not requested just for this.
+    fn non_sync_literal(&self, len: usize) -> bool {
+        let limit = self
+            .capabilities
+            .as_ref()
+            .and_then(|caps| parse_capabilities(caps.to_vec()).ok())
+            .and_then(|caps| caps.non_sync_literal_limit());
+        limit.is_some_and(|limit| len as u64 <= limit)
+    }
+
     fn read_response(&mut self) -> Result<Vec<u8>> {
         let mut v = Vec::new();
         self.read_response_onto(&mut v)?;
@@ -712,10 +3217,48 @@ impl <T: Read + Write> Connection<T> {
     }
 
     fn read_response_onto(&mut self, data: &mut Vec<u8>) -> Result<()> {
+        self.read_tagged_response_onto(data).map(|_| ())
+    }
+
+    // Like `read_response_onto`, but also returns the text of the tagged OK response, which may
+    // carry response codes that imap-proto does not know about.
+    //
+    // If the server hangs up before the tagged response, we return `Error::Bye` if it said `* BYE`
+    // first, and otherwise `Error::ConnectionClosed` (and poison the connection). If it times
+    // out, the rest of the response may still arrive, so the connection is poisoned as well.
+    fn read_tagged_response_onto(&mut self, data: &mut Vec<u8>) -> Result<Option<String>> {
+        match self.read_tagged_response_inner(data) {
+            Err(Error::ConnectionLost) => {}
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::UnexpectedEof => {}
+            Err(Error::Timeout) => {
+                self.poisoned = true;
+                return Err(Error::Timeout);
+            }
+            r => return r,
+        }
+
+        self.poisoned = true;
+        Err(match bye_text(data) {
+            Some(text) => Error::bye(&text),
+            None => Error::ConnectionClosed(format!(
+                "incomplete response: connection closed before the tagged response to {}{}",
+                self.details.tag_prefix, self.tag
+            )),
+        })
+    }
+
+    fn read_tagged_response_inner(&mut self, data: &mut Vec<u8>) -> Result<Option<String>> {
         let mut continue_from = None;
         let mut try_first = !data.is_empty();
-        let match_tag = format!("{}{}", TAG_PREFIX, self.tag);
+        let mut pending_literal = 0;
+        let mut announced = None;
+        let match_tag = format!("{}{}", self.details.tag_prefix, self.tag);
         loop {
+            if pending_literal > 0 {
+                self.read_literal(data, pending_literal)?;
+                pending_literal = 0;
+            }
+
             let line_start = if try_first {
                 try_first = false;
                 0
@@ -740,41 +3283,64 @@ impl <T: Read + Write> Connection<T> {
                         },
                     ) => {
                         assert_eq!(tag.as_bytes(), match_tag.as_bytes());
+                        self.note_completed(&match_tag);
                         Some(match status {
-                            Status::Bad | Status::No => {
-                                Err((status, information.map(|s| s.to_string())))
+                            Status::Bad | Status::No => Err((status, Some(parse_response_text(line)))),
+                            Status::Ok => {
+                                self.details.last_ok = Some(parse_response_text(line));
+                                Ok(information.map(|s| s.to_string()))
                             }
-                            Status::Ok => Ok(()),
                             status => Err((status, None)),
                         })
                     }
+                    IResult::Done(
+                        _,
+                        Response::Data {
+                            status: Status::Ok,
+                            information: Some(information),
+                            ..
+                        },
+                    ) => {
+                        announced = Some(information.to_string());
+                        None
+                    }
                     IResult::Done(..) => None,
+                    // the response continues on the next line (such as after a literal), so
+                    // read more and parse it again, until it is complete
                     IResult::Incomplete(..) => {
                         continue_from = Some(line_start);
                         None
                     }
+                    // imap-proto doesn't know every untagged response; leave those for the
+                    // caller's parser, making sure to read all of any literal they contain.
+                    _ if line.starts_with(b"* ") => {
+                        if let Some(len) = trailing_literal_len(line) {
+                            continue_from = Some(line_start);
+                            pending_literal = len;
+                        }
+                        None
+                    }
                     _ => Some(Err((Status::Bye, None))),
                 }
             };
 
+            if let Some(information) = announced.take() {
+                self.note_capabilities(&information);
+            }
+
             match break_with {
-                Some(Ok(_)) => {
+                Some(Ok(information)) => {
+                    if let Some(ref information) = information {
+                        self.note_capabilities(information);
+                    }
                     data.truncate(line_start);
-                    break Ok(());
+                    break Ok(information);
                 }
-                Some(Err((status, expl))) => {
+                Some(Err((status, text))) => {
                     use imap_proto::Status;
-                    match status {
-                        Status::Bad => {
-                            break Err(Error::BadResponse(
-                                expl.unwrap_or_else(|| "no explanation given".to_string()),
-                            ))
-                        }
-                        Status::No => {
-                            break Err(Error::NoResponse(
-                                expl.unwrap_or_else(|| "no explanation given".to_string()),
-                            ))
-                        }
+                    match (status, text) {
+                        (Status::Bad, Some(text)) => break Err(Error::Bad(text)),
+                        (Status::No, Some(text)) => break Err(no_response(text)),
                         _ => break Err(Error::Parse(ParseError::Invalid(data.split_off(0)))),
                     }
                 }
@@ -783,49 +3349,310 @@ impl <T: Read + Write> Connection<T> {
         }
     }
 
-    fn readline(&mut self, into: &mut Vec<u8>) -> Result<usize> {
-        use std::io::BufRead;
-        let read = self.stream.read_until(LF, into)?;
-        if read == 0 {
-            return Err(Error::ConnectionLost);
-        }
-
-        if self.debug {
-            // Remove CRLF
-            let len = into.len();
-            let line = &into[(len - read)..(len - 2)];
-            print!("S: {}\n", String::from_utf8_lossy(line));
+    fn run_pipeline(&mut self, commands: &[&str]) -> Result<Vec<Result<Vec<u8>>>> {
+        let mut tags = Vec::with_capacity(commands.len());
+        for command in commands {
+            self.run_command(command)?;
+            tags.push(self.last_tag().unwrap_or_default());
         }
 
-        Ok(read)
-    }
+        let mut results: Vec<_> = tags.iter().map(|_| None).collect();
+        let mut data = Vec::new();
+        while results.iter().any(Option::is_none) {
+            let start = data.len();
+            if let Err(e) = self.read_untagged(&mut data) {
+                // the state of the outstanding commands is unknown
+                self.poisoned = true;
+                return Err(match e {
+                    Error::ConnectionLost => match bye_text(&data) {
+                        Some(text) => Error::bye(&text),
+                        None => Error::ConnectionClosed(
+                            "incomplete response: connection closed during a pipeline".to_string(),
+                        ),
+                    },
+                    e => e,
+                });
+            }
+            if data[start..].starts_with(b"* ") {
+                continue;
+            }
 
-    fn create_command(&mut self, command: &str) -> String {
-        self.tag += 1;
-        format!("{}{} {}", TAG_PREFIX, self.tag, command)
-    }
+            let line = data.split_off(start);
+            let untagged = ::std::mem::take(&mut data);
+            let tag = line.split(|&c| c == b' ').next().unwrap_or(&[]);
+            let i = match tags.iter().position(|t| t.as_bytes() == tag) {
+                Some(i) if results[i].is_none() => i,
+                _ => {
+                    // an unknown or repeated tag: we can no longer tell which response is which
+                    self.poisoned = true;
+                    return Err(Error::Parse(ParseError::Invalid(line)));
+                }
+            };
+            self.note_completed(&tags[i]);
+
+            use imap_proto::{parse_response, Response, Status};
+            results[i] = Some(match parse_response(&line) {
+                IResult::Done(_, Response::Done { status, .. }) => {
+                    let text = parse_response_text(&line);
+                    match status {
+                        Status::Ok => {
+                            self.details.last_ok = Some(text);
+                            Ok(untagged)
+                        }
+                        Status::No => Err(no_response(text)),
+                        Status::Bad => Err(Error::Bad(text)),
+                        _ => Err(Error::Parse(ParseError::Invalid(line))),
+                    }
+                }
+                _ => Err(Error::Parse(ParseError::Invalid(line))),
+            });
+        }
+        Ok(results.into_iter().map(Option::unwrap).collect())
+    }
+
+    // Read a single untagged response, including any literals it contains.
+    fn read_untagged(&mut self, into: &mut Vec<u8>) -> Result<()> {
+        let mut line_start = into.len();
+        self.readline(into)?;
+        while let Some(len) = trailing_literal_len(&into[line_start..]) {
+            self.read_literal(into, len)?;
+            line_start = into.len();
+            self.readline(into)?;
+        }
+        Ok(())
+    }
+
+    // Read a literal of `len` bytes onto `into`.
+    fn read_literal(&mut self, into: &mut Vec<u8>, len: usize) -> Result<()> {
+        let start = into.len();
+        into.resize(start + len, 0);
+        match self.stream.read_exact(&mut into[start..]) {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::ConnectionLost),
+            r => r.map_err(Error::from),
+        }
+    }
+
+    // Read a line, including its CRLF. If the connection is closed before the end of the line,
+    // this fails with `Error::ConnectionLost`, so that a truncated response is never mistaken for
+    // one that does not parse.
+    fn readline(&mut self, into: &mut Vec<u8>) -> Result<usize> {
+        use std::io::BufRead;
+        let start = into.len();
+        let result = self.stream.read_until(LF, into);
+        let read = into.len() - start;
+        if read > 0 {
+            self.log_traffic("S", &into[start..]);
+        }
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(Error::ConnectionLost),
+            Err(e) => Err(e.into()),
+            Ok(_) if into.last() != Some(&LF) || read == 0 => Err(Error::ConnectionLost),
+            Ok(read) => Ok(read),
+        }
+    }
+
+    fn create_command(&mut self, command: &str) -> String {
+        self.tag += 1;
+        format!("{}{} {}", self.details.tag_prefix, self.tag, command)
+    }
 
     fn write_line(&mut self, buf: &[u8]) -> Result<()> {
-        self.stream.write_all(buf)?;
-        self.stream.write_all(&[CR, LF])?;
+        let logged = redact(buf);
+        self.write_line_as(buf, &logged)
+    }
+
+    // Write a line, but show it as `logged` in the traffic log (see `log_traffic`).
+    fn write_line_as(&mut self, buf: &[u8], logged: &[u8]) -> Result<()> {
+        let mut line = Vec::with_capacity(buf.len() + 2);
+        line.extend_from_slice(buf);
+        line.extend_from_slice(&[CR, LF]);
+        self.stream.write_all(&line)?;
         self.stream.flush()?;
+        self.log_traffic("C", logged);
+        Ok(())
+    }
+
+    // Log a line sent by the client (`C`) or the server (`S`), and print it if `debug` is set.
+    fn log_traffic(&self, from: &str, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end_matches(['\r', '\n']);
         if self.debug {
-            print!("C: {}\n", String::from_utf8(buf.to_vec()).unwrap());
+            println!("{}: {}", from, line);
         }
-        Ok(())
+        trace!("{}: {}", from, line);
+    }
+}
+
+// Hide the credentials in a `LOGIN` command, or the initial response of an `AUTHENTICATE`, from
+// the traffic log.
+fn redact(line: &[u8]) -> Cow<'_, [u8]> {
+    let mut words = line.splitn(4, |&c| c == b' ');
+    let (tag, command) = match (words.next(), words.next()) {
+        (Some(tag), Some(command)) => (tag, command),
+        _ => return Cow::Borrowed(line),
+    };
+    if command.eq_ignore_ascii_case(b"LOGIN") && words.next().is_some() {
+        Cow::Owned([tag, b" ", command, b" <redacted>"].concat())
+    } else if command.eq_ignore_ascii_case(b"AUTHENTICATE") {
+        match (words.next(), words.next()) {
+            (Some(mechanism), Some(_)) => {
+                Cow::Owned([tag, b" ", command, b" ", mechanism, b" <redacted>"].concat())
+            }
+            _ => Cow::Borrowed(line),
+        }
+    } else {
+        Cow::Borrowed(line)
+    }
+}
+
+
+// Split search criteria into lines for `run_command_with_literals`, turning the strings (quoted or
+// not) that contain non-ASCII characters into literals, which go between the lines. Literals that
+// are already in the criteria (see `ImapArg`) are kept.
+fn literal_terms(criteria: &str) -> (Vec<String>, Vec<Vec<u8>>) {
+    let mut lines = vec![String::new()];
+    let mut literals = Vec::new();
+    let mut rest = criteria;
+    while let Some(c) = rest.chars().next() {
+        let (term, len) = match c {
+            ' ' | '(' | ')' => {
+                lines.last_mut().unwrap().push(c);
+                rest = &rest[1..];
+                continue;
+            }
+            '{' => match inline_literal(rest) {
+                Some((data, len)) => {
+                    literals.push(data.as_bytes().to_vec());
+                    lines.push(String::new());
+                    rest = &rest[len..];
+                    continue;
+                }
+                None => {
+                    let end = rest.find([' ', '(', ')']).unwrap_or(rest.len());
+                    (rest[..end].to_string(), end)
+                }
+            },
+            '"' => {
+                let mut term = String::new();
+                let mut escaped = false;
+                let mut end = rest.len();
+                for (i, c) in rest.char_indices().skip(1) {
+                    if escaped {
+                        term.push(c);
+                        escaped = false;
+                    } else if c == '\\' {
+                        escaped = true;
+                    } else if c == '"' {
+                        end = i + 1;
+                        break;
+                    } else {
+                        term.push(c);
+                    }
+                }
+                (term, end)
+            }
+            _ => {
+                let end = rest.find([' ', '(', ')']).unwrap_or(rest.len());
+                (rest[..end].to_string(), end)
+            }
+        };
+
+        if rest[..len].is_ascii() {
+            lines.last_mut().unwrap().push_str(&rest[..len]);
+        } else {
+            literals.push(term.into_bytes());
+            lines.push(String::new());
+        }
+        rest = &rest[len..];
+    }
+    (lines, literals)
+}
+
+// The data of the literal that `s` starts with (e.g. `{3}\r\nfoo`), and the length of the
+// literal with its announcement.
+fn inline_literal(s: &str) -> Option<(&str, usize)> {
+    let end = s.find("}\r\n")?;
+    let len: usize = s[1..end].parse().ok()?;
+    let data = s.get(end + 3..end + 3 + len)?;
+    Some((data, end + 3 + len))
+}
+
+// The additional data of a successful `AUTHENTICATE`, which a server may send as the single
+// argument of a response code of its tagged `OK` (e.g. `[SASL dj1ybUY5...]`), base64-decoded.
+fn sasl_data(text: &ResponseText) -> Option<Vec<u8>> {
+    let mut words = text.code()?.split(' ').skip(1);
+    match (words.next(), words.next()) {
+        (Some(data), None) => base64::decode(data).ok(),
+        _ => None,
+    }
+}
+
+// The announcement of a literal of `len` octets at the end of a line, e.g. `{12}`, or `{12+}` if
+// it is non-synchronizing.
+fn literal_announcement(len: usize, non_sync: bool) -> String {
+    if non_sync {
+        format!("{{{}+}}", len)
+    } else {
+        format!("{{{}}}", len)
+    }
+}
+
+// Whether a command can be sent before the responses to earlier ones have arrived (see
+// `Session::pipeline`).
+fn can_pipeline(command: &str) -> bool {
+    let name = command.split(' ').next().unwrap_or("").to_uppercase();
+    let not_pipelinable = [
+        "LOGIN",
+        "AUTHENTICATE",
+        "STARTTLS",
+        "COMPRESS",
+        "SELECT",
+        "EXAMINE",
+        "CLOSE",
+        "UNSELECT",
+        "ENABLE",
+        "LOGOUT",
+        "IDLE",
+        "APPEND",
+    ];
+    !not_pipelinable.contains(&&name[..]) && !command.trim_end().ends_with('}')
+}
+
+// Fail if the UIDVALIDITY of a newly selected mailbox is not the one that cached UIDs belong to.
+fn check_uid_validity(expected: u32, mailbox: &Mailbox) -> Result<()> {
+    match mailbox.uid_validity {
+        Some(actual) if actual != expected => Err(Error::UidValidityChanged(expected, actual)),
+        _ => Ok(()),
     }
 }
 
+// The text of the `* BYE` response among `data`, if any.
+fn bye_text(data: &[u8]) -> Option<String> {
+    data.split(|&c| c == LF)
+        .find(|line| line.len() >= 5 && line[..5].eq_ignore_ascii_case(b"* BYE"))
+        .map(|line| String::from_utf8_lossy(&line[5..]).trim().to_string())
+}
+
+// The length of the literal announced at the end of `line` (`{<len>}\r\n`), if any.
+fn trailing_literal_len(line: &[u8]) -> Option<usize> {
+    if !line.ends_with(b"}\r\n") {
+        return None;
+    }
+    let line = &line[..line.len() - 3];
+    let start = line.iter().rposition(|&c| c == b'{')?;
+    ::std::str::from_utf8(&line[start + 1..]).ok()?.parse().ok()
+}
 
 #[cfg(test)]
 mod tests {
-    use super::super::error::Result;
+    use super::super::authenticator::{Login, OAuth2, Plain, Scram};
+    use super::super::error::{ResponseCode, Result};
     use super::super::mock_stream::MockStream;
     use super::*;
 
     macro_rules! mock_session {
         ($s:expr) => {
-            Session { conn: Client::new($s).conn }
+            Session::from_stream($s)
         }
     }
 
@@ -850,196 +3677,1367 @@ mod tests {
     }
 
     #[test]
-    fn read_greeting() {
-        let greeting = "* OK Dovecot ready.\r\n";
-        let mock_stream = MockStream::new(greeting.as_bytes().to_vec());
-        let mut client = Client::new(mock_stream);
-        client.read_greeting().unwrap();
-    }
+    fn run_command_and_parse() {
+        use imap_proto::{MailboxDatum, Response};
+
+        let response = b"* LIST (\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+            * 4 EXISTS\r\n\
+            a1 OK [UIDNEXT 5] LIST completed\r\n\
+            * XUNKNOWN what is this\r\n\
+            a2 OK done\r\n\
+            a3 NO not today\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let responses = session.run_command_and_parse("LIST \"\" \"*\"").unwrap();
+        let responses = responses.responses();
+        assert_eq!(responses.len(), 2);
+        match responses[0] {
+            Response::MailboxData(MailboxDatum::List { name, .. }) => assert_eq!(name, "INBOX"),
+            ref r => panic!("expected LIST, got {:?}", r),
+        }
+        assert_eq!(responses[1], Response::MailboxData(MailboxDatum::Exists(4)));
+        assert_eq!(
+            session.last_ok_response().unwrap().information(),
+            "LIST completed"
+        );
+        assert_eq!(session.unsolicited_responses().count(), 0);
 
-    #[test]
-    fn readline_delay_read() {
-        let greeting = "* OK Dovecot ready.\r\n";
-        let expected_response: String = greeting.to_string();
-        let mock_stream = MockStream::default()
-            .with_buf(greeting.as_bytes().to_vec())
-            .with_delay();
-        let mut client = Client::new(mock_stream);
-        let mut v = Vec::new();
-        client.readline(&mut v).unwrap();
-        let actual_response = String::from_utf8(v).unwrap();
-        assert_eq!(expected_response, actual_response);
+        match session.run_command_and_parse("XUNKNOWN") {
+            Err(Error::Parse(ParseError::Invalid(_))) => {}
+            r => panic!("expected a parse error, got {:?}", r),
+        }
+        match session.run_command_and_parse("XNO") {
+            Err(Error::No(_)) => {}
+            r => panic!("expected NO, got {:?}", r),
+        }
     }
 
     #[test]
-    fn readline_eof() {
-        let mock_stream = MockStream::default().with_eof();
-        let mut client = Client::new(mock_stream);
-        let mut v = Vec::new();
-        if let Err(Error::ConnectionLost) = client.readline(&mut v) {
-        } else {
-            unreachable!("EOF read did not return connection lost");
+    fn tag_prefix() {
+        let response = b"a1 OK NOOP completed\r\nimap2 OK NOOP completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.last_tag(), None);
+        session.noop().unwrap();
+        assert_eq!(session.last_tag(), Some("a1".to_string()));
+
+        session.set_tag_prefix("imap").unwrap();
+        assert_eq!(session.run_command("NOOP").unwrap(), "imap2");
+        session.read_response().unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 NOOP\r\nimap2 NOOP\r\n".to_vec(),
+            "Invalid tags"
+        );
+
+        match session.set_tag_prefix("a+") {
+            Err(Error::Validate(ValidateError('+'))) => {}
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+        match session.set_tag_prefix("a b") {
+            Err(Error::Validate(ValidateError(' '))) => {}
+            r => panic!("expected a validation error, got {:?}", r),
         }
     }
 
     #[test]
-    #[should_panic]
-    fn readline_err() {
-        // TODO Check the error test
-        let mock_stream = MockStream::default().with_err();
-        let mut client = Client::new(mock_stream);
-        let mut v = Vec::new();
-        client.readline(&mut v).unwrap();
+    fn read_buffer_size() {
+        let response = b"* 1 FETCH (BODY[] {20}\r\n0123456789abcdefghij)\r\n\
+            a1 OK Fetch completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.read_buffer_size(), 8 * 1024);
+        session.set_read_buffer_size(16);
+        assert_eq!(session.read_buffer_size(), 16);
+        let fetches = session.fetch("1", "BODY[]").unwrap();
+        assert_eq!(fetches[0].body(), Some(&b"0123456789abcdefghij"[..]));
     }
 
     #[test]
-    fn create_command() {
-        let base_command = "CHECK";
-        let mock_stream = MockStream::default();
-        let mut imap_stream = Client::new(mock_stream);
-
-        let expected_command = format!("a1 {}", base_command);
-        let command = imap_stream.create_command(&base_command);
-        assert!(
-            command == expected_command,
-            "expected command doesn't equal actual command"
-        );
+    fn timing_callback() {
+        let response = b"* SEARCH 2\r\n\
+            a1 OK Search completed\r\n\
+            a2 NO No such mailbox\r\n\
+            a4 OK NOOP completed\r\n\
+            a3 OK NOOP completed\r\n\
+            a5 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let (sender, receiver) = mpsc::channel();
+        session.set_timing_callback(move |name, duration| {
+            sender.send((name.to_string(), duration)).unwrap();
+        });
+        session.uid_search("ALL").unwrap();
+        assert!(session.select("Gone").is_err());
+        session.pipeline(&["NOOP", "noop"]).unwrap();
+        session.clear_timing_callback();
+        session.noop().unwrap();
 
-        let expected_command2 = format!("a2 {}", base_command);
-        let command2 = imap_stream.create_command(&base_command);
-        assert!(
-            command2 == expected_command2,
-            "expected command doesn't equal actual command"
-        );
+        let names: Vec<String> = receiver.try_iter().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["UID SEARCH", "SELECT", "NOOP", "NOOP"]);
     }
 
     #[test]
-    fn login() {
-        let response = b"a1 OK Logged in\r\n".to_vec();
-        let username = "username";
-        let password = "password";
-        let command = format!("a1 LOGIN {} {}\r\n", quote!(username), quote!(password));
-        let mock_stream = MockStream::new(response);
-        let client = Client::new(mock_stream);
-        let session = client.login(username, password).unwrap();
+    fn pipeline() {
+        let response = b"* STATUS \"INBOX\" (MESSAGES 3)\r\n\
+            a1 OK STATUS completed\r\n\
+            * STATUS \"Sent\" (MESSAGES {1}\r\n5)\r\n\
+            a3 NO [NONEXISTENT] No such mailbox\r\n\
+            a2 OK STATUS completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let results = session
+            .pipeline(&[
+                "STATUS \"INBOX\" (MESSAGES)",
+                "STATUS \"Sent\" (MESSAGES)",
+                "STATUS \"Gone\" (MESSAGES)",
+            ]).unwrap();
         assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid login command"
+            session.stream.get_ref().written_buf
+                == b"a1 STATUS \"INBOX\" (MESSAGES)\r\n\
+                     a2 STATUS \"Sent\" (MESSAGES)\r\n\
+                     a3 STATUS \"Gone\" (MESSAGES)\r\n"
+                    .to_vec(),
+            "Invalid pipelined commands"
+        );
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            b"* STATUS \"INBOX\" (MESSAGES 3)\r\n"
         );
+        // the untagged response before `a3` is given to it, even though it was meant for `a2`
+        assert_eq!(results[1].as_ref().unwrap(), b"");
+        match results[2] {
+            Err(Error::Code(ResponseCode::NonExistent, _)) => {}
+            ref r => panic!("expected NONEXISTENT, got {:?}", r),
+        }
+        assert!(!session.is_poisoned());
     }
 
     #[test]
-    fn logout() {
-        let response = b"a1 OK Logout completed.\r\n".to_vec();
-        let command = format!("a1 LOGOUT\r\n");
+    fn status() {
+        let response = b"* STATUS INBOX (MESSAGES 17 UNSEEN 3)\r\n\
+            a1 OK STATUS completed\r\n"
+            .to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        session.logout().unwrap();
+        let mailbox = session.status("INBOX", "(MESSAGES UNSEEN)").unwrap();
+        let command = b"a1 STATUS \"INBOX\" (MESSAGES UNSEEN)\r\n".to_vec();
         assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid logout command"
+            session.stream.get_ref().written_buf == command,
+            "Invalid status command"
         );
+        assert_eq!(mailbox.exists, 17);
+        assert_eq!(mailbox.unseen_count, Some(3));
+        assert_eq!(mailbox.first_unseen, None);
     }
 
     #[test]
-    fn rename() {
-        let response = b"a1 OK RENAME completed\r\n".to_vec();
-        let current_mailbox_name = "INBOX";
-        let new_mailbox_name = "NEWINBOX";
-        let command = format!(
-            "a1 RENAME {} {}\r\n",
-            quote!(current_mailbox_name),
-            quote!(new_mailbox_name)
-        );
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session
-            .rename(current_mailbox_name, new_mailbox_name)
+    fn status_highest_mod_seq() {
+        let response = b"* STATUS \"Sent Items\" (UIDVALIDITY 1800 HIGHESTMODSEQ 715194045007)\r\n\
+            a1 OK STATUS completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session
+            .status("Sent Items", "(UIDVALIDITY HIGHESTMODSEQ)")
             .unwrap();
-        assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid rename command"
-        );
+        assert_eq!(mailbox.uid_validity, Some(1800));
+        assert_eq!(mailbox.highest_mod_seq, Some(715194045007));
+
+        // as sent through NOTIFY, while another command is running
+        let response = b"* STATUS INBOX (MESSAGES 4 HIGHESTMODSEQ 12)\r\n\
+            a1 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let status = match session.noop().unwrap().pop() {
+            Some(UnsolicitedResponse::Status(status)) => status,
+            r => panic!("expected a STATUS, got {:?}", r),
+        };
+        assert_eq!(status.mailbox, "INBOX");
+        assert_eq!(status.messages, Some(4));
+        assert_eq!(status.highest_mod_seq, Some(12));
     }
 
     #[test]
-    fn subscribe() {
-        let response = b"a1 OK SUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("a1 SUBSCRIBE {}\r\n", quote!(mailbox));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.subscribe(mailbox).unwrap();
+    fn unread_counts() {
+        let response = b"* STATUS INBOX (UNSEEN 3)\r\n\
+            a1 OK STATUS completed\r\n\
+            a2 NO [CANNOT] Mailbox is not selectable\r\n\
+            * STATUS \"Gel&APY-scht\" (UNSEEN 0)\r\n\
+            * 5 EXISTS\r\n\
+            a3 OK STATUS completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let counts = session
+            .unread_counts(&["inbox", "[Gmail]", "Gel\u{f6}scht"])
+            .unwrap();
         assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid subscribe command"
+            session.stream.get_ref().written_buf
+                == b"a1 STATUS \"inbox\" (UNSEEN)\r\n\
+                     a2 STATUS \"[Gmail]\" (UNSEEN)\r\n\
+                     a3 STATUS \"Gel&APY-scht\" (UNSEEN)\r\n"
+                    .to_vec(),
+            "Invalid status commands"
+        );
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts["inbox"], 3);
+        assert_eq!(counts["Gel\u{f6}scht"], 0);
+        assert_eq!(
+            session.unsolicited_responses.pop_front(),
+            Some(UnsolicitedResponse::Exists(5))
         );
     }
 
     #[test]
-    fn unsubscribe() {
-        let response = b"a1 OK UNSUBSCRIBE completed\r\n".to_vec();
-        let mailbox = "INBOX";
-        let command = format!("a1 UNSUBSCRIBE {}\r\n", quote!(mailbox));
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.unsubscribe(mailbox).unwrap();
-        assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid unsubscribe command"
-        );
+    fn pipeline_rejected() {
+        let mut session = mock_session!(MockStream::new(Vec::new()));
+        for command in &["SELECT \"INBOX\"", "idle", "APPEND \"INBOX\" {3}"] {
+            match session.pipeline(&["NOOP", command]) {
+                Err(Error::NotPipelinable(ref c)) if c == command => {}
+                r => panic!("expected NotPipelinable, got {:?}", r),
+            }
+        }
+        assert!(session.stream.get_ref().written_buf.is_empty());
     }
 
     #[test]
-    fn expunge() {
-        let response = b"a1 OK EXPUNGE completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.expunge().unwrap();
-        assert!(
-            session.stream.get_ref().written_buf == b"a1 EXPUNGE\r\n".to_vec(),
-            "Invalid expunge command"
+    fn pipeline_connection_lost() {
+        let response = b"a1 OK NOOP completed\r\n* BYE going away\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.pipeline(&["NOOP", "NOOP"]) {
+            Err(Error::Bye(_, ref text)) if text == "going away" => {}
+            r => panic!("expected BYE, got {:?}", r),
+        }
+        assert!(session.is_poisoned());
+    }
+
+    #[test]
+    fn pipeline_unknown_tag() {
+        for response in &[
+            &b"a1 OK NOOP completed\r\nb7 OK NOOP completed\r\n"[..],
+            &b"a1 OK NOOP completed\r\na1 OK NOOP completed\r\n"[..],
+        ] {
+            let mut session = mock_session!(MockStream::new(response.to_vec()));
+            match session.pipeline(&["NOOP", "NOOP"]) {
+                Err(Error::Parse(ParseError::Invalid(_))) => {}
+                r => panic!("expected a parse error, got {:?}", r),
+            }
+            assert!(session.is_poisoned());
+        }
+    }
+
+    #[test]
+    fn read_greeting() {
+        let greeting = "* OK Dovecot ready.\r\n";
+        let mock_stream = MockStream::new(greeting.as_bytes().to_vec());
+        let mut client = Client::new(mock_stream);
+        client.read_greeting().unwrap();
+    }
+
+    #[test]
+    fn redact_credentials() {
+        assert_eq!(
+            &*redact(b"a1 LOGIN \"user\" \"secret\""),
+            &b"a1 LOGIN <redacted>"[..]
         );
+        assert_eq!(
+            &*redact(b"a2 authenticate PLAIN AHVzZXIAcGFzcw=="),
+            &b"a2 authenticate PLAIN <redacted>"[..]
+        );
+        assert_eq!(&*redact(b"a2 AUTHENTICATE PLAIN"), &b"a2 AUTHENTICATE PLAIN"[..]);
+        assert_eq!(&*redact(b"a3 SELECT LOGIN"), &b"a3 SELECT LOGIN"[..]);
     }
 
     #[test]
-    fn uid_expunge() {
-        let response = b"* 2 EXPUNGE\r\n\
-            * 3 EXPUNGE\r\n\
-            * 4 EXPUNGE\r\n\
-            a1 OK UID EXPUNGE completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.uid_expunge("2:4").unwrap();
+    fn client_from_stream() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 AUTH=PLAIN] Dovecot ready.\r\n\
+            a1 OK Logged in\r\n"
+            .to_vec();
+        let mut client = Client::from_stream(MockStream::new(response)).unwrap();
+        assert!(client.has_capability("AUTH=PLAIN").unwrap());
+        let session = client.login("username", "password").ok().unwrap();
         assert!(
-            session.stream.get_ref().written_buf == b"a1 UID EXPUNGE 2:4\r\n".to_vec(),
-            "Invalid expunge command"
+            session.stream.get_ref().written_buf
+                == b"a1 LOGIN \"username\" \"password\"\r\n".to_vec(),
+            "Invalid login command"
         );
     }
 
     #[test]
-    fn check() {
-        let response = b"a1 OK CHECK completed\r\n".to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        session.check().unwrap();
+    fn greeting() {
+        let greeting = b"* OK [CAPABILITY IMAP4rev1 STARTTLS] Dovecot ready.\r\n".to_vec();
+        let client = Client::from_stream(MockStream::new(greeting)).unwrap();
+        {
+            let greeting = client.greeting().unwrap();
+            assert_eq!(greeting.status(), GreetingStatus::Ok);
+            assert_eq!(greeting.text().code(), Some("CAPABILITY IMAP4rev1 STARTTLS"));
+            assert_eq!(greeting.text().information(), "Dovecot ready.");
+        }
+        assert!(client.into_preauthenticated_session().is_err());
+
+        let greeting = b"* PREAUTH IMAP4rev1 server logged in as Smith\r\n\
+            a1 OK NOOP completed\r\n"
+            .to_vec();
+        let client = Client::from_stream(MockStream::new(greeting)).unwrap();
+        assert!(client.greeting().unwrap().is_preauth());
+        let mut session = client.into_preauthenticated_session().ok().unwrap();
+        session.noop().unwrap();
         assert!(
-            session.stream.get_ref().written_buf == b"a1 CHECK\r\n".to_vec(),
-            "Invalid check command"
+            session.stream.get_ref().written_buf == b"a1 NOOP\r\n".to_vec(),
+            "Invalid noop command"
         );
     }
 
     #[test]
-    fn examine() {
-        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
-            * OK [PERMANENTFLAGS ()] Read-only mailbox.\r\n\
-            * 1 EXISTS\r\n\
-            * 1 RECENT\r\n\
-            * OK [UNSEEN 1] First unseen.\r\n\
-            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
-            * OK [UIDNEXT 2] Predicted next UID\r\n\
-            a1 OK [READ-ONLY] Select completed.\r\n"
-            .to_vec();
+    fn login_after_preauth() {
+        let greeting = b"* PREAUTH IMAP4rev1 server logged in as Smith\r\n".to_vec();
+        let client = Client::from_stream(MockStream::new(greeting)).unwrap();
+        let (err, client) = client.login("smith", "secret").err().unwrap();
+        match err {
+            Error::AlreadyAuthenticated => {}
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        let (err, client) = client.authenticate("LOGIN", Login::new("smith", "secret")).err().unwrap();
+        match err {
+            Error::AlreadyAuthenticated => {}
+            e => panic!("Unexpected error: {:?}", e),
+        }
+        assert!(client.stream.get_ref().written_buf.is_empty());
+    }
+
+    #[test]
+    fn read_greeting_after_proxy_banner() {
+        let greeting = "* Proxy connected to backend\r\n\
+                        * OK [CAPABILITY IMAP4rev1 IDLE] Dovecot ready.\r\n";
+        let mock_stream = MockStream::new(greeting.as_bytes().to_vec());
+        let mut client = Client::new(mock_stream);
+        client.read_greeting().unwrap();
+        assert!(client.has_capability("IDLE").unwrap());
+        assert!(client.stream.get_ref().written_buf.is_empty());
+    }
+
+    #[test]
+    fn read_greeting_unexpected() {
+        let greeting = "\r\nHTTP/1.1 400 Bad Request\r\n\r\n";
+        let mut client = Client::new(MockStream::new(greeting.as_bytes().to_vec()));
+        match client.read_greeting() {
+            Err(e @ Error::UnexpectedGreeting(_)) => assert_eq!(
+                e.to_string(),
+                "Unexpected data instead of the server greeting: HTTP/1.1 400 Bad Request"
+            ),
+            r => panic!("expected UnexpectedGreeting, got {:?}", r),
+        }
+
+        let greeting = b"\x16\x03\x01\r\n".to_vec();
+        let mut client = Client::new(MockStream::new(greeting));
+        match client.read_greeting() {
+            Err(Error::UnexpectedGreeting(ref data)) => assert_eq!(data, "\\x16\\x03\\x01"),
+            r => panic!("expected UnexpectedGreeting, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn read_greeting_bye() {
+        let greeting = "* BYE Too many connections\r\n";
+        let mock_stream = MockStream::new(greeting.as_bytes().to_vec());
+        let mut client = Client::new(mock_stream);
+        match client.read_greeting() {
+            Err(Error::Bye(_, ref text)) => assert_eq!(text, "Too many connections"),
+            r => panic!("expected BYE, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn readline_delay_read() {
+        let greeting = "* OK Dovecot ready.\r\n";
+        let expected_response: String = greeting.to_string();
+        let mock_stream = MockStream::default()
+            .with_buf(greeting.as_bytes().to_vec())
+            .with_delay();
+        let mut client = Client::new(mock_stream);
+        let mut v = Vec::new();
+        client.readline(&mut v).unwrap();
+        let actual_response = String::from_utf8(v).unwrap();
+        assert_eq!(expected_response, actual_response);
+    }
+
+    #[test]
+    fn readline_eof() {
+        let mock_stream = MockStream::default().with_eof();
+        let mut client = Client::new(mock_stream);
+        let mut v = Vec::new();
+        if let Err(Error::ConnectionLost) = client.readline(&mut v) {
+        } else {
+            unreachable!("EOF read did not return connection lost");
+        }
+    }
+
+    #[test]
+    fn authenticate_login() {
+        let response = b"+ VXNlcm5hbWU6\r\n\
+            + UGFzc3dvcmQ6\r\n\
+            a1 OK Logged in\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        let auth = Login::new("user", "pass");
+        let session = client.authenticate(auth.mechanism(), auth).unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 AUTHENTICATE LOGIN\r\ndXNlcg==\r\ncGFzcw==\r\n"
+        );
+    }
+
+    #[test]
+    fn authenticate_plain() {
+        let response = b"+ \r\na1 OK Logged in\r\n".to_vec();
+        let client = Client::new(MockStream::new(response));
+        let auth = Plain::new("user", "pass");
+        let session = client.authenticate(auth.mechanism(), auth).unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 AUTHENTICATE PLAIN\r\nAHVzZXIAcGFzcw==\r\n"
+        );
+    }
+
+    #[test]
+    fn authenticate_scram_cancelled() {
+        // the server's nonce does not extend the client's
+        let response = b"+ \r\n\
+            + cj1hYmMxMjMscz1RU1hDUitRNnNlazhiZjkyLGk9NDA5Ng==\r\n\
+            a1 BAD Authentication cancelled\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        let auth = Scram::sha1("user", "pencil");
+        let client = match client.authenticate(auth.mechanism(), auth) {
+            Err((Error::Parse(ParseError::Authentication(_)), client)) => client,
+            r => panic!("expected a parse error, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        };
+        let written = String::from_utf8(client.stream.get_ref().written_buf.clone()).unwrap();
+        assert!(written.starts_with("a1 AUTHENTICATE SCRAM-SHA-1\r\n"));
+        assert!(written.ends_with("\r\n*\r\n"));
+    }
+
+    #[test]
+    fn authenticate_finished() {
+        struct Proven;
+        impl Authenticator for Proven {
+            type Response = String;
+            fn process(&self, _: &[u8]) -> String {
+                "hello".to_string()
+            }
+            fn finished(&self, data: Option<&[u8]>) -> Result<()> {
+                match data {
+                    Some(b"v=proof") => Ok(()),
+                    _ => Err(Error::ServerSignatureMismatch),
+                }
+            }
+        }
+
+        let response = b"+ \r\na1 OK [SASL dj1wcm9vZg==] Logged in\r\n".to_vec();
+        let client = Client::new(MockStream::new(response));
+        client.authenticate("X-PROVEN", Proven).unwrap();
+
+        // a server that accepts without the proof is not trusted
+        for response in &[
+            &b"+ \r\na1 OK Logged in\r\n"[..],
+            &b"+ \r\na1 OK [CAPABILITY IMAP4rev1 IDLE] Logged in\r\n"[..],
+        ] {
+            let client = Client::new(MockStream::new(response.to_vec()));
+            match client.authenticate("X-PROVEN", Proven) {
+                Err((Error::ServerSignatureMismatch, _)) => {}
+                r => panic!("expected a mismatch, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+            }
+        }
+    }
+
+    #[test]
+    fn authenticate_text_prompt() {
+        struct Prompted;
+        impl Authenticator for Prompted {
+            type Response = String;
+            fn process(&self, _: &[u8]) -> String {
+                "secret".to_string()
+            }
+            fn process_text(&self, text: &str) -> Option<String> {
+                Some(format!("answer to {}", text))
+            }
+        }
+
+        let response = b"+ Who goes there?\r\na1 OK Logged in\r\n".to_vec();
+        let client = Client::new(MockStream::new(response));
+        let session = client.authenticate("X-PROMPT", Prompted).unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 AUTHENTICATE X-PROMPT\r\nYW5zd2VyIHRvIFdobyBnb2VzIHRoZXJlPw==\r\n"
+        );
+
+        // mechanisms that do not expect text cancel the exchange
+        let response = b"+ Go ahead\r\na1 BAD Authentication cancelled\r\n".to_vec();
+        let client = Client::new(MockStream::new(response));
+        let auth = Plain::new("user", "pass");
+        let client = match client.authenticate(auth.mechanism(), auth) {
+            Err((Error::Parse(ParseError::Authentication(ref text)), client)) => {
+                assert_eq!(text, "Go ahead");
+                client
+            }
+            r => panic!("expected a parse error, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        };
+        assert_eq!(
+            String::from_utf8(client.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 AUTHENTICATE PLAIN\r\n*\r\n"
+        );
+    }
+
+    #[test]
+    fn authenticate_oauth2_rejected() {
+        let response = b"+ \r\n\
+            + eyJzdGF0dXMiOiI0MDEifQ==\r\n\
+            a1 NO [AUTHENTICATIONFAILED] Invalid credentials\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        let auth = OAuth2::xoauth2("user", "token");
+        let client = match client.authenticate(auth.mechanism(), auth) {
+            Err((Error::Code(ResponseCode::AuthenticationFailed, ref text), client)) => {
+                assert_eq!(text.information(), "Invalid credentials");
+                client
+            }
+            r => panic!("expected NO, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        };
+        assert_eq!(
+            String::from_utf8(client.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 AUTHENTICATE XOAUTH2\r\n\
+             dXNlcj11c2VyAWF1dGg9QmVhcmVyIHRva2VuAQE=\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn compress() {
+        let mut deflated = DeflateStream::new(Vec::new(), Vec::new());
+        deflated
+            .write_all(b"* 4 EXISTS\r\na3 OK NOOP completed\r\n")
+            .unwrap();
+        deflated.flush().unwrap();
+
+        let mut response = b"* CAPABILITY IMAP4rev1 COMPRESS=DEFLATE\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK DEFLATE active\r\n"
+            .to_vec();
+        response.extend_from_slice(deflated.get_ref());
+        let session = mock_session!(MockStream::new(response));
+        let mut session = session.compress().unwrap();
+        session.noop().unwrap();
+
+        let written = &session.stream.get_ref().get_ref().written_buf;
+        let prefix = b"a1 CAPABILITY\r\na2 COMPRESS DEFLATE\r\n";
+        assert_eq!(&written[..prefix.len()], &prefix[..]);
+        let mut inflated = DeflateStream::new(&written[prefix.len()..], Vec::new());
+        let mut command = String::new();
+        inflated.read_to_string(&mut command).unwrap();
+        assert_eq!(command, "a3 NOOP\r\n");
+    }
+
+    #[test]
+    fn compress_unsupported() {
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let session = mock_session!(MockStream::new(response));
+        match session.compress() {
+            Err((Error::MissingCapability(ref c), _)) => assert_eq!(c, "COMPRESS=DEFLATE"),
+            r => panic!("expected MissingCapability, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        }
+    }
+
+    #[test]
+    fn compress_refused() {
+        let response = b"* CAPABILITY IMAP4rev1 COMPRESS=DEFLATE\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 NO [COMPRESSIONACTIVE] DEFLATE active via TLS\r\n\
+            a3 OK NOOP completed\r\n"
+            .to_vec();
+        let session = mock_session!(MockStream::new(response));
+        let mut session = match session.compress() {
+            Err((Error::No(_), session)) | Err((Error::Code(_, _), session)) => session,
+            r => panic!("expected No, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        };
+        session.noop().unwrap();
+        assert!(session.stream.get_ref().written_buf.ends_with(b"a3 NOOP\r\n"));
+    }
+
+    #[test]
+    fn noop_flags_update() {
+        let response = b"* 3 EXISTS\r\n\
+            * FLAGS (\\Seen \\Deleted)\r\n\
+            a1 OK Select completed\r\n\
+            * FLAGS (\\Seen \\Deleted $Important)\r\n\
+            * 4 EXISTS\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mailbox = session.select("INBOX").unwrap();
+        assert_eq!(mailbox.flags, vec!["\\Seen", "\\Deleted"]);
+        assert_eq!(session.unsolicited_responses().count(), 0);
+
+        let updates = session.noop().unwrap();
+        let mailbox = session.selected_mailbox().unwrap();
+        assert_eq!(mailbox.flags, vec!["\\Seen", "\\Deleted", "$Important"]);
+        assert_eq!(mailbox.exists, 4);
+        assert_eq!(
+            updates,
+            vec![
+                UnsolicitedResponse::Flags(vec![
+                    "\\Seen".to_string(),
+                    "\\Deleted".to_string(),
+                    "$Important".to_string(),
+                ]),
+                UnsolicitedResponse::Exists(4),
+            ]
+        );
+    }
+
+    #[test]
+    fn noop_status_event() {
+        let response = b"* STATUS \"Archive\" (MESSAGES 5)\r\n\
+            a1 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(
+            session.noop().unwrap(),
+            vec![UnsolicitedResponse::Status(MailboxStatus {
+                mailbox: "Archive".to_string(),
+                messages: Some(5),
+                ..MailboxStatus::default()
+            })]
+        );
+    }
+
+    #[test]
+    fn get_annotation() {
+        let response = b"* CAPABILITY IMAP4rev1 ANNOTATEMORE\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * ANNOTATION \"INBOX\" \"/comment\" (\"value.priv\" \"foo\")\r\n\
+            a2 OK GETANNOTATION completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let annotations = session
+            .get_annotation("INBOX", "/comment", &["value.priv"])
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 GETANNOTATION \"INBOX\" \"/comment\" (\"value.priv\")\r\n"
+                    .to_vec(),
+            "Invalid getannotation command"
+        );
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].value("value.priv"), Some("foo"));
+    }
+
+    #[test]
+    fn set_annotation_unsupported() {
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.set_annotation("INBOX", "/comment", &[("value.priv", None)]) {
+            Err(Error::MissingCapability(_)) => {}
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn eof_mid_literal() {
+        let response = b"* 1 FETCH (BODY[] {10}\r\nhello".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.fetch("1", "BODY[]") {
+            Err(Error::ConnectionClosed(ref note)) if note.contains("incomplete") => {}
+            r => panic!("expected ConnectionClosed, got {:?}", r),
+        }
+        assert!(session.is_poisoned());
+
+        match session.noop() {
+            Err(Error::ConnectionClosed(_)) => {}
+            r => panic!("expected ConnectionClosed, got {:?}", r),
+        }
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 FETCH 1 BODY[]\r\n".to_vec(),
+            "command sent on a poisoned session"
+        );
+    }
+
+    #[test]
+    fn bye_during_command() {
+        let response = b"* BYE [UNAVAILABLE] server shutting down\r\n\
+            a1 OK LIST completed\r\n\
+            * BYE Autologout; idle for too long\r\n\
+            a2 OK SELECT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.list("", "*") {
+            Err(Error::Bye(code, ref text)) => {
+                assert_eq!(code, Some(ResponseCode::Unavailable));
+                assert_eq!(text, "server shutting down");
+            }
+            r => panic!("expected Bye, got {:?}", r.map(|_| ())),
+        }
+        match session.select("INBOX") {
+            Err(Error::Bye(None, ref text)) => assert_eq!(text, "Autologout; idle for too long"),
+            r => panic!("expected Bye, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn eof_after_bye() {
+        let response = b"* BYE Server shutting down\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.noop() {
+            Err(Error::Bye(_, ref text)) => assert_eq!(text, "Server shutting down"),
+            r => panic!("expected Bye, got {:?}", r),
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn readline_err() {
+        // TODO Check the error test
+        let mock_stream = MockStream::default().with_err();
+        let mut client = Client::new(mock_stream);
+        let mut v = Vec::new();
+        client.readline(&mut v).unwrap();
+    }
+
+    #[test]
+    fn create_command() {
+        let base_command = "CHECK";
+        let mock_stream = MockStream::default();
+        let mut imap_stream = Client::new(mock_stream);
+
+        let expected_command = format!("a1 {}", base_command);
+        let command = imap_stream.create_command(&base_command);
+        assert!(
+            command == expected_command,
+            "expected command doesn't equal actual command"
+        );
+
+        let expected_command2 = format!("a2 {}", base_command);
+        let command2 = imap_stream.create_command(&base_command);
+        assert!(
+            command2 == expected_command2,
+            "expected command doesn't equal actual command"
+        );
+    }
+
+    #[test]
+    fn login() {
+        let response = b"a1 OK Logged in\r\n".to_vec();
+        let username = "username";
+        let password = "password";
+        let command = format!("a1 LOGIN {} {}\r\n", quote!(username), quote!(password));
+        let mock_stream = MockStream::new(response);
+        let client = Client::new(mock_stream);
+        let session = client.login(username, password).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid login command"
+        );
+    }
+
+    #[test]
+    fn login_disabled() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED] ready\r\n".to_vec();
+        let mut client = Client::new(MockStream::new(response));
+        client.read_greeting().unwrap();
+        match client.login("username", "password") {
+            Err((Error::LoginDisabled, client)) => {
+                assert!(client.stream.get_ref().written_buf.is_empty())
+            }
+            r => panic!("expected LoginDisabled, got {:?}", r.map(|_| ()).map_err(|(e, _)| e)),
+        }
+    }
+
+    #[test]
+    fn capabilities_piggybacked() {
+        let response = b"* OK [CAPABILITY IMAP4rev1 STARTTLS AUTH=PLAIN] ready\r\n\
+            a1 OK [CAPABILITY IMAP4rev1 IDLE APPENDLIMIT=9] Logged in\r\n\
+            * OK [CAPABILITY IMAP4rev1 IDLE MOVE] Capabilities changed\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut client = Client::new(MockStream::new(response));
+        client.read_greeting().unwrap();
+        let mut session = client.login("username", "password").unwrap();
+        assert_eq!(session.append_limit(), Some(9));
+        {
+            let caps = session.capabilities().unwrap();
+            assert!(caps.has("IDLE"));
+            assert!(!caps.has("STARTTLS"));
+        }
+        session.noop().unwrap();
+        assert!(session.capabilities().unwrap().has("MOVE"));
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 LOGIN \"username\" \"password\"\r\na2 NOOP\r\n"
+        );
+    }
+
+    #[test]
+    fn starttls() {
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK Begin TLS negotiation now\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        let mut client = client
+            .starttls_with(|plain| {
+                assert_eq!(plain.written_buf, b"a1 CAPABILITY\r\na2 STARTTLS\r\n".to_vec());
+                Ok(MockStream::new(
+                    b"* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\na1 OK CAPABILITY completed\r\n"
+                        .to_vec(),
+                ))
+            }).unwrap();
+        assert!(client.has_capability("AUTH=PLAIN").unwrap());
+        assert!(!client.has_capability("LOGINDISABLED").unwrap());
+        assert_eq!(client.stream.get_ref().written_buf, b"a1 CAPABILITY\r\n".to_vec());
+    }
+
+    #[test]
+    fn starttls_not_offered() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let client = Client::new(MockStream::new(response));
+        match client.starttls_with(|_| -> Result<MockStream> { panic!("STARTTLS is not offered") }) {
+            Err(Error::MissingCapability(ref cap)) => assert_eq!(cap, "STARTTLS"),
+            Err(e) => panic!("expected MissingCapability, got {:?}", e),
+            Ok(_) => panic!("upgraded without STARTTLS"),
+        }
+    }
+
+    #[test]
+    fn login_auto_starttls() {
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS LOGINDISABLED\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK Begin TLS negotiation now\r\n"
+            .to_vec();
+        let mut client = Client::new(MaybeTls::Plain(MockStream::new(response)));
+        client.details.lenient = true;
+        let session = login_auto(
+            client,
+            |plain| {
+                assert_eq!(plain.written_buf, b"a1 CAPABILITY\r\na2 STARTTLS\r\n".to_vec());
+                // the capabilities are asked for again, and no longer forbid LOGIN
+                Ok(MockStream::new(
+                    b"* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n\
+                      a1 OK CAPABILITY completed\r\n\
+                      a2 OK Logged in\r\n"
+                        .to_vec(),
+                ))
+            },
+            "username",
+            "password",
+        ).unwrap();
+        match *session.stream.get_ref() {
+            MaybeTls::Tls(ref tls) => assert_eq!(
+                tls.written_buf,
+                b"a1 CAPABILITY\r\na2 LOGIN \"username\" \"password\"\r\n".to_vec()
+            ),
+            MaybeTls::Plain(_) => panic!("did not upgrade to TLS"),
+        }
+        assert!(session.details.lenient);
+    }
+
+    #[test]
+    fn login_auto_plaintext() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK Logged in\r\n"
+            .to_vec();
+        let client: Client<MaybeTls<_, MockStream>> =
+            Client::new(MaybeTls::Plain(MockStream::new(response)));
+        let session = login_auto(
+            client,
+            |_| panic!("STARTTLS is not offered"),
+            "username",
+            "password",
+        ).unwrap();
+        match *session.stream.get_ref() {
+            MaybeTls::Plain(ref plain) => assert_eq!(
+                plain.written_buf,
+                b"a1 CAPABILITY\r\na2 LOGIN \"username\" \"password\"\r\n".to_vec()
+            ),
+            MaybeTls::Tls(_) => panic!("unexpected TLS upgrade"),
+        }
+    }
+
+    #[test]
+    fn login_auto_refuses_plaintext() {
+        let response = b"* CAPABILITY IMAP4rev1 LOGINDISABLED\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let client: Client<MaybeTls<_, MockStream>> =
+            Client::new(MaybeTls::Plain(MockStream::new(response)));
+        match login_auto(client, |_| panic!("STARTTLS is not offered"), "user", "pass") {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "STARTTLS"),
+            r => panic!("expected MissingCapability, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn has_capability_cached() {
+        let response = b"* CAPABILITY IMAP4rev1 MOVE\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(session.has_capability("move").unwrap());
+        assert!(!session.has_capability("UIDPLUS").unwrap());
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 CAPABILITY\r\n".to_vec(),
+            "capabilities were requested more than once"
+        );
+    }
+
+    #[test]
+    fn read_timeout() {
+        let response = b"* 3 EXISTS\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response).with_timeout());
+        session.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        assert_eq!(
+            session.stream.get_ref().read_timeout,
+            Some(Duration::from_secs(5))
+        );
+        match session.noop() {
+            Err(Error::Timeout) => {}
+            r => panic!("expected Timeout, got {:?}", r),
+        }
+        assert!(session.is_poisoned());
+    }
+
+    #[test]
+    fn idle_restores_read_timeout() {
+        let response = b"+ idling\r\n\
+            * 4 EXISTS\r\n\
+            a1 OK IDLE terminated\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        session
+            .idle()
+            .unwrap()
+            .wait_with_timeout(Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            session.stream.get_ref().read_timeout,
+            Some(Duration::from_secs(5))
+        );
+    }
+
+    #[test]
+    fn idle_wait_with_timeout() {
+        let response = b"+ idling\r\n\
+            * OK Still here\r\n\
+            * 4 EXISTS\r\n\
+            * 2 FETCH (FLAGS (\\Seen))\r\n\
+            a1 OK IDLE terminated\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.mailbox = Some(Mailbox::default());
+        let events = session
+            .idle()
+            .unwrap()
+            .wait_with_timeout(Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], UnsolicitedResponse::Exists(4));
+        match events[1] {
+            UnsolicitedResponse::Fetch(ref fetch) => {
+                assert_eq!(fetch.message, 2);
+                assert_eq!(fetch.flags(), &["\\Seen"]);
+            }
+            ref event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(session.selected_mailbox().unwrap().exists, 4);
+        assert_eq!(session.unsolicited_responses().count(), 0);
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 IDLE\r\nDONE\r\n".to_vec(),
+            "Invalid idle commands"
+        );
+    }
+
+    #[test]
+    fn idle_wait_with_callback() {
+        let response = b"+ idling\r\n\
+            * OK Still here\r\n\
+            * 3 RECENT\r\n\
+            * 4 EXISTS\r\n\
+            * 5 EXISTS\r\n\
+            a1 OK IDLE terminated\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut seen = Vec::new();
+        session
+            .idle()
+            .unwrap()
+            .wait_with_callback(|event| {
+                seen.push(event);
+                seen.len() < 2
+            })
+            .unwrap();
+        assert_eq!(
+            seen,
+            vec![
+                UnsolicitedResponse::Recent(3),
+                UnsolicitedResponse::Exists(4),
+            ]
+        );
+        assert_eq!(
+            session.unsolicited_responses().collect::<Vec<_>>(),
+            vec![UnsolicitedResponse::Exists(5)]
+        );
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 IDLE\r\nDONE\r\n".to_vec(),
+            "Invalid idle commands"
+        );
+    }
+
+    #[test]
+    fn idle_wait_with_channel_refresh() {
+        let response = b"+ idling\r\n\
+            * 2 EXISTS\r\n\
+            a1 OK IDLE terminated\r\n\
+            * 3 EXISTS\r\n\
+            + idling\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let (tx, rx) = mpsc::channel();
+        let mut idle = session.idle().unwrap();
+        idle.set_keepalive(Duration::from_secs(0));
+        match idle.wait_with_channel(tx) {
+            Err(Error::ConnectionClosed(_)) => {}
+            r => panic!("expected the connection to be closed, got {:?}", r),
+        }
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![UnsolicitedResponse::Exists(2), UnsolicitedResponse::Exists(3)]
+        );
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 IDLE\r\nDONE\r\na2 IDLE\r\nDONE\r\n".to_vec(),
+            "Invalid idle commands"
+        );
+    }
+
+    #[test]
+    fn rfc5530_response_codes() {
+        let codes = [
+            "AUTHENTICATIONFAILED", "AUTHORIZATIONFAILED", "EXPIRED", "PRIVACYREQUIRED",
+            "CONTACTADMIN", "OVERQUOTA", "CANNOT", "LIMIT", "INUSE", "EXPUNGEISSUED",
+            "CORRUPTION", "SERVERBUG", "CLIENTBUG", "NONEXISTENT", "ALREADYEXISTS",
+        ];
+        for atom in codes.iter() {
+            let response = format!("a1 NO [{}] Failed\r\n", atom).into_bytes();
+            let mut session = mock_session!(MockStream::new(response));
+            let err = session.noop().unwrap_err();
+            match err {
+                Error::Code(code, _) => assert_eq!(code.as_atom(), *atom),
+                ref e => panic!("expected {}, got {:?}", atom, e),
+            }
+            assert_eq!(err.response_code().map(|c| c.as_atom()), Some(*atom));
+        }
+
+        let response = b"a1 BAD [CLIENTBUG] Invalid sequence set\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let err = session.noop().unwrap_err();
+        assert_eq!(err.response_code(), Some(ResponseCode::ClientBug));
+    }
+
+    #[test]
+    fn mailbox_exists_errors() {
+        let response = b"a1 NO [ALREADYEXISTS] Mailbox already exists\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.create("Archive") {
+            Err(Error::Code(ResponseCode::AlreadyExists, ref text)) => {
+                assert_eq!(text.information(), "Mailbox already exists")
+            }
+            r => panic!("expected ALREADYEXISTS, got {:?}", r),
+        }
+
+        let response = b"a1 NO [NONEXISTENT] No such mailbox\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.delete("Archive") {
+            Err(Error::Code(ResponseCode::NonExistent, _)) => {}
+            r => panic!("expected NONEXISTENT, got {:?}", r),
+        }
+
+        let response = b"a1 OK RENAME completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.rename("INBOX", "Old Mail").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 RENAME \"INBOX\" \"Old Mail\"\r\n".to_vec(),
+            "Invalid rename command"
+        );
+    }
+
+    #[test]
+    fn no_response_text() {
+        let response = b"a1 NO [ALERT] Over quota\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.create("INBOX.new") {
+            Err(Error::Code(ResponseCode::Alert, ref text)) => {
+                assert!(text.is_alert());
+                assert_eq!(text.code(), Some("ALERT"));
+                assert_eq!(text.information(), "Over quota");
+                assert_eq!(text.line(), "a1 NO [ALERT] Over quota");
+            }
+            r => panic!("expected ALERT, got {:?}", r),
+        }
+
+        let response = b"a1 BAD Unknown command\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.noop() {
+            Err(Error::Bad(ref text)) => {
+                assert!(!text.is_alert());
+                assert_eq!(text.code(), None);
+                assert_eq!(text.information(), "Unknown command");
+            }
+            r => panic!("expected Bad, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn tagged_response_codes() {
+        let response = b"a1 NO [TRYCREATE] No such mailbox\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.copy("1:3", "Archive") {
+            Err(Error::Code(ResponseCode::TryCreate, _)) => {}
+            r => panic!("expected TRYCREATE, got {:?}", r),
+        }
+        assert!(session.last_ok_response().is_none());
+
+        let response = b"a1 OK [ALERT] Maintenance at midnight\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.noop().unwrap();
+        let ok = session.last_ok_response().unwrap();
+        assert_eq!(ok.response_code(), Some(ResponseCode::Alert));
+        assert_eq!(ok.information(), "Maintenance at midnight");
+
+        let response = b"a1 OK [PARSE] Bad header in message 3\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.noop().unwrap();
+        assert_eq!(
+            session.last_ok_response().unwrap().response_code(),
+            Some(ResponseCode::Parse)
+        );
+    }
+
+    #[test]
+    fn rfc5530_codes() {
+        let cases = [
+            (
+                "[AUTHENTICATIONFAILED] Bad password",
+                ResponseCode::AuthenticationFailed,
+                "Bad password",
+            ),
+            ("[expired] Password expired", ResponseCode::Expired, "Password expired"),
+            ("[PRIVACYREQUIRED] Use STARTTLS", ResponseCode::PrivacyRequired, "Use STARTTLS"),
+            ("[CONTACTADMIN] Ask the admin", ResponseCode::ContactAdmin, "Ask the admin"),
+            ("[SERVERBUG] Oops", ResponseCode::ServerBug, "Oops"),
+            ("[CLIENTBUG] Bad syntax", ResponseCode::ClientBug, "Bad syntax"),
+            ("[AUTHORIZATIONFAILED] Not you", ResponseCode::AuthorizationFailed, "Not you"),
+        ];
+        for &(information, expected_code, expected_text) in &cases {
+            let response = format!("a1 NO {}\r\n", information).into_bytes();
+            let mut session = mock_session!(MockStream::new(response));
+            match session.noop() {
+                Err(Error::Code(code, ref text)) => {
+                    assert_eq!(code, expected_code);
+                    assert_eq!(text.information(), expected_text);
+                }
+                r => panic!("expected {:?}, got {:?}", expected_code, r),
+            }
+        }
+
+        // codes from other extensions are left alone
+        let response = b"a1 NO [UNKNOWN-CTE] Can't decode\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.noop() {
+            Err(Error::No(ref text)) => assert!(text.has_code("UNKNOWN-CTE")),
+            r => panic!("expected No, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn logout() {
+        let response = b"a1 OK Logout completed.\r\n".to_vec();
+        let command = format!("a1 LOGOUT\r\n");
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.logout().unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid logout command"
+        );
+    }
+
+    #[test]
+    fn logout_bye() {
+        let response = b"* BYE IMAP4rev1 Server logging out\r\n\
+            a1 OK LOGOUT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.logout().unwrap();
+        assert_eq!(session.bye_response(), Some("IMAP4rev1 Server logging out"));
+        assert!(session.is_poisoned());
+        assert!(session.noop().is_err());
+
+        // some servers hang up without the tagged response
+        let response = b"* BYE Bye\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.logout().unwrap();
+        assert_eq!(session.bye_response(), Some("Bye"));
+    }
+
+    #[test]
+    fn rename() {
+        let response = b"a1 OK RENAME completed\r\n".to_vec();
+        let current_mailbox_name = "INBOX";
+        let new_mailbox_name = "NEWINBOX";
+        let command = format!(
+            "a1 RENAME {} {}\r\n",
+            quote!(current_mailbox_name),
+            quote!(new_mailbox_name)
+        );
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session
+            .rename(current_mailbox_name, new_mailbox_name)
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid rename command"
+        );
+    }
+
+    #[test]
+    fn subscribe() {
+        let response = b"a1 OK SUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "Sent Items";
+        let command = format!("a1 SUBSCRIBE {}\r\n", quote!(mailbox));
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.subscribe(mailbox).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid subscribe command"
+        );
+    }
+
+    #[test]
+    fn unsubscribe() {
+        let response = b"a1 OK UNSUBSCRIBE completed\r\n".to_vec();
+        let mailbox = "Entwürfe";
+        let command = "a1 UNSUBSCRIBE \"Entw&APw-rfe\"\r\n";
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.unsubscribe(mailbox).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid unsubscribe command"
+        );
+    }
+
+    #[test]
+    fn expunge() {
+        let response = b"a1 OK EXPUNGE completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        assert!(session.expunge().unwrap().is_empty());
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 EXPUNGE\r\n".to_vec(),
+            "Invalid expunge command"
+        );
+    }
+
+    #[test]
+    fn expunge_sequence_numbers() {
+        // messages 3, 4, and 7 are removed; each number accounts for the ones removed before it
+        let response = b"* 3 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            * 5 EXPUNGE\r\n\
+            a1 OK EXPUNGE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.expunge().unwrap(), vec![3, 3, 5]);
+        assert_eq!(session.unsolicited_responses().count(), 0);
+    }
+
+    #[test]
+    fn uid_expunge() {
+        let response = b"* CAPABILITY IMAP4rev1 UIDPLUS\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * 2 FETCH (UID 300)\r\n\
+            * 3 FETCH (UID 302)\r\n\
+            * 5 FETCH (UID 310)\r\n\
+            a2 OK UID FETCH completed\r\n\
+            * 3 EXPUNGE\r\n\
+            * 2 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            a3 OK UID EXPUNGE completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        assert_eq!(session.uid_expunge("300:310").unwrap(), vec![302, 300, 310]);
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 UID FETCH 300:310 UID\r\na3 UID EXPUNGE 300:310\r\n".to_vec(),
+            "Invalid expunge command"
+        );
+    }
+
+    #[test]
+    fn uid_expunge_vanished() {
+        let response = b"* ENABLED QRESYNC\r\n\
+            a1 OK Enabled\r\n\
+            * CAPABILITY IMAP4rev1 UIDPLUS QRESYNC\r\n\
+            a2 OK CAPABILITY completed\r\n\
+            * VANISHED 302,307:308\r\n\
+            a3 OK UID EXPUNGE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.enable(&["QRESYNC"]).unwrap();
+        assert_eq!(session.uid_expunge("300:310").unwrap(), vec![302, 307, 308]);
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 ENABLE QRESYNC\r\na2 CAPABILITY\r\na3 UID EXPUNGE 300:310\r\n".to_vec(),
+            "Invalid expunge command"
+        );
+    }
+
+    #[test]
+    fn uid_expunge_without_uidplus() {
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            a1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.uid_expunge("300:310") {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "UIDPLUS"),
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn check() {
+        let response = b"a1 OK CHECK completed\r\n".to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.check().unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 CHECK\r\n".to_vec(),
+            "Invalid check command"
+        );
+    }
+
+    #[test]
+    fn examine() {
+        let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
+            * OK [PERMANENTFLAGS ()] Read-only mailbox.\r\n\
+            * 1 EXISTS\r\n\
+            * 1 RECENT\r\n\
+            * OK [UNSEEN 1] First unseen.\r\n\
+            * OK [UIDVALIDITY 1257842737] UIDs valid\r\n\
+            * OK [UIDNEXT 2] Predicted next UID\r\n\
+            a1 OK [READ-ONLY] Select completed.\r\n"
+            .to_vec();
         let expected_mailbox = Mailbox {
             flags: vec![
                 "\\Answered".to_string(),
@@ -1050,10 +5048,15 @@ mod tests {
             ],
             exists: 1,
             recent: 1,
-            unseen: Some(1),
+            first_unseen: Some(1),
+            unseen_count: None,
             permanent_flags: vec![],
             uid_next: Some(2),
             uid_validity: Some(1257842737),
+            highest_mod_seq: None,
+            no_mod_seq: false,
+            read_only: true,
+            can_create_keywords: false,
         };
         let mailbox_name = "INBOX";
         let command = format!("a1 EXAMINE {}\r\n", quote!(mailbox_name));
@@ -1067,6 +5070,89 @@ mod tests {
         assert_eq!(mailbox, expected_mailbox);
     }
 
+    #[test]
+    fn reconnect() {
+        let select = |validity: u32| {
+            format!(
+                "* 2 EXISTS\r\n* OK [UIDVALIDITY {}] UIDs valid\r\na2 OK [READ-WRITE] Selected\r\n",
+                validity
+            )
+        };
+        let response = format!("* OK ready\r\na1 OK Logged in\r\n{}", select(7));
+        let client = Client::from_stream(MockStream::new(response.into_bytes())).unwrap();
+        let mut session = client.login_reconnectable("smith", "secret").ok().unwrap();
+        session.select("Work Stuff").unwrap();
+        assert!(!format!("{:?}", session).contains("secret"));
+
+        let expected = b"a1 LOGIN \"smith\" \"secret\"\r\na2 SELECT \"Work Stuff\"\r\n".to_vec();
+        let response = format!("* OK ready\r\na1 OK Logged in\r\n{}", select(7));
+        session
+            .reconnect(|| Client::from_stream(MockStream::new(response.into_bytes())))
+            .unwrap();
+        assert_eq!(session.stream.get_ref().written_buf, expected);
+        assert_eq!(session.selected_mailbox().unwrap().uid_validity, Some(7));
+
+        let response = format!("* OK ready\r\na1 OK Logged in\r\n{}", select(8));
+        match session.reconnect(|| Client::from_stream(MockStream::new(response.into_bytes()))) {
+            Err(Error::UidValidityChanged(7, 8)) => {}
+            r => panic!("expected UidValidityChanged, got {:?}", r),
+        }
+        assert_eq!(session.selected_mailbox().unwrap().uid_validity, Some(8));
+    }
+
+    #[test]
+    fn reconnect_without_credentials() {
+        let mut session = mock_session!(MockStream::new(Vec::new()));
+        let greeting = b"* OK ready\r\n".to_vec();
+        match session.reconnect(|| Client::from_stream(MockStream::new(greeting))) {
+            Err(Error::NoCredentials) => {}
+            r => panic!("expected NoCredentials, got {:?}", r),
+        }
+
+        let greeting = b"* PREAUTH ready\r\n".to_vec();
+        session
+            .reconnect(|| Client::from_stream(MockStream::new(greeting)))
+            .unwrap();
+
+        // `login` does not keep the password
+        let client = Client::new(MockStream::new(b"a1 OK Logged in\r\n".to_vec()));
+        let mut session = client.login("smith", "secret").ok().unwrap();
+        let greeting = b"* OK ready\r\n".to_vec();
+        match session.reconnect(|| Client::from_stream(MockStream::new(greeting))) {
+            Err(Error::NoCredentials) => {}
+            r => panic!("expected NoCredentials, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn examine_read_only() {
+        // a mailbox that was examined is read-only even if the server does not say so
+        let response = b"* 2 EXISTS\r\na1 OK Examine completed\r\n\
+            * 2 EXISTS\r\na2 OK [READ-WRITE] Select completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert!(session.examine("INBOX").unwrap().read_only);
+        assert!(session.selected_mailbox().unwrap().read_only);
+        assert!(!session.select("INBOX").unwrap().read_only);
+    }
+
+    #[test]
+    fn select_expecting() {
+        let response = b"* 1 EXISTS\r\n* OK [UIDVALIDITY 42] UIDs valid\r\na1 OK Selected\r\n\
+            * 1 EXISTS\r\n* OK [UIDVALIDITY 43] UIDs valid\r\na2 OK Selected\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(
+            session.select_expecting("INBOX", 42).unwrap().uid_validity,
+            Some(42)
+        );
+        match session.select_expecting("INBOX", 42) {
+            Err(Error::UidValidityChanged(42, 43)) => {}
+            r => panic!("expected UidValidityChanged, got {:?}", r),
+        }
+        assert_eq!(session.selected_mailbox().unwrap().uid_validity, Some(43));
+    }
+
     #[test]
     fn select() {
         let response = b"* FLAGS (\\Answered \\Flagged \\Deleted \\Seen \\Draft)\r\n\
@@ -1089,9 +5175,9 @@ mod tests {
             ],
             exists: 1,
             recent: 1,
-            unseen: Some(1),
+            first_unseen: Some(1),
+            unseen_count: None,
             permanent_flags: vec![
-                "\\*".to_string(),
                 "\\Answered".to_string(),
                 "\\Flagged".to_string(),
                 "\\Deleted".to_string(),
@@ -1100,6 +5186,10 @@ mod tests {
             ],
             uid_next: Some(2),
             uid_validity: Some(1257842737),
+            highest_mod_seq: None,
+            no_mod_seq: false,
+            read_only: true,
+            can_create_keywords: true,
         };
         let mailbox_name = "INBOX";
         let command = format!("a1 SELECT {}\r\n", quote!(mailbox_name));
@@ -1107,60 +5197,712 @@ mod tests {
         let mut session = mock_session!(mock_stream);
         let mailbox = session.select(mailbox_name).unwrap();
         assert!(
-            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
-            "Invalid select command"
+            session.stream.get_ref().written_buf == command.as_bytes().to_vec(),
+            "Invalid select command"
+        );
+        assert_eq!(mailbox, expected_mailbox);
+    }
+
+    #[test]
+    fn fetch_full() {
+        let response = b"* 3 FETCH (FLAGS (\\Seen))\r\n\
+            * 7 FETCH (UID 42 FLAGS (\\Answered) INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+              RFC822.SIZE 18 BODY[] {18}\r\nSubject: a\r\n\r\nHi\r\n)\r\n\
+            a1 OK FETCH completed\r\n\
+            a2 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetch = session.fetch_full(42).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID FETCH 42 (UID FLAGS INTERNALDATE RFC822.SIZE BODY.PEEK[])\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(fetch.message, 7);
+        assert_eq!(fetch.flags(), &["\\Answered"]);
+        assert_eq!(fetch.internal_date(), Some("17-Jul-1996 02:44:25 -0700"));
+        assert_eq!(fetch.size(), Some(18));
+        assert_eq!(fetch.body(), Some(&b"Subject: a\r\n\r\nHi\r\n"[..]));
+
+        match session.fetch_full(43) {
+            Err(Error::MessageNotFound(43)) => {}
+            r => panic!("expected MessageNotFound, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn uid_fetch_map() {
+        let response = b"* 1 FETCH (UID 10 FLAGS (\\Seen))\r\n\
+            * 9 FETCH (UID 90 FLAGS ())\r\n\
+            * 2 FETCH (UID 11 FLAGS ())\r\n\
+            * 3 FETCH (UID 12 FLAGS (\\Flagged))\r\n\
+            a1 OK FETCH completed\r\n\
+            * 1 FETCH (UID 10 FLAGS (\\Seen))\r\n\
+            a2 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let map = session.uid_fetch_map(&[12, 10, 11], "FLAGS").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID FETCH 12,10:11 (UID FLAGS)\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(map.len(), 3);
+        assert_eq!(map[&10].flags(), &["\\Seen"]);
+        assert_eq!(map[&12].flags(), &["\\Flagged"]);
+
+        match session.uid_fetch_map(&[10, 13], "(UID FLAGS)") {
+            Err(Error::MessageNotFound(13)) => {}
+            r => panic!("expected MessageNotFound, got {:?}", r),
+        }
+        assert!(session.uid_fetch_map(&[], "FLAGS").unwrap().is_empty());
+    }
+
+    #[test]
+    fn resolve_uids() {
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            * 7 FETCH (FLAGS (\\Seen))\r\n\
+            * 9 FETCH (UID 90)\r\n\
+            * 3 FETCH (UID 12)\r\n\
+            a1 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let map = session.resolve_uids(&[10, 11, 12]).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 UID FETCH 10:12 (UID)\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[&10], 1);
+        assert_eq!(map[&12], 3);
+        assert!(session.resolve_uids(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn fetch_since_uid() {
+        let response = b"* 3 FETCH (UID 12 FLAGS ())\r\n\
+            * 4 FETCH (UID 13 FLAGS (\\Seen))\r\n\
+            a1 OK FETCH completed\r\n\
+            * 4 FETCH (UID 13 FLAGS (\\Seen))\r\n\
+            a2 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session.fetch_since_uid(11, "FLAGS").unwrap();
+        assert_eq!(
+            fetches.iter().map(|f| f.uid).collect::<Vec<_>>(),
+            vec![Some(12), Some(13)]
+        );
+
+        // nothing new: the server still returns the last message
+        assert!(session.fetch_since_uid(13, "(UID FLAGS)").unwrap().is_empty());
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID FETCH 12:* (UID FLAGS)\r\na2 UID FETCH 14:* (UID FLAGS)\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert!(session.fetch_since_uid(u32::MAX, "FLAGS").unwrap().is_empty());
+    }
+
+    #[test]
+    fn fetch_iter() {
+        let response = b"* 1 FETCH (UID 10 RFC822.HEADER {12}\r\nSubject: a\r\n)\r\n\
+            * 4 EXISTS\r\n\
+            * 2 FETCH (UID 11 RFC822.HEADER {12}\r\nSubject: b\r\n)\r\n\
+            a1 OK FETCH completed\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let uids: Vec<_> = session
+            .fetch_iter("1:2", "(UID RFC822.HEADER)")
+            .unwrap()
+            .map(|fetch| fetch.unwrap().uid.unwrap())
+            .collect();
+        assert_eq!(uids, vec![10, 11]);
+        assert_eq!(
+            session.unsolicited_responses().collect::<Vec<_>>(),
+            vec![UnsolicitedResponse::Exists(4)]
+        );
+        session.noop().unwrap();
+        assert_eq!(
+            session.stream.get_ref().written_buf,
+            b"a1 FETCH 1:2 (UID RFC822.HEADER)\r\na2 NOOP\r\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn fetch_iter_dropped_early() {
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            * 2 FETCH (UID 11)\r\n\
+            * 3 FETCH (UID 12)\r\n\
+            a1 OK FETCH completed\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        {
+            let mut fetches = session.uid_fetch_iter("10:12", "UID").unwrap();
+            assert_eq!(fetches.next().unwrap().unwrap().uid, Some(10));
+        }
+        session.noop().unwrap();
+        assert!(!session.is_poisoned());
+    }
+
+    #[test]
+    fn fetch_iter_connection_lost() {
+        // the connection drops in the middle of a literal
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            * 2 FETCH (UID 11 RFC822 {100}\r\nFrom: a"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        {
+            let mut fetches = session.fetch_iter("1:2", "(UID RFC822)").unwrap();
+            assert_eq!(fetches.next().unwrap().unwrap().uid, Some(10));
+            match fetches.next() {
+                Some(Err(Error::ConnectionLost)) => {}
+                r => panic!("expected ConnectionLost, got {:?}", r),
+            }
+            assert!(fetches.next().is_none());
+        }
+        assert!(session.is_poisoned());
+
+        // and in the middle of a line
+        let response = b"* 1 FETCH (UID 10 FLA".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut fetches = session.fetch_iter("1", "(UID FLAGS)").unwrap();
+        match fetches.next() {
+            Some(Err(Error::ConnectionLost)) => {}
+            r => panic!("expected ConnectionLost, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn response_split_across_reads() {
+        // a response that arrives in small pieces is read until it is complete, including
+        // literals, before it is parsed
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * 1 FETCH (UID 10 RFC822 {21}\r\nSubject: a\r\n\r\nHello\r\n FLAGS (\\Seen))\r\n\
+            a2 OK FETCH completed\r\n"
+            .to_vec();
+        for &size in &[1, 5, 64] {
+            let mut session = mock_session!(MockStream::new(response.clone()).with_chunks(size));
+            assert!(session.capabilities().unwrap().has("STARTTLS"));
+            let fetches = session.fetch("1", "(UID RFC822 FLAGS)").unwrap();
+            assert_eq!(fetches.len(), 1);
+            assert_eq!(fetches[0].rfc822(), Some(&b"Subject: a\r\n\r\nHello\r\n"[..]));
+            assert_eq!(fetches[0].flags(), &["\\Seen"]);
+        }
+    }
+
+    #[test]
+    fn fetch_with_progress() {
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            * 2 FETCH (UID 11)\r\n\
+            * 3 EXISTS\r\n\
+            a1 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut progress = Vec::new();
+        let fetches = session
+            .uid_fetch_with_progress("10:11", "UID", |fetch, count, bytes| {
+                progress.push((fetch.uid, count, bytes))
+            }).unwrap();
+        assert_eq!(fetches.len(), 2);
+        assert_eq!(progress, vec![(Some(10), 1, 20), (Some(11), 2, 40)]);
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 UID FETCH 10:11 UID\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(session.unsolicited_responses().count(), 1);
+    }
+
+    #[test]
+    fn fetch_iter_error() {
+        let response = b"* 1 FETCH (UID 10)\r\n\
+            a1 NO Some messages could not be fetched\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let mut fetches = session.fetch_iter("1:2", "UID").unwrap();
+        assert_eq!(fetches.next().unwrap().unwrap().uid, Some(10));
+        match fetches.next() {
+            Some(Err(Error::No(_))) => {}
+            r => panic!("expected No, got {:?}", r),
+        }
+        assert!(fetches.next().is_none());
+    }
+
+    #[test]
+    fn select_qresync() {
+        let response = b"* 10 EXISTS\r\n\
+            * OK [UIDVALIDITY 67890007] UIDs valid\r\n\
+            * OK [HIGHESTMODSEQ 90060128194045007] Highest mailbox modseq\r\n\
+            * VANISHED (EARLIER) 41,43:45\r\n\
+            * 1 FETCH (UID 49 MODSEQ (90060115194045000) FLAGS (\\Seen))\r\n\
+            * 4 FETCH (UID 117 FLAGS (\\Deleted) MODSEQ (90060115194045001))\r\n\
+            a1 OK [READ-WRITE] mailbox selected\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let resync = session
+            .select_qresync("INBOX", 67890007, 90060115194045000, Some("41:211"))
+            .unwrap();
+        assert_eq!(
+            session.stream.get_ref().written_buf,
+            b"a1 SELECT \"INBOX\" (QRESYNC (67890007 90060115194045000 41:211))\r\n".to_vec()
+        );
+        assert_eq!(resync.mailbox.exists, 10);
+        assert_eq!(resync.mailbox.highest_mod_seq, Some(90060128194045007));
+        assert!(!resync.mailbox.read_only);
+        assert_eq!(resync.vanished.to_string(), "41,43:45");
+        assert_eq!(resync.changed.len(), 2);
+        assert_eq!(resync.changed[0].uid, Some(49));
+        assert_eq!(resync.changed[0].mod_seq(), Some(90060115194045000));
+        assert_eq!(resync.changed[1].flags(), &["\\Deleted"]);
+        assert_eq!(resync.changed[1].mod_seq(), Some(90060115194045001));
+        assert_eq!(session.selected_mailbox(), Some(&resync.mailbox));
+    }
+
+    #[test]
+    fn search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            a1 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.search("Unseen").unwrap();
+        assert!(!ids.is_uid());
+        let ids = ids.sequence_numbers().unwrap().clone();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[test]
+    fn search_query() {
+        let response = b"* SEARCH 3\r\n\
+            a1 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let query = SearchQuery::new()
+            .key(SearchKey::Subject("quarterly report".to_string()))
+            .key(SearchKey::Since(SearchDate::new(2020, 1, 1)))
+            .key(SearchKey::Unseen);
+        let ids = session.search(query).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 SEARCH SUBJECT \"quarterly report\" SINCE 1-Jan-2020 UNSEEN\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids.to_vec(), vec![3]);
+    }
+
+    #[test]
+    fn search_within() {
+        let response = b"* CAPABILITY IMAP4rev1 WITHIN\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * SEARCH 7 9\r\n\
+            a2 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let ids = session.uid_search(SearchKey::Younger(3600)).unwrap();
+        assert_eq!(ids.to_vec(), vec![7, 9]);
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 UID SEARCH YOUNGER 3600\r\n".to_vec(),
+            "Invalid search command"
+        );
+
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.search(SearchKey::Older(86400)) {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "WITHIN"),
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn search_save() {
+        let response = b"* CAPABILITY IMAP4rev1 SEARCHRES\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK Search completed\r\n\
+            * 2 FETCH (FLAGS (\\Seen))\r\n\
+            a3 OK Fetch completed\r\n\
+            * 1 EXISTS\r\n\
+            a4 OK [READ-WRITE] Select completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.fetch("$", "FLAGS") {
+            Err(Error::NoSavedSearch) => {}
+            r => panic!("expected NoSavedSearch, got {:?}", r),
+        }
+        session.search_save(SearchKey::Unseen).unwrap();
+        let fetches = session.fetch("$", "FLAGS").unwrap();
+        assert_eq!(fetches[0].message, 2);
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 SEARCH RETURN (SAVE) UNSEEN\r\na3 FETCH $ FLAGS\r\n"
+                    .to_vec(),
+            "Invalid search command"
+        );
+
+        // selecting a mailbox discards the saved result
+        session.select("INBOX").unwrap();
+        match session.uid_copy("$", "Archive") {
+            Err(Error::NoSavedSearch) => {}
+            r => panic!("expected NoSavedSearch, got {:?}", r),
+        }
+
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.uid_search_save("ALL") {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "SEARCHRES"),
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn search_literal() {
+        let response = b"+ go ahead\r\n\
+            * SEARCH 4\r\n\
+            a1 OK Search completed\r\n\
+            a2 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let query = SearchQuery::from(SearchKey::Subject("line1\r\nline2".to_string()))
+            .key(SearchKey::Unseen);
+        let ids = session.search(query).unwrap();
+        assert_eq!(ids.to_vec(), vec![4]);
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 SEARCH SUBJECT {12}\r\nline1\r\nline2 UNSEEN\r\n".to_vec(),
+            "Invalid search command"
+        );
+
+        // commands that cannot carry literals are refused rather than sent broken
+        match session.pipeline(&["SEARCH SUBJECT {1}\r\nx"]) {
+            Err(Error::Validate(ValidateError('\r'))) => {}
+            r => panic!("expected a validation error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn search_charset() {
+        let response = b"+ go ahead\r\n\
+            * 3 EXISTS\r\n\
+            + go ahead\r\n\
+            * SEARCH 2\r\n\
+            a1 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let ids = session
+            .search_charset("UTF-8", "SUBJECT \"caf\u{e9} au lait\" FROM m\u{fc}ller UNSEEN")
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == "a1 SEARCH CHARSET UTF-8 SUBJECT {13}\r\n\
+                    caf\u{e9} au lait FROM {7}\r\n\
+                    m\u{fc}ller UNSEEN\r\n"
+                    .as_bytes()
+                    .to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids.to_vec(), vec![2]);
+        assert_eq!(
+            session.unsolicited_responses.pop_front(),
+            Some(UnsolicitedResponse::Exists(3))
+        );
+    }
+
+    #[test]
+    fn search_charset_literal_plus() {
+        let response = b"* CAPABILITY IMAP4rev1 LITERAL+\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * SEARCH 2\r\n\
+            a2 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.capabilities().unwrap();
+        let ids = session
+            .search_charset("UTF-8", "SUBJECT caf\u{e9} FROM m\u{fc}ller")
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == "a1 CAPABILITY\r\n\
+                    a2 SEARCH CHARSET UTF-8 SUBJECT {5+}\r\n\
+                    caf\u{e9} FROM {7+}\r\n\
+                    m\u{fc}ller\r\n"
+                    .as_bytes()
+                    .to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids.to_vec(), vec![2]);
+    }
+
+    #[test]
+    fn search_bad_charset() {
+        let response = b"a1 NO [BADCHARSET (US-ASCII UTF-8)] Unknown charset\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.search_charset("KOI8-R", SearchKey::Body("\u{43f}".to_string())) {
+            Err(Error::Code(ResponseCode::BadCharset, ref text)) => {
+                assert_eq!(
+                    text.bad_charsets(),
+                    Some(vec!["US-ASCII".to_string(), "UTF-8".to_string()])
+                )
+            }
+            r => panic!("expected BADCHARSET, got {:?}", r),
+        }
+        assert!(
+            session.stream.get_ref().written_buf
+                == "a1 SEARCH CHARSET KOI8-R BODY {2}\r\n".as_bytes().to_vec(),
+            "Invalid search command"
+        );
+    }
+
+    #[test]
+    fn uid_search() {
+        let response = b"* SEARCH 1 2 3 4 5\r\n\
+            a1 OK Search completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let ids = session.uid_search("Unseen").unwrap();
+        assert_eq!(ids.sequence_numbers(), None);
+        let ids = ids.uids().unwrap().clone();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 UID SEARCH Unseen\r\n".to_vec(),
+            "Invalid search command"
+        );
+        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+    }
+
+    #[test]
+    fn capability() {
+        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let capabilities = session.capabilities().unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 CAPABILITY\r\n".to_vec(),
+            "Invalid capability command"
+        );
+        assert_eq!(capabilities.len(), 4);
+        for e in expected_capabilities {
+            assert!(capabilities.has(e));
+        }
+        assert_eq!(capabilities.iter().count(), 4);
+        assert_eq!(capabilities.auth_mechanisms(), vec!["GSSAPI"]);
+    }
+
+    #[test]
+    fn auth_mechanisms() {
+        let response = b"* CAPABILITY IMAP4rev1 AUTH=PLAIN auth=xoauth2 AUTH=CRAM-MD5 AUTH=\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let capabilities = session.capabilities().unwrap();
+        assert_eq!(
+            capabilities.auth_mechanisms(),
+            vec!["CRAM-MD5", "PLAIN", "XOAUTH2"]
+        );
+    }
+
+    #[test]
+    fn append_limit_client_side() {
+        let response = b"* CAPABILITY IMAP4rev1 APPENDLIMIT=5\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        session.capabilities().unwrap();
+        assert_eq!(session.append_limit(), Some(5));
+        match session.append("INBOX", b"too long") {
+            Err(Error::MessageTooBig) => {}
+            r => panic!("expected MessageTooBig, got {:?}", r),
+        }
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 CAPABILITY\r\n".to_vec(),
+            "message was sent despite exceeding APPENDLIMIT"
+        );
+    }
+
+    #[test]
+    fn append_stream() {
+        let response = b"+ Ready for literal data\r\na1 OK APPEND completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session
+            .append_stream("INBOX", io::Cursor::new(b"Subject: hi\r\n\r\n".to_vec()))
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 APPEND \"INBOX\" {15}\r\nSubject: hi\r\n\r\n\r\n".to_vec(),
+            "Invalid append command"
+        );
+
+        let response = b"* CAPABILITY IMAP4rev1 APPENDLIMIT=5\r\n\
+            a1 OK CAPABILITY completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.capabilities().unwrap();
+        // stops reading once the limit is exceeded
+        match session.append_stream("INBOX", io::repeat(b'x')) {
+            Err(Error::MessageTooBig) => {}
+            r => panic!("expected MessageTooBig, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn append_literal_plus() {
+        let response = b"* CAPABILITY IMAP4rev1 LITERAL+\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.capabilities().unwrap();
+        session.append("INBOX", b"message").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 APPEND \"INBOX\" {7+}\r\nmessage\r\n".to_vec(),
+            "Invalid append command"
         );
-        assert_eq!(mailbox, expected_mailbox);
     }
 
     #[test]
-    fn search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            a1 OK Search completed\r\n"
+    fn notify() {
+        let response = b"* CAPABILITY IMAP4rev1 NOTIFY\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * STATUS Lists (MESSAGES 12 UIDNEXT 80)\r\n\
+            a2 OK NOTIFY completed\r\n\
+            + idling\r\n\
+            * STATUS Lists (MESSAGES 13 UIDNEXT 81)\r\n\
+            a3 OK IDLE terminated\r\n\
+            a4 OK NOTIFY completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.search("Unseen").unwrap();
-        let ids: HashSet<u32> = ids.iter().cloned().collect();
+        let mut session = mock_session!(MockStream::new(response));
+        let spec = NotifySpec::new()
+            .status(true)
+            .selected(&["MessageNew", "MessageExpunge"])
+            .mailboxes(&["Lists"], &["MessageNew"]);
+        session.notify(&spec).unwrap();
+        let status = match session.unsolicited_responses().next() {
+            Some(UnsolicitedResponse::Status(status)) => status,
+            r => panic!("expected a STATUS, got {:?}", r),
+        };
+        assert_eq!(status.mailbox, "Lists");
+        assert_eq!(status.messages, Some(12));
+
+        let events = session
+            .idle()
+            .unwrap()
+            .wait_with_timeout(Duration::from_secs(1))
+            .unwrap();
+        match events[..] {
+            [UnsolicitedResponse::Status(ref status)] => assert_eq!(status.uid_next, Some(81)),
+            ref r => panic!("expected a STATUS, got {:?}", r),
+        }
+
+        session.notify_none().unwrap();
         assert!(
-            session.stream.get_ref().written_buf == b"a1 SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\n\
+                    a2 NOTIFY SET STATUS (SELECTED (MessageNew MessageExpunge)) \
+                    (MAILBOXES (\"Lists\") (MessageNew))\r\n\
+                    a3 IDLE\r\nDONE\r\na4 NOTIFY NONE\r\n"
+                    .to_vec(),
+            "Invalid notify command"
         );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
     }
 
     #[test]
-    fn uid_search() {
-        let response = b"* SEARCH 1 2 3 4 5\r\n\
-            a1 OK Search completed\r\n"
+    fn append_multiple() {
+        let response = b"* CAPABILITY IMAP4rev1 MULTIAPPEND UIDPLUS\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            + Ready for literal data\r\n\
+            + Ready for literal data\r\n\
+            a2 OK [APPENDUID 38505 3955:3956] APPEND completed\r\n"
             .to_vec();
-        let mock_stream = MockStream::new(response);
-        let mut session = mock_session!(mock_stream);
-        let ids = session.uid_search("Unseen").unwrap();
-        let ids: HashSet<u32> = ids.iter().cloned().collect();
+        let mut session = mock_session!(MockStream::new(response));
+        let messages = [
+            AppendMessage::new(b"first")
+                .flag("\\Seen")
+                .internal_date("17-Jul-1996 02:44:25 -0700"),
+            AppendMessage::new(b"second"),
+        ];
+        let uids = session.append_multiple("INBOX", &messages).unwrap().unwrap();
+        assert_eq!(uids.uid_validity, 38505);
+        assert_eq!(uids.uids, vec![3955, 3956]);
         assert!(
-            session.stream.get_ref().written_buf == b"a1 UID SEARCH Unseen\r\n".to_vec(),
-            "Invalid search command"
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\n\
+                    a2 APPEND \"INBOX\" (\\Seen) \"17-Jul-1996 02:44:25 -0700\" {5}\r\n\
+                    first {6}\r\nsecond\r\n"
+                    .to_vec(),
+            "Invalid append command"
         );
-        assert_eq!(ids, [1, 2, 3, 4, 5].iter().cloned().collect());
+
+        let response = b"* CAPABILITY IMAP4rev1 MULTIAPPEND UIDPLUS LITERAL+\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK [APPENDUID 1 1:4294967295] APPEND completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.append_multiple("INBOX", &messages) {
+            Err(Error::Parse(ParseError::Invalid(_))) => {}
+            r => panic!("expected a parse error, got {:?}", r),
+        }
+
+        let response = b"* CAPABILITY IMAP4rev1 UIDPLUS\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.append_multiple("INBOX", &messages) {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "MULTIAPPEND"),
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
     }
 
     #[test]
-    fn capability() {
-        let response = b"* CAPABILITY IMAP4rev1 STARTTLS AUTH=GSSAPI LOGINDISABLED\r\n\
-            a1 OK CAPABILITY completed\r\n"
+    fn append_literal_minus() {
+        // LITERAL- only allows non-synchronizing literals of up to 4096 octets
+        let message = vec![b'x'; 4097];
+        let response = b"* CAPABILITY IMAP4rev1 LITERAL-\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            a2 OK APPEND completed\r\n\
+            + Ready for literal data\r\n\
+            a3 OK APPEND completed\r\n"
             .to_vec();
-        let expected_capabilities = vec!["IMAP4rev1", "STARTTLS", "AUTH=GSSAPI", "LOGINDISABLED"];
+        let mut session = mock_session!(MockStream::new(response));
+        session.capabilities().unwrap();
+        session.append("INBOX", &message[..4096]).unwrap();
+        session.append("INBOX", &message).unwrap();
+        let written = session.stream.get_ref().written_buf.clone();
+        let expected = [
+            &b"a1 CAPABILITY\r\na2 APPEND \"INBOX\" {4096+}\r\n"[..],
+            &message[..4096],
+            b"\r\na3 APPEND \"INBOX\" {4097}\r\n",
+            &message,
+            b"\r\n",
+        ].concat();
+        assert!(written == expected, "Invalid append commands");
+    }
+
+    #[test]
+    fn append_toobig_server_side() {
+        let response = b"a1 NO [TOOBIG] Message too large\r\n".to_vec();
         let mock_stream = MockStream::new(response);
         let mut session = mock_session!(mock_stream);
-        let capabilities = session.capabilities().unwrap();
-        assert!(
-            session.stream.get_ref().written_buf == b"a1 CAPABILITY\r\n".to_vec(),
-            "Invalid capability command"
-        );
-        assert_eq!(capabilities.len(), 4);
-        for e in expected_capabilities {
-            assert!(capabilities.has(e));
+        match session.append("INBOX", b"message") {
+            Err(Error::MessageTooBig) => {}
+            r => panic!("expected MessageTooBig, got {:?}", r),
+        }
+
+        let response = b"+ Ready for literal data\r\n\
+            a1 NO [TOOBIG] Message too large\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        match session.append("INBOX", b"message") {
+            Err(Error::MessageTooBig) => {}
+            r => panic!("expected MessageTooBig, got {:?}", r),
         }
     }
 
@@ -1204,6 +5946,61 @@ mod tests {
         );
     }
 
+    #[test]
+    fn noop_updates() {
+        let response = b"* 4 EXISTS\r\n\
+            a1 OK Search completed\r\n\
+            * 5 EXISTS\r\n\
+            * 2 EXPUNGE\r\n\
+            * 3 FETCH (FLAGS (\\Seen \\Flagged))\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.search("UNSEEN").unwrap();
+        let updates = session.noop().unwrap();
+        assert_eq!(updates.len(), 3);
+        assert_eq!(updates[0], UnsolicitedResponse::Exists(5));
+        assert_eq!(updates[1], UnsolicitedResponse::Expunge(2));
+        match updates[2] {
+            UnsolicitedResponse::Fetch(ref fetch) => {
+                assert_eq!(fetch.message, 3);
+                assert_eq!(fetch.flags(), &["\\Seen", "\\Flagged"]);
+            }
+            ref r => panic!("expected a FETCH, got {:?}", r),
+        }
+
+        // the update that came with the SEARCH is still queued
+        assert_eq!(session.unsolicited_responses().count(), 1);
+    }
+
+    #[test]
+    fn keepalive() {
+        let response = b"a1 OK NOOP completed\r\n\
+            * 4 EXISTS\r\n\
+            * 2 EXPUNGE\r\n\
+            * 1 FETCH (FLAGS (\\Seen))\r\n\
+            a2 OK NOOP completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        assert_eq!(session.idle_time(), None);
+        assert_eq!(session.keepalive(Duration::from_secs(300)).unwrap(), Some(vec![]));
+        assert!(session.idle_time().unwrap() < Duration::from_secs(300));
+        assert_eq!(session.keepalive(Duration::from_secs(300)).unwrap(), None);
+        let responses = session.keepalive(Duration::from_secs(0)).unwrap().unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 NOOP\r\na2 NOOP\r\n".to_vec(),
+            "Invalid keepalive commands"
+        );
+
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0], UnsolicitedResponse::Exists(4));
+        assert_eq!(responses[1], UnsolicitedResponse::Expunge(2));
+        match responses[2] {
+            UnsolicitedResponse::Fetch(ref fetch) => assert_eq!(fetch.flags(), &["\\Seen"]),
+            ref r => panic!("expected a FETCH, got {:?}", r),
+        }
+    }
+
     #[test]
     fn close() {
         let response = b"a1 OK CLOSE completed\r\n".to_vec();
@@ -1216,6 +6013,35 @@ mod tests {
         );
     }
 
+    #[test]
+    fn close_deselects() {
+        let response = b"* 3 EXISTS\r\na1 OK [READ-WRITE] Selected\r\n\
+            a2 OK CLOSE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").unwrap();
+        assert!(session.selected_mailbox().is_some());
+        session.close().unwrap();
+        assert!(session.selected_mailbox().is_none());
+    }
+
+    #[test]
+    fn unselect() {
+        let response = b"* 3 EXISTS\r\na1 OK [READ-WRITE] Selected\r\n\
+            * CAPABILITY IMAP4rev1 UNSELECT\r\na2 OK CAPABILITY completed\r\n\
+            a3 OK UNSELECT completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").unwrap();
+        session.unselect().unwrap();
+        assert!(session.selected_mailbox().is_none());
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 SELECT \"INBOX\"\r\na2 CAPABILITY\r\na3 UNSELECT\r\n".to_vec(),
+            "Invalid unselect command"
+        );
+    }
+
     #[test]
     fn store() {
         generic_store(" ", |c, set, query| c.store(set, query));
@@ -1238,6 +6064,127 @@ mod tests {
         generic_with_uid(res, "STORE", "2.4", "+FLAGS (\\Deleted)", prefix, op);
     }
 
+    #[test]
+    fn store_returns_flags() {
+        for &(query, ref expected) in &[
+            ("+FLAGS (\\Deleted)", vec!["\\Seen", "\\Deleted"]),
+            ("-FLAGS (\\Deleted)", vec!["\\Seen"]),
+            ("FLAGS (\\Flagged)", vec!["\\Flagged"]),
+        ] {
+            let response = format!(
+                "* 2 FETCH (FLAGS ({}))\r\n\
+                 a1 OK STORE completed\r\n",
+                expected.join(" ")
+            );
+            let mock_stream = MockStream::new(response.into_bytes());
+            let mut session = mock_session!(mock_stream);
+            let fetches = session.store("2", query).unwrap();
+            assert_eq!(fetches.len(), 1);
+            assert_eq!(fetches[0].message, 2);
+            assert_eq!(fetches[0].flags(), &expected[..]);
+        }
+    }
+
+    #[test]
+    fn store_silent() {
+        let response = b"* 3 FETCH (FLAGS (\\Seen))\r\n\
+            a1 OK STORE completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let fetches = session.uid_store("2", "+FLAGS.SILENT (\\Deleted)").unwrap();
+        assert!(fetches.is_empty());
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID STORE 2 +FLAGS.SILENT (\\Deleted)\r\n".to_vec(),
+            "Invalid store command"
+        );
+    }
+
+    #[test]
+    fn set_flags_diff() {
+        let response = b"a1 OK Store completed\r\na2 OK Store completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session
+            .set_flags_diff(
+                42,
+                &["\\Seen", "\\Recent", "$Label1", "\\Flagged"],
+                &["\\seen", "$Forwarded", "\\Answered", "\\Flagged"],
+            ).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID STORE 42 +FLAGS.SILENT ($Forwarded \\Answered)\r\n\
+                    a2 UID STORE 42 -FLAGS.SILENT ($Label1)\r\n"
+                    .to_vec(),
+            "Invalid store command"
+        );
+
+        let mut session = mock_session!(MockStream::new(Vec::new()));
+        session.set_flags_diff(42, &["\\Seen"], &["\\Seen"]).unwrap();
+        assert!(session.stream.get_ref().written_buf.is_empty());
+    }
+
+    #[test]
+    fn uid_store_unchanged_since() {
+        let condstore = "* CAPABILITY IMAP4rev1 CONDSTORE\r\na1 OK CAPABILITY completed\r\n";
+        let response = format!(
+            "{}* 5 FETCH (UID 8 MODSEQ (12346) FLAGS (\\Seen))\r\n\
+             a2 OK [MODIFIED 7,9:10] Conditional STORE failed\r\n",
+            condstore
+        );
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let modified = session
+            .uid_store_unchanged_since("7:10", 12345, "+FLAGS (\\Seen)")
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\n\
+                    a2 UID STORE 7:10 (UNCHANGEDSINCE 12345) +FLAGS (\\Seen)\r\n"
+                    .to_vec(),
+            "Invalid store command"
+        );
+        assert_eq!(modified.to_string(), "7,9:10");
+        assert_eq!(session.unsolicited_responses.len(), 1);
+
+        // the set is not expanded
+        let response = format!(
+            "{}a2 NO [MODIFIED 1:4294967295] Conditional STORE failed\r\n",
+            condstore
+        );
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let modified = session
+            .uid_store_unchanged_since("1:*", 12345, "+FLAGS (\\Seen)")
+            .unwrap();
+        assert!(modified.contains(4294967295));
+
+        let response = format!("{}a2 OK Conditional STORE completed\r\n", condstore);
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        let modified = session
+            .uid_store_unchanged_since("7", 12345, "+FLAGS (\\Seen)")
+            .unwrap();
+        assert!(modified.is_empty());
+
+        let response = format!("{}a2 NO STORE failed\r\n", condstore);
+        let mut session = mock_session!(MockStream::new(response.into_bytes()));
+        match session.uid_store_unchanged_since("7", 12345, "+FLAGS (\\Seen)") {
+            Err(Error::No(_)) => {}
+            r => panic!("expected No, got {:?}", r),
+        }
+
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.uid_store_unchanged_since("7", 12345, "+FLAGS (\\Seen)") {
+            Err(Error::MissingCapability(_)) => {}
+            r => panic!("expected MissingCapability, got {:?}", r),
+        }
+
+        let mut session = mock_session!(MockStream::new(condstore.as_bytes().to_vec()));
+        match session.uid_store_unchanged_since("$", 12345, "+FLAGS (\\Seen)") {
+            Err(Error::NoSavedSearch) => {}
+            r => panic!("expected NoSavedSearch, got {:?}", r),
+        }
+    }
+
     #[test]
     fn copy() {
         generic_copy(" ", |c, set, query| c.copy(set, query))
@@ -1256,9 +6203,9 @@ mod tests {
             "OK COPY completed\r\n",
             "COPY",
             "2:4",
-            "MEETING",
+            "\"MEETING\"",
             prefix,
-            op,
+            |c, set, _| op(c, set, "MEETING"),
         );
     }
 
@@ -1296,6 +6243,205 @@ mod tests {
         );
     }
 
+    #[test]
+    fn list_recursivematch() {
+        let response = b"* LIST () \"/\" \"Foo\" (\"CHILDINFO\" (\"SUBSCRIBED\"))\r\n\
+            * LIST (\\Subscribed) \"/\" \"Foo/Bar\"\r\n\
+            * LIST (\\Noselect) NIL {3}\r\nBaz\r\n\
+            a1 OK List completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let names = session
+            .list_extended(&["SUBSCRIBED", "RECURSIVEMATCH"], "", "*", &[])
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 LIST (SUBSCRIBED RECURSIVEMATCH) \"\" *\r\n".to_vec(),
+            "Invalid list command"
+        );
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].name(), "Foo");
+        assert_eq!(names[0].child_info(), &["SUBSCRIBED"]);
+        assert_eq!(names[2].name(), "Baz");
+    }
+
+    #[test]
+    fn list_lenient() {
+        let response = b"* LIST(\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+            a1 OK List completed\r\n\
+            * LIST(\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+            * LIST \\HasNoChildren \"/\" \"Sent\"\r\n\
+            a2 OK List completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.list("", "*") {
+            Err(Error::Parse(_)) => {}
+            r => panic!("expected a parse error, got {:?}", r.map(|n| n.len())),
+        }
+
+        session.set_lenient(true);
+        let names = session.list("", "*").unwrap();
+        assert_eq!(names.len(), 1);
+        assert_eq!(names[0].name(), "INBOX");
+    }
+
+    #[test]
+    fn get_quota() {
+        let response = b"* CAPABILITY IMAP4rev1 QUOTA\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * QUOTA \"\" (STORAGE 10 512 MESSAGE 4 10000)\r\n\
+            a2 OK Getquota completed\r\n\
+            * QUOTAROOT INBOX \"\"\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            a3 OK Getquotaroot completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let quotas = session.get_quota("").unwrap();
+        assert_eq!(quotas.len(), 1);
+        assert_eq!(quotas[0].resource("STORAGE"), Some((10, 512)));
+        assert_eq!(quotas[0].resource("MESSAGE"), Some((4, 10000)));
+
+        let (roots, quotas) = session.get_quota_root("INBOX").unwrap();
+        assert_eq!(roots, vec![String::new()]);
+        assert_eq!(quotas[0].root(), "");
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 GETQUOTA \"\"\r\na3 GETQUOTAROOT \"INBOX\"\r\n".to_vec(),
+            "Invalid quota commands"
+        );
+    }
+
+    #[test]
+    fn set_quota() {
+        let response = b"* CAPABILITY IMAP4rev1 QUOTA\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            a2 OK Setquota completed\r\n\
+            a3 NO [NOPERM] Only administrators may change quotas\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let quota = session.set_quota("", &[("STORAGE", 512)]).unwrap().unwrap();
+        assert_eq!(quota.root(), "");
+        assert_eq!(quota.resource("STORAGE"), Some((10, 512)));
+
+        match session.set_quota("", &[("STORAGE", 512), ("MESSAGE", 100)]) {
+            Err(Error::Code(ResponseCode::NoPerm, _)) => {}
+            r => panic!("expected NOPERM, got {:?}", r),
+        }
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 SETQUOTA \"\" (STORAGE 512)\r\n\
+                     a3 SETQUOTA \"\" (STORAGE 512 MESSAGE 100)\r\n"
+                    .to_vec(),
+            "Invalid quota commands"
+        );
+    }
+
+    #[test]
+    fn lsub() {
+        // `Foo` is only listed because its child `Foo/Bar` is subscribed
+        let response = b"* LSUB (\\Noselect) \"/\" \"Foo\"\r\n\
+            * LSUB () \"/\" \"Sent Items\"\r\n\
+            a1 OK LSUB completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names = session.lsub("", "%").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf == b"a1 LSUB \"\" %\r\n".to_vec(),
+            "Invalid lsub command"
+        );
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0].name(), "Foo");
+        assert_eq!(names[0].attributes(), &["\\Noselect"]);
+        assert_eq!(names[1].name(), "Sent Items");
+        assert!(names[1].attributes().is_empty());
+    }
+
+    #[test]
+    fn list_return_options() {
+        let response = b"* LIST (\\HasNoChildren \\Subscribed) \"/\" \"INBOX\"\r\n\
+            * LIST (\\HasChildren) \"/\" \"Archive\"\r\n\
+            * LIST (\\NonExistent \\HasChildren) \"/\" \"Old\" (\"CHILDINFO\" (\"SUBSCRIBED\"))\r\n\
+            a1 OK List completed\r\n"
+            .to_vec();
+        let mock_stream = MockStream::new(response);
+        let mut session = mock_session!(mock_stream);
+        let names = session
+            .list_extended(&[], "", "*", &["SUBSCRIBED", "CHILDREN"])
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 LIST \"\" * RETURN (SUBSCRIBED CHILDREN)\r\n".to_vec(),
+            "Invalid list command"
+        );
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].attributes(), &["\\HasNoChildren", "\\Subscribed"]);
+        assert_eq!(names[1].attributes(), &["\\HasChildren"]);
+        assert_eq!(names[2].name(), "Old");
+        assert_eq!(names[2].attributes(), &["\\NonExistent", "\\HasChildren"]);
+        assert_eq!(names[2].child_info(), &["SUBSCRIBED"]);
+    }
+
+    #[test]
+    fn list_patterns() {
+        let response = b"* CAPABILITY IMAP4rev1 LIST-EXTENDED\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * LIST (\\HasChildren) \".\" \"INBOX\"\r\n\
+            * LIST (\\HasNoChildren) \".\" \"INBOX.Sent\"\r\n\
+            a2 OK List completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names = session.list_patterns("", &["INBOX", "INBOX.*"]).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 LIST \"\" (INBOX INBOX.*)\r\n".to_vec(),
+            "Invalid list command"
+        );
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[1].name(), "INBOX.Sent");
+
+        // without LIST-EXTENDED, each pattern is listed on its own
+        let response = b"* CAPABILITY IMAP4rev1\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * LIST (\\HasChildren) \".\" \"INBOX\"\r\n\
+            a2 OK List completed\r\n\
+            * LIST (\\HasChildren) \".\" \"INBOX\"\r\n\
+            * LIST (\\HasNoChildren) \".\" \"INBOX.Sent\"\r\n\
+            a3 OK List completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names = session.list_patterns("", &["INBOX", "INBOX*"]).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CAPABILITY\r\na2 LIST \"\" INBOX\r\na3 LIST \"\" INBOX*\r\n".to_vec(),
+            "Invalid list command"
+        );
+        let names: Vec<_> = names.iter().map(|n| n.name()).collect();
+        assert_eq!(names, vec!["INBOX", "INBOX.Sent"]);
+    }
+
+    #[test]
+    fn list_special_use() {
+        let response = b"* LIST (\\Sent \\HasNoChildren) \"/\" \"Gesendet\"\r\n\
+            * LIST (\\HasNoChildren \\Trash) \"/\" \"Papierkorb\"\r\n\
+            * LIST (\\Drafts) \"/\" \"Entw&APw-rfe\"\r\n\
+            a1 OK List completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let names = session.list_special_use("", "*").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 LIST (SPECIAL-USE) \"\" *\r\n".to_vec(),
+            "Invalid list command"
+        );
+        assert_eq!(names.len(), 3);
+        assert!(names[0].is_sent());
+        assert!(names[1].is_trash());
+        assert!(!names[1].is_sent());
+        assert_eq!(names[2].special_use(), Some(SpecialUse::Drafts));
+    }
+
     #[test]
     fn fetch() {
         generic_fetch(" ", |c, seq, query| c.fetch(seq, query))
@@ -1306,6 +6452,147 @@ mod tests {
         generic_fetch(" UID ", |c, seq, query| c.uid_fetch(seq, query))
     }
 
+    #[test]
+    fn fetch_peek() {
+        let response = b"a1 OK FETCH completed\r\na2 OK FETCH completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.fetch_peek("1", "(UID BODY[TEXT])").unwrap();
+        session.uid_fetch_peek("7", "RFC822").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 FETCH 1 (UID BODY.PEEK[TEXT])\r\na2 UID FETCH 7 BODY.PEEK[]\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+    }
+
+    #[test]
+    fn fetch_batched() {
+        let response = b"* 1 FETCH (FLAGS (\\Seen))\r\n\
+            * 2 FETCH (FLAGS ())\r\n\
+            a1 OK FETCH completed\r\n\
+            * 2 FETCH (FLAGS ())\r\n\
+            * 5 FETCH (FLAGS (\\Deleted))\r\n\
+            a2 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session.fetch_batched("1,2,1,5", "FLAGS", 2).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 FETCH 1:2 FLAGS\r\na2 FETCH 5 FLAGS\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        let messages: Vec<_> = fetches.iter().map(|f| f.message).collect();
+        assert_eq!(messages, vec![1, 2, 5]);
+        assert_eq!(fetches[2].flags(), &["\\Deleted"]);
+    }
+
+    #[test]
+    fn fetch_batched_open_ended() {
+        let response = b"* 250 EXISTS\r\n\
+            a1 OK [READ-WRITE] Select completed\r\n\
+            a2 OK FETCH completed\r\n\
+            a3 OK FETCH completed\r\n\
+            * 250 FETCH (FLAGS ())\r\n\
+            a4 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.select("INBOX").unwrap();
+        let fetches = session.fetch_batched("1:*", "FLAGS", 100).unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 SELECT \"INBOX\"\r\n\
+             a2 FETCH 1:100 FLAGS\r\n\
+             a3 FETCH 101:200 FLAGS\r\n\
+             a4 FETCH 201:250 FLAGS\r\n"
+        );
+        assert_eq!(fetches.len(), 1);
+    }
+
+    #[test]
+    fn uid_fetch_batched_wide() {
+        let response = b"a1 OK FETCH completed\r\na2 OK FETCH completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session
+            .uid_fetch_batched("1:4294967295", "FLAGS", 1 << 31)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 UID FETCH 1:2147483648 FLAGS\r\n\
+             a2 UID FETCH 2147483649:4294967295 FLAGS\r\n"
+        );
+    }
+
+    #[test]
+    fn search_fetch() {
+        let response = b"* SEARCH 12 10 14\r\n\
+            a1 OK Search completed\r\n\
+            * 1 FETCH (UID 10 FLAGS ())\r\n\
+            * 2 FETCH (UID 12 FLAGS (\\Seen))\r\n\
+            * 3 FETCH (UID 13 FLAGS ())\r\n\
+            * 4 FETCH (UID 14 FLAGS ())\r\n\
+            a2 OK Fetch completed\r\n\
+            * SEARCH\r\n\
+            a3 OK Search completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session.search_fetch("UNSEEN", "FLAGS").unwrap();
+        let uids: Vec<_> = fetches.iter().map(|f| f.uid).collect();
+        assert_eq!(uids, vec![Some(10), Some(12), Some(14)]);
+        assert_eq!(fetches[1].flags(), &["\\Seen"]);
+
+        // nothing is fetched if nothing was found
+        assert!(session.search_fetch("DELETED", "FLAGS").unwrap().is_empty());
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID SEARCH UNSEEN\r\n\
+                     a2 UID FETCH 10,12,14 (UID FLAGS)\r\n\
+                     a3 UID SEARCH DELETED\r\n"
+                    .to_vec(),
+            "Invalid search_fetch commands"
+        );
+    }
+
+    #[test]
+    fn fetch_header_fields() {
+        let response = b"* 1 FETCH (BODY[HEADER.FIELDS (SUBJECT FROM DATE)] {13}\r\n\
+            Subject: hi\r\n)\r\n\
+            a1 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session
+            .fetch_header_fields("1", &["Subject", "From", "Date"])
+            .unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 FETCH 1 (BODY.PEEK[HEADER.FIELDS (SUBJECT FROM DATE)])\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(
+            fetches[0].section("HEADER.FIELDS (SUBJECT FROM DATE)"),
+            Some(&b"Subject: hi\r\n"[..])
+        );
+    }
+
+    #[test]
+    fn fetch_partial() {
+        // the message ends after 6 bytes, which is less than the 4096 requested
+        let response = b"* 3 FETCH (UID 42 BODY[]<1024> {6}\r\nfoobar)\r\n\
+            a1 OK FETCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session.fetch_partial(42, "", 1024, 4096).unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 UID FETCH 42 (BODY.PEEK[]<1024.4096>)\r\n".to_vec(),
+            "Invalid fetch command"
+        );
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(42));
+        assert_eq!(fetches[0].origin(), Some(1024));
+        assert_eq!(fetches[0].body(), Some(&b"foobar"[..]));
+        assert_eq!(fetches[0].section(""), Some(&b"foobar"[..]));
+    }
+
     fn generic_fetch<F, T>(prefix: &str, op: F)
     where
         F: FnOnce(&mut Session<MockStream>, &str, &str) -> Result<T>,
@@ -1327,6 +6614,91 @@ mod tests {
         );
     }
 
+    #[test]
+    fn store_gmail_labels() {
+        let response = b"* CAPABILITY IMAP4rev1 X-GM-EXT-1\r\n\
+            a1 OK CAPABILITY completed\r\n\
+            * 1 FETCH (UID 7 X-GM-LABELS (\\Important \"Gel&APY-scht\"))\r\n\
+            a2 OK STORE completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        let fetches = session
+            .uid_store_gmail_labels("7", "+X-GM-LABELS", &["\\Important", "Gel\u{f6}scht"])
+            .unwrap();
+        assert_eq!(fetches[0].gmail_labels(), &["\\Important", "Gel\u{f6}scht"]);
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 CAPABILITY\r\na2 UID STORE 7 +X-GM-LABELS (\\Important \"Gel&APY-scht\")\r\n"
+        );
+
+        let response = b"* CAPABILITY IMAP4rev1\r\na1 OK CAPABILITY completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        match session.store_gmail_labels("1", "X-GM-LABELS", &["Work"]) {
+            Err(Error::MissingCapability(ref c)) => assert_eq!(c, "X-GM-EXT-1"),
+            r => panic!("expected MissingCapability, got {:?}", r.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn quote_mailbox_names() {
+        let response = b"a1 OK CREATE completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.create("My \"Test\" Folder").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 CREATE \"My \\\"Test\\\" Folder\"\r\n".to_vec(),
+            "Invalid create command"
+        );
+
+        // names are sent in modified UTF-7, which also takes care of control characters
+        let response = b"a1 OK RENAME completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.rename("Tom & Jerry", "Gel\u{f6}schte\r\n").unwrap();
+        assert!(
+            session.stream.get_ref().written_buf
+                == b"a1 RENAME \"Tom &- Jerry\" \"Gel&APY-schte&AA0ACg-\"\r\n".to_vec(),
+            "Invalid rename command"
+        );
+    }
+
+    #[test]
+    fn search_non_ascii_at_byte_8() {
+        // the second `é` straddles the 8th byte, where `CHARSET ` would end
+        let response = b"a1 OK SEARCH completed\r\n".to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.search("BODY \u{e9}\u{e9}\u{e9}").unwrap();
+        assert!(String::from_utf8(session.stream.get_ref().written_buf.clone())
+            .unwrap()
+            .starts_with("a1 SEARCH CHARSET UTF-8 BODY "));
+    }
+
+    #[test]
+    fn enable_utf8_accept() {
+        let response = b"a1 OK SEARCH completed\r\n\
+            * ENABLED UTF8=ACCEPT\r\n\
+            a2 OK ENABLE completed\r\n\
+            a3 OK CREATE completed\r\n\
+            * LIST () \"/\" \"Tom &- Jerry\"\r\n\
+            a4 OK LIST completed\r\n\
+            a5 OK SEARCH completed\r\n"
+            .to_vec();
+        let mut session = mock_session!(MockStream::new(response));
+        session.search("SUBJECT \"Gr\u{fc}\u{df}e\"").unwrap();
+        assert_eq!(session.enable(&["UTF8=ACCEPT"]).unwrap(), vec!["UTF8=ACCEPT"]);
+        session.create("Gel\u{f6}schte").unwrap();
+        let names = session.list("", "*").unwrap();
+        assert_eq!(names[0].name(), "Tom &- Jerry");
+        session.search("SUBJECT \"Gr\u{fc}\u{df}e\"").unwrap();
+        assert_eq!(
+            String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap(),
+            "a1 SEARCH CHARSET UTF-8 SUBJECT \"Gr\u{fc}\u{df}e\"\r\n\
+             a2 ENABLE UTF8=ACCEPT\r\n\
+             a3 CREATE \"Gel\u{f6}schte\"\r\n\
+             a4 LIST \"\" *\r\n\
+             a5 SEARCH SUBJECT \"Gr\u{fc}\u{df}e\"\r\n"
+        );
+    }
+
     #[test]
     fn quote_backslash() {
         assert_eq!("\"test\\\\text\"", quote!(r"test\text"));
@@ -0,0 +1,153 @@
+//! Support for the `COMPRESS=DEFLATE` extension defined in
+//! [RFC 4978](https://tools.ietf.org/html/rfc4978).
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use client::SetReadTimeout;
+use error::Result;
+
+const READ_CHUNK: usize = 8 * 1024;
+
+/// A stream that transparently deflates everything written to, and inflates everything read
+/// from, an underlying stream.
+///
+/// Sessions switch to this stream type after a successful
+/// [`Session::compress`](../client/struct.Session.html#method.compress).
+#[derive(Debug)]
+pub struct DeflateStream<T> {
+    inner: T,
+    compress: Compress,
+    decompress: Decompress,
+    // compressed input that has been read from `inner`, but not yet inflated
+    input: Vec<u8>,
+    output: Vec<u8>,
+}
+
+impl<T> DeflateStream<T> {
+    /// Wrap `inner`, which has already produced the compressed bytes in `input`.
+    pub(crate) fn new(inner: T, input: Vec<u8>) -> Self {
+        DeflateStream {
+            inner,
+            compress: Compress::new(Compression::default(), false),
+            decompress: Decompress::new(false),
+            input,
+            output: Vec::new(),
+        }
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+}
+
+impl<T: SetReadTimeout> SetReadTimeout for DeflateStream<T> {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.inner.read_timeout()
+    }
+
+    fn set_write_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.inner.set_write_timeout(timeout)
+    }
+}
+
+impl<T: Read> Read for DeflateStream<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        loop {
+            // the inflater may hold on to output even once it has consumed all our input
+            let (in_before, out_before) =
+                (self.decompress.total_in(), self.decompress.total_out());
+            let status = self
+                .decompress
+                .decompress(&self.input, buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            let consumed = (self.decompress.total_in() - in_before) as usize;
+            let produced = (self.decompress.total_out() - out_before) as usize;
+            self.input.drain(..consumed);
+
+            if produced > 0 || status == Status::StreamEnd {
+                return Ok(produced);
+            }
+            if consumed > 0 {
+                continue;
+            }
+
+            let mut chunk = [0; READ_CHUNK];
+            let n = self.inner.read(&mut chunk)?;
+            if n == 0 {
+                return Ok(0);
+            }
+            self.input.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+impl<T: Write> DeflateStream<T> {
+    fn deflate(&mut self, mut buf: &[u8], flush: FlushCompress) -> io::Result<()> {
+        self.output.clear();
+        loop {
+            self.output.reserve(buf.len() + 64);
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(buf, &mut self.output, flush)
+                .map_err(io::Error::other)?;
+            buf = &buf[(self.compress.total_in() - before) as usize..];
+
+            // the compressor is done once it no longer fills up all the space it is given
+            if buf.is_empty() && self.output.len() < self.output.capacity() {
+                break;
+            }
+        }
+        self.inner.write_all(&self.output)
+    }
+}
+
+impl<T: Write> Write for DeflateStream<T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflate(buf, FlushCompress::None)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflate(&[], FlushCompress::Sync)?;
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn roundtrip() {
+        let mut deflated = DeflateStream::new(Vec::new(), Vec::new());
+        deflated.write_all(b"a1 NOOP\r\n").unwrap();
+        deflated.flush().unwrap();
+        deflated.write_all(b"a2 LOGOUT\r\n").unwrap();
+        deflated.flush().unwrap();
+
+        // the first few compressed bytes were already read off the wire
+        let compressed = deflated.get_ref().clone();
+        let (head, tail) = compressed.split_at(3);
+        let mut inflated = DeflateStream::new(Cursor::new(tail.to_vec()), head.to_vec());
+        let mut plain = String::new();
+        inflated.read_to_string(&mut plain).unwrap();
+        assert_eq!(plain, "a1 NOOP\r\na2 LOGOUT\r\n");
+    }
+}
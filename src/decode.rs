@@ -0,0 +1,99 @@
+//! Decoding of the `Content-Transfer-Encoding`s that MIME body parts are fetched in.
+
+use base64;
+
+/// Decode `data` according to the given `Content-Transfer-Encoding` (case-insensitively), such as
+/// `base64` or `quoted-printable`. Data in any other encoding (`7bit`, `8bit`, `binary`, or
+/// unknown encodings) is returned as is.
+///
+/// Decoding is lenient, since mail in the wild often is not quite well-formed: whitespace and
+/// other stray characters in base64 are skipped and missing padding is tolerated, and malformed
+/// quoted-printable escapes are kept literally.
+pub fn decode_transfer_encoding(data: &[u8], encoding: &str) -> Vec<u8> {
+    match &*encoding.trim().to_lowercase() {
+        "base64" => decode_base64(data),
+        "quoted-printable" => decode_quoted_printable(data),
+        _ => data.to_vec(),
+    }
+}
+
+fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut clean: Vec<u8> = data
+        .iter()
+        .cloned()
+        .filter(|&c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/')
+        .collect();
+    // a single character left over cannot encode anything
+    if clean.len() % 4 == 1 {
+        clean.pop();
+    }
+    let padding = (4 - clean.len() % 4) % 4;
+    clean.extend(b"==".iter().take(padding));
+    base64::decode(&clean).unwrap_or_default()
+}
+
+fn decode_quoted_printable(data: &[u8]) -> Vec<u8> {
+    let hex = |c: u8| (c as char).to_digit(16).map(|d| d as u8);
+
+    let mut decoded = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        if data[i] != b'=' {
+            decoded.push(data[i]);
+            i += 1;
+            continue;
+        }
+
+        let rest = &data[i + 1..];
+        if rest.starts_with(b"\r\n") {
+            // soft line break
+            i += 3;
+        } else if rest.starts_with(b"\n") {
+            i += 2;
+        } else if let (Some(hi), Some(lo)) = (
+            rest.first().cloned().and_then(hex),
+            rest.get(1).cloned().and_then(hex),
+        ) {
+            decoded.push(hi << 4 | lo);
+            i += 3;
+        } else {
+            decoded.push(b'=');
+            i += 1;
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64() {
+        assert_eq!(
+            decode_transfer_encoding(b"SGVsbG8s\r\nIHdvcmxk\r\nIQ==\r\n", "BASE64"),
+            b"Hello, world!".to_vec()
+        );
+        // missing padding and stray whitespace
+        assert_eq!(
+            decode_transfer_encoding(b" SGVsbG8sIHdvcmxkIQ \r\n", "base64"),
+            b"Hello, world!".to_vec()
+        );
+    }
+
+    #[test]
+    fn quoted_printable() {
+        assert_eq!(
+            decode_transfer_encoding(
+                b"Caf=C3=A9 au lait, =\r\nsoft break; 1+1=3D2; bad =ZZ escape=",
+                "quoted-printable"
+            ),
+            "Café au lait, soft break; 1+1=2; bad =ZZ escape=".as_bytes().to_vec()
+        );
+    }
+
+    #[test]
+    fn identity() {
+        assert_eq!(decode_transfer_encoding(b"a=3Db", "7bit"), b"a=3Db".to_vec());
+    }
+}
@@ -0,0 +1,229 @@
+//! The hash functions needed for SCRAM authentication: SHA-1 (RFC 3174), SHA-256 (FIPS 180-4),
+//! HMAC (RFC 2104), and PBKDF2 (RFC 8018).
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Algorithm {
+    Sha1,
+    Sha256,
+}
+
+// Both algorithms work on 64-byte blocks.
+const BLOCK_LEN: usize = 64;
+
+impl Algorithm {
+    pub(crate) fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            Algorithm::Sha1 => sha1(data),
+            Algorithm::Sha256 => sha256(data),
+        }
+    }
+
+    pub(crate) fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut key = if key.len() > BLOCK_LEN {
+            self.hash(key)
+        } else {
+            key.to_vec()
+        };
+        key.resize(BLOCK_LEN, 0);
+
+        let mut inner: Vec<u8> = key.iter().map(|b| b ^ 0x36).collect();
+        inner.extend_from_slice(data);
+        let mut outer: Vec<u8> = key.iter().map(|b| b ^ 0x5c).collect();
+        outer.extend(self.hash(&inner));
+        self.hash(&outer)
+    }
+
+    // PBKDF2 with HMAC as the pseudorandom function, deriving a key as long as one hash (which is
+    // all that SCRAM needs).
+    pub(crate) fn pbkdf2(self, password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+        let mut block = salt.to_vec();
+        block.extend_from_slice(&[0, 0, 0, 1]);
+        let mut u = self.hmac(password, &block);
+        let mut key = u.clone();
+        for _ in 1..iterations {
+            u = self.hmac(password, &u);
+            for (k, b) in key.iter_mut().zip(&u) {
+                *k ^= b;
+            }
+        }
+        key
+    }
+}
+
+// Pad the message as both SHA-1 and SHA-256 do, and split it into 64-byte blocks.
+fn padded(data: &[u8]) -> Vec<u8> {
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % BLOCK_LEN != 56 {
+        message.push(0);
+    }
+    let bits = (data.len() as u64).wrapping_mul(8);
+    message.extend_from_slice(&bits.to_be_bytes());
+    message
+}
+
+fn sha1(data: &[u8]) -> Vec<u8> {
+    let mut h: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+
+    for block in padded(data).chunks(BLOCK_LEN) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+        for (i, &w) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let t = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = t;
+        }
+
+        for (h, v) in h.iter_mut().zip(&[a, b, c, d, e]) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    h.iter().flat_map(|h| h.to_be_bytes().to_vec()).collect()
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a_2f98, 0x7137_4491, 0xb5c0_fbcf, 0xe9b5_dba5, 0x3956_c25b, 0x59f1_11f1, 0x923f_82a4,
+    0xab1c_5ed5, 0xd807_aa98, 0x1283_5b01, 0x2431_85be, 0x550c_7dc3, 0x72be_5d74, 0x80de_b1fe,
+    0x9bdc_06a7, 0xc19b_f174, 0xe49b_69c1, 0xefbe_4786, 0x0fc1_9dc6, 0x240c_a1cc, 0x2de9_2c6f,
+    0x4a74_84aa, 0x5cb0_a9dc, 0x76f9_88da, 0x983e_5152, 0xa831_c66d, 0xb003_27c8, 0xbf59_7fc7,
+    0xc6e0_0bf3, 0xd5a7_9147, 0x06ca_6351, 0x1429_2967, 0x27b7_0a85, 0x2e1b_2138, 0x4d2c_6dfc,
+    0x5338_0d13, 0x650a_7354, 0x766a_0abb, 0x81c2_c92e, 0x9272_2c85, 0xa2bf_e8a1, 0xa81a_664b,
+    0xc24b_8b70, 0xc76c_51a3, 0xd192_e819, 0xd699_0624, 0xf40e_3585, 0x106a_a070, 0x19a4_c116,
+    0x1e37_6c08, 0x2748_774c, 0x34b0_bcb5, 0x391c_0cb3, 0x4ed8_aa4a, 0x5b9c_ca4f, 0x682e_6ff3,
+    0x748f_82ee, 0x78a5_636f, 0x84c8_7814, 0x8cc7_0208, 0x90be_fffa, 0xa450_6ceb, 0xbef9_a3f7,
+    0xc671_78f2,
+];
+
+fn sha256(data: &[u8]) -> Vec<u8> {
+    let mut h: [u32; 8] = [
+        0x6a09_e667, 0xbb67_ae85, 0x3c6e_f372, 0xa54f_f53a, 0x510e_527f, 0x9b05_688c, 0x1f83_d9ab,
+        0x5be0_cd19,
+    ];
+
+    for block in padded(data).chunks(BLOCK_LEN) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let mut v = h;
+        for (&k, &w) in SHA256_K.iter().zip(w.iter()) {
+            let (a, b, c, d, e, f, g, hh) = (v[0], v[1], v[2], v[3], v[4], v[5], v[6], v[7]);
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(w);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            v = [t1.wrapping_add(t2), a, b, c, d.wrapping_add(t1), e, f, g];
+        }
+
+        for (h, v) in h.iter_mut().zip(&v) {
+            *h = h.wrapping_add(*v);
+        }
+    }
+
+    h.iter().flat_map(|h| h.to_be_bytes().to_vec()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn sha() {
+        assert_eq!(
+            hex(&Algorithm::Sha1.hash(b"abc")),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+        assert_eq!(
+            hex(&Algorithm::Sha1.hash(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1"
+        );
+        assert_eq!(
+            hex(&Algorithm::Sha256.hash(b"")),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            hex(&Algorithm::Sha256.hash(
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"
+            )),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn hmac() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            hex(&Algorithm::Sha256.hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        // RFC 2202, test case 2
+        assert_eq!(
+            hex(&Algorithm::Sha1.hmac(b"Jefe", b"what do ya want for nothing?")),
+            "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79"
+        );
+        // a key longer than one block is hashed first (RFC 4231, test case 6)
+        assert_eq!(
+            hex(&Algorithm::Sha256.hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn pbkdf2() {
+        // RFC 6070
+        assert_eq!(
+            hex(&Algorithm::Sha1.pbkdf2(b"password", b"salt", 4096)),
+            "4b007901b765489abead49d926f721d065a429c1"
+        );
+        assert_eq!(
+            hex(&Algorithm::Sha256.pbkdf2(b"password", b"salt", 1)),
+            "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"
+        );
+    }
+}
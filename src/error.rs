@@ -10,6 +10,8 @@ use imap_proto::Response;
 use native_tls::Error as TlsError;
 use native_tls::HandshakeError as TlsHandshakeError;
 
+use types::AppendUid;
+
 pub type Result<T> = result::Result<T, Error>;
 
 /// A set of errors that can occur in the IMAP client
@@ -22,22 +24,135 @@ pub enum Error {
     /// An error from the `native_tls` library while managing the socket.
     Tls(TlsError),
     /// A BAD response from the IMAP server.
-    BadResponse(String),
+    Bad(ResponseText),
     /// A NO response from the IMAP server.
-    NoResponse(String),
-    /// The connection was terminated unexpectedly.
+    No(ResponseText),
+    /// A NO response from the IMAP server carrying one of the well-known response codes, such as
+    /// `TRYCREATE` or those from [RFC 5530](https://tools.ietf.org/html/rfc5530).
+    Code(ResponseCode, ResponseText),
+    /// The connection was terminated unexpectedly, possibly in the middle of a response. The data
+    /// received up to that point is not parsed, since the problem is not with the protocol;
+    /// reconnecting may help.
     ConnectionLost,
+    /// The connection was closed before the server finished responding to a command. The session
+    /// is left in an unknown state and can no longer be used.
+    ConnectionClosed(String),
+    /// The server said `* BYE`, in response to a command or on its own (e.g. when shutting
+    /// down), and is closing the connection. The session is dead: reconnect rather than retrying
+    /// on it. This carries the response code the server gave, if any (such as `UNAVAILABLE`), and
+    /// the text that follows it.
+    Bye(Option<ResponseCode>, String),
+    /// Reading from or writing to the server took longer than the configured timeout (see
+    /// `Connection::set_read_timeout`). If this happens while waiting for the response to a
+    /// command, the session is left in an unknown state and can no longer be used.
+    Timeout,
     // Error parsing a server response.
     Parse(ParseError),
     // Error validating input data
     Validate(ValidateError),
     // Error appending a mail
     Append,
+    /// The message is larger than the server is willing to accept, either because it exceeds the
+    /// advertised `APPENDLIMIT`, or because the server rejected it with `[TOOBIG]`.
+    MessageTooBig,
+    /// The server does not advertise a capability that the requested operation needs.
+    MissingCapability(String),
+    /// The command cannot be sent in a pipeline (see `Session::pipeline`), because it changes the
+    /// state of the connection or waits for the server's go-ahead.
+    NotPipelinable(String),
+    /// `login` or `authenticate` was called on a connection that the server greeted with
+    /// `* PREAUTH`, which is already authenticated (see `Client::into_preauthenticated_session`).
+    AlreadyAuthenticated,
+    /// `login` was called although the server advertises `LOGINDISABLED`, so it would refuse the
+    /// password anyway. The password was not sent. Upgrade the connection with `STARTTLS` first
+    /// (see `Client::starttls`), or use `authenticate`.
+    LoginDisabled,
+    /// `Session::reconnect` could not log in again, because the session was not set up with
+    /// `Client::login_reconnectable`.
+    NoCredentials,
+    /// The UIDVALIDITY of the mailbox is not the expected one (the first value) but the second,
+    /// as found by `Session::select_expecting` or `Session::reconnect`, so any UIDs known from
+    /// before are invalid.
+    UidValidityChanged(u32, u32),
+    /// There is no message with the given UID in the selected mailbox.
+    MessageNotFound(u32),
+    /// During SCRAM authentication, the server failed to prove that it knows the password. It
+    /// may be an impostor, e.g. a man in the middle, so the exchange was aborted.
+    ServerSignatureMismatch,
+    /// A strict `FetchQuery` mixed `BODY.PEEK[...]` with sections that set the `\Seen` flag.
+    MixedPeek,
+    /// The server's first line was neither an IMAP greeting nor an untagged response, e.g.
+    /// because the port is not that of an IMAP server, or because a proxy or a firewall got in
+    /// the way. This carries the line, with unprintable bytes escaped.
+    UnexpectedGreeting(String),
+    /// The message set `$` was used, but no search result was saved in the selected mailbox
+    /// (see `Session::search_save`).
+    NoSavedSearch,
+    /// The server did not report a UIDVALIDITY for the mailbox, so its UIDs cannot be relied on
+    /// from one session to the next (e.g. by `Session::export_maildir`).
+    NoUidValidity,
+}
+
+impl Error {
+    /// The well-known response code of a `NO`, `BAD`, or `BYE` response from the server, such as
+    /// `OVERQUOTA` or `EXPIRED`, so that callers can react to it (e.g. by backing off, or by
+    /// asking for new credentials). `None` for all other errors.
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        match *self {
+            Error::Code(code, _) => Some(code),
+            Error::Bad(ref text) | Error::No(ref text) => text.response_code(),
+            Error::Bye(code, _) => code,
+            _ => None,
+        }
+    }
+
+    // The error for a `* BYE` response with the given text, which may start with a response code.
+    pub(crate) fn bye(text: &str) -> Error {
+        let text = text.trim();
+        if let Some(end) = text.find(']').filter(|_| text.starts_with('[')) {
+            let code = text[1..end]
+                .split(' ')
+                .next()
+                .and_then(ResponseCode::from_atom);
+            return Error::Bye(code, text[end + 1..].trim_start().to_string());
+        }
+        Error::Bye(None, text.to_string())
+    }
+}
+
+impl Error {
+    /// Whether the operation that failed with this error may succeed if tried again later: the
+    /// server said so with `[UNAVAILABLE]` or `[INUSE]`, the connection timed out, or it was
+    /// lost or reset. Authentication failures, syntax errors, and the like are not transient.
+    ///
+    /// See the [`retry`](../retry/index.html) module.
+    pub fn is_transient(&self) -> bool {
+        use std::io::ErrorKind;
+        match *self {
+            Error::Timeout | Error::ConnectionLost => true,
+            // the connection is gone, whatever the reason
+            Error::Bye(..) => false,
+            Error::Io(ref e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::Interrupted
+                    | ErrorKind::UnexpectedEof
+            ),
+            _ => self.response_code().is_some_and(ResponseCode::is_transient),
+        }
+    }
 }
 
 impl From<IoError> for Error {
     fn from(err: IoError) -> Error {
-        Error::Io(err)
+        use std::io::ErrorKind;
+        match err.kind() {
+            // depending on the platform, a timed out socket read reports either of these
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => Error::Timeout,
+            _ => Error::Io(err),
+        }
     }
 }
 
@@ -61,7 +176,17 @@ impl From<TlsError> for Error {
 
 impl<'a> From<Response<'a>> for Error {
     fn from(err: Response<'a>) -> Error {
-        Error::Parse(ParseError::Unexpected(format!("{:?}", err)))
+        use imap_proto::Status;
+        match err {
+            // imap-proto leaves the codes it does not know (such as `UNAVAILABLE`) in the text,
+            // and none of those it knows are meaningful with `BYE`
+            Response::Data {
+                status: Status::Bye,
+                information,
+                ..
+            } => Error::bye(information.unwrap_or("")),
+            err => Error::Parse(ParseError::Unexpected(format!("{:?}", err))),
+        }
     }
 }
 
@@ -72,7 +197,17 @@ impl fmt::Display for Error {
             Error::Tls(ref e) => fmt::Display::fmt(e, f),
             Error::TlsHandshake(ref e) => fmt::Display::fmt(e, f),
             Error::Validate(ref e) => fmt::Display::fmt(e, f),
-            Error::NoResponse(ref data) | Error::BadResponse(ref data) => {
+            Error::No(ref text) | Error::Code(_, ref text) => write!(f, "No Response: {}", text),
+            Error::Bad(ref text) => write!(f, "Bad Response: {}", text),
+            Error::MessageNotFound(uid) => write!(f, "No message with UID {}", uid),
+            Error::UidValidityChanged(old, new) => {
+                write!(f, "The mailbox's UIDVALIDITY changed from {} to {}", old, new)
+            }
+            Error::ConnectionClosed(ref data)
+            | Error::Bye(_, ref data)
+            | Error::MissingCapability(ref data)
+            | Error::UnexpectedGreeting(ref data)
+            | Error::NotPipelinable(ref data) => {
                 write!(f, "{}: {}", &String::from(self.description()), data)
             }
             ref e => f.write_str(e.description()),
@@ -88,10 +223,29 @@ impl StdError for Error {
             Error::TlsHandshake(ref e) => e.description(),
             Error::Parse(ref e) => e.description(),
             Error::Validate(ref e) => e.description(),
-            Error::BadResponse(_) => "Bad Response",
-            Error::NoResponse(_) => "No Response",
+            Error::Bad(_) => "Bad Response",
+            Error::No(_) => "No Response",
+            Error::Code(..) => "No Response",
             Error::ConnectionLost => "Connection lost",
+            Error::ConnectionClosed(_) => "Connection closed",
+            Error::Bye(..) => "Server closed the connection",
+            Error::Timeout => "Timed out waiting for the server",
             Error::Append => "Could not append mail to mailbox",
+            Error::MessageTooBig => "Message exceeds the server's size limit",
+            Error::MissingCapability(_) => "Missing server capability",
+            Error::NotPipelinable(_) => "Command cannot be pipelined",
+            Error::AlreadyAuthenticated => "Connection is already authenticated",
+            Error::LoginDisabled => {
+                "The server does not allow LOGIN on this connection; use STARTTLS or AUTHENTICATE"
+            }
+            Error::MessageNotFound(_) => "No message with the given UID",
+            Error::ServerSignatureMismatch => "The server could not be authenticated",
+            Error::NoCredentials => "No credentials to log in again with",
+            Error::UidValidityChanged(..) => "The mailbox's UIDVALIDITY changed",
+            Error::MixedPeek => "Fetch query mixes peek and non-peek body sections",
+            Error::UnexpectedGreeting(_) => "Unexpected data instead of the server greeting",
+            Error::NoSavedSearch => "No search result was saved to refer to with $",
+            Error::NoUidValidity => "The mailbox has no UIDVALIDITY",
         }
     }
 
@@ -106,6 +260,236 @@ impl StdError for Error {
     }
 }
 
+/// The text of a tagged NO or BAD response.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ResponseText {
+    line: String,
+    // where the response code and the explanation are within `line`
+    code: (usize, usize),
+    information: usize,
+}
+
+impl ResponseText {
+    pub(crate) fn new(line: String, code: (usize, usize), information: usize) -> Self {
+        ResponseText {
+            line,
+            code,
+            information,
+        }
+    }
+
+    /// The response code in brackets at the start of the text, if any, such as `ALERT` or
+    /// `MODIFIED 7,9`.
+    pub fn code(&self) -> Option<&str> {
+        if self.code.0 == self.code.1 {
+            None
+        } else {
+            Some(&self.line[self.code.0..self.code.1])
+        }
+    }
+
+    /// The human-readable explanation that follows the response code.
+    pub fn information(&self) -> &str {
+        &self.line[self.information..]
+    }
+
+    /// The whole response as the server sent it (without the CRLF), e.g. `a3 NO [ALERT] Over
+    /// quota`.
+    pub fn line(&self) -> &str {
+        &self.line
+    }
+
+    /// The response code, if it is one of those known to `ResponseCode`.
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        self.code()
+            .and_then(|c| c.split(' ').next())
+            .and_then(ResponseCode::from_atom)
+    }
+
+    /// Whether the response code is the given one (e.g. `TRYCREATE`), ignoring case and any
+    /// arguments of the code.
+    pub fn has_code(&self, code: &str) -> bool {
+        self.code()
+            .and_then(|c| c.split(' ').next())
+            .is_some_and(|c| c.eq_ignore_ascii_case(code))
+    }
+
+    /// For a `[BADCHARSET (...)]` response code, the charsets that the server supports for
+    /// `SEARCH` (which may be empty, as the list is optional). `None` for any other response.
+    pub fn bad_charsets(&self) -> Option<Vec<String>> {
+        if !self.has_code("BADCHARSET") {
+            return None;
+        }
+        let code = self.code().unwrap_or("");
+        let list = code
+            .find('(')
+            .map(|start| code[start + 1..].trim_end_matches(')'))
+            .unwrap_or("");
+        Some(
+            list.split_whitespace()
+                .map(|c| c.trim_matches('"').to_string())
+                .collect(),
+        )
+    }
+
+    /// For an `[APPENDUID ...]` response code, the UIDVALIDITY of the mailbox and the UIDs of the
+    /// `count` messages that were appended to it (see `Session::append_multiple`). `None` for any
+    /// other response, or if the code does not list exactly `count` UIDs.
+    pub fn append_uid(&self, count: usize) -> Option<AppendUid> {
+        if !self.has_code("APPENDUID") {
+            return None;
+        }
+        let code = self.code().unwrap_or("");
+        AppendUid::parse(code.split_once(' ').map_or("", |(_, arguments)| arguments), count)
+    }
+
+    /// Whether this is an `[ALERT]`, whose text RFC 3501 requires to be shown to the user.
+    pub fn is_alert(&self) -> bool {
+        self.response_code() == Some(ResponseCode::Alert)
+    }
+}
+
+impl fmt::Display for ResponseText {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.code() {
+            Some(code) => write!(f, "[{}] {}", code, self.information()),
+            None => f.write_str(self.information()),
+        }
+    }
+}
+
+/// The response codes from [RFC 3501](https://tools.ietf.org/html/rfc3501) and [RFC
+/// 5530](https://tools.ietf.org/html/rfc5530) that tell more about the outcome of a command.
+///
+/// Codes whose arguments are data (such as `UIDNEXT`) and codes from other extensions are
+/// available as text through `ResponseText::code`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum ResponseCode {
+    /// The text must be shown to the user.
+    Alert,
+    /// The server failed to parse the headers of a message.
+    Parse,
+    /// The mailbox was selected read-only.
+    ReadOnly,
+    /// The mailbox was selected read-write.
+    ReadWrite,
+    /// The target mailbox of a `COPY` or `APPEND` does not exist, but may be created.
+    TryCreate,
+    /// The charset of a `SEARCH` is not supported. `ResponseText::bad_charsets` gives the
+    /// supported ones, if the server listed them.
+    BadCharset,
+    /// A temporary failure of a subsystem the server needs; the command may succeed later.
+    Unavailable,
+    /// Authentication failed, e.g. because of a wrong password.
+    AuthenticationFailed,
+    /// Authentication succeeded, but the user may not act as the requested authorization
+    /// identity.
+    AuthorizationFailed,
+    /// The credentials are valid, but have expired and must be renewed.
+    Expired,
+    /// The operation is not permitted over an unprotected connection; use `STARTTLS` first.
+    PrivacyRequired,
+    /// The user should contact the system administrator.
+    ContactAdmin,
+    /// The access control list does not permit the operation.
+    NoPerm,
+    /// The operation could not be done because the mailbox is in use.
+    InUse,
+    /// Some of the requested messages have been expunged.
+    ExpungeIssued,
+    /// The server discovered corrupted data.
+    Corruption,
+    /// The server encountered a bug in itself.
+    ServerBug,
+    /// The server detected a bug in the client.
+    ClientBug,
+    /// The operation violates an invariant of the server and can never succeed.
+    CanNot,
+    /// The operation ran into a server-imposed limit.
+    Limit,
+    /// The user's quota would be exceeded.
+    OverQuota,
+    /// The target of the operation (e.g. a mailbox) already exists.
+    AlreadyExists,
+    /// The target of the operation does not exist.
+    NonExistent,
+}
+
+impl ResponseCode {
+    /// Whether the condition is temporary, so that the command may succeed if it is sent again
+    /// later (`UNAVAILABLE` and `INUSE`).
+    pub fn is_transient(self) -> bool {
+        matches!(self, ResponseCode::Unavailable | ResponseCode::InUse)
+    }
+
+    /// The response code with the given name (e.g. `AUTHENTICATIONFAILED`), if it is known.
+    pub fn from_atom(atom: &str) -> Option<ResponseCode> {
+        use self::ResponseCode::*;
+        Some(match &*atom.to_uppercase() {
+            "ALERT" => Alert,
+            "PARSE" => Parse,
+            "READ-ONLY" => ReadOnly,
+            "READ-WRITE" => ReadWrite,
+            "TRYCREATE" => TryCreate,
+            "BADCHARSET" => BadCharset,
+            "UNAVAILABLE" => Unavailable,
+            "AUTHENTICATIONFAILED" => AuthenticationFailed,
+            "AUTHORIZATIONFAILED" => AuthorizationFailed,
+            "EXPIRED" => Expired,
+            "PRIVACYREQUIRED" => PrivacyRequired,
+            "CONTACTADMIN" => ContactAdmin,
+            "NOPERM" => NoPerm,
+            "INUSE" => InUse,
+            "EXPUNGEISSUED" => ExpungeIssued,
+            "CORRUPTION" => Corruption,
+            "SERVERBUG" => ServerBug,
+            "CLIENTBUG" => ClientBug,
+            "CANNOT" => CanNot,
+            "LIMIT" => Limit,
+            "OVERQUOTA" => OverQuota,
+            "ALREADYEXISTS" => AlreadyExists,
+            "NONEXISTENT" => NonExistent,
+            _ => return None,
+        })
+    }
+
+    /// The name of this response code as it appears on the wire.
+    pub fn as_atom(&self) -> &'static str {
+        use self::ResponseCode::*;
+        match *self {
+            Alert => "ALERT",
+            Parse => "PARSE",
+            ReadOnly => "READ-ONLY",
+            ReadWrite => "READ-WRITE",
+            TryCreate => "TRYCREATE",
+            BadCharset => "BADCHARSET",
+            Unavailable => "UNAVAILABLE",
+            AuthenticationFailed => "AUTHENTICATIONFAILED",
+            AuthorizationFailed => "AUTHORIZATIONFAILED",
+            Expired => "EXPIRED",
+            PrivacyRequired => "PRIVACYREQUIRED",
+            ContactAdmin => "CONTACTADMIN",
+            NoPerm => "NOPERM",
+            InUse => "INUSE",
+            ExpungeIssued => "EXPUNGEISSUED",
+            Corruption => "CORRUPTION",
+            ServerBug => "SERVERBUG",
+            ClientBug => "CLIENTBUG",
+            CanNot => "CANNOT",
+            Limit => "LIMIT",
+            OverQuota => "OVERQUOTA",
+            AlreadyExists => "ALREADYEXISTS",
+            NonExistent => "NONEXISTENT",
+        }
+    }
+}
+
+impl fmt::Display for ResponseCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_atom())
+    }
+}
+
 #[derive(Debug)]
 pub enum ParseError {
     // Indicates an error parsing the status response. Such as OK, NO, and BAD.
@@ -0,0 +1,591 @@
+//! Helpers for exporting the messages of a mailbox to the local filesystem.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::client::Session;
+use super::error::{Error, ParseError, Result};
+use super::types::SearchKey;
+use super::types::{Fetch, SequenceSet};
+
+/// The name of the sidecar file in which `export_maildir` records its progress.
+pub const CHECKPOINT_FILE: &str = ".imap-export";
+
+/// The number of messages that `export_maildir`, `export_to_dir` and `export_mbox` fetch at a
+/// time.
+pub const EXPORT_BATCH_SIZE: usize = 100;
+
+/// The character that separates the unique part of a message's file name from its flags in
+/// `export_maildir` (as in `42_7:2,S`). The maildir format uses `:`, which Windows does not
+/// allow in file names, so `!` is used there instead.
+#[cfg(not(windows))]
+pub const MAILDIR_INFO_SEPARATOR: char = ':';
+#[cfg(windows)]
+pub const MAILDIR_INFO_SEPARATOR: char = '!';
+
+/// The progress of an earlier export: the UIDVALIDITY of the exported mailbox, and the highest
+/// UID that has been written out.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    pub uid_validity: u32,
+    pub last_uid: u32,
+}
+
+impl Checkpoint {
+    /// Read the checkpoint stored in the maildir at `path`, if any.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Checkpoint>> {
+        let mut contents = String::new();
+        match File::open(path.as_ref().join(CHECKPOINT_FILE)) {
+            Ok(mut f) => f.read_to_string(&mut contents)?,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut fields = contents.split_whitespace().map(|f| f.parse::<u32>());
+        match (fields.next(), fields.next()) {
+            (Some(Ok(uid_validity)), Some(Ok(last_uid))) => Ok(Some(Checkpoint {
+                uid_validity,
+                last_uid,
+            })),
+            _ => Err(Error::Parse(ParseError::Invalid(contents.into_bytes()))),
+        }
+    }
+
+    /// Atomically store this checkpoint in the maildir at `path`.
+    pub fn store<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let tmp = path.as_ref().join(format!("{}.tmp", CHECKPOINT_FILE));
+        {
+            let mut f = File::create(&tmp)?;
+            writeln!(f, "{} {}", self.uid_validity, self.last_uid)?;
+            f.sync_all()?;
+        }
+        fs::rename(tmp, path.as_ref().join(CHECKPOINT_FILE))?;
+        Ok(())
+    }
+}
+
+// Maildir info flags must appear in ASCII order.
+fn maildir_flags<'a, I: IntoIterator<Item = &'a str>>(flags: I) -> String {
+    let mut info: Vec<char> = flags
+        .into_iter()
+        .filter_map(|f| match f {
+            "\\Draft" => Some('D'),
+            "\\Flagged" => Some('F'),
+            "\\Answered" => Some('R'),
+            "\\Seen" => Some('S'),
+            "\\Deleted" => Some('T'),
+            _ => None,
+        }).collect();
+    info.sort();
+    info.into_iter().collect()
+}
+
+fn message_file_prefix(uid_validity: u32) -> String {
+    format!("{}_", uid_validity)
+}
+
+impl<T: Read + Write> Session<T> {
+    /// Export every message in `mailbox` into the maildir at `path`, creating it if necessary.
+    ///
+    /// Messages are fetched with `BODY.PEEK[]`, so their `\Seen` flag is left untouched, in
+    /// batches of [`EXPORT_BATCH_SIZE`] UIDs, and are stored in `cur/` named after the mailbox's
+    /// UIDVALIDITY and the message's UID, followed by [`MAILDIR_INFO_SEPARATOR`] and the flags.
+    /// After each batch is written, the highest exported UID is recorded in a
+    /// [`CHECKPOINT_FILE`] sidecar file, so that running the export again only downloads messages
+    /// that arrived (or were not exported) since. If the mailbox's UIDVALIDITY has changed since
+    /// the checkpoint was written, the previously exported messages no longer correspond to the
+    /// server's UIDs: they are removed and the export restarts from scratch.
+    ///
+    /// The mailbox is opened with `EXAMINE`, and fails with `Error::NoUidValidity` if the server
+    /// does not report its UIDVALIDITY. Returns the number of messages exported by this call.
+    pub fn export_maildir<P: AsRef<Path>>(&mut self, mailbox: &str, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        for dir in &["cur", "new", "tmp"] {
+            fs::create_dir_all(path.join(dir))?;
+        }
+
+        let mbox = self.examine(mailbox)?;
+        let uid_validity = mbox.uid_validity.ok_or(Error::NoUidValidity)?;
+
+        let mut checkpoint = match Checkpoint::load(path)? {
+            Some(ref c) if c.uid_validity == uid_validity => *c,
+            Some(c) => {
+                remove_exported(path, c.uid_validity)?;
+                Checkpoint {
+                    uid_validity,
+                    last_uid: 0,
+                }
+            }
+            None => Checkpoint {
+                uid_validity,
+                last_uid: 0,
+            },
+        };
+
+        if mbox.exists == 0 {
+            checkpoint.store(path)?;
+            return Ok(0);
+        }
+
+        let start = checkpoint.last_uid + 1;
+        let new: SequenceSet = format!("{}:*", start).parse()?;
+        // `n:*` always includes the last message, even if its UID is below n.
+        let uids: Vec<u32> = self
+            .uid_search(SearchKey::Uid(new))?
+            .to_vec()
+            .into_iter()
+            .filter(|&uid| uid >= start)
+            .collect();
+
+        let mut exported = 0;
+        for batch in SequenceSet::from(&uids[..]).split(EXPORT_BATCH_SIZE) {
+            let fetches = self.uid_fetch(&batch.to_string(), "(UID FLAGS BODY.PEEK[])")?;
+            let mut fetches: Vec<_> = fetches
+                .iter()
+                .filter_map(|f| f.uid.map(|uid| (uid, f)))
+                .filter(|&(uid, _)| batch.contains(uid))
+                .collect();
+            fetches.sort_by_key(|&(uid, _)| uid);
+
+            for (uid, fetch) in fetches {
+                let body = match fetch.body() {
+                    Some(body) => body,
+                    None => continue,
+                };
+
+                let name = format!("{}{}", message_file_prefix(uid_validity), uid);
+                let tmp = path.join("tmp").join(&name);
+                {
+                    let mut f = File::create(&tmp)?;
+                    f.write_all(body)?;
+                    f.sync_all()?;
+                }
+                let flags = maildir_flags(fetch.flags().iter().map(|f| &**f));
+                let file = format!("{}{}2,{}", name, MAILDIR_INFO_SEPARATOR, flags);
+                fs::rename(&tmp, path.join("cur").join(file))?;
+                exported += 1;
+            }
+
+            if let Some((_, Some(last))) = batch.ranges().last() {
+                checkpoint.last_uid = checkpoint.last_uid.max(*last);
+            }
+            checkpoint.store(path)?;
+        }
+
+        checkpoint.store(path)?;
+        Ok(exported)
+    }
+
+    /// Export the messages in `sequence_set` of the selected mailbox as `.eml` files into the
+    /// directory at `path`, creating it if necessary. Each file is named after the message's UID
+    /// (e.g. `4827.eml`), and an existing file of that name is overwritten.
+    ///
+    /// Messages are fetched with `BODY.PEEK[]`, so their `\Seen` flag is left untouched, in
+    /// batches of [`EXPORT_BATCH_SIZE`] so that only one batch is held in memory at a time.
+    /// Returns the number of messages written.
+    pub fn export_to_dir<P: AsRef<Path>>(&mut self, sequence_set: &str, path: P) -> Result<usize> {
+        let path = path.as_ref();
+        fs::create_dir_all(path)?;
+        self.export_batches(sequence_set, "(UID BODY.PEEK[])", |fetch, body| {
+            let uid = match fetch.uid {
+                Some(uid) => uid,
+                None => return Ok(false),
+            };
+            let mut f = File::create(path.join(format!("{}.eml", uid)))?;
+            f.write_all(body)?;
+            f.sync_all()?;
+            Ok(true)
+        })
+    }
+
+    /// Export the messages in `sequence_set` of the selected mailbox into a single mbox file at
+    /// `path`, which is created or truncated.
+    ///
+    /// Each message is preceded by a `From ` line made of the envelope's sender and the message's
+    /// `INTERNALDATE`, and followed by an empty line. Lines are written with `\n` line endings,
+    /// and lines that begin with `From ` (after any number of `>`) are escaped with one more `>`,
+    /// as in the `mboxrd` format, so that readers can undo the escaping. Messages are fetched in
+    /// batches, as with [`export_to_dir`](#method.export_to_dir). Returns the number of messages
+    /// written.
+    pub fn export_mbox<P: AsRef<Path>>(&mut self, sequence_set: &str, path: P) -> Result<usize> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let exported = self.export_batches(
+            sequence_set,
+            "(UID INTERNALDATE ENVELOPE BODY.PEEK[])",
+            |fetch, body| {
+                write_mbox_message(&mut out, fetch, body)?;
+                Ok(true)
+            },
+        )?;
+        out.into_inner()
+            .map_err(|e| Error::Io(e.into_error()))?
+            .sync_all()?;
+        Ok(exported)
+    }
+
+    // Fetch the messages of `sequence_set` in batches, and hand each message that has a body to
+    // `export`, which returns whether it exported it. `*` is resolved against the number of
+    // messages in the selected mailbox first, so that open-ended ranges are split as well.
+    fn export_batches<F>(&mut self, sequence_set: &str, query: &str, mut export: F) -> Result<usize>
+    where
+        F: FnMut(&Fetch, &[u8]) -> Result<bool>,
+    {
+        let mut set: SequenceSet = sequence_set.parse()?;
+        if let Some(mailbox) = self.selected_mailbox() {
+            if mailbox.exists == 0 {
+                return Ok(0);
+            }
+            set = set.resolve(mailbox.exists);
+        }
+        let mut seen = HashSet::new();
+        let mut exported = 0;
+        for batch in set.split(EXPORT_BATCH_SIZE) {
+            let fetches = self.fetch(&batch.to_string(), query)?;
+            for fetch in fetches.iter().filter(|f| seen.insert(f.message)) {
+                if let Some(body) = fetch.body() {
+                    if export(fetch, body)? {
+                        exported += 1;
+                    }
+                }
+            }
+        }
+        Ok(exported)
+    }
+}
+
+fn write_mbox_message<W: Write>(out: &mut W, fetch: &Fetch, body: &[u8]) -> io::Result<()> {
+    let sender = fetch
+        .envelope()
+        .and_then(|e| {
+            e.sender_addresses()
+                .iter()
+                .chain(e.from_addresses())
+                .find(|a| a.host.is_some())
+        }).map(|a| {
+            format!(
+                "{}@{}",
+                a.mailbox.as_ref().map(|s| &s[..]).unwrap_or(""),
+                a.host.as_ref().map(|s| &s[..]).unwrap_or("")
+            )
+        }).unwrap_or_else(|| "MAILER-DAEMON".to_string());
+    let date = fetch
+        .internal_date()
+        .and_then(asctime)
+        .unwrap_or_else(|| "Thu Jan  1 00:00:00 1970".to_string());
+    writeln!(out, "From {} {}", sender, date)?;
+
+    let body = body.strip_suffix(b"\r\n").unwrap_or(body);
+    for line in body.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let unquoted = &line[line.iter().take_while(|&&b| b == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            out.write_all(b">")?;
+        }
+        out.write_all(line)?;
+        out.write_all(b"\n")?;
+    }
+    out.write_all(b"\n")
+}
+
+// Convert an `INTERNALDATE` (`17-Jul-1996 02:44:25 -0700`) to the `asctime` form used on mbox
+// `From ` lines (`Wed Jul 17 02:44:25 1996`), keeping the local time of the date.
+fn asctime(date: &str) -> Option<String> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+    let mut parts = date.trim().splitn(3, '-');
+    let day: i64 = parts.next()?.trim().parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|&m| m.eq_ignore_ascii_case(month_name))? as i64 + 1;
+    let mut rest = parts.next()?.split_whitespace();
+    let year: i64 = rest.next()?.parse().ok()?;
+    let time = rest.next()?;
+
+    // days since 1970-01-01 (a Thursday) in the proleptic Gregorian calendar
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    Some(format!(
+        "{} {} {:2} {} {}",
+        DAYS[(days + 4).rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        time,
+        year
+    ))
+}
+
+// Remove the messages exported under an earlier UIDVALIDITY.
+fn remove_exported(path: &Path, uid_validity: u32) -> Result<()> {
+    let prefix = message_file_prefix(uid_validity);
+    let cur: PathBuf = path.join("cur");
+    for entry in fs::read_dir(cur)? {
+        let entry = entry?;
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::Client;
+    use super::super::mock_stream::MockStream;
+    use super::*;
+    use std::env;
+
+    fn examine_response(uid_validity: u32) -> String {
+        format!(
+            "* 3 EXISTS\r\n\
+             * OK [UIDVALIDITY {}] UIDs valid\r\n\
+             a1 OK [READ-ONLY] Examine completed.\r\n",
+            uid_validity
+        )
+    }
+
+    fn export(path: &Path, response: String) -> (usize, Vec<u8>) {
+        let mut session = Session::new(Client::new(MockStream::new(response.into_bytes())).conn);
+        let n = session.export_maildir("INBOX", path).unwrap();
+        (n, session.stream.get_ref().written_buf.clone())
+    }
+
+    fn exported(path: &Path) -> Vec<String> {
+        let mut names: Vec<_> = fs::read_dir(path.join("cur"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    // The name of an exported message with the given flags.
+    fn file(name: &str, flags: &str) -> String {
+        format!("{}{}2,{}", name, MAILDIR_INFO_SEPARATOR, flags)
+    }
+
+    #[test]
+    fn export_resume() {
+        let path = env::temp_dir().join(format!("imap-export-test-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let first = examine_response(42) + "\
+            * SEARCH 1 2\r\n\
+            a2 OK Search completed\r\n\
+            * 1 FETCH (UID 1 FLAGS (\\Seen) BODY[] {5}\r\nfirst)\r\n\
+            * 2 FETCH (UID 2 FLAGS () BODY[] {6}\r\nsecond)\r\n\
+            a3 OK Fetch completed\r\n";
+        let (n, written) = export(&path, first);
+        assert_eq!(n, 2);
+        assert!(String::from_utf8(written).unwrap().ends_with(
+            "a2 UID SEARCH UID 1:*\r\n\
+             a3 UID FETCH 1:2 (UID FLAGS BODY.PEEK[])\r\n"
+        ));
+        assert_eq!(exported(&path), vec![file("42_1", "S"), file("42_2", "")]);
+        assert_eq!(
+            Checkpoint::load(&path).unwrap(),
+            Some(Checkpoint {
+                uid_validity: 42,
+                last_uid: 2,
+            })
+        );
+
+        // resuming only downloads what is new; `3:*` also yields the last message if it is old
+        let second = examine_response(42) + "\
+            * SEARCH 2 3\r\n\
+            a2 OK Search completed\r\n\
+            * 3 FETCH (UID 3 FLAGS (\\Flagged \\Seen) BODY[] {5}\r\nthird)\r\n\
+            a3 OK Fetch completed\r\n";
+        let (n, written) = export(&path, second);
+        assert_eq!(n, 1);
+        assert!(String::from_utf8(written).unwrap().ends_with(
+            "a2 UID SEARCH UID 3:*\r\n\
+             a3 UID FETCH 3 (UID FLAGS BODY.PEEK[])\r\n"
+        ));
+        assert_eq!(
+            exported(&path),
+            vec![file("42_1", "S"), file("42_2", ""), file("42_3", "FS")]
+        );
+
+        // a new UIDVALIDITY invalidates everything exported so far
+        let third = examine_response(43) + "\
+            * SEARCH 7\r\n\
+            a2 OK Search completed\r\n\
+            * 1 FETCH (UID 7 FLAGS () BODY[] {3}\r\nnew)\r\n\
+            a3 OK Fetch completed\r\n";
+        let (n, written) = export(&path, third);
+        assert_eq!(n, 1);
+        assert!(String::from_utf8(written).unwrap().ends_with(
+            "a2 UID SEARCH UID 1:*\r\n\
+             a3 UID FETCH 7 (UID FLAGS BODY.PEEK[])\r\n"
+        ));
+        assert_eq!(exported(&path), vec![file("43_7", "")]);
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn export_batch_checkpoint() {
+        let path = env::temp_dir().join(format!("imap-export-batch-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        // the connection is lost while fetching the second batch
+        let uids: Vec<String> = (1..=EXPORT_BATCH_SIZE + 1).map(|n| n.to_string()).collect();
+        let mut response = examine_response(42);
+        response += &format!("* SEARCH {}\r\na2 OK Search completed\r\n", uids.join(" "));
+        for uid in 1..=EXPORT_BATCH_SIZE {
+            response += &format!("* {0} FETCH (UID {0} FLAGS () BODY[] {{1}}\r\nx)\r\n", uid);
+        }
+        response += "a3 OK Fetch completed\r\n";
+        let mut session = Session::new(Client::new(MockStream::new(response.into_bytes())).conn);
+        assert!(session.export_maildir("INBOX", &path).is_err());
+        assert!(String::from_utf8(session.stream.get_ref().written_buf.clone())
+            .unwrap()
+            .ends_with(&format!(
+                "a3 UID FETCH 1:{} (UID FLAGS BODY.PEEK[])\r\n\
+                 a4 UID FETCH {} (UID FLAGS BODY.PEEK[])\r\n",
+                EXPORT_BATCH_SIZE,
+                EXPORT_BATCH_SIZE + 1
+            )));
+        assert_eq!(exported(&path).len(), EXPORT_BATCH_SIZE);
+        assert_eq!(
+            Checkpoint::load(&path).unwrap(),
+            Some(Checkpoint {
+                uid_validity: 42,
+                last_uid: EXPORT_BATCH_SIZE as u32,
+            })
+        );
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn export_no_uid_validity() {
+        let path = env::temp_dir().join(format!("imap-export-uidv-{}", ::std::process::id()));
+        let response = b"* 3 EXISTS\r\na1 OK [READ-ONLY] Examine completed.\r\n".to_vec();
+        let mut session = Session::new(Client::new(MockStream::new(response)).conn);
+        match session.export_maildir("INBOX", &path) {
+            Err(Error::NoUidValidity) => {}
+            r => panic!("expected NoUidValidity, got {:?}", r),
+        }
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    fn session(response: &str) -> Session<MockStream> {
+        let response =
+            "* 4 EXISTS\r\na1 OK [READ-WRITE] Select completed\r\n".to_string() + response;
+        let mut session = Session::new(Client::new(MockStream::new(response.into_bytes())).conn);
+        session.select("INBOX").unwrap();
+        session
+    }
+
+    #[test]
+    fn export_dir() {
+        let path = env::temp_dir().join(format!("imap-export-eml-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let mut session = session(
+            "* 1 FETCH (UID 10 BODY[] {5}\r\nfirst)\r\n\
+             * 2 FETCH (UID 12 BODY[] {6}\r\nsecond)\r\n\
+             * 4 FETCH (FLAGS (\\Seen))\r\n\
+             a2 OK Fetch completed\r\n",
+        );
+        assert_eq!(session.export_to_dir("1:2", &path).unwrap(), 2);
+        assert!(String::from_utf8(session.stream.get_ref().written_buf.clone())
+            .unwrap()
+            .ends_with("a2 FETCH 1:2 (UID BODY.PEEK[])\r\n"));
+        assert_eq!(fs::read(path.join("10.eml")).unwrap(), b"first");
+        assert_eq!(fs::read(path.join("12.eml")).unwrap(), b"second");
+        assert_eq!(fs::read_dir(&path).unwrap().count(), 2);
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn export_all_in_batches() {
+        let path = env::temp_dir().join(format!("imap-export-all-{}", ::std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+
+        let exists = EXPORT_BATCH_SIZE + 20;
+        let response = format!(
+            "* {} EXISTS\r\na1 OK [READ-WRITE] Select completed\r\n\
+             * 1 FETCH (UID 10 BODY[] {{5}}\r\nfirst)\r\n\
+             a2 OK Fetch completed\r\n\
+             * {} FETCH (UID 99 BODY[] {{4}}\r\nlast)\r\n\
+             a3 OK Fetch completed\r\n",
+            exists, exists
+        );
+        let mut session = Session::new(Client::new(MockStream::new(response.into_bytes())).conn);
+        session.select("INBOX").unwrap();
+        assert_eq!(session.export_to_dir("1:*", &path).unwrap(), 2);
+        assert!(String::from_utf8(session.stream.get_ref().written_buf.clone())
+            .unwrap()
+            .ends_with(&format!(
+                "a2 FETCH 1:{} (UID BODY.PEEK[])\r\n\
+                 a3 FETCH {}:{} (UID BODY.PEEK[])\r\n",
+                EXPORT_BATCH_SIZE,
+                EXPORT_BATCH_SIZE + 1,
+                exists
+            )));
+        assert_eq!(fs::read(path.join("99.eml")).unwrap(), b"last");
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn export_mbox() {
+        let path = env::temp_dir().join(format!("imap-export-mbox-{}", ::std::process::id()));
+        let message = "Subject: hi\r\n\r\nFrom here\r\n>From there\r\nFrom: no\r\n";
+        let response = format!(
+            "* 1 FETCH (UID 1 INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+             ENVELOPE (NIL NIL ((\"Terry Gray\" NIL \"gray\" \"cac.washington.edu\")) \
+             NIL NIL NIL NIL NIL NIL NIL) BODY[] {{{}}}\r\n{})\r\n\
+             * 2 FETCH (UID 2 INTERNALDATE \" 1-Jan-2000 00:00:00 +0000\" \
+             ENVELOPE (NIL NIL NIL NIL NIL NIL NIL NIL NIL NIL) BODY[] {{1}}\r\nx)\r\n\
+             a2 OK Fetch completed\r\n",
+            message.len(),
+            message
+        );
+        let mut session = session(&response);
+        assert_eq!(session.export_mbox("1:*", &path).unwrap(), 2);
+        assert!(String::from_utf8(session.stream.get_ref().written_buf.clone())
+            .unwrap()
+            .ends_with("a2 FETCH 1:4 (UID INTERNALDATE ENVELOPE BODY.PEEK[])\r\n"));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "From gray@cac.washington.edu Wed Jul 17 02:44:25 1996\n\
+             Subject: hi\n\
+             \n\
+             >From here\n\
+             >>From there\n\
+             From: no\n\
+             \n\
+             From MAILER-DAEMON Sat Jan  1 00:00:00 2000\n\
+             x\n\
+             \n"
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mbox_date() {
+        assert_eq!(
+            asctime("17-Jul-1996 02:44:25 -0700").unwrap(),
+            "Wed Jul 17 02:44:25 1996"
+        );
+        assert_eq!(
+            asctime(" 29-Feb-2024 23:59:59 +0100").unwrap(),
+            "Thu Feb 29 23:59:59 2024"
+        );
+        assert_eq!(asctime("garbage"), None);
+    }
+}
@@ -56,18 +56,31 @@
 //! }
 //! ```
 
+extern crate base64;
 extern crate bufstream;
-extern crate imap_proto;
+extern crate flate2;
+extern crate getrandom;
+pub extern crate imap_proto;
+#[macro_use]
+extern crate log;
 extern crate native_tls;
 extern crate nom;
-extern crate regex;
 
+mod buf_stream;
+mod digest;
 mod parse;
+mod socks5;
 mod types;
 
 pub mod authenticator;
 pub mod client;
+pub mod compress;
+pub mod decode;
 pub mod error;
+pub mod export;
+pub mod retry;
+pub mod tls;
+pub mod utf7;
 
 pub use types::*;
 
@@ -1,7 +1,8 @@
 use std::cmp::min;
 use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::time::Duration;
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
 pub struct MockStream {
     read_buf: Vec<u8>,
     read_pos: usize,
@@ -9,19 +10,9 @@ pub struct MockStream {
     err_on_read: bool,
     eof_on_read: bool,
     read_delay: usize,
-}
-
-impl Default for MockStream {
-    fn default() -> Self {
-        MockStream {
-            read_buf: Vec::new(),
-            read_pos: 0,
-            written_buf: Vec::new(),
-            err_on_read: false,
-            eof_on_read: false,
-            read_delay: 0,
-        }
-    }
+    timeout_on_eof: bool,
+    chunk_size: Option<usize>,
+    pub read_timeout: Option<Duration>,
 }
 
 impl MockStream {
@@ -48,6 +39,18 @@ impl MockStream {
         self.read_delay = 1;
         self
     }
+
+    // Return at most `size` bytes from every read, as if the data arrived in small packets.
+    pub fn with_chunks(mut self, size: usize) -> MockStream {
+        self.chunk_size = Some(size);
+        self
+    }
+
+    // Time out instead of failing with `UnexpectedEof` once all the data has been read.
+    pub fn with_timeout(mut self) -> MockStream {
+        self.timeout_on_eof = true;
+        self
+    }
 }
 
 impl Read for MockStream {
@@ -56,12 +59,18 @@ impl Read for MockStream {
             return Ok(0);
         }
         if self.err_on_read {
-            return Err(Error::new(ErrorKind::Other, "MockStream Error"));
+            return Err(Error::other("MockStream Error"));
+        }
+        if self.read_pos >= self.read_buf.len() && self.timeout_on_eof {
+            return Err(Error::new(ErrorKind::WouldBlock, "timed out"));
         }
         if self.read_pos >= self.read_buf.len() {
             return Err(Error::new(ErrorKind::UnexpectedEof, "EOF"));
         }
         let mut write_len = min(buf.len(), self.read_buf.len() - self.read_pos);
+        if let Some(size) = self.chunk_size {
+            write_len = min(write_len, size);
+        }
         if self.read_delay > 0 {
             self.read_delay -= 1;
             write_len = min(write_len, 1);
@@ -85,3 +94,14 @@ impl Write for MockStream {
         Ok(())
     }
 }
+
+impl ::client::SetReadTimeout for MockStream {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> ::error::Result<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+
+    fn read_timeout(&self) -> ::error::Result<Option<Duration>> {
+        Ok(self.read_timeout)
+    }
+}
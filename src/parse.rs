@@ -1,20 +1,54 @@
+use base64;
 use imap_proto::{self, MailboxDatum, Response};
 use nom::IResult;
-use regex::Regex;
-use std::collections::HashSet;
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
 
-use super::error::{Error, ParseError, Result};
+use super::error::{Error, ParseError, ResponseText, Result};
 use super::types::*;
+use super::utf7;
 
-pub fn parse_authenticate_response(line: String) -> Result<String> {
-    let authenticate_regex = Regex::new("^+(.*)\r\n").unwrap();
+/// A continuation request sent during `AUTHENTICATE`.
+#[derive(Debug, Eq, PartialEq)]
+pub enum AuthContinuation {
+    /// A challenge for the mechanism, base64-decoded.
+    Challenge(Vec<u8>),
+    /// Text that is not base64, such as a human-readable prompt or error message.
+    Text(String),
+}
 
-    if let Some(cap) = authenticate_regex.captures_iter(line.as_str()).next() {
-        let data = cap.get(1).map(|x| x.as_str()).unwrap_or("");
-        return Ok(String::from(data));
+/// Parse a continuation request sent during `AUTHENTICATE` (e.g. `+ VXNlcm5hbWU6`), and decode
+/// the base64 challenge it carries. An empty challenge (`+` or `+ `) decodes to no bytes. The
+/// trailing CRLF is optional, and whitespace within the challenge is ignored, so that challenges
+/// folded over several lines decode as well.
+///
+/// Some servers send plain text after the `+` instead (e.g. `+ Ready for literal data`). Anything
+/// that is not valid (padded) base64 is returned as such, rather than decoded into garbage.
+pub fn parse_authenticate_response(line: &[u8]) -> Result<AuthContinuation> {
+    let text = match line.split_first() {
+        Some((&b'+', text)) => text,
+        _ => {
+            let line = String::from_utf8_lossy(line).into_owned();
+            return Err(Error::Parse(ParseError::Authentication(line)));
+        }
+    };
+    let challenge: Vec<u8> = text
+        .iter()
+        .cloned()
+        .filter(|c| !c.is_ascii_whitespace())
+        .collect();
+    // base64 in IMAP is always padded, so that text such as `Go ahead` is not mistaken for it
+    let decoded = if challenge.len().is_multiple_of(4) {
+        base64::decode(&challenge).ok()
+    } else {
+        None
+    };
+    match decoded {
+        Some(challenge) => Ok(AuthContinuation::Challenge(challenge)),
+        None => Ok(AuthContinuation::Text(
+            String::from_utf8_lossy(text).trim().to_string(),
+        )),
     }
-
-    Err(Error::Parse(ParseError::Authentication(line)))
 }
 
 enum MapOrNot<T> {
@@ -24,9 +58,88 @@ enum MapOrNot<T> {
     Ignore,
 }
 
-unsafe fn parse_many<T, F>(lines: Vec<u8>, mut map: F) -> ZeroCopyResult<Vec<T>>
+// If this is simply a unilateral server response (see Section 7 of RFC 3501), which may be
+// interleaved with the responses to any command, queue it in `unsolicited`. Otherwise, give it
+// back.
+fn handle_unilateral<'a>(
+    resp: Response<'a>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> Option<Response<'a>> {
+    match resp {
+        Response::MailboxData(MailboxDatum::Recent(n)) => {
+            unsolicited.push_back(UnsolicitedResponse::Recent(n));
+        }
+        Response::MailboxData(MailboxDatum::Exists(n)) => {
+            unsolicited.push_back(UnsolicitedResponse::Exists(n));
+        }
+        Response::MailboxData(MailboxDatum::Flags(flags)) => {
+            unsolicited.push_back(UnsolicitedResponse::Flags(
+                flags.into_iter().map(|s| s.to_string()).collect(),
+            ));
+        }
+        Response::MailboxData(MailboxDatum::Status { mailbox, status }) => {
+            unsolicited.push_back(UnsolicitedResponse::Status(mailbox_status(mailbox, status)));
+        }
+        Response::Expunge(n) => {
+            unsolicited.push_back(UnsolicitedResponse::Expunge(n));
+        }
+        Response::Fetch(num, attrs) => {
+            // the queued response outlives the buffer `resp` borrows from, so it owns its data
+            unsolicited.push_back(UnsolicitedResponse::Fetch(build_fetch(
+                num,
+                attrs,
+                |s| Cow::Owned(s.to_string()),
+                |b| Cow::Owned(b.to_vec()),
+            )));
+        }
+        resp => return Some(resp),
+    }
+    None
+}
+
+fn mailbox_status(mailbox: &str, status: Vec<imap_proto::StatusAttribute>) -> MailboxStatus {
+    let mut mailbox = MailboxStatus {
+        mailbox: mailbox.to_string(),
+        ..MailboxStatus::default()
+    };
+    for attr in status {
+        use imap_proto::StatusAttribute;
+        match attr {
+            StatusAttribute::Messages(n) => mailbox.messages = Some(n),
+            StatusAttribute::Recent(n) => mailbox.recent = Some(n),
+            StatusAttribute::UidNext(n) => mailbox.uid_next = Some(n),
+            StatusAttribute::UidValidity(n) => mailbox.uid_validity = Some(n),
+            StatusAttribute::Unseen(n) => mailbox.unseen = Some(n),
+        }
+    }
+    mailbox
+}
+
+// Like `handle_unilateral`, for the unilateral responses that imap-proto cannot parse. Returns the
+// remaining input if `lines` starts with one, which is then queued in `unsolicited`.
+fn handle_unilateral_extended<'a>(
+    lines: &'a [u8],
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> Option<&'a [u8]> {
+    let (status, rest) = parse_status_extended(lines)?;
+    unsolicited.push_back(UnsolicitedResponse::Status(status));
+    Some(rest)
+}
+
+// Parse the responses in `lines` with `map`, queueing any unilateral responses in `unsolicited`.
+// Responses that imap-proto cannot parse are first handed to `fallback`, which may parse them into
+// a `T` (returning the remaining input) before we give up. If `lenient` is set, we skip such
+// responses instead of giving up.
+unsafe fn parse_many_with_fallback<T, F, G>(
+    lines: Vec<u8>,
+    mut map: F,
+    mut fallback: G,
+    lenient: bool,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<T>>
 where
     F: FnMut(Response<'static>) -> MapOrNot<T>,
+    G: FnMut(&'static [u8]) -> Option<(T, &'static [u8])>,
 {
     let f = |mut lines: &'static [u8]| {
         let mut things = Vec::new();
@@ -42,23 +155,28 @@ where
                     match map(resp) {
                         MapOrNot::Map(t) => things.push(t),
                         MapOrNot::Not(resp) => {
-                            // check if this is simply a unilateral server response
-                            // (see Section 7 of RFC 3501):
-                            match resp {
-                                Response::MailboxData(MailboxDatum::Recent { .. })
-                                | Response::MailboxData(MailboxDatum::Exists { .. })
-                                | Response::Fetch(..)
-                                | Response::Expunge(..) => {
-                                    continue;
-                                }
-                                resp => break Err(resp.into()),
+                            if let Some(resp) = handle_unilateral(resp, unsolicited) {
+                                break Err(resp.into());
                             }
                         }
                         MapOrNot::Ignore => continue,
                     }
                 }
                 _ => {
-                    break Err(Error::Parse(ParseError::Invalid(lines.to_vec())));
+                    if let Some((t, rest)) = fallback(lines) {
+                        lines = rest;
+                        things.push(t);
+                    } else if let Some(rest) = handle_unilateral_extended(lines, unsolicited) {
+                        lines = rest;
+                    } else if let Some(rest) = skip_response(lines).filter(|_| lenient) {
+                        warn!(
+                            "skipping unparseable response: {:?}",
+                            String::from_utf8_lossy(&lines[..lines.len() - rest.len()])
+                        );
+                        lines = rest;
+                    } else {
+                        break Err(Error::Parse(ParseError::Invalid(lines.to_vec())));
+                    }
                 }
             }
         }
@@ -67,7 +185,270 @@ where
     ZeroCopy::new(lines, f)
 }
 
-pub fn parse_names(lines: Vec<u8>) -> ZeroCopyResult<Vec<Name>> {
+// Skip an untagged response, including any literals in it, returning the remaining input.
+fn skip_response(mut i: &[u8]) -> Option<&[u8]> {
+    if !i.starts_with(b"* ") {
+        return None;
+    }
+    loop {
+        let end = i.windows(2).position(|w| w == b"\r\n")?;
+        let line = &i[..end];
+        i = &i[end + 2..];
+
+        // a line ending in `{n}` (or `{n+}`) is followed by n bytes of literal data
+        let literal = line
+            .strip_suffix(b"}")
+            .and_then(|l| l.iter().rposition(|&b| b == b'{').map(|p| &l[p + 1..]))
+            .map(|n| n.strip_suffix(b"+").unwrap_or(n))
+            .and_then(|n| ::std::str::from_utf8(n).ok())
+            .and_then(|n| n.parse::<usize>().ok());
+        match literal {
+            Some(len) if len <= i.len() => i = &i[len..],
+            Some(_) => return None,
+            None => return Some(i),
+        }
+    }
+}
+
+// A few primitives for the responses (or parts thereof) that imap-proto 0.4 does not handle.
+
+// A string (quoted, literal, or atom), or a parenthesized list of values.
+#[derive(Debug, Eq, PartialEq)]
+enum Value {
+    Str(Cow<'static, str>),
+    List(Vec<Value>),
+}
+
+impl Value {
+    fn as_str(&self) -> Option<&str> {
+        match *self {
+            Value::Str(ref s) => Some(s),
+            Value::List(_) => None,
+        }
+    }
+}
+
+fn take_prefix<'a>(i: &'a [u8], prefix: &str) -> Option<&'a [u8]> {
+    if i.len() >= prefix.len() && i[..prefix.len()].eq_ignore_ascii_case(prefix.as_bytes()) {
+        Some(&i[prefix.len()..])
+    } else {
+        None
+    }
+}
+
+fn parse_quoted<'a>(i: &'a [u8]) -> Option<(Cow<'a, str>, &'a [u8])> {
+    let i = take_prefix(i, "\"")?;
+    let mut escaped = false;
+    let mut has_escapes = false;
+    for (n, &c) in i.iter().enumerate() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            b'\\' => {
+                escaped = true;
+                has_escapes = true;
+            }
+            b'"' => {
+                let raw = ::std::str::from_utf8(&i[..n]).ok()?;
+                let s = if has_escapes {
+                    let mut unescaped = String::with_capacity(raw.len());
+                    let mut chars = raw.chars();
+                    while let Some(c) = chars.next() {
+                        unescaped.push(if c == '\\' { chars.next()? } else { c });
+                    }
+                    Cow::Owned(unescaped)
+                } else {
+                    Cow::Borrowed(raw)
+                };
+                return Some((s, &i[n + 1..]));
+            }
+            b'\r' | b'\n' => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+fn parse_literal<'a>(i: &'a [u8]) -> Option<(Cow<'a, str>, &'a [u8])> {
+    let i = take_prefix(i, "{")?;
+    let end = i.iter().position(|&c| c == b'}')?;
+    let len: usize = ::std::str::from_utf8(&i[..end]).ok()?.parse().ok()?;
+    let i = take_prefix(&i[end + 1..], "\r\n")?;
+    if i.len() < len {
+        return None;
+    }
+    let s = ::std::str::from_utf8(&i[..len]).ok()?;
+    Some((Cow::Borrowed(s), &i[len..]))
+}
+
+fn parse_atom<'a>(i: &'a [u8]) -> Option<(Cow<'a, str>, &'a [u8])> {
+    let end = i
+        .iter()
+        .position(|&c| c == b' ' || c == b'(' || c == b')' || c == b'\r' || c == b'\n')
+        .unwrap_or(i.len());
+    if end == 0 {
+        return None;
+    }
+    let s = ::std::str::from_utf8(&i[..end]).ok()?;
+    Some((Cow::Borrowed(s), &i[end..]))
+}
+
+fn parse_astring<'a>(i: &'a [u8]) -> Option<(Cow<'a, str>, &'a [u8])> {
+    match i.first() {
+        Some(&b'"') => parse_quoted(i),
+        Some(&b'{') => parse_literal(i),
+        _ => parse_atom(i),
+    }
+}
+
+// A string, or NIL.
+fn parse_nstring(i: &'static [u8]) -> Option<(Option<Cow<'static, str>>, &'static [u8])> {
+    match parse_atom(i) {
+        Some((ref s, rest)) if s.eq_ignore_ascii_case("NIL") => Some((None, rest)),
+        _ => parse_astring(i).map(|(s, rest)| (Some(s), rest)),
+    }
+}
+
+fn parse_value(i: &'static [u8]) -> Option<(Value, &'static [u8])> {
+    match take_prefix(i, "(") {
+        Some(mut i) => {
+            let mut values = Vec::new();
+            loop {
+                if let Some(rest) = take_prefix(i, ")") {
+                    break Some((Value::List(values), rest));
+                }
+                if !values.is_empty() {
+                    i = take_prefix(i, " ")?;
+                }
+                let (value, rest) = parse_value(i)?;
+                values.push(value);
+                i = rest;
+            }
+        }
+        None => parse_astring(i).map(|(s, rest)| (Value::Str(s), rest)),
+    }
+}
+
+// Parse a `* LIST` or `* LSUB` response, including the extended data items of RFC 5258 (such as
+// `CHILDINFO`) and a `NIL` hierarchy delimiter, neither of which imap-proto supports. If `lenient`
+// is set, the space before the flags may be missing, as some servers forget it.
+fn parse_name_extended(i: &'static [u8], lenient: bool) -> Option<(Name, &'static [u8])> {
+    let i = take_prefix(i, "* LIST").or_else(|| take_prefix(i, "* LSUB"))?;
+    let i = match take_prefix(i, " ") {
+        Some(i) => i,
+        None if lenient => i,
+        None => return None,
+    };
+    let (flags, i) = parse_value(i)?;
+    let attributes = match flags {
+        Value::List(flags) => flags
+            .into_iter()
+            .map(|f| match f {
+                Value::Str(s) => Some(s),
+                Value::List(_) => None,
+            }).collect::<Option<Vec<_>>>()?,
+        Value::Str(_) => return None,
+    };
+
+    let i = take_prefix(i, " ")?;
+    let (delimiter, i) = match take_prefix(i, "NIL") {
+        Some(i) => (Cow::Borrowed(""), i),
+        None => parse_quoted(i)?,
+    };
+
+    let i = take_prefix(i, " ")?;
+    let (name, mut i) = parse_astring(i)?;
+
+    let mut child_info = Vec::new();
+    if let Some(rest) = take_prefix(i, " ") {
+        let (extended, rest) = parse_value(rest)?;
+        i = rest;
+        if let Value::List(items) = extended {
+            // tagged extension items come in (tag, value) pairs
+            let mut items = items.into_iter();
+            while let (Some(tag), Some(value)) = (items.next(), items.next()) {
+                match (tag.as_str(), value) {
+                    (Some(tag), Value::List(values)) if tag.eq_ignore_ascii_case("CHILDINFO") => {
+                        child_info.extend(values.into_iter().filter_map(|v| match v {
+                            Value::Str(s) => Some(s),
+                            Value::List(_) => None,
+                        }));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let i = take_prefix(i, "\r\n")?;
+    Some((
+        Name {
+            attributes,
+            delimiter,
+            decoded: decode_name(&name),
+            name,
+            child_info,
+        },
+        i,
+    ))
+}
+
+// Parse a `* STATUS` response, including the `HIGHESTMODSEQ` item of CONDSTORE (RFC 7162), which
+// imap-proto does not support. Items that `MailboxStatus` has no room for are skipped.
+fn parse_status_extended(i: &[u8]) -> Option<(MailboxStatus, &[u8])> {
+    let i = take_prefix(i, "* STATUS ")?;
+    let (mailbox, i) = parse_astring(i)?;
+    let mut i = take_prefix(i, " (")?;
+    let mut status = MailboxStatus {
+        mailbox: mailbox.to_string(),
+        ..MailboxStatus::default()
+    };
+    let mut first = true;
+    let i = loop {
+        if let Some(rest) = take_prefix(i, ")") {
+            break rest;
+        }
+        if !first {
+            i = take_prefix(i, " ")?;
+        }
+        first = false;
+        let (item, rest) = parse_atom(i)?;
+        let (value, rest) = parse_atom(take_prefix(rest, " ")?)?;
+        i = rest;
+        match &*item.to_uppercase() {
+            "MESSAGES" => status.messages = Some(value.parse().ok()?),
+            "RECENT" => status.recent = Some(value.parse().ok()?),
+            "UIDNEXT" => status.uid_next = Some(value.parse().ok()?),
+            "UIDVALIDITY" => status.uid_validity = Some(value.parse().ok()?),
+            "UNSEEN" => status.unseen = Some(value.parse().ok()?),
+            "HIGHESTMODSEQ" => status.highest_mod_seq = Some(value.parse().ok()?),
+            _ => {}
+        }
+    };
+    let i = take_prefix(i, "\r\n")?;
+    Some((status, i))
+}
+
+// Decode a mailbox name from modified UTF-7, if that changes anything.
+fn decode_name(name: &str) -> Option<String> {
+    if name.contains('&') {
+        utf7::decode(name).ok()
+    } else {
+        None
+    }
+}
+
+/// Parse `LIST` and `LSUB` responses. Unless `decode_utf7` is false (because the server sends
+/// names in UTF-8), mailbox names are decoded from modified UTF-7. If `lenient` is set, slightly
+/// malformed responses are accepted where possible, and those that cannot be parsed are skipped.
+pub fn parse_names(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+    decode_utf7: bool,
+    lenient: bool,
+) -> ZeroCopyResult<Vec<Name>> {
     use imap_proto::MailboxDatum;
     let f = |resp| match resp {
         // https://github.com/djc/imap-proto/issues/4
@@ -81,50 +462,526 @@ pub fn parse_names(lines: Vec<u8>) -> ZeroCopyResult<Vec<Name>> {
             delimiter,
             name,
         }) => MapOrNot::Map(Name {
-            attributes: flags,
-            delimiter,
-            name,
+            attributes: flags.into_iter().map(Cow::Borrowed).collect(),
+            delimiter: Cow::Borrowed(delimiter),
+            name: Cow::Borrowed(name),
+            decoded: if decode_utf7 { decode_name(name) } else { None },
+            child_info: Vec::new(),
         }),
         resp => MapOrNot::Not(resp),
     };
+    let fallback = |i| {
+        parse_name_extended(i, lenient).map(|(mut name, rest)| {
+            if !decode_utf7 {
+                name.decoded = None;
+            }
+            (name, rest)
+        })
+    };
 
-    unsafe { parse_many(lines, f) }
+    unsafe { parse_many_with_fallback(lines, f, fallback, lenient, unsolicited) }
 }
 
-pub fn parse_fetches(lines: Vec<u8>) -> ZeroCopyResult<Vec<Fetch>> {
-    let f = |resp| match resp {
-        Response::Fetch(num, attrs) => {
-            let mut fetch = Fetch {
-                message: num,
-                flags: vec![],
-                uid: None,
-                rfc822_header: None,
-                rfc822: None,
-                body: None,
-            };
-
-            for attr in attrs {
-                use imap_proto::AttributeValue;
-                match attr {
-                    AttributeValue::Flags(flags) => {
-                        fetch.flags.extend(flags);
+// Parse an `* ANNOTATION mailbox entry (attribute value ...)` response, as sent in reply to
+// `GETANNOTATION`.
+fn parse_annotation(i: &'static [u8]) -> Option<(Annotation, &'static [u8])> {
+    let i = take_prefix(i, "* ANNOTATION ")?;
+    let (mailbox, i) = parse_astring(i)?;
+    let i = take_prefix(i, " ")?;
+    let (entry, i) = parse_astring(i)?;
+    let mut i = take_prefix(i, " (")?;
+
+    let mut values = Vec::new();
+    loop {
+        if let Some(rest) = take_prefix(i, ")") {
+            i = rest;
+            break;
+        }
+        if !values.is_empty() {
+            i = take_prefix(i, " ")?;
+        }
+        let (attribute, rest) = parse_astring(i)?;
+        let rest = take_prefix(rest, " ")?;
+        let (value, rest) = parse_nstring(rest)?;
+        values.push((attribute, value));
+        i = rest;
+    }
+
+    let i = take_prefix(i, "\r\n")?;
+    Some((
+        Annotation {
+            mailbox,
+            entry,
+            values,
+        },
+        i,
+    ))
+}
+
+pub fn parse_annotations(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<Annotation>> {
+    unsafe { parse_many_with_fallback(lines, MapOrNot::Not, parse_annotation, false, unsolicited) }
+}
+
+// Parse a `* QUOTA root (resource usage limit ...)` response (RFC 2087).
+fn parse_quota(i: &'static [u8]) -> Option<(Quota, &'static [u8])> {
+    let i = take_prefix(i, "* QUOTA ")?;
+    let (root, i) = parse_astring(i)?;
+    let i = take_prefix(i, " ")?;
+    let (list, i) = parse_value(i)?;
+    let list = match list {
+        Value::List(list) => list,
+        Value::Str(_) => return None,
+    };
+    let mut resources = Vec::new();
+    for triple in list.chunks(3) {
+        if triple.len() < 3 {
+            return None;
+        }
+        resources.push((
+            Cow::Owned(triple[0].as_str()?.to_string()),
+            triple[1].as_str()?.parse().ok()?,
+            triple[2].as_str()?.parse().ok()?,
+        ));
+    }
+    let i = take_prefix(i, "\r\n")?;
+    Some((Quota { root, resources }, i))
+}
+
+pub fn parse_quotas(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<Quota>> {
+    unsafe { parse_many_with_fallback(lines, MapOrNot::Not, parse_quota, false, unsolicited) }
+}
+
+/// Parse the response to `GETQUOTAROOT`: the quota roots of the mailbox, from `* QUOTAROOT
+/// mailbox root ...`, and their `* QUOTA` responses.
+pub fn parse_quota_root(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> Result<(Vec<String>, Vec<Quota>)> {
+    let mut roots = Vec::new();
+    let mut rest = Vec::new();
+    let mut i = &lines[..];
+    while !i.is_empty() {
+        if let Some(after) = take_prefix(i, "* QUOTAROOT ") {
+            let (line_roots, after) = parse_quota_root_line(after)
+                .ok_or_else(|| Error::Parse(ParseError::Invalid(i.to_vec())))?;
+            roots.extend(line_roots);
+            i = after;
+        } else {
+            let end = i.iter().position(|&c| c == b'\n').map_or(i.len(), |end| end + 1);
+            rest.extend_from_slice(&i[..end]);
+            i = &i[end..];
+        }
+    }
+    Ok((roots, parse_quotas(rest, unsolicited)?.into_owned()))
+}
+
+// The quota roots of a `* QUOTAROOT` response, after the mailbox name that starts `i`.
+fn parse_quota_root_line(i: &[u8]) -> Option<(Vec<String>, &[u8])> {
+    let (_mailbox, mut i) = parse_astring(i)?;
+    let mut roots = Vec::new();
+    while let Some(next) = take_prefix(i, " ") {
+        let (root, next) = parse_astring(next)?;
+        roots.push(root.into_owned());
+        i = next;
+    }
+    Some((roots, take_prefix(i, "\r\n")?))
+}
+
+// Render a section path the way it is written between the brackets of `BODY[...]`.
+fn section_spec(section: Option<imap_proto::SectionPath>) -> String {
+    use imap_proto::{MessageSection, SectionPath};
+    let text = |s: MessageSection| match s {
+        MessageSection::Header => "HEADER",
+        MessageSection::Mime => "MIME",
+        MessageSection::Text => "TEXT",
+    };
+    match section {
+        None => String::new(),
+        Some(SectionPath::Full(s)) => text(s).to_string(),
+        Some(SectionPath::Part(parts, s)) => {
+            let mut spec: Vec<String> = parts.iter().map(|p| p.to_string()).collect();
+            spec.extend(s.map(|s| text(s).to_string()));
+            spec.join(".")
+        }
+    }
+}
+
+// Build a `Fetch` from the attributes of a `* FETCH` response, using `text` and `bytes` to either
+// borrow or copy the data.
+fn build_fetch<'a, S, B>(
+    num: u32,
+    attrs: Vec<imap_proto::AttributeValue<'a>>,
+    text: S,
+    bytes: B,
+) -> Fetch
+where
+    S: Fn(&'a str) -> Cow<'static, str>,
+    B: Fn(&'a [u8]) -> Cow<'static, [u8]>,
+{
+    let mut fetch = Fetch {
+        message: num,
+        flags: vec![],
+        uid: None,
+        rfc822_header: None,
+        rfc822: None,
+        body: None,
+        origin: None,
+        mod_seq: None,
+        size: None,
+        internal_date: None,
+        envelope: None,
+        body_structure: None,
+        sections: Vec::new(),
+        gmail: None,
+    };
+
+    for attr in attrs {
+        use imap_proto::AttributeValue;
+        match attr {
+            AttributeValue::Flags(flags) => {
+                for flag in flags {
+                    fetch.add_flag(text(flag));
+                }
+            }
+            AttributeValue::Uid(uid) => fetch.uid = Some(uid),
+            AttributeValue::ModSeq(mod_seq) => fetch.mod_seq = Some(mod_seq),
+            AttributeValue::Rfc822Size(size) => fetch.size = Some(size),
+            AttributeValue::InternalDate(date) => fetch.internal_date = Some(text(date)),
+            AttributeValue::Envelope(envelope) => {
+                let addresses = |list: Option<Vec<imap_proto::Address>>| {
+                    list.unwrap_or_default()
+                        .into_iter()
+                        .map(|a| Address {
+                            name: a.name.map(str::to_string),
+                            adl: a.adl.map(str::to_string),
+                            mailbox: a.mailbox.map(str::to_string),
+                            host: a.host.map(str::to_string),
+                        }).collect()
+                };
+                fetch.envelope = Some(Box::new(Envelope {
+                    date: envelope.date.map(&text),
+                    subject: envelope.subject.map(&text),
+                    from: addresses(envelope.from),
+                    sender: addresses(envelope.sender),
+                    reply_to: addresses(envelope.reply_to),
+                    to: addresses(envelope.to),
+                    cc: addresses(envelope.cc),
+                    bcc: addresses(envelope.bcc),
+                    in_reply_to: envelope.in_reply_to.map(&text),
+                    message_id: envelope.message_id.map(&text),
+                }))
+            }
+            AttributeValue::Rfc822(rfc) => fetch.rfc822 = rfc.map(&bytes),
+            AttributeValue::Rfc822Header(rfc) => fetch.rfc822_header = rfc.map(&bytes),
+            AttributeValue::BodySection {
+                section,
+                index,
+                data,
+            } => {
+                if let Some(data) = data {
+                    fetch.add_section(section_spec(section), bytes(data));
+                }
+                fetch.body = data.map(&bytes);
+                fetch.origin = index;
+            }
+        }
+    }
+
+    fetch
+}
+
+type Bytes = Cow<'static, [u8]>;
+
+// Like `parse_nstring`, but for data that need not be UTF-8, such as message bodies.
+fn parse_nbytes(i: &'static [u8]) -> Option<(Option<Bytes>, &'static [u8])> {
+    if let Some(i) = take_prefix(i, "{") {
+        let end = i.iter().position(|&c| c == b'}')?;
+        let len: usize = ::std::str::from_utf8(&i[..end]).ok()?.parse().ok()?;
+        let i = take_prefix(&i[end + 1..], "\r\n")?;
+        if i.len() < len {
+            return None;
+        }
+        return Some((Some(Cow::Borrowed(&i[..len])), &i[len..]));
+    }
+    parse_nstring(i).map(|(s, rest)| {
+        let data = s.map(|s| match s {
+            Cow::Borrowed(s) => Cow::Borrowed(s.as_bytes()),
+            Cow::Owned(s) => Cow::Owned(s.into_bytes()),
+        });
+        (data, rest)
+    })
+}
+
+// Parse the parenthesized `ENVELOPE` of a message.
+fn parse_envelope(i: &'static [u8]) -> Option<(Envelope, &'static [u8])> {
+    let i = take_prefix(i, "(")?;
+    let (date, i) = parse_nstring(i)?;
+    let (subject, mut i) = parse_nstring(take_prefix(i, " ")?)?;
+    let mut lists = Vec::with_capacity(6);
+    for _ in 0..6 {
+        let (list, rest) = parse_addresses(take_prefix(i, " ")?)?;
+        lists.push(list);
+        i = rest;
+    }
+    let (in_reply_to, i) = parse_nstring(take_prefix(i, " ")?)?;
+    let (message_id, i) = parse_nstring(take_prefix(i, " ")?)?;
+    let i = take_prefix(i, ")")?;
+
+    let mut lists = lists.into_iter();
+    Some((
+        Envelope {
+            date,
+            subject,
+            from: lists.next()?,
+            sender: lists.next()?,
+            reply_to: lists.next()?,
+            to: lists.next()?,
+            cc: lists.next()?,
+            bcc: lists.next()?,
+            in_reply_to,
+            message_id,
+        },
+        i,
+    ))
+}
+
+// Parse an address list of an envelope, i.e. `NIL` or `((name adl mailbox host) ...)`.
+fn parse_addresses(i: &'static [u8]) -> Option<(Vec<Address>, &'static [u8])> {
+    if let Some(rest) = take_prefix(i, "NIL") {
+        return Some((Vec::new(), rest));
+    }
+    let mut i = take_prefix(i, "(")?;
+    let mut addresses = Vec::new();
+    loop {
+        if let Some(rest) = take_prefix(i, ")") {
+            break Some((addresses, rest));
+        }
+        // some servers separate the addresses with spaces
+        if let Some(rest) = take_prefix(i, " ") {
+            i = rest;
+            continue;
+        }
+        let rest = take_prefix(i, "(")?;
+        let (name, rest) = parse_nstring(rest)?;
+        let (adl, rest) = parse_nstring(take_prefix(rest, " ")?)?;
+        let (mailbox, rest) = parse_nstring(take_prefix(rest, " ")?)?;
+        let (host, rest) = parse_nstring(take_prefix(rest, " ")?)?;
+        i = take_prefix(rest, ")")?;
+        addresses.push(Address {
+            name: name.map(Cow::into_owned),
+            adl: adl.map(Cow::into_owned),
+            mailbox: mailbox.map(Cow::into_owned),
+            host: host.map(Cow::into_owned),
+        });
+    }
+}
+
+// Parse a `* FETCH` response with data that imap-proto cannot parse, such as
+// `BODY[HEADER.FIELDS (SUBJECT FROM)]` or a body structure. The section specifier is kept
+// verbatim, and attributes that we do not expose are skipped.
+fn parse_fetch_extended(i: &'static [u8]) -> Option<(Fetch, &'static [u8])> {
+    let i = take_prefix(i, "* ")?;
+    let (num, i) = parse_atom(i)?;
+    let mut fetch = Fetch {
+        message: num.parse().ok()?,
+        flags: vec![],
+        uid: None,
+        rfc822_header: None,
+        rfc822: None,
+        body: None,
+        origin: None,
+        mod_seq: None,
+        size: None,
+        internal_date: None,
+        envelope: None,
+        body_structure: None,
+        sections: Vec::new(),
+        gmail: None,
+    };
+
+    let mut i = take_prefix(i, " FETCH (")?;
+    let mut first = true;
+    loop {
+        if let Some(rest) = take_prefix(i, ")") {
+            i = rest;
+            break;
+        }
+        if !first {
+            i = take_prefix(i, " ")?;
+        }
+        first = false;
+
+        let end = i.iter().position(|&c| c == b' ' || c == b'[')?;
+        let name = ::std::str::from_utf8(&i[..end]).ok()?.to_uppercase();
+        i = &i[end..];
+
+        if let Some(rest) = take_prefix(i, "[") {
+            let end = rest.iter().position(|&c| c == b']')?;
+            let section = ::std::str::from_utf8(&rest[..end]).ok()?;
+            i = &rest[end + 1..];
+            let mut origin = None;
+            if let Some(rest) = take_prefix(i, "<") {
+                let end = rest.iter().position(|&c| c == b'>')?;
+                origin = Some(::std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?);
+                i = &rest[end + 1..];
+            }
+            let (data, rest) = parse_nbytes(take_prefix(i, " ")?)?;
+            i = rest;
+            if name != "BODY" {
+                continue;
+            }
+            if let Some(ref data) = data {
+                fetch.add_section(section.to_string(), data.clone());
+            }
+            fetch.body = data;
+            fetch.origin = origin;
+            continue;
+        }
+
+        i = take_prefix(i, " ")?;
+        match &name[..] {
+            "FLAGS" => match parse_value(i)? {
+                (Value::List(flags), rest) => {
+                    for flag in flags {
+                        match flag {
+                            Value::Str(s) => fetch.add_flag(s),
+                            Value::List(_) => return None,
+                        }
                     }
-                    AttributeValue::Uid(uid) => fetch.uid = Some(uid),
-                    AttributeValue::Rfc822(rfc) => fetch.rfc822 = rfc,
-                    AttributeValue::Rfc822Header(rfc) => fetch.rfc822_header = rfc,
-                    AttributeValue::BodySection {
-                        data, ..
-                    } => fetch.body = data,
-                    _ => {}
+                    i = rest;
+                }
+                _ => return None,
+            },
+            "UID" => {
+                let (uid, rest) = parse_atom(i)?;
+                fetch.uid = Some(uid.parse().ok()?);
+                i = rest;
+            }
+            "RFC822.SIZE" => {
+                let (size, rest) = parse_atom(i)?;
+                fetch.size = Some(size.parse().ok()?);
+                i = rest;
+            }
+            "INTERNALDATE" => match parse_value(i)? {
+                (Value::Str(date), rest) => {
+                    fetch.internal_date = Some(date);
+                    i = rest;
                 }
+                _ => return None,
+            },
+            "ENVELOPE" => {
+                let (envelope, rest) = parse_envelope(i)?;
+                fetch.envelope = Some(Box::new(envelope));
+                i = rest;
             }
+            "BODY" | "BODYSTRUCTURE" => {
+                let rest = parse_value(i)?.1;
+                let raw = ::std::str::from_utf8(&i[..i.len() - rest.len()]).ok()?;
+                fetch.body_structure = Some(Cow::Borrowed(raw));
+                i = rest;
+            }
+            "MODSEQ" => match parse_value(i)? {
+                (Value::List(ref values), rest) if values.len() == 1 => {
+                    fetch.mod_seq = Some(values[0].as_str()?.parse().ok()?);
+                    i = rest;
+                }
+                _ => return None,
+            },
+            "X-GM-MSGID" | "X-GM-THRID" => {
+                let (id, rest) = parse_atom(i)?;
+                let id = Some(id.parse().ok()?);
+                if name == "X-GM-MSGID" {
+                    fetch.gmail_mut().message_id = id;
+                } else {
+                    fetch.gmail_mut().thread_id = id;
+                }
+                i = rest;
+            }
+            "X-GM-LABELS" => match parse_value(i)? {
+                (Value::List(labels), rest) => {
+                    fetch.gmail_mut().labels = labels
+                        .into_iter()
+                        .map(|l| match l {
+                            // labels are named like mailboxes
+                            Value::Str(l) => {
+                                Some(utf7::decode(&l).unwrap_or_else(|_| l.into_owned()))
+                            }
+                            Value::List(_) => None,
+                        }).collect::<Option<_>>()?;
+                    i = rest;
+                }
+                _ => return None,
+            },
+            "RFC822" | "RFC822.HEADER" => {
+                let (data, rest) = parse_nbytes(i)?;
+                if name == "RFC822" {
+                    fetch.rfc822 = data;
+                } else {
+                    fetch.rfc822_header = data;
+                }
+                i = rest;
+            }
+            _ => i = parse_value(i)?.1,
+        }
+    }
+
+    let i = take_prefix(i, "\r\n")?;
+    Some((fetch, i))
+}
+
+pub fn parse_fetches(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<Fetch>> {
+    parse_fetches_with(lines, unsolicited, false)
+}
+
+/// Like `parse_fetches`, but skips `FETCH` responses (and any others) that cannot be parsed.
+pub fn parse_fetches_lenient(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<Vec<Fetch>> {
+    parse_fetches_with(lines, unsolicited, true)
+}
 
-            MapOrNot::Map(fetch)
+fn parse_fetches_with(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+    lenient: bool,
+) -> ZeroCopyResult<Vec<Fetch>> {
+    let f = |resp| match resp {
+        Response::Fetch(num, attrs) => {
+            MapOrNot::Map(build_fetch(num, attrs, Cow::Borrowed, Cow::Borrowed))
         }
         resp => MapOrNot::Not(resp),
     };
 
-    unsafe { parse_many(lines, f) }
+    unsafe { parse_many_with_fallback(lines, f, parse_fetch_extended, lenient, unsolicited) }
+}
+
+/// Parse every response in `lines` with imap-proto, without treating any of them specially.
+pub fn parse_untagged(lines: Vec<u8>) -> ZeroCopyResult<UntaggedResponses> {
+    let f = |mut lines: &'static [u8]| {
+        let mut responses = Vec::new();
+        while !lines.is_empty() {
+            match imap_proto::parse_response(lines) {
+                IResult::Done(rest, resp) => {
+                    lines = rest;
+                    responses.push(resp);
+                }
+                _ => return Err(Error::Parse(ParseError::Invalid(lines.to_vec()))),
+            }
+        }
+        Ok(UntaggedResponses { responses })
+    };
+
+    unsafe { ZeroCopy::new(lines, f) }
 }
 
 pub fn parse_capabilities(lines: Vec<u8>) -> ZeroCopyResult<Capabilities> {
@@ -158,13 +1015,23 @@ pub fn parse_mailbox(mut lines: &[u8]) -> Result<Mailbox> {
 
     loop {
         match imap_proto::parse_response(lines) {
-            IResult::Done(rest, Response::Data { status, code, .. }) => {
+            IResult::Done(
+                rest,
+                Response::Data {
+                    status,
+                    code,
+                    information,
+                },
+            ) => {
                 lines = rest;
 
-                if let imap_proto::Status::Ok = status {
-                } else {
-                    // how can this happen for a Response::Data?
-                    unreachable!();
+                match status {
+                    imap_proto::Status::Ok => {}
+                    imap_proto::Status::Bye => {
+                        break Err(Error::bye(information.unwrap_or("")));
+                    }
+                    // warnings such as `* NO` do not describe the mailbox
+                    _ => continue,
                 }
 
                 use imap_proto::ResponseCode;
@@ -176,13 +1043,27 @@ pub fn parse_mailbox(mut lines: &[u8]) -> Result<Mailbox> {
                         mailbox.uid_next = Some(unext);
                     }
                     Some(ResponseCode::Unseen(n)) => {
-                        mailbox.unseen = Some(n);
+                        mailbox.first_unseen = Some(n);
                     }
                     Some(ResponseCode::PermanentFlags(flags)) => {
-                        mailbox
-                            .permanent_flags
-                            .extend(flags.into_iter().map(|s| s.to_string()));
+                        for flag in flags {
+                            if flag == "\\*" {
+                                mailbox.can_create_keywords = true;
+                            } else {
+                                mailbox.permanent_flags.push(flag.to_string());
+                            }
+                        }
                     }
+                    Some(ResponseCode::HighestModSeq(modseq)) => {
+                        mailbox.highest_mod_seq = Some(modseq);
+                    }
+                    // imap-proto leaves the codes it does not know in the text
+                    None => match information.and_then(split_response_code) {
+                        Some((code, _)) if code.eq_ignore_ascii_case("NOMODSEQ") => {
+                            mailbox.no_mod_seq = true;
+                        }
+                        _ => {}
+                    },
                     _ => {}
                 }
             }
@@ -191,8 +1072,11 @@ pub fn parse_mailbox(mut lines: &[u8]) -> Result<Mailbox> {
 
                 use imap_proto::MailboxDatum;
                 match m {
-                    MailboxDatum::Status { .. } => {
-                        // TODO: we probably want to expose statuses too
+                    MailboxDatum::Status {
+                        mailbox: name,
+                        status,
+                    } => {
+                        apply_status(&mut mailbox, mailbox_status(name, status));
                     }
                     MailboxDatum::Exists(e) => {
                         mailbox.exists = e;
@@ -211,9 +1095,13 @@ pub fn parse_mailbox(mut lines: &[u8]) -> Result<Mailbox> {
             IResult::Done(_, resp) => {
                 break Err(resp.into());
             }
-            _ => {
-                break Err(Error::Parse(ParseError::Invalid(lines.to_vec())));
-            }
+            _ => match parse_status_extended(lines) {
+                Some((status, rest)) => {
+                    lines = rest;
+                    apply_status(&mut mailbox, status);
+                }
+                None => break Err(Error::Parse(ParseError::Invalid(lines.to_vec()))),
+            },
         }
 
         if lines.is_empty() {
@@ -222,25 +1110,171 @@ pub fn parse_mailbox(mut lines: &[u8]) -> Result<Mailbox> {
     }
 }
 
-pub fn parse_ids(lines: Vec<u8>) -> ZeroCopyResult<HashSet<u32>> {
-    let f = |mut lines| {
+// Fill in the items of a `STATUS` response to `Session::status`.
+fn apply_status(mailbox: &mut Mailbox, status: MailboxStatus) {
+    if let Some(n) = status.messages {
+        mailbox.exists = n;
+    }
+    if let Some(n) = status.recent {
+        mailbox.recent = n;
+    }
+    mailbox.uid_next = status.uid_next.or(mailbox.uid_next);
+    mailbox.uid_validity = status.uid_validity.or(mailbox.uid_validity);
+    mailbox.unseen_count = status.unseen.or(mailbox.unseen_count);
+    mailbox.highest_mod_seq = status.highest_mod_seq.or(mailbox.highest_mod_seq);
+}
+
+/// Split the response code off the human-readable text of a status response.
+///
+/// imap-proto only parses the response codes defined in RFC 3501 (and a few extensions); any
+/// other code is left at the start of the text, like `[MODIFIED 7,9] Conditional STORE failed`.
+/// For that example, this returns `Some(("MODIFIED", "7,9"))`.
+pub fn split_response_code(information: &str) -> Option<(&str, &str)> {
+    if !information.starts_with('[') {
+        return None;
+    }
+    let end = information.find(']')?;
+    let code = &information[1..end];
+    match code.find(' ') {
+        Some(i) => Some((&code[..i], &code[i + 1..])),
+        None => Some((code, "")),
+    }
+}
+
+/// Split a tagged response line like `a1 NO [ALERT] Over quota` into its response code and
+/// explanation.
+pub fn parse_response_text(line: &[u8]) -> ResponseText {
+    let line = String::from_utf8_lossy(line).trim_end().to_string();
+    // skip the tag and the status
+    let start = line
+        .match_indices(' ')
+        .nth(1)
+        .map_or(line.len(), |(i, _)| i + 1);
+    let rest = &line[start..];
+    let (code, information) = match rest.find(']').filter(|_| rest.starts_with('[')) {
+        Some(end) => {
+            let after = &rest[end + 1..];
+            let skip = after.len() - after.trim_start().len();
+            ((start + 1, start + end), start + end + 1 + skip)
+        }
+        None => ((start, start), start),
+    };
+    ResponseText::new(line, code, information)
+}
+
+/// Parse a sequence set like `2,4:7,9` sent by the server, which lists the numbers of specific
+/// messages and thus may not contain `*`.
+///
+/// The set is kept as ranges rather than expanded, since a server may send something like
+/// `1:4294967295`.
+pub fn parse_sequence_set(set: &str) -> Result<SequenceSet> {
+    let parsed: SequenceSet = set.parse()?;
+    if parsed.ranges().iter().any(|&(_, end)| end.is_none()) {
+        return Err(Error::Parse(ParseError::Invalid(set.as_bytes().to_vec())));
+    }
+    Ok(parsed)
+}
+
+/// Parse the UIDs of a `* VANISHED` response (RFC 7162), with or without `(EARLIER)`. Returns
+/// `None` if `line` is not a `VANISHED` response.
+pub fn parse_vanished(line: &[u8]) -> Option<Result<SequenceSet>> {
+    if line.len() < 11 || !line[..11].eq_ignore_ascii_case(b"* VANISHED ") {
+        return None;
+    }
+    let set = String::from_utf8_lossy(&line[11..]);
+    let mut set = set.trim();
+    let earlier = set.as_bytes().get(..9);
+    if earlier.is_some_and(|p| p.eq_ignore_ascii_case(b"(EARLIER)")) {
+        set = set[9..].trim_start();
+    }
+    Some(parse_sequence_set(set))
+}
+
+/// Parse the response to `SELECT ... (QRESYNC ...)`: the usual mailbox data, interleaved with the
+/// `* FETCH` responses for the messages that changed and the `* VANISHED (EARLIER)` responses for
+/// those that were expunged.
+pub fn parse_qresync(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> Result<Resync> {
+    let mut status = Vec::new();
+    let mut fetches = Vec::new();
+    let mut vanished = SequenceSet::default();
+    let mut rest = &lines[..];
+    while !rest.is_empty() {
+        let len = match imap_proto::parse_response(rest) {
+            IResult::Done(after, Response::Fetch(..)) => {
+                let len = rest.len() - after.len();
+                fetches.extend_from_slice(&rest[..len]);
+                len
+            }
+            IResult::Done(after, _) => {
+                let len = rest.len() - after.len();
+                status.extend_from_slice(&rest[..len]);
+                len
+            }
+            // imap-proto does not know `VANISHED`, which is always a single line
+            _ => {
+                let len = rest
+                    .iter()
+                    .position(|&c| c == b'\n')
+                    .map_or(rest.len(), |end| end + 1);
+                match parse_vanished(&rest[..len]) {
+                    Some(uids) => vanished.append(uids?),
+                    None => status.extend_from_slice(&rest[..len]),
+                }
+                len
+            }
+        };
+        rest = &rest[len..];
+    }
+
+    Ok(Resync {
+        mailbox: parse_mailbox(&status)?,
+        vanished,
+        changed: parse_fetches(fetches, unsolicited)?.into_owned(),
+    })
+}
+
+/// Extract the UIDs from the `[MODIFIED ...]` response code (RFC 7162) of a status response, if
+/// it has one.
+pub fn parse_modified(text: &ResponseText) -> Result<Option<SequenceSet>> {
+    let mut code = text.code().unwrap_or("").splitn(2, ' ');
+    match (code.next(), code.next()) {
+        (Some(name), Some(set)) if name.eq_ignore_ascii_case("MODIFIED") => {
+            parse_sequence_set(set).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn parse_ids(
+    lines: Vec<u8>,
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> ZeroCopyResult<HashSet<u32>> {
+    let f = |mut lines: &'static [u8]| {
         let mut ids = HashSet::new();
         loop {
+            // a server may send no `* SEARCH` at all, or split its results over several
+            if lines.is_empty() {
+                break Ok(ids);
+            }
+
             match imap_proto::parse_response(lines) {
                 IResult::Done(rest, Response::IDs(c)) => {
                     lines = rest;
                     ids.extend(c);
-
-                    if lines.is_empty() {
-                        break Ok(ids);
-                    }
                 }
-                IResult::Done(_, resp) => {
-                    break Err(resp.into());
-                }
-                _ => {
-                    break Err(Error::Parse(ParseError::Invalid(lines.to_vec())));
+                IResult::Done(rest, resp) => {
+                    lines = rest;
+                    if let Some(resp) = handle_unilateral(resp, unsolicited) {
+                        break Err(resp.into());
+                    }
                 }
+                _ => match handle_unilateral_extended(lines, unsolicited) {
+                    Some(rest) => lines = rest,
+                    None => break Err(Error::Parse(ParseError::Invalid(lines.to_vec()))),
+                },
             }
         }
     };
@@ -248,9 +1282,101 @@ pub fn parse_ids(lines: Vec<u8>) -> ZeroCopyResult<HashSet<u32>> {
     unsafe { ZeroCopy::new(lines, f) }
 }
 
+/// Parse the capabilities listed in the `* ENABLED` response to an `ENABLE` command.
+pub fn parse_enabled(lines: &[u8], unsolicited: &mut VecDeque<UnsolicitedResponse>) -> Vec<String> {
+    parse_noop(lines, unsolicited);
+    let mut enabled = Vec::new();
+    for line in lines.split(|&c| c == b'\n') {
+        if line.len() >= 9 && line[..9].eq_ignore_ascii_case(b"* ENABLED") {
+            let caps = String::from_utf8_lossy(&line[9..]);
+            enabled.extend(caps.split_whitespace().map(String::from));
+        }
+    }
+    enabled
+}
+
+/// Parse the message sequence numbers of the `* n EXPUNGE` responses to an `EXPUNGE` or `UID
+/// EXPUNGE` command, in the order they were sent. Other unilateral responses are queued.
+pub fn parse_expunge(mut lines: &[u8], unsolicited: &mut VecDeque<UnsolicitedResponse>) -> Vec<u32> {
+    let mut expunged = Vec::new();
+    while !lines.is_empty() {
+        match imap_proto::parse_response(lines) {
+            IResult::Done(rest, Response::Expunge(n)) => {
+                lines = rest;
+                expunged.push(n);
+            }
+            IResult::Done(rest, resp) => {
+                lines = rest;
+                handle_unilateral(resp, unsolicited);
+            }
+            _ => match handle_unilateral_extended(lines, unsolicited) {
+                Some(rest) => lines = rest,
+                None => match lines.iter().position(|&c| c == b'\n') {
+                    Some(end) => lines = &lines[end + 1..],
+                    None => break,
+                },
+            },
+        }
+    }
+    expunged
+}
+
+/// Parse the response to `UID EXPUNGE` once QRESYNC is enabled, where the server reports the
+/// removed messages by UID with `* VANISHED` (RFC 7162) instead of `* EXPUNGE`. Returns those
+/// UIDs, queueing any unilateral responses.
+pub fn parse_expunge_vanished(
+    mut lines: &[u8],
+    unsolicited: &mut VecDeque<UnsolicitedResponse>,
+) -> Result<SequenceSet> {
+    let mut vanished = SequenceSet::default();
+    while !lines.is_empty() {
+        let end = lines
+            .iter()
+            .position(|&c| c == b'\n')
+            .map_or(lines.len(), |end| end + 1);
+        if let Some(uids) = parse_vanished(&lines[..end]) {
+            vanished.append(uids?);
+            lines = &lines[end..];
+            continue;
+        }
+        match imap_proto::parse_response(lines) {
+            IResult::Done(rest, resp) => {
+                lines = rest;
+                handle_unilateral(resp, unsolicited);
+            }
+            _ => match handle_unilateral_extended(lines, unsolicited) {
+                Some(rest) => lines = rest,
+                None => lines = &lines[end..],
+            },
+        }
+    }
+    Ok(vanished)
+}
+
+/// Queue the unilateral responses that arrived with the response to a command that has no
+/// untagged responses of its own, such as `NOOP`. Anything else is ignored.
+pub fn parse_noop(mut lines: &[u8], unsolicited: &mut VecDeque<UnsolicitedResponse>) {
+    while !lines.is_empty() {
+        match imap_proto::parse_response(lines) {
+            IResult::Done(rest, resp) => {
+                lines = rest;
+                handle_unilateral(resp, unsolicited);
+            }
+            _ => match handle_unilateral_extended(lines, unsolicited) {
+                Some(rest) => lines = rest,
+                None => match lines.iter().position(|&c| c == b'\n') {
+                    Some(end) => lines = &lines[end + 1..],
+                    None => break,
+                },
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use error::ResponseCode;
 
     #[test]
     fn parse_capability_test() {
@@ -263,6 +1389,17 @@ mod tests {
         }
     }
 
+    #[test]
+    fn parse_capability_append_limit_test() {
+        let lines = b"* CAPABILITY IMAP4rev1 APPENDLIMIT=35651584\r\n";
+        let capabilities = parse_capabilities(lines.to_vec()).unwrap();
+        assert_eq!(capabilities.append_limit(), Some(35651584));
+
+        let lines = b"* CAPABILITY IMAP4rev1 APPENDLIMIT\r\n";
+        let capabilities = parse_capabilities(lines.to_vec()).unwrap();
+        assert_eq!(capabilities.append_limit(), None);
+    }
+
     #[test]
     #[should_panic]
     fn parse_capability_invalid_test() {
@@ -273,17 +1410,262 @@ mod tests {
     #[test]
     fn parse_names_test() {
         let lines = b"* LIST (\\HasNoChildren) \".\" \"INBOX\"\r\n";
-        let names = parse_names(lines.to_vec()).unwrap();
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
         assert_eq!(names.len(), 1);
         assert_eq!(names[0].attributes(), &["\\HasNoChildren"]);
         assert_eq!(names[0].delimiter(), ".");
         assert_eq!(names[0].name(), "INBOX");
+
+        let names: Vec<Name> = names.into_owned();
+        assert_eq!(names[0].attributes(), &["\\HasNoChildren"]);
+        assert_eq!(names[0].delimiter(), ".");
+        assert_eq!(names[0].name(), "INBOX");
+    }
+
+    #[test]
+    fn parse_names_children() {
+        let lines = b"* LIST (\\HasChildren) \"/\" \"Work\"\r\n\
+                      * LIST (\\Marked \\hasnochildren) \"/\" \"Work/Done\"\r\n\
+                      * LIST (\\Noinferiors) \"/\" \"Notes\"\r\n\
+                      * LIST () \"/\" \"Other\"\r\n";
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
+        assert_eq!(names[0].has_children(), Some(true));
+        assert_eq!(names[1].has_children(), Some(false));
+        assert_eq!(names[2].has_children(), Some(false));
+        assert_eq!(names[3].has_children(), None);
+    }
+
+    #[test]
+    fn parse_names_marked() {
+        let lines = b"* LIST (\\Marked) \"/\" \"News\"\r\n\
+                      * LIST (\\HasNoChildren \\unmarked) \"/\" \"Old\"\r\n\
+                      * LIST () \"/\" \"Other\"\r\n";
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
+        assert_eq!(names[0].name(), "News");
+        assert_eq!(names[0].is_marked(), Some(true));
+        assert_eq!(names[1].is_marked(), Some(false));
+        assert_eq!(names[2].is_marked(), None);
+    }
+
+    #[test]
+    fn parse_names_selectable() {
+        let lines = b"* LIST (\\Noselect \\HasChildren) \"/\" \"Archive\"\r\n\
+                      * LIST (\\NonExistent) \"/\" \"Gone\"\r\n\
+                      * LIST (\\noselect) \"/\" \"Lower\"\r\n\
+                      * LIST (\\HasNoChildren) \"/\" \"Archive/2019\"\r\n";
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
+        assert!(!names[0].is_selectable());
+        assert!(!names[1].is_selectable());
+        assert!(!names[2].is_selectable());
+        assert!(names[3].is_selectable());
+    }
+
+    #[test]
+    fn parse_names_utf7() {
+        let lines = b"* LIST () \"/\" \"Gel&APY-schte\"\r\n\
+                      * LIST () \"/\" \"Tom &- Jerry\"\r\n\
+                      * LIST () \"/\" \"Broken &AP\"\r\n";
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].name(), "Gel\u{f6}schte");
+        assert_eq!(names[0].raw_name(), "Gel&APY-schte");
+        assert_eq!(names[1].name(), "Tom & Jerry");
+        // names that are not valid modified UTF-7 are left alone
+        assert_eq!(names[2].name(), "Broken &AP");
+    }
+
+    #[test]
+    fn parse_authenticate_response_test() {
+        let challenge = |line: &[u8]| match parse_authenticate_response(line).unwrap() {
+            AuthContinuation::Challenge(challenge) => challenge,
+            c => panic!("expected a challenge, got {:?}", c),
+        };
+        assert_eq!(challenge(b"+ \r\n"), b"");
+        assert_eq!(challenge(b"+\r\n"), b"");
+        assert_eq!(
+            challenge(b"+ eyJzdGF0dXMiOiI0MDEifQ==\r\n"),
+            b"{\"status\":\"401\"}"
+        );
+        // no CRLF at the end of the input
+        assert_eq!(challenge(b"+ VXNlcm5hbWU6"), b"Username:");
+        // a challenge folded over several lines
+        assert_eq!(challenge(b"+ VXNlcm5h\r\n bWU6\r\n"), b"Username:");
+        assert!(parse_authenticate_response(b"a1 OK done\r\n").is_err());
+        assert_eq!(
+            parse_authenticate_response(b"+ not base64!\r\n").unwrap(),
+            AuthContinuation::Text("not base64!".to_string())
+        );
+        assert_eq!(
+            parse_authenticate_response(b"+ Ready for additional command text\r\n").unwrap(),
+            AuthContinuation::Text("Ready for additional command text".to_string())
+        );
+        assert_eq!(
+            parse_authenticate_response(b"+ Go ahead\r\n").unwrap(),
+            AuthContinuation::Text("Go ahead".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_mailbox_permanent_flags() {
+        let lines = b"* OK [PERMANENTFLAGS (\\Deleted \\Seen \\*)] Limited\r\n";
+        let mailbox = parse_mailbox(lines).unwrap();
+        assert_eq!(mailbox.permanent_flags, vec!["\\Deleted", "\\Seen"]);
+        assert!(mailbox.can_create_keywords);
+
+        let lines = b"* OK [PERMANENTFLAGS (\\Deleted \\Seen)] Limited\r\n";
+        let mailbox = parse_mailbox(lines).unwrap();
+        assert_eq!(mailbox.permanent_flags.len(), 2);
+        assert!(!mailbox.can_create_keywords);
+    }
+
+    #[test]
+    fn parse_mailbox_mod_seq() {
+        let lines = b"* 3 EXISTS\r\n\
+            * OK [UIDVALIDITY 3857529045] UIDs valid\r\n\
+            * OK [HIGHESTMODSEQ 715194045007] Highest\r\n";
+        let mailbox = parse_mailbox(lines).unwrap();
+        assert_eq!(mailbox.highest_mod_seq, Some(715194045007));
+        assert!(!mailbox.no_mod_seq);
+
+        let lines = b"* 3 EXISTS\r\n\
+            * OK [NOMODSEQ] Sorry, this mailbox format doesn't support modsequences\r\n";
+        let mailbox = parse_mailbox(lines).unwrap();
+        assert_eq!(mailbox.highest_mod_seq, None);
+        assert!(mailbox.no_mod_seq);
+    }
+
+    #[test]
+    fn parse_vanished_test() {
+        let uids = parse_vanished(b"* VANISHED (EARLIER) 41,43:45\r\n").unwrap().unwrap();
+        assert_eq!(uids.to_string(), "41,43:45");
+        let uids = parse_vanished(b"* VANISHED 7\r\n").unwrap().unwrap();
+        assert_eq!(uids.to_string(), "7");
+        assert!(parse_vanished(b"* 7 EXPUNGE\r\n").is_none());
+        // non-ASCII where `(EARLIER)` would end is an error, not a panic
+        let line = "* VANISHED (EARLIER\u{e9}) 1\r\n";
+        assert!(parse_vanished(line.as_bytes()).unwrap().is_err());
+    }
+
+    #[test]
+    fn parse_expunge_vanished_test() {
+        let lines = b"* VANISHED 405,407:408\r\n* 9 EXISTS\r\n* VANISHED 410\r\n";
+        let mut queue = VecDeque::new();
+        let uids = parse_expunge_vanished(lines, &mut queue).unwrap();
+        assert_eq!(uids.to_string(), "405,407:408,410");
+        assert_eq!(queue, vec![UnsolicitedResponse::Exists(9)]);
+    }
+
+    #[test]
+    fn parse_quotas_test() {
+        let lines = b"* QUOTA \"\" (STORAGE 10 512 MESSAGE 4 10000)\r\n\
+            * QUOTA \"shared\" (X-ATTACHMENTS 1 5)\r\n";
+        let quotas = parse_quotas(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(quotas.len(), 2);
+        assert_eq!(quotas[0].root(), "");
+        assert_eq!(quotas[0].resources().len(), 2);
+        assert_eq!(quotas[0].resources()[0], (Cow::Borrowed("STORAGE"), 10, 512));
+        assert_eq!(quotas[0].resource("message"), Some((4, 10000)));
+        assert_eq!(quotas[1].root(), "shared");
+        assert_eq!(quotas[1].resource("X-ATTACHMENTS"), Some((1, 5)));
+        assert_eq!(quotas[1].resource("STORAGE"), None);
+
+        let lines = b"* QUOTA \"\" (STORAGE 10)\r\n";
+        assert!(parse_quotas(lines.to_vec(), &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn parse_quota_root_test() {
+        let lines = b"* QUOTAROOT \"Sent Items\" \"\" \"user quota\"\r\n\
+            * QUOTA \"\" (STORAGE 10 512)\r\n\
+            * QUOTAROOT {4}\r\nA Bc {6}\r\nmy two\r\n\
+            * 3 EXISTS\r\n";
+        let mut queue = VecDeque::new();
+        let (roots, quotas) = parse_quota_root(lines.to_vec(), &mut queue).unwrap();
+        assert_eq!(roots, vec!["", "user quota", "my two"]);
+        assert_eq!(quotas.len(), 1);
+        assert_eq!(quotas[0].resource("STORAGE"), Some((10, 512)));
+        assert_eq!(queue, vec![UnsolicitedResponse::Exists(3)]);
+
+        let lines = b"* QUOTAROOT \"Sent Items\r\n";
+        assert!(parse_quota_root(lines.to_vec(), &mut VecDeque::new()).is_err());
+    }
+
+    #[test]
+    fn parse_expunge_test() {
+        let lines = b"* 3 EXPUNGE\r\n\
+            * 3 EXPUNGE\r\n\
+            * 5 EXISTS\r\n\
+            * 4 EXPUNGE\r\n";
+        let mut queue = VecDeque::new();
+        assert_eq!(parse_expunge(lines, &mut queue), vec![3, 3, 4]);
+        assert_eq!(queue.pop_front(), Some(UnsolicitedResponse::Exists(5)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn parse_annotations_test() {
+        let lines = b"\
+            * ANNOTATION \"INBOX\" \"/comment\" (\"value.priv\" \"My comment\" \"value.shared\" NIL)\r\n\
+            * 3 EXISTS\r\n";
+        let mut queue = VecDeque::new();
+        let annotations = parse_annotations(lines.to_vec(), &mut queue).unwrap();
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].mailbox(), "INBOX");
+        assert_eq!(annotations[0].entry(), "/comment");
+        assert_eq!(annotations[0].value("value.priv"), Some("My comment"));
+        assert_eq!(annotations[0].value("value.shared"), None);
+        assert_eq!(annotations[0].values().len(), 2);
+        assert_eq!(queue, vec![UnsolicitedResponse::Exists(3)]);
+    }
+
+    #[test]
+    fn parse_names_extended_test() {
+        let lines = b"\
+            * LIST () \"/\" \"Foo\" (\"CHILDINFO\" (\"SUBSCRIBED\"))\r\n\
+            * LIST (\\Subscribed) \"/\" \"Foo/Bar\"\r\n\
+            * LIST (\\Noselect) NIL {7}\r\nMy \"Box\r\n";
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).unwrap();
+        assert_eq!(names.len(), 3);
+        assert_eq!(names[0].name(), "Foo");
+        assert!(names[0].attributes().is_empty());
+        assert_eq!(names[0].child_info(), &["SUBSCRIBED"]);
+        assert_eq!(names[1].name(), "Foo/Bar");
+        assert_eq!(names[1].attributes(), &["\\Subscribed"]);
+        assert!(names[1].child_info().is_empty());
+        assert_eq!(names[2].name(), "My \"Box");
+        assert_eq!(names[2].delimiter(), "");
+    }
+
+    #[test]
+    fn parse_names_lenient() {
+        let lines = b"\
+            * LIST(\\HasNoChildren) \"/\" \"INBOX\"\r\n\
+            * LIST (\\HasNoChildren) \"/\" {3}\r\nA\r\n \"garbage\r\n\
+            * LIST (\\HasChildren) \"/\" \"Archive\"\r\n";
+        assert!(parse_names(lines.to_vec(), &mut VecDeque::new(), true, false).is_err());
+        let names = parse_names(lines.to_vec(), &mut VecDeque::new(), true, true).unwrap();
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[0].name(), "INBOX");
+        assert_eq!(names[0].attributes(), &["\\HasNoChildren"]);
+        assert_eq!(names[1].name(), "Archive");
+    }
+
+    #[test]
+    fn parse_fetches_lenient_skips() {
+        let lines = b"\
+            * 1 FETCH (UID 1 FLAGS (\\Seen))\r\n\
+            * 2 FETCH (UID 2 BODY[] {5}\r\nab\r\nc\r\n\
+            * 3 FETCH (UID 3 FLAGS ())\r\n";
+        assert!(parse_fetches(lines.to_vec(), &mut VecDeque::new()).is_err());
+        let fetches = parse_fetches_lenient(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        let uids: Vec<_> = fetches.iter().map(|f| f.uid).collect();
+        assert_eq!(uids, vec![Some(1), Some(3)]);
     }
 
     #[test]
     fn parse_fetches_empty() {
         let lines = b"";
-        let fetches = parse_fetches(lines.to_vec()).unwrap();
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
         assert!(fetches.is_empty());
     }
 
@@ -292,7 +1674,7 @@ mod tests {
         let lines = b"\
                     * 24 FETCH (FLAGS (\\Seen) UID 4827943)\r\n\
                     * 25 FETCH (FLAGS (\\Seen))\r\n";
-        let fetches = parse_fetches(lines.to_vec()).unwrap();
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
         assert_eq!(fetches.len(), 2);
         assert_eq!(fetches[0].message, 24);
         assert_eq!(fetches[0].flags(), &["\\Seen"]);
@@ -304,23 +1686,280 @@ mod tests {
         assert_eq!(fetches[1].rfc822(), None);
     }
 
+    #[test]
+    fn parse_fetches_into_owned() {
+        let lines = b"* 24 FETCH (FLAGS (\\Seen) UID 4827943 BODY[TEXT] {3}\r\nfoo)\r\n";
+        let fetches: Vec<Fetch> = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap().into_owned();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].message, 24);
+        assert_eq!(fetches[0].flags(), &["\\Seen"]);
+        assert_eq!(fetches[0].uid, Some(4827943));
+        assert_eq!(fetches[0].body(), Some(&b"foo"[..]));
+        assert!(fetches[0].flags.iter().all(|f| match *f {
+            Cow::Owned(_) => true,
+            Cow::Borrowed(_) => false,
+        }));
+    }
+
+    #[test]
+    fn parse_fetches_clone() {
+        let lines = b"* 1 FETCH (FLAGS (\\Seen) BODY[TEXT] {3}\r\nfoo \
+            ENVELOPE (\"Wed, 17 Jul 1996 02:23:25 -0700\" \"Hi\" NIL NIL NIL NIL NIL NIL NIL \
+            \"<1@x.org>\"))\r\n";
+        let (fetch, envelope) = {
+            let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+            (fetches[0].clone(), fetches[0].envelope().cloned().unwrap())
+        };
+        // the clones do not borrow from the dropped response buffer
+        let owned = |data: &Option<Cow<'static, [u8]>>| matches!(*data, Some(Cow::Owned(_)));
+        assert!(owned(&fetch.body));
+        assert!(fetch.sections.iter().all(|(_, d)| matches!(*d, Cow::Owned(_))));
+        assert!(fetch.flags.iter().all(|f| matches!(*f, Cow::Owned(_))));
+        assert!(matches!(envelope.subject, Some(Cow::Owned(_))));
+        assert!(matches!(envelope.message_id, Some(Cow::Owned(_))));
+        assert_eq!(fetch.text(), Some(&b"foo"[..]));
+        assert_eq!(envelope.subject(), Some("Hi"));
+    }
+
+    #[test]
+    fn unsolicited_fetch_owned() {
+        let mut queue = VecDeque::new();
+        parse_noop(b"* 1 FETCH (FLAGS (\\Seen) BODY[TEXT] {3}\r\nfoo)\r\n", &mut queue);
+        match queue.pop_front() {
+            Some(UnsolicitedResponse::Fetch(fetch)) => {
+                assert!(matches!(fetch.body, Some(Cow::Owned(_))));
+                assert!(fetch.flags.iter().all(|f| matches!(*f, Cow::Owned(_))));
+            }
+            r => panic!("expected a FETCH, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn parse_fetches_sections() {
+        let lines = b"* 1 FETCH (UID 3 \
+            BODY[1.MIME] {37}\r\nContent-Transfer-Encoding: base64\r\n\r\n \
+            BODY[1] {8}\r\naGkgdGhl \
+            BODY[2] {6}\r\ncmU=\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(
+            fetches[0].section("1.mime"),
+            Some(&b"Content-Transfer-Encoding: base64\r\n\r\n"[..])
+        );
+        assert_eq!(fetches[0].decoded_part("1", "base64"), Some(b"hi the".to_vec()));
+        assert_eq!(fetches[0].decoded_part("2", "base64"), Some(b"re".to_vec()));
+        assert_eq!(fetches[0].section("3"), None);
+    }
+
+    #[test]
+    fn parse_fetches_header_fields() {
+        let lines = b"* 1 FETCH (FLAGS (\\Seen) UID 7 RFC822.SIZE 2048 \
+            INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+            BODY[HEADER.FIELDS (SUBJECT FROM)] {31}\r\nSubject: hi\r\nFrom: me@x.org\r\n\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].message, 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(fetches[0].flags(), &["\\Seen"]);
+        assert_eq!(fetches[0].size(), Some(2048));
+        assert_eq!(fetches[0].internal_date(), Some("17-Jul-1996 02:44:25 -0700"));
+        assert_eq!(
+            fetches[0].section("HEADER.FIELDS (SUBJECT FROM)"),
+            Some(&b"Subject: hi\r\nFrom: me@x.org\r\n\r\n"[..])
+        );
+        assert_eq!(fetches[0].section("HEADER"), None);
+    }
+
+    #[test]
+    fn parse_fetches_header_fields_not() {
+        let lines = b"* 1 FETCH (UID 7 BODY[HEADER.FIELDS.NOT (RECEIVED)] {15}\r\nSubject: hi\r\n\r\n \
+            BODY[HEADER.FIELDS (SUBJECT)] {15}\r\nSubject: hi\r\n\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(
+            fetches[0].section("HEADER.FIELDS.NOT (RECEIVED)"),
+            Some(&b"Subject: hi\r\n\r\n"[..])
+        );
+        assert_eq!(
+            fetches[0].section("header.fields (subject)"),
+            Some(&b"Subject: hi\r\n\r\n"[..])
+        );
+        assert_eq!(fetches[0].section("HEADER.FIELDS (RECEIVED)"), None);
+    }
+
+    #[test]
+    fn parse_fetches_repeated_attributes() {
+        // parsed by imap-proto
+        let lines = b"* 1 FETCH (FLAGS (\\Seen) BODY[HEADER] {12}\r\nSubject: a\r\n \
+            FLAGS (\\seen \\Flagged) BODY[TEXT] {4}\r\nHi\r\n \
+            BODY[HEADER] {12}\r\nSubject: b\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].flags(), &["\\Seen", "\\Flagged"]);
+        assert_eq!(fetches[0].header(), Some(&b"Subject: b\r\n"[..]));
+        assert_eq!(fetches[0].text(), Some(&b"Hi\r\n"[..]));
+        assert_eq!(fetches[0].body(), Some(&b"Subject: b\r\n"[..]));
+
+        // parsed by our fallback, for the body structure of the FULL macro
+        let lines = b"* 1 FETCH (FLAGS (\\Seen) RFC822.SIZE 12 \
+            BODY (\"TEXT\" \"PLAIN\" NIL NIL NIL \"7BIT\" 12 1) \
+            BODY[HEADER] {12}\r\nSubject: a\r\n FLAGS (\\Answered) \
+            BODY[HEADER.FIELDS (SUBJECT)] {12}\r\nSubject: a\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].flags(), &["\\Seen", "\\Answered"]);
+        assert!(fetches[0].body_structure().is_some());
+        assert_eq!(fetches[0].header(), Some(&b"Subject: a\r\n"[..]));
+        assert_eq!(
+            fetches[0].section("HEADER.FIELDS (SUBJECT)"),
+            Some(&b"Subject: a\r\n"[..])
+        );
+    }
+
+    #[test]
+    fn parse_fetches_gmail() {
+        let lines = b"* 1 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334866 \
+            UID 7 X-GM-LABELS (\\Inbox \\Sent Important \"Muy Importante\" \"Gel&APY-scht\"))\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        assert_eq!(fetches[0].uid, Some(7));
+        assert_eq!(fetches[0].gmail_thread_id(), Some(1278455344230334865));
+        assert_eq!(fetches[0].gmail_message_id(), Some(1278455344230334866));
+        assert_eq!(
+            fetches[0].gmail_labels(),
+            &["\\Inbox", "\\Sent", "Important", "Muy Importante", "Gel\u{f6}scht"]
+        );
+    }
+
+    #[test]
+    fn parse_fetches_header_and_text() {
+        let lines = b"* 1 FETCH (BODY[HEADER] {12}\r\nSubject: a\r\n BODY[TEXT] {4}\r\nHi\r\n)\r\n\
+            * 2 FETCH (BODY[HEADER] {12}\r\nSubject: b\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches[0].header(), Some(&b"Subject: a\r\n"[..]));
+        assert_eq!(fetches[0].text(), Some(&b"Hi\r\n"[..]));
+        assert_eq!(fetches[1].header(), Some(&b"Subject: b\r\n"[..]));
+        assert_eq!(fetches[1].text(), None);
+    }
+
+    #[test]
+    fn parse_fetches_full() {
+        let lines = b"* 1 FETCH (FLAGS (\\Seen) INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+            RFC822.SIZE 4286 ENVELOPE (\"Wed, 17 Jul 1996 02:23:25 -0700 (PDT)\" \
+            \"IMAP4rev1 WG mtg summary and minutes\" \
+            ((\"Terry Gray\" NIL \"gray\" \"cac.washington.edu\")) \
+            ((\"Terry Gray\" NIL \"gray\" \"cac.washington.edu\")) \
+            ((\"Terry Gray\" NIL \"gray\" \"cac.washington.edu\")) \
+            ((NIL NIL \"imap\" \"cac.washington.edu\")) \
+            ((NIL NIL \"minutes\" \"CNRI.Reston.VA.US\") \
+            (\"John Klensin\" NIL \"KLENSIN\" \"MIT.EDU\")) NIL NIL \
+            \"<B27397-0100000@cac.washington.edu>\") \
+            BODY (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") NIL NIL \"7BIT\" 3028 92))\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 1);
+        let fetch = &fetches[0];
+        assert_eq!(fetch.message, 1);
+        assert_eq!(fetch.flags(), &["\\Seen"]);
+        assert_eq!(fetch.internal_date(), Some("17-Jul-1996 02:44:25 -0700"));
+        assert_eq!(fetch.size(), Some(4286));
+        let envelope = fetch.envelope().unwrap();
+        assert_eq!(envelope.date(), Some("Wed, 17 Jul 1996 02:23:25 -0700 (PDT)"));
+        assert_eq!(envelope.subject(), Some("IMAP4rev1 WG mtg summary and minutes"));
+        assert_eq!(
+            envelope.from_addresses()[0].to_string(),
+            "Terry Gray <gray@cac.washington.edu>"
+        );
+        assert_eq!(envelope.to_addresses()[0].to_string(), "imap@cac.washington.edu");
+        let cc = envelope.cc_addresses();
+        assert_eq!(cc.len(), 2);
+        assert_eq!(cc[1].name.as_ref().map(|s| &s[..]), Some("John Klensin"));
+        assert_eq!(cc[1].mailbox.as_ref().map(|s| &s[..]), Some("KLENSIN"));
+        assert_eq!(cc[1].host.as_ref().map(|s| &s[..]), Some("MIT.EDU"));
+        assert!(envelope.bcc_addresses().is_empty());
+        assert_eq!(envelope.in_reply_to(), None);
+        assert_eq!(
+            envelope.message_id(),
+            Some("<B27397-0100000@cac.washington.edu>")
+        );
+        assert_eq!(
+            fetch.body_structure(),
+            Some("(\"TEXT\" \"PLAIN\" (\"CHARSET\" \"US-ASCII\") NIL NIL \"7BIT\" 3028 92)")
+        );
+
+        let owned = fetches.into_owned();
+        assert_eq!(
+            owned[0].envelope().unwrap().subject(),
+            Some("IMAP4rev1 WG mtg summary and minutes")
+        );
+    }
+
+    #[test]
+    fn parse_fetches_all() {
+        let lines = b"* 2 FETCH (FLAGS () INTERNALDATE \"17-Jul-1996 02:44:25 -0700\" \
+            RFC822.SIZE 12 ENVELOPE (NIL \"hi\" NIL NIL NIL NIL NIL NIL \"<a@b>\" NIL))\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        let envelope = fetches[0].envelope().unwrap();
+        assert_eq!(envelope.date(), None);
+        assert_eq!(envelope.subject(), Some("hi"));
+        assert_eq!(envelope.in_reply_to(), Some("<a@b>"));
+        assert_eq!(envelope.message_id(), None);
+        assert_eq!(fetches[0].size(), Some(12));
+        assert_eq!(fetches[0].body_structure(), None);
+    }
+
+    #[test]
+    fn parse_envelope_groups() {
+        let lines = b"* 3 FETCH (ENVELOPE (NIL NIL ((\"Smith, J.\" NIL \"js\" \"example.com\")) \
+            NIL NIL ((NIL NIL \"Undisclosed recipients\" NIL)(NIL NIL NIL NIL)) NIL NIL NIL NIL) \
+            BODY[] {0}\r\n)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        let envelope = fetches[0].envelope().unwrap();
+        assert_eq!(
+            envelope.from_addresses()[0].to_string(),
+            "\"Smith, J.\" <js@example.com>"
+        );
+        let to = envelope.to_addresses();
+        assert_eq!(to.len(), 2);
+        assert!(to[0].is_group_start());
+        assert!(to[1].is_group_end());
+        let rendered: Vec<_> = to.iter().map(ToString::to_string).collect();
+        assert_eq!(rendered.concat(), "Undisclosed recipients:;");
+    }
+
+    #[test]
+    fn parse_fetches_origin() {
+        let lines = b"* 1 FETCH (BODY[HEADER.FIELDS (SUBJECT)]<10> {2}\r\nhi)\r\n\
+            * 2 FETCH (BODY[1]<0> {0}\r\n)\r\n\
+            * 3 FETCH (BODY[1] {1}\r\nx)\r\n";
+        let fetches = parse_fetches(lines.to_vec(), &mut VecDeque::new()).unwrap();
+        assert_eq!(fetches.len(), 3);
+        assert_eq!(fetches[0].origin(), Some(10));
+        assert_eq!(fetches[0].body(), Some(&b"hi"[..]));
+        assert_eq!(fetches[1].origin(), Some(0));
+        assert_eq!(fetches[1].body(), Some(&b""[..]));
+        assert_eq!(fetches[2].origin(), None);
+    }
+
     #[test]
     fn parse_fetches_w_unilateral() {
         // https://github.com/mattnenterprise/rust-imap/issues/81
         let lines = b"\
             * 37 FETCH (UID 74)\r\n\
             * 1 RECENT\r\n";
-        let fetches = parse_fetches(lines.to_vec()).unwrap();
+        let mut queue = VecDeque::new();
+        let fetches = parse_fetches(lines.to_vec(), &mut queue).unwrap();
         assert_eq!(fetches.len(), 1);
         assert_eq!(fetches[0].message, 37);
         assert_eq!(fetches[0].uid, Some(74));
+        assert_eq!(queue, vec![UnsolicitedResponse::Recent(1)]);
     }
 
     #[test]
     fn parse_ids_test() {
         let lines = b"* SEARCH 1600 1698 1739 1781 1795 1885 1891 1892 1893 1898 1899 1901 1911 1926 1932 1933 1993 1994 2007 2032 2033 2041 2053 2062 2063 2065 2066 2072 2078 2079 2082 2084 2095 2100 2101 2102 2103 2104 2107 2116 2120 2135 2138 2154 2163 2168 2172 2189 2193 2198 2199 2205 2212 2213 2221 2227 2267 2275 2276 2295 2300 2328 2330 2332 2333 2334\r\n\
             * SEARCH 2335 2336 2337 2338 2339 2341 2342 2347 2349 2350 2358 2359 2362 2369 2371 2372 2373 2374 2375 2376 2377 2378 2379 2380 2381 2382 2383 2384 2385 2386 2390 2392 2397 2400 2401 2403 2405 2409 2411 2414 2417 2419 2420 2424 2426 2428 2439 2454 2456 2467 2468 2469 2490 2515 2519 2520 2521\r\n";
-        let ids = parse_ids(lines.to_vec()).unwrap();
+        let ids = parse_ids(lines.to_vec(), &mut VecDeque::new()).unwrap();
         let ids: HashSet<u32> = ids.iter().cloned().collect();
         assert_eq!(
             ids,
@@ -338,8 +1977,72 @@ mod tests {
         );
 
         let lines = b"* SEARCH\r\n";
-        let ids = parse_ids(lines.to_vec()).unwrap();
+        let ids = parse_ids(lines.to_vec(), &mut VecDeque::new()).unwrap();
         let ids: HashSet<u32> = ids.iter().cloned().collect();
         assert_eq!(ids, HashSet::<u32>::new());
     }
+
+    #[test]
+    fn parse_ids_w_unilateral() {
+        let lines = b"\
+            * SEARCH 2 4\r\n\
+            * 7 EXISTS\r\n\
+            * SEARCH 7\r\n";
+        let mut queue = VecDeque::new();
+        let ids = parse_ids(lines.to_vec(), &mut queue).unwrap();
+        let ids: HashSet<u32> = ids.iter().cloned().collect();
+        assert_eq!(ids, [2, 4, 7].iter().cloned().collect());
+        assert_eq!(queue, vec![UnsolicitedResponse::Exists(7)]);
+    }
+
+    #[test]
+    fn parse_sequence_set_test() {
+        let ids = parse_sequence_set("2,4:6,9").unwrap();
+        assert_eq!(ids.ranges(), vec![(2, Some(2)), (4, Some(6)), (9, Some(9))]);
+        let ids = parse_sequence_set("6:4").unwrap();
+        assert_eq!(ids.ranges(), vec![(4, Some(6))]);
+        // huge ranges are not expanded
+        let ids = parse_sequence_set("1:4294967295").unwrap();
+        assert!(ids.contains(4294967295));
+        assert!(parse_sequence_set("1:*").is_err());
+        assert!(parse_sequence_set("").is_err());
+    }
+
+    #[test]
+    fn split_response_code_test() {
+        assert_eq!(
+            split_response_code("[MODIFIED 7,9] Conditional STORE failed"),
+            Some(("MODIFIED", "7,9"))
+        );
+        assert_eq!(split_response_code("[TOOBIG] Too big"), Some(("TOOBIG", "")));
+        assert_eq!(split_response_code("Done"), None);
+    }
+
+    #[test]
+    fn parse_response_text_test() {
+        let text = parse_response_text(b"a3 NO [MODIFIED 7,9] Conditional STORE failed\r\n");
+        assert_eq!(text.code(), Some("MODIFIED 7,9"));
+        assert!(text.has_code("modified"));
+        assert_eq!(text.information(), "Conditional STORE failed");
+        assert_eq!(text.line(), "a3 NO [MODIFIED 7,9] Conditional STORE failed");
+
+        let text = parse_response_text(b"a3 BAD\r\n");
+        assert_eq!(text.code(), None);
+        assert_eq!(text.information(), "");
+
+        let text = parse_response_text(b"a4 NO [BADCHARSET] Unsupported charset\r\n");
+        assert_eq!(text.bad_charsets(), Some(vec![]));
+        let text = parse_response_text(b"a4 NO [BADCHARSET (\"UTF-8\" KOI8-R)] No\r\n");
+        assert_eq!(
+            text.bad_charsets(),
+            Some(vec!["UTF-8".to_string(), "KOI8-R".to_string()])
+        );
+        assert_eq!(text.response_code(), Some(ResponseCode::BadCharset));
+    }
+
+    #[test]
+    fn parse_ids_empty() {
+        let ids = parse_ids(Vec::new(), &mut VecDeque::new()).unwrap();
+        assert!(ids.is_empty());
+    }
 }
@@ -0,0 +1,249 @@
+//! Retrying operations that fail for transient reasons, such as a mailbox that is in use or a
+//! connection that was reset, with exponential backoff.
+//!
+//! ```rust,no_run
+//! # use imap::client::Session;
+//! # use imap::retry::RetryPolicy;
+//! # use std::net::TcpStream;
+//! # let mut session = Session::from_stream(TcpStream::connect("imap.example.org:143").unwrap());
+//! let policy = RetryPolicy::new().max_attempts(5);
+//! let mailbox = session.with_retry(&policy, |session| session.select("INBOX")).unwrap();
+//! ```
+
+use std::io::{Read, Write};
+use std::thread;
+use std::time::Duration;
+
+use super::client::Session;
+use super::error::{Error, Result};
+
+/// How often, and how long apart, to try an operation that fails with a transient error (see
+/// `Error::is_transient`).
+///
+/// By default, an operation is tried 3 times, first waiting 1 second, and then twice as long as
+/// the time before, but never more than 30 seconds.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_delay: Duration,
+    max_delay: Duration,
+    multiplier: u32,
+    retry_non_idempotent: bool,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create a policy with the default settings.
+    pub fn new() -> Self {
+        RetryPolicy::default()
+    }
+
+    /// Try the operation at most `attempts` times in total (at least once).
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait `delay` before the first retry.
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// Never wait longer than `delay` between two attempts.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Multiply the delay by `multiplier` after each retry.
+    pub fn multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Also retry operations that sent a command that should not be repeated blindly, since it may
+    /// have taken effect even though the operation failed: `APPEND` could store the message twice,
+    /// and `COPY` could copy the messages twice. See `Session::with_retry`.
+    pub fn retry_non_idempotent(mut self, retry: bool) -> Self {
+        self.retry_non_idempotent = retry;
+        self
+    }
+
+    // How long to wait before the given retry (the first one being 1).
+    fn delay(&self, retry: u32) -> Duration {
+        let mut delay = self.initial_delay;
+        for _ in 1..retry {
+            delay = match delay.checked_mul(self.multiplier) {
+                Some(d) if d < self.max_delay => d,
+                _ => return self.max_delay,
+            };
+        }
+        delay.min(self.max_delay)
+    }
+}
+
+/// Run `op` until it succeeds, fails with an error that is not transient (see
+/// `Error::is_transient`), or has been tried as often as `policy` allows, waiting longer and
+/// longer in between. The result of the last attempt is returned.
+///
+/// `op` should be safe to repeat. Use `Session::with_retry` for operations on a session, which
+/// checks this.
+pub fn with_retry<R, F>(policy: &RetryPolicy, mut op: F) -> Result<R>
+where
+    F: FnMut() -> Result<R>,
+{
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(ref e) if policy.should_retry(attempt, e) => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+impl RetryPolicy {
+    // Whether to retry after the given attempt failed with `e`; if so, wait before returning.
+    fn should_retry(&self, attempt: u32, e: &Error) -> bool {
+        if attempt >= self.max_attempts || !e.is_transient() {
+            return false;
+        }
+        let delay = self.delay(attempt);
+        warn!("retrying in {:?} after transient error: {}", delay, e);
+        thread::sleep(delay);
+        true
+    }
+}
+
+impl<T: Read + Write> Session<T> {
+    /// Run `op` on this session until it succeeds, or fails with an error that is not transient,
+    /// as with [`retry::with_retry`](../retry/fn.with_retry.html).
+    ///
+    /// An attempt that sent `APPEND` or `COPY` is not retried, unless the policy allows it (see
+    /// `RetryPolicy::retry_non_idempotent`), since the command may have taken effect even though
+    /// the attempt failed.
+    ///
+    /// Note that after a timeout, or when the connection is lost, the session can no longer be
+    /// used (see `Connection::is_poisoned`), and retrying on it fails for good. `op` can call
+    /// [`reconnect`](#method.reconnect) first if the session is poisoned, to recover from that.
+    pub fn with_retry<R, F>(&mut self, policy: &RetryPolicy, mut op: F) -> Result<R>
+    where
+        F: FnMut(&mut Session<T>) -> Result<R>,
+    {
+        let mut attempt = 1;
+        loop {
+            self.reset_non_idempotent();
+            match op(self) {
+                Err(ref e)
+                    if (policy.retry_non_idempotent || !self.sent_non_idempotent())
+                        && policy.should_retry(attempt, e) =>
+                {
+                    attempt += 1
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::client::Client;
+    use super::super::mock_stream::MockStream;
+    use super::*;
+    use parse::parse_response_text;
+    use std::cell::Cell;
+    use std::io;
+
+    fn no_delay() -> RetryPolicy {
+        RetryPolicy::new().initial_delay(Duration::from_secs(0))
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::new();
+        assert_eq!(policy.delay(1), Duration::from_secs(1));
+        assert_eq!(policy.delay(2), Duration::from_secs(2));
+        assert_eq!(policy.delay(5), Duration::from_secs(16));
+        assert_eq!(policy.delay(6), Duration::from_secs(30));
+        assert_eq!(policy.delay(100), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn retries_transient() {
+        let attempts = Cell::new(0);
+        let result = with_retry(&no_delay(), || {
+            attempts.set(attempts.get() + 1);
+            match attempts.get() {
+                1 => Err(Error::Timeout),
+                2 => Err(Error::Io(io::Error::from(io::ErrorKind::ConnectionReset))),
+                _ => Ok("done"),
+            }
+        });
+        assert_eq!(result.unwrap(), "done");
+        assert_eq!(attempts.get(), 3);
+
+        attempts.set(0);
+        let result: Result<()> = with_retry(&no_delay().max_attempts(2), || {
+            attempts.set(attempts.get() + 1);
+            Err(Error::ConnectionLost)
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn permanent_not_retried() {
+        let attempts = Cell::new(0);
+        let result: Result<()> = with_retry(&no_delay(), || {
+            attempts.set(attempts.get() + 1);
+            Err(Error::No(parse_response_text(
+                b"a1 NO [AUTHENTICATIONFAILED] Invalid credentials",
+            )))
+        });
+        assert!(result.is_err());
+        assert_eq!(attempts.get(), 1);
+    }
+
+    #[test]
+    fn session_retry() {
+        let response = b"a1 NO [INUSE] Mailbox is locked\r\n\
+            * 1 EXISTS\r\n\
+            a2 OK [READ-WRITE] Select completed\r\n\
+            a3 NO [INUSE] Mailbox is locked\r\n\
+            a4 NO [INUSE] Mailbox is locked\r\n"
+            .to_vec();
+        let mut session = Session::new(Client::new(MockStream::new(response)).conn);
+        let mailbox = session
+            .with_retry(&no_delay(), |session| session.select("INBOX"))
+            .unwrap();
+        assert_eq!(mailbox.exists, 1);
+
+        // APPEND may have stored the message, so it is not tried again
+        match session.with_retry(&no_delay(), |session| session.append("INBOX", b"x")) {
+            Err(e) => assert!(e.is_transient()),
+            Ok(_) => panic!("expected an error"),
+        }
+        let written = String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap();
+        assert_eq!(written.matches("APPEND").count(), 1);
+
+        // unless the policy allows it
+        let _ = session.with_retry(
+            &no_delay().retry_non_idempotent(true).max_attempts(2),
+            |s| s.append("INBOX", b"x"),
+        );
+        let written = String::from_utf8(session.stream.get_ref().written_buf.clone()).unwrap();
+        assert_eq!(written.matches("APPEND").count(), 3);
+    }
+}
@@ -0,0 +1,127 @@
+//! The client side of a SOCKS5 CONNECT (RFC 1928), without authentication.
+
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+fn proxy_error(message: &str) -> io::Error {
+    io::Error::other(format!("SOCKS5 proxy: {}", message))
+}
+
+/// Ask the SOCKS5 proxy at the other end of `stream` to connect to `host` and `port`. Once this
+/// returns, the stream is a tunnel to that host.
+///
+/// Host names are sent to the proxy as they are, so that it is the proxy that resolves them.
+pub(crate) fn connect<S: Read + Write>(stream: &mut S, host: &str, port: u16) -> io::Result<()> {
+    stream.write_all(&[VERSION, 1, NO_AUTHENTICATION])?;
+    stream.flush()?;
+    let mut reply = [0; 2];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(proxy_error("not a SOCKS5 server"));
+    }
+    if reply[1] != NO_AUTHENTICATION {
+        return Err(proxy_error("authentication is required"));
+    }
+
+    let mut request = vec![VERSION, CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            request.push(IPV4);
+            request.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            request.push(IPV6);
+            request.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.is_empty() || host.len() > 255 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "host name must be 1 to 255 bytes long",
+                ));
+            }
+            request.push(DOMAIN_NAME);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request)?;
+    stream.flush()?;
+
+    let mut reply = [0; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(proxy_error("not a SOCKS5 server"));
+    }
+    match reply[1] {
+        0 => {}
+        1 => return Err(proxy_error("general failure")),
+        2 => return Err(proxy_error("connection not allowed by ruleset")),
+        3 => return Err(proxy_error("network unreachable")),
+        4 => return Err(proxy_error("host unreachable")),
+        5 => return Err(proxy_error("connection refused")),
+        6 => return Err(proxy_error("TTL expired")),
+        7 => return Err(proxy_error("command not supported")),
+        8 => return Err(proxy_error("address type not supported")),
+        _ => return Err(proxy_error("unknown error")),
+    }
+
+    // skip the address the proxy bound to, and its port
+    let len = match reply[3] {
+        IPV4 => 4,
+        IPV6 => 16,
+        DOMAIN_NAME => {
+            let mut len = [0; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(proxy_error("invalid address type")),
+    };
+    let mut bound = vec![0; len + 2];
+    stream.read_exact(&mut bound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::mock_stream::MockStream;
+    use super::*;
+
+    #[test]
+    fn connect_domain() {
+        let mut stream = MockStream::new(vec![5, 0, 5, 0, 0, 1, 10, 0, 0, 1, 0x10, 0x92]);
+        connect(&mut stream, "imap.example.org", 993).unwrap();
+        let mut expected = vec![5, 1, 0, 5, 1, 0, 3, 16];
+        expected.extend_from_slice(b"imap.example.org");
+        expected.extend_from_slice(&[0x03, 0xe1]);
+        assert_eq!(stream.written_buf, expected);
+    }
+
+    #[test]
+    fn connect_ip() {
+        let mut stream = MockStream::new(vec![5, 0, 5, 0, 0, 3, 1, b'x', 0, 0]);
+        connect(&mut stream, "192.0.2.7", 143).unwrap();
+        assert_eq!(
+            stream.written_buf,
+            vec![5, 1, 0, 5, 1, 0, 1, 192, 0, 2, 7, 0, 143]
+        );
+    }
+
+    #[test]
+    fn connect_refused() {
+        let mut stream = MockStream::new(vec![5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+        let e = connect(&mut stream, "imap.example.org", 993).unwrap_err();
+        assert_eq!(e.to_string(), "SOCKS5 proxy: connection refused");
+
+        let mut stream = MockStream::new(vec![5, 0xff]);
+        let e = connect(&mut stream, "imap.example.org", 993).unwrap_err();
+        assert_eq!(e.to_string(), "SOCKS5 proxy: authentication is required");
+    }
+}
@@ -0,0 +1,136 @@
+//! Configuration of the TLS layer, for servers that need more than the system's defaults.
+//!
+//! ```rust,no_run
+//! # extern crate native_tls;
+//! # extern crate imap;
+//! # use native_tls::Certificate;
+//! # use imap::tls::TlsConfig;
+//! # fn main() {
+//! let ca = Certificate::from_pem(&std::fs::read("ca.pem").unwrap()).unwrap();
+//! let config = TlsConfig::new()
+//!     .add_root_certificate(ca)
+//!     .server_name("imap.internal.example.org");
+//! // connect by IP, but verify the certificate against the name above
+//! let client = imap::client::secure_connect_with_config(
+//!     ("10.0.0.5", 993),
+//!     "10.0.0.5",
+//!     &config).unwrap();
+//! # }
+//! ```
+
+use std::net::TcpStream;
+
+use native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+
+use super::error::{Error, Result};
+
+/// A builder for the TLS settings used to connect to a server.
+///
+/// By default, this is the same as `TlsConnector::new()`: the server's certificate is checked
+/// against the system's root certificates and the domain that is connected to, which is also
+/// sent for SNI.
+#[derive(Clone, Default)]
+pub struct TlsConfig {
+    root_certificates: Vec<Certificate>,
+    disable_built_in_roots: bool,
+    identity: Option<Identity>,
+    server_name: Option<String>,
+    accept_invalid_certs: bool,
+}
+
+impl TlsConfig {
+    /// Create a configuration with the default settings.
+    pub fn new() -> Self {
+        TlsConfig::default()
+    }
+
+    /// Also trust certificates issued by `certificate`, such as the root of a private CA.
+    pub fn add_root_certificate(mut self, certificate: Certificate) -> Self {
+        self.root_certificates.push(certificate);
+        self
+    }
+
+    /// Only trust the certificates added with `add_root_certificate`, not the system's.
+    pub fn disable_built_in_roots(mut self, disable: bool) -> Self {
+        self.disable_built_in_roots = disable;
+        self
+    }
+
+    /// Present `identity` as a client certificate to servers that ask for one.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// The name to send for SNI and to verify the server's certificate against, in place of the
+    /// domain given when connecting. This is for connecting to a server by its IP address, or
+    /// through an alias that is not on its certificate.
+    pub fn server_name(mut self, name: &str) -> Self {
+        self.server_name = Some(name.to_string());
+        self
+    }
+
+    /// Accept any certificate the server presents, even expired or self-signed ones, or ones
+    /// issued for another host.
+    ///
+    /// # Warning
+    ///
+    /// This makes the connection open to man-in-the-middle attacks, and thus voids the point of
+    /// using TLS. It is only meant for testing against servers with throwaway certificates.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Build a `TlsConnector` with these settings, for use with any of the functions that take
+    /// one, such as `Client::starttls` or `connect_auto`. Note that the `server_name` is not part
+    /// of the connector; pass it as the domain to those functions instead.
+    pub fn connector(&self) -> Result<TlsConnector> {
+        let mut builder = TlsConnector::builder();
+        for certificate in &self.root_certificates {
+            builder.add_root_certificate(certificate.clone());
+        }
+        if let Some(ref identity) = self.identity {
+            builder.identity(identity.clone());
+        }
+        builder
+            .disable_built_in_roots(self.disable_built_in_roots)
+            .danger_accept_invalid_certs(self.accept_invalid_certs)
+            .build()
+            .map_err(Error::Tls)
+    }
+
+    /// Perform the TLS handshake over `stream` with these settings, using the `server_name` if
+    /// one was set, and `domain` otherwise.
+    pub fn handshake(&self, domain: &str, stream: TcpStream) -> Result<TlsStream<TcpStream>> {
+        self.connector()?
+            .connect(self.domain(domain), stream)
+            .map_err(Error::TlsHandshake)
+    }
+
+    fn domain<'a>(&'a self, domain: &'a str) -> &'a str {
+        self.server_name.as_ref().map(|s| &s[..]).unwrap_or(domain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn server_name() {
+        assert_eq!(TlsConfig::new().domain("10.0.0.5"), "10.0.0.5");
+        let config = TlsConfig::new().server_name("imap.example.org");
+        assert_eq!(config.domain("10.0.0.5"), "imap.example.org");
+    }
+
+    #[test]
+    fn connector() {
+        assert!(TlsConfig::new().connector().is_ok());
+        assert!(TlsConfig::new()
+            .danger_accept_invalid_certs(true)
+            .disable_built_in_roots(true)
+            .connector()
+            .is_ok());
+    }
+}
@@ -0,0 +1,55 @@
+use std::borrow::Cow;
+
+use super::{owned, IntoOwned};
+
+/// An annotation entry on a mailbox, as returned by `GETANNOTATION` (see
+/// [`Session::get_annotation`](../client/struct.Session.html#method.get_annotation)).
+// Note that none of these fields are *actually* 'static.
+// Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Annotation`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Annotation {
+    pub(crate) mailbox: Cow<'static, str>,
+    pub(crate) entry: Cow<'static, str>,
+    pub(crate) values: Vec<(Cow<'static, str>, Option<Cow<'static, str>>)>,
+}
+
+impl Annotation {
+    /// The mailbox the entry belongs to.
+    pub fn mailbox(&self) -> &str {
+        &self.mailbox
+    }
+
+    /// The name of the entry, such as `/comment`.
+    pub fn entry(&self) -> &str {
+        &self.entry
+    }
+
+    /// The attributes of the entry (such as `value.priv`) along with their values. A value of
+    /// `None` means that the attribute is not set.
+    pub fn values(&self) -> &[(Cow<'_, str>, Option<Cow<'_, str>>)] {
+        &self.values[..]
+    }
+
+    /// The value of the given attribute, if it is set.
+    pub fn value(&self, attribute: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(a, _)| a.eq_ignore_ascii_case(attribute))
+            .and_then(|(_, v)| v.as_ref().map(|v| &**v))
+    }
+}
+
+impl IntoOwned for Annotation {
+    type Owned = Annotation;
+    fn into_owned(self) -> Annotation {
+        Annotation {
+            mailbox: owned(self.mailbox),
+            entry: owned(self.entry),
+            values: self
+                .values
+                .into_iter()
+                .map(|(a, v)| (owned(a), v.map(owned)))
+                .collect(),
+        }
+    }
+}
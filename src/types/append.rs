@@ -0,0 +1,123 @@
+use super::SequenceSet;
+
+/// A message to upload with `Session::append_multiple`, along with the flags and the internal
+/// date it should be stored with.
+///
+/// ```
+/// # use imap::AppendMessage;
+/// let message = AppendMessage::new(b"From: alice@example.org\r\n\r\nHi!\r\n")
+///     .flag("\\Seen")
+///     .internal_date("17-Jul-1996 02:44:25 -0700");
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppendMessage<'a> {
+    content: &'a [u8],
+    flags: Vec<String>,
+    internal_date: Option<String>,
+}
+
+impl<'a> AppendMessage<'a> {
+    /// A message with the given content, without flags, whose internal date is left to the server
+    /// (which uses the current time).
+    pub fn new(content: &'a [u8]) -> Self {
+        AppendMessage {
+            content,
+            flags: Vec::new(),
+            internal_date: None,
+        }
+    }
+
+    /// Store the message with the given flag, such as `\Seen` or a keyword.
+    pub fn flag(mut self, flag: &str) -> Self {
+        self.flags.push(flag.to_string());
+        self
+    }
+
+    /// Store the message with the given internal date, in the format of RFC 3501, e.g.
+    /// `17-Jul-1996 02:44:25 -0700`. This is typically the date the message was received, as
+    /// kept when migrating it from another server.
+    pub fn internal_date(mut self, date: &str) -> Self {
+        self.internal_date = Some(date.to_string());
+        self
+    }
+
+    pub(crate) fn content(&self) -> &[u8] {
+        self.content
+    }
+
+    // The flags and date that precede the message's literal in an APPEND command, followed by a
+    // space (e.g. `(\Seen) "17-Jul-1996 02:44:25 -0700" `).
+    pub(crate) fn arguments(&self) -> String {
+        let mut arguments = String::new();
+        if !self.flags.is_empty() {
+            arguments.push_str(&format!("({}) ", self.flags.join(" ")));
+        }
+        if let Some(ref date) = self.internal_date {
+            arguments.push_str(&format!("\"{}\" ", date));
+        }
+        arguments
+    }
+}
+
+/// The UIDs that the server assigned to appended messages, as reported with an `[APPENDUID ...]`
+/// response code by servers that support `UIDPLUS` ([RFC
+/// 4315](https://tools.ietf.org/html/rfc4315#section-3)).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AppendUid {
+    /// The UIDVALIDITY of the mailbox the messages were appended to.
+    pub uid_validity: u32,
+    /// The UIDs of the messages, in the order in which they were appended.
+    pub uids: Vec<u32>,
+}
+
+impl AppendUid {
+    // Parse the arguments of an `APPENDUID` response code, e.g. `38505 3955:3957`, for `count`
+    // appended messages. The UIDs are only expanded if there are exactly that many of them.
+    pub(crate) fn parse(arguments: &str, count: usize) -> Option<AppendUid> {
+        let mut arguments = arguments.split_whitespace();
+        let uid_validity = arguments.next()?.parse().ok()?;
+        let set: SequenceSet = arguments.next()?.parse().ok()?;
+        let mut total = 0u64;
+        for (start, end) in set.ranges() {
+            total += u64::from(end? - start) + 1;
+        }
+        if total != count as u64 {
+            return None;
+        }
+        let mut uids = Vec::new();
+        for (start, end) in set.ranges() {
+            uids.extend(start..=end?);
+        }
+        Some(AppendUid { uid_validity, uids })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        assert_eq!(AppendMessage::new(b"x").arguments(), "");
+        let message = AppendMessage::new(b"x")
+            .flag("\\Seen")
+            .flag("$Forwarded")
+            .internal_date("17-Jul-1996 02:44:25 -0700");
+        assert_eq!(
+            message.arguments(),
+            "(\\Seen $Forwarded) \"17-Jul-1996 02:44:25 -0700\" "
+        );
+    }
+
+    #[test]
+    fn parse_append_uid() {
+        let uid = AppendUid::parse("38505 3955:3957,4000", 4).unwrap();
+        assert_eq!(uid.uid_validity, 38505);
+        assert_eq!(uid.uids, vec![3955, 3956, 3957, 4000]);
+        assert_eq!(AppendUid::parse("38505", 1).map(|u| u.uids), None);
+        assert_eq!(AppendUid::parse("38505 7:*", 1).map(|u| u.uids), None);
+        // the number of UIDs must match, and is checked before they are expanded
+        assert_eq!(AppendUid::parse("38505 3955:3957", 2).map(|u| u.uids), None);
+        assert_eq!(AppendUid::parse("1 1:4294967295", 2).map(|u| u.uids), None);
+    }
+}
@@ -15,6 +15,7 @@ impl Capabilities {
         self.0.contains(s)
     }
 
+    /// An iterator over the advertised capabilities, in no particular order.
     pub fn iter(&self) -> Iter<&str> {
         self.0.iter()
     }
@@ -26,4 +27,53 @@ impl Capabilities {
     pub fn is_empty(&self) -> bool {
         self.0.is_empty()
     }
+
+    /// The SASL mechanisms the server supports for `AUTHENTICATE`, i.e. the names after `AUTH=`
+    /// (e.g. `GSSAPI` for `AUTH=GSSAPI`), in upper case and sorted. This is empty if `AUTHENTICATE`
+    /// cannot be used.
+    pub fn auth_mechanisms(&self) -> Vec<String> {
+        let mut mechanisms: Vec<String> = self
+            .0
+            .iter()
+            .filter(|c| c.len() > 5 && c.get(..5).is_some_and(|p| p.eq_ignore_ascii_case("AUTH=")))
+            .map(|c| c[5..].to_uppercase())
+            .collect();
+        mechanisms.sort();
+        mechanisms.dedup();
+        mechanisms
+    }
+
+    /// The largest literal (in octets) that may be sent without waiting for the server to ask
+    /// for it, i.e. as a non-synchronizing literal `{n+}` ([RFC
+    /// 7888](https://tools.ietf.org/html/rfc7888)). This is unlimited (`u64::MAX`) with
+    /// `LITERAL+`, 4096 with `LITERAL-`, and `None` if the server supports neither.
+    pub fn non_sync_literal_limit(&self) -> Option<u64> {
+        let has = |name: &str| self.0.iter().any(|c| c.eq_ignore_ascii_case(name));
+        if has("LITERAL+") {
+            Some(u64::MAX)
+        } else if has("LITERAL-") {
+            Some(4096)
+        } else {
+            None
+        }
+    }
+
+    /// The maximum message size (in octets) the server accepts for `APPEND`, as advertised by
+    /// the `APPENDLIMIT=<n>` capability defined in [RFC
+    /// 7889](https://tools.ietf.org/html/rfc7889).
+    ///
+    /// Returns `None` if no limit is advertised, or if the server only announces `APPENDLIMIT`
+    /// without a value (meaning the limit may vary per mailbox).
+    pub fn append_limit(&self) -> Option<u64> {
+        self.0
+            .iter()
+            .filter_map(|c| {
+                let (name, value) = c.split_at(c.find('=')?);
+                if name.eq_ignore_ascii_case("APPENDLIMIT") {
+                    value[1..].parse().ok()
+                } else {
+                    None
+                }
+            }).next()
+    }
 }
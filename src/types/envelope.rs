@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use super::{owned, IntoOwned};
+
+/// The envelope of a message, i.e. the most commonly used fields of its header as parsed by the
+/// server, as returned when fetching `ENVELOPE` (or the `ALL` and `FULL` macros).
+// Note that none of these fields are *actually* 'static.
+// Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Envelope`, which is
+// why a clone copies them (see `Fetch`).
+#[derive(Debug, Eq, PartialEq)]
+pub struct Envelope {
+    pub(crate) date: Option<Cow<'static, str>>,
+    pub(crate) subject: Option<Cow<'static, str>>,
+    pub(crate) from: Vec<Address>,
+    pub(crate) sender: Vec<Address>,
+    pub(crate) reply_to: Vec<Address>,
+    pub(crate) to: Vec<Address>,
+    pub(crate) cc: Vec<Address>,
+    pub(crate) bcc: Vec<Address>,
+    pub(crate) in_reply_to: Option<Cow<'static, str>>,
+    pub(crate) message_id: Option<Cow<'static, str>>,
+}
+
+impl Envelope {
+    /// The `Date` header, exactly as it appears in the message.
+    pub fn date(&self) -> Option<&str> {
+        self.date.as_ref().map(|s| &s[..])
+    }
+
+    /// The `Subject` header. Note that this is not decoded, so it may contain RFC 2047 encoded
+    /// words such as `=?utf-8?q?...?=`.
+    pub fn subject(&self) -> Option<&str> {
+        self.subject.as_ref().map(|s| &s[..])
+    }
+
+    /// The addresses of the `From` header.
+    pub fn from_addresses(&self) -> &[Address] {
+        &self.from[..]
+    }
+
+    /// The addresses of the `Sender` header. Servers fill this in from `From` if the message has
+    /// no `Sender` header.
+    pub fn sender_addresses(&self) -> &[Address] {
+        &self.sender[..]
+    }
+
+    /// The addresses of the `Reply-To` header. Servers fill this in from `From` if the message
+    /// has no `Reply-To` header.
+    pub fn reply_to_addresses(&self) -> &[Address] {
+        &self.reply_to[..]
+    }
+
+    /// The addresses of the `To` header.
+    pub fn to_addresses(&self) -> &[Address] {
+        &self.to[..]
+    }
+
+    /// The addresses of the `Cc` header.
+    pub fn cc_addresses(&self) -> &[Address] {
+        &self.cc[..]
+    }
+
+    /// The addresses of the `Bcc` header.
+    pub fn bcc_addresses(&self) -> &[Address] {
+        &self.bcc[..]
+    }
+
+    /// The `In-Reply-To` header.
+    pub fn in_reply_to(&self) -> Option<&str> {
+        self.in_reply_to.as_ref().map(|s| &s[..])
+    }
+
+    /// The `Message-ID` header.
+    pub fn message_id(&self) -> Option<&str> {
+        self.message_id.as_ref().map(|s| &s[..])
+    }
+}
+
+impl Clone for Envelope {
+    fn clone(&self) -> Envelope {
+        Envelope {
+            date: self.date.clone().map(owned),
+            subject: self.subject.clone().map(owned),
+            from: self.from.clone(),
+            sender: self.sender.clone(),
+            reply_to: self.reply_to.clone(),
+            to: self.to.clone(),
+            cc: self.cc.clone(),
+            bcc: self.bcc.clone(),
+            in_reply_to: self.in_reply_to.clone().map(owned),
+            message_id: self.message_id.clone().map(owned),
+        }
+    }
+}
+
+impl IntoOwned for Envelope {
+    type Owned = Envelope;
+    fn into_owned(self) -> Envelope {
+        Envelope {
+            date: self.date.map(owned),
+            subject: self.subject.map(owned),
+            from: self.from,
+            sender: self.sender,
+            reply_to: self.reply_to,
+            to: self.to,
+            cc: self.cc,
+            bcc: self.bcc,
+            in_reply_to: self.in_reply_to.map(owned),
+            message_id: self.message_id.map(owned),
+        }
+    }
+}
+
+/// An address in an `Envelope`.
+///
+/// Groups (such as `Undisclosed recipients:;`) are represented as in [RFC
+/// 3501](https://tools.ietf.org/html/rfc3501#section-7.4.2): the addresses of the group are
+/// preceded by one with no `host` whose `mailbox` is the name of the group, and followed by one
+/// with neither `mailbox` nor `host`.
+///
+/// Its `Display` form is the usual `Name <mailbox@host>`.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Address {
+    /// The display name, e.g. `Terry Gray`.
+    pub name: Option<String>,
+    /// The obsolete source route.
+    pub adl: Option<String>,
+    /// The local part, such as `gray` in `gray@example.com`, or the name of a group.
+    pub mailbox: Option<String>,
+    /// The domain, such as `example.com` in `gray@example.com`.
+    pub host: Option<String>,
+}
+
+impl Address {
+    /// Whether this marks the start of a group, rather than being an address.
+    pub fn is_group_start(&self) -> bool {
+        self.host.is_none() && self.mailbox.is_some()
+    }
+
+    /// Whether this marks the end of a group, rather than being an address.
+    pub fn is_group_end(&self) -> bool {
+        self.host.is_none() && self.mailbox.is_none()
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_group_end() {
+            return f.write_str(";");
+        }
+        let mailbox = self.mailbox.as_ref().map(|s| &s[..]).unwrap_or("");
+        if self.is_group_start() {
+            return write!(f, "{}:", mailbox);
+        }
+
+        let host = self.host.as_ref().map(|s| &s[..]).unwrap_or("");
+        match self.name {
+            Some(ref name) if name.contains(|c| "()<>[]:;@\\,.\"".contains(c)) => write!(
+                f,
+                "\"{}\" <{}@{}>",
+                name.replace('\\', "\\\\").replace('"', "\\\""),
+                mailbox,
+                host
+            ),
+            Some(ref name) => write!(f, "{} <{}@{}>", name, mailbox, host),
+            None => write!(f, "{}@{}", mailbox, host),
+        }
+    }
+}
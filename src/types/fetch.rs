@@ -1,29 +1,300 @@
+use std::borrow::Cow;
+
+use super::{owned, Envelope, IntoOwned};
+use decode::decode_transfer_encoding;
+
+/// The data of a message, as returned in a `* FETCH` response.
+///
+/// If a response carries the same kind of attribute more than once (as servers may do when a
+/// query combines a macro such as `FULL` with explicit items), the values are merged: `flags`
+/// accumulate without duplicates, and every body section is kept, so that each can be looked up
+/// with `section`; a section that is sent twice keeps the later data. All other attributes,
+/// including `body` (the last body section), are last-wins.
 // Note that none of these fields are *actually* 'static.
-// Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Name`.
+// Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Fetch`. This is
+// also why `Clone` is implemented by hand: a clone copies all the data, so that it does not
+// borrow from that `ZeroCopy`.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Fetch {
     pub message: u32,
-    pub(crate) flags: Vec<&'static str>,
+    pub(crate) flags: Vec<Cow<'static, str>>,
     pub uid: Option<u32>,
-    pub(crate) rfc822_header: Option<&'static [u8]>,
-    pub(crate) rfc822: Option<&'static [u8]>,
-    pub(crate) body: Option<&'static [u8]>,
+    pub(crate) rfc822_header: Option<Cow<'static, [u8]>>,
+    pub(crate) rfc822: Option<Cow<'static, [u8]>>,
+    pub(crate) body: Option<Cow<'static, [u8]>>,
+    pub(crate) origin: Option<u32>,
+    pub(crate) mod_seq: Option<u64>,
+    pub(crate) size: Option<u32>,
+    pub(crate) internal_date: Option<Cow<'static, str>>,
+    pub(crate) envelope: Option<Box<Envelope>>,
+    pub(crate) body_structure: Option<Cow<'static, str>>,
+    // the data of each fetched body section, by section specifier (e.g. `1.2` or `HEADER`)
+    pub(crate) sections: Vec<(String, Cow<'static, [u8]>)>,
+    // boxed, like the envelope, as only Gmail sends these
+    pub(crate) gmail: Option<Box<GmailAttributes>>,
+}
+
+// The attributes of the `X-GM-EXT-1` extension.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub(crate) struct GmailAttributes {
+    pub(crate) message_id: Option<u64>,
+    pub(crate) thread_id: Option<u64>,
+    pub(crate) labels: Vec<String>,
 }
 
 impl Fetch {
-    pub fn flags(&self) -> &[&str] {
+    pub fn flags(&self) -> &[Cow<'_, str>] {
         &self.flags[..]
     }
 
     pub fn rfc822_header(&self) -> Option<&[u8]> {
-        self.rfc822_header
+        self.rfc822_header.as_ref().map(|b| &b[..])
     }
 
     pub fn rfc822(&self) -> Option<&[u8]> {
-        self.rfc822
+        self.rfc822.as_ref().map(|b| &b[..])
     }
 
     pub fn body(&self) -> Option<&[u8]> {
-        self.body
+        self.body.as_ref().map(|b| &b[..])
+    }
+
+    /// The offset of `body` within the fetched section, if a partial fetch was requested (e.g.
+    /// `2048` for `BODY[]<2048>`). See `Session::fetch_partial`.
+    pub fn origin(&self) -> Option<u32> {
+        self.origin
+    }
+
+    /// The mod-sequence of the message, if `MODSEQ` was fetched or the server reported it on its
+    /// own (as it does after `CONDSTORE` or `QRESYNC` is enabled).
+    pub fn mod_seq(&self) -> Option<u64> {
+        self.mod_seq
+    }
+
+    /// The size of the message in bytes, if `RFC822.SIZE` was fetched.
+    pub fn size(&self) -> Option<u32> {
+        self.size
+    }
+
+    /// The date and time at which the server received the message, as sent by the server (e.g.
+    /// `17-Jul-1996 02:44:25 -0700`), if `INTERNALDATE` was fetched.
+    pub fn internal_date(&self) -> Option<&str> {
+        self.internal_date.as_ref().map(|d| &d[..])
+    }
+
+    /// The envelope of the message, if `ENVELOPE` (or the `ALL` or `FULL` macro) was fetched.
+    pub fn envelope(&self) -> Option<&Envelope> {
+        self.envelope.as_deref()
+    }
+
+    /// The MIME structure of the message, if `BODYSTRUCTURE` (or `BODY`, or the `FULL` macro) was
+    /// fetched. This is the parenthesized list exactly as sent by the server, e.g.
+    /// `("TEXT" "PLAIN" ("CHARSET" "US-ASCII") NIL NIL "7BIT" 3028 92)`.
+    pub fn body_structure(&self) -> Option<&str> {
+        self.body_structure.as_ref().map(|s| &s[..])
+    }
+
+    /// The data of the body section with the given specifier, i.e. what is between the brackets
+    /// of `BODY[...]`, such as `1.2`, `HEADER`, or the empty string for the whole message.
+    pub fn section(&self, section: &str) -> Option<&[u8]> {
+        self.sections
+            .iter()
+            .find(|(s, _)| s.eq_ignore_ascii_case(section))
+            .map(|(_, data)| &data[..])
+    }
+
+    /// The header of the message, if `BODY[HEADER]` (or `BODY.PEEK[HEADER]`) was fetched. This
+    /// is a shorthand for `section("HEADER")`.
+    pub fn header(&self) -> Option<&[u8]> {
+        self.section("HEADER")
+    }
+
+    /// The body text of the message, i.e. everything after the header, if `BODY[TEXT]` (or
+    /// `BODY.PEEK[TEXT]`) was fetched. This is a shorthand for `section("TEXT")`.
+    pub fn text(&self) -> Option<&[u8]> {
+        self.section("TEXT")
+    }
+
+    /// Gmail's unique ID of the message, if `X-GM-MSGID` was fetched from a server with the
+    /// `X-GM-EXT-1` capability.
+    pub fn gmail_message_id(&self) -> Option<u64> {
+        self.gmail.as_ref().and_then(|g| g.message_id)
+    }
+
+    /// The ID of the Gmail conversation the message belongs to, if `X-GM-THRID` was fetched
+    /// from a server with the `X-GM-EXT-1` capability.
+    pub fn gmail_thread_id(&self) -> Option<u64> {
+        self.gmail.as_ref().and_then(|g| g.thread_id)
+    }
+
+    /// The Gmail labels of the message, if `X-GM-LABELS` was fetched from a server with the
+    /// `X-GM-EXT-1` capability. These are decoded from modified UTF-7 like mailbox names, and
+    /// system labels keep their backslash, e.g. `\Important` or `\Inbox`. See
+    /// `Session::store_gmail_labels` to change them.
+    pub fn gmail_labels(&self) -> &[String] {
+        self.gmail.as_ref().map_or(&[], |g| &g.labels[..])
+    }
+
+    /// The fields of the message header, if `RFC822.HEADER` (or, failing that, `BODY[HEADER]`)
+    /// was fetched, as pairs of names and values in the order they appear in the header.
+    ///
+    /// Folded fields are unfolded, i.e. continuation lines (those starting with whitespace) are
+    /// joined to the line before them. Fields that occur more than once, such as `Received`, are
+    /// all kept. Values are neither trimmed of inner whitespace nor decoded (e.g. from RFC 2047
+    /// encoded words), and invalid UTF-8 is replaced.
+    pub fn parsed_headers(&self) -> Option<Vec<(String, String)>> {
+        self.rfc822_header()
+            .or_else(|| self.header())
+            .map(parse_headers)
+    }
+
+    /// The data of a body section (see `section`), decoded according to the given
+    /// `Content-Transfer-Encoding` of that part (e.g. `base64` or `quoted-printable`).
+    ///
+    /// The encoding can be found in the part's MIME header, which can be fetched as section
+    /// `<part>.MIME`. See [`decode_transfer_encoding`](../decode/fn.decode_transfer_encoding.html)
+    /// for how malformed data is handled.
+    pub fn decoded_part(&self, section: &str, encoding: &str) -> Option<Vec<u8>> {
+        self.section(section)
+            .map(|data| decode_transfer_encoding(data, encoding))
+    }
+}
+
+impl Fetch {
+    // Add a flag, unless the message already has it.
+    pub(crate) fn add_flag(&mut self, flag: Cow<'static, str>) {
+        if !self.flags.iter().any(|f| f.eq_ignore_ascii_case(&flag)) {
+            self.flags.push(flag);
+        }
+    }
+
+    // The Gmail attributes, to be filled in as they are parsed.
+    pub(crate) fn gmail_mut(&mut self) -> &mut GmailAttributes {
+        self.gmail.get_or_insert_with(Box::default)
+    }
+
+    // Record the data of a body section, replacing that of an earlier one with the same
+    // specifier.
+    pub(crate) fn add_section(&mut self, section: String, data: Cow<'static, [u8]>) {
+        self.sections.retain(|(s, _)| !s.eq_ignore_ascii_case(&section));
+        self.sections.push((section, data));
+    }
+}
+
+// Split a message header into its fields, unfolding continuation lines. Parsing stops at the
+// empty line that ends the header, and lines that are not fields are skipped.
+fn parse_headers(header: &[u8]) -> Vec<(String, String)> {
+    let header = String::from_utf8_lossy(header);
+    let mut fields: Vec<(String, String)> = Vec::new();
+    // whether the last line was a field, to which continuation lines belong
+    let mut in_field = false;
+    for line in header.split('\n') {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        if line.is_empty() {
+            break;
+        }
+        if line.starts_with(' ') || line.starts_with('\t') {
+            if let (true, Some((_, value))) = (in_field, fields.last_mut()) {
+                value.push_str(line);
+            }
+            continue;
+        }
+        in_field = match line.find(':') {
+            Some(colon) => {
+                let name = line[..colon].trim_end().to_string();
+                let value = line[colon + 1..].trim_start().to_string();
+                fields.push((name, value));
+                true
+            }
+            None => false,
+        };
+    }
+    for (_, value) in &mut fields {
+        let len = value.trim_end().len();
+        value.truncate(len);
+    }
+    fields
+}
+
+impl Clone for Fetch {
+    fn clone(&self) -> Fetch {
+        Fetch {
+            message: self.message,
+            flags: self.flags.iter().cloned().map(owned).collect(),
+            uid: self.uid,
+            rfc822_header: self.rfc822_header.clone().map(owned),
+            rfc822: self.rfc822.clone().map(owned),
+            body: self.body.clone().map(owned),
+            origin: self.origin,
+            mod_seq: self.mod_seq,
+            size: self.size,
+            internal_date: self.internal_date.clone().map(owned),
+            envelope: self.envelope.clone(),
+            body_structure: self.body_structure.clone().map(owned),
+            sections: self
+                .sections
+                .iter()
+                .map(|(section, data)| (section.clone(), owned(data.clone())))
+                .collect(),
+            gmail: self.gmail.clone(),
+        }
+    }
+}
+
+impl IntoOwned for Fetch {
+    type Owned = Fetch;
+    fn into_owned(self) -> Fetch {
+        Fetch {
+            message: self.message,
+            flags: self.flags.into_iter().map(owned).collect(),
+            uid: self.uid,
+            rfc822_header: self.rfc822_header.map(owned),
+            rfc822: self.rfc822.map(owned),
+            body: self.body.map(owned),
+            origin: self.origin,
+            mod_seq: self.mod_seq,
+            size: self.size,
+            internal_date: self.internal_date.map(owned),
+            envelope: self.envelope.map(|e| Box::new(e.into_owned())),
+            body_structure: self.body_structure.map(owned),
+            sections: self
+                .sections
+                .into_iter()
+                .map(|(section, data)| (section, owned(data)))
+                .collect(),
+            gmail: self.gmail,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_headers_unfolds() {
+        let header = b"Received: from a.example.org\r\n\
+            \tby b.example.org; Wed, 17 Jul 1996 02:23:25 -0700\r\n\
+            Received: from c.example.org\r\n\
+            Subject: a long\r\n   subject \r\n\
+            not a field\r\n\
+            \x20and its continuation\r\n\
+            X-Empty:\r\n\
+            \r\n\
+            Body: not a header\r\n";
+        assert_eq!(
+            parse_headers(header),
+            vec![
+                (
+                    "Received".to_string(),
+                    "from a.example.org\tby b.example.org; Wed, 17 Jul 1996 02:23:25 -0700"
+                        .to_string()
+                ),
+                ("Received".to_string(), "from c.example.org".to_string()),
+                ("Subject".to_string(), "a long   subject".to_string()),
+                ("X-Empty".to_string(), "".to_string()),
+            ]
+        );
+        assert!(parse_headers(b"").is_empty());
     }
 }
@@ -0,0 +1,313 @@
+use error::{Error, Result};
+
+/// A builder for the data items requested by a `FETCH` or `UID FETCH` command.
+///
+/// Note that fetching a body section with `BODY[...]` (or `RFC822`/`RFC822.TEXT`) implicitly
+/// sets the `\Seen` flag on the message, whereas `BODY.PEEK[...]` does not. Mixing the two in
+/// one query will thus still mark the message as read, which is easy to miss. `mixes_peek`
+/// reports whether a query does this, and in strict mode `build` refuses such queries with
+/// `Error::MixedPeek`.
+///
+/// ```
+/// # use imap::FetchQuery;
+/// let query = FetchQuery::new()
+///     .item("UID")
+///     .item("FLAGS")
+///     .body_section("HEADER", true);
+/// assert_eq!(query.build().unwrap(), "(UID FLAGS BODY.PEEK[HEADER])");
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FetchQuery {
+    items: Vec<String>,
+    strict: bool,
+}
+
+impl FetchQuery {
+    /// Create an empty query.
+    pub fn new() -> Self {
+        FetchQuery::default()
+    }
+
+    /// A query for the `FAST` macro, i.e. `FLAGS`, `INTERNALDATE`, and `RFC822.SIZE`.
+    pub fn fast() -> Self {
+        FetchQuery::new().item("FAST")
+    }
+
+    /// A query for the `ALL` macro, i.e. `FAST` and `ENVELOPE`.
+    pub fn all() -> Self {
+        FetchQuery::new().item("ALL")
+    }
+
+    /// A query for the `FULL` macro, i.e. `ALL` and `BODY` (the body structure).
+    pub fn full() -> Self {
+        FetchQuery::new().item("FULL")
+    }
+
+    /// Request a raw data item, such as `FLAGS`, `UID`, or `BODY[TEXT]`.
+    pub fn item(mut self, item: &str) -> Self {
+        self.items.push(item.to_string());
+        self
+    }
+
+    /// Request a body section (e.g. `HEADER`, `TEXT`, `1.2`, or the empty string for the whole
+    /// message). If `peek` is true, `BODY.PEEK` is used so that the `\Seen` flag is not set.
+    pub fn body_section(self, section: &str, peek: bool) -> Self {
+        let item = if peek {
+            format!("BODY.PEEK[{}]", section)
+        } else {
+            format!("BODY[{}]", section)
+        };
+        self.item(&item)
+    }
+
+    /// Request only the given header fields of the message, with `HEADER.FIELDS (...)`. The
+    /// fields are sent in upper case, which is how the data can be looked up with
+    /// `Fetch::section`, e.g. `section("HEADER.FIELDS (SUBJECT FROM)")`.
+    pub fn header_fields(self, fields: &[&str], peek: bool) -> Self {
+        self.body_section(&format!("HEADER.FIELDS {}", field_list(fields)), peek)
+    }
+
+    /// Request all header fields of the message *except* the given ones, with
+    /// `HEADER.FIELDS.NOT (...)`, e.g. to leave out long `Received` chains. As with
+    /// `header_fields`, the fields are sent in upper case, and the data can be looked up with
+    /// `Fetch::section`, e.g. `section("HEADER.FIELDS.NOT (RECEIVED)")`.
+    pub fn header_fields_not(self, fields: &[&str], peek: bool) -> Self {
+        self.body_section(&format!("HEADER.FIELDS.NOT {}", field_list(fields)), peek)
+    }
+
+    /// In strict mode, `build` returns an error instead of a query that mixes peek and non-peek
+    /// body sections.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Returns true if this query contains both `BODY.PEEK[...]` items and items that set the
+    /// `\Seen` flag (`BODY[...]`, `RFC822`, or `RFC822.TEXT`).
+    pub fn mixes_peek(&self) -> bool {
+        let peek = self.items.iter().any(|i| is_peek(i));
+        let non_peek = self.items.iter().any(|i| sets_seen(i));
+        peek && non_peek
+    }
+
+    /// Render the query as it should be passed to `Session::fetch`.
+    pub fn build(&self) -> Result<String> {
+        if self.strict && self.mixes_peek() {
+            return Err(Error::MixedPeek);
+        }
+
+        if self.items.len() == 1 {
+            return Ok(self.items[0].clone());
+        }
+
+        // a macro cannot be combined with other items, so spell it out
+        let mut items = Vec::with_capacity(self.items.len());
+        for item in &self.items {
+            match macro_items(item) {
+                Some(expanded) => items.extend(expanded.iter().map(|s| s.to_string())),
+                None => items.push(item.clone()),
+            }
+        }
+        Ok(format!("({})", items.join(" ")))
+    }
+}
+
+// The data items that a fetch macro (RFC 3501, section 6.4.5) stands for.
+fn macro_items(item: &str) -> Option<&'static [&'static str]> {
+    match &item.to_uppercase()[..] {
+        "FAST" => Some(&["FLAGS", "INTERNALDATE", "RFC822.SIZE"]),
+        "ALL" => Some(&["FLAGS", "INTERNALDATE", "RFC822.SIZE", "ENVELOPE"]),
+        "FULL" => Some(&["FLAGS", "INTERNALDATE", "RFC822.SIZE", "ENVELOPE", "BODY"]),
+        _ => None,
+    }
+}
+
+// The parenthesized list of header fields of a `HEADER.FIELDS` section.
+fn field_list(fields: &[&str]) -> String {
+    let fields: Vec<String> = fields.iter().map(|f| f.to_uppercase()).collect();
+    format!("({})", fields.join(" "))
+}
+
+fn is_peek(item: &str) -> bool {
+    item.to_uppercase().starts_with("BODY.PEEK[")
+}
+
+fn sets_seen(item: &str) -> bool {
+    let item = item.to_uppercase();
+    item.starts_with("BODY[") || item == "RFC822" || item == "RFC822.TEXT"
+}
+
+/// Add `UID` to the items of a fetch query, unless it already asks for it. Macros are spelled out,
+/// since they cannot be combined with other items.
+pub(crate) fn with_uid(query: &str) -> Result<String> {
+    let query = query.trim();
+    let inner = query
+        .strip_prefix('(')
+        .and_then(|q| q.strip_suffix(')'))
+        .unwrap_or(query);
+
+    // items are separated by spaces outside of section specifiers
+    let mut items = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in inner.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            ' ' if depth == 0 => {
+                items.push(&inner[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&inner[start..]);
+    items.retain(|i| !i.is_empty());
+
+    if items.iter().any(|i| i.eq_ignore_ascii_case("UID")) {
+        return Ok(query.to_string());
+    }
+    items
+        .into_iter()
+        .fold(FetchQuery::new().item("UID"), FetchQuery::item)
+        .build()
+}
+
+/// Rewrite the items of a fetch query that set the `\Seen` flag into their `BODY.PEEK` form:
+/// `BODY[...]` becomes `BODY.PEEK[...]`, `RFC822` becomes `BODY.PEEK[]`, and `RFC822.TEXT` becomes
+/// `BODY.PEEK[TEXT]`. Everything else is left untouched.
+pub(crate) fn peek_query(query: &str) -> String {
+    let mut peeked = String::with_capacity(query.len());
+    let mut rest = query;
+    while !rest.is_empty() {
+        let start = rest
+            .find(|c| c != ' ' && c != '(' && c != ')')
+            .unwrap_or(rest.len());
+        peeked.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        // an item ends at a space or closing parenthesis outside of a section specifier
+        let mut depth = 0;
+        let mut end = rest.len();
+        for (i, c) in rest.char_indices() {
+            match c {
+                '[' => depth += 1,
+                ']' => depth -= 1,
+                ' ' | ')' if depth == 0 => {
+                    end = i;
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        let item = &rest[..end];
+        let upper = item.to_uppercase();
+        if upper.starts_with("BODY[") {
+            peeked.push_str("BODY.PEEK");
+            peeked.push_str(&item[4..]);
+        } else if upper == "RFC822" {
+            peeked.push_str("BODY.PEEK[]");
+        } else if upper == "RFC822.TEXT" {
+            peeked.push_str("BODY.PEEK[TEXT]");
+        } else {
+            peeked.push_str(item);
+        }
+        rest = &rest[end..];
+    }
+    peeked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mixed_peek() {
+        let query = FetchQuery::new()
+            .body_section("HEADER", true)
+            .body_section("TEXT", false);
+        assert!(query.mixes_peek());
+        assert_eq!(query.build().unwrap(), "(BODY.PEEK[HEADER] BODY[TEXT])");
+
+        match query.strict(true).build() {
+            Err(Error::MixedPeek) => {}
+            r => panic!("expected MixedPeek, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn not_mixed_peek() {
+        let query = FetchQuery::new()
+            .item("UID")
+            .item("rfc822.header")
+            .body_section("TEXT", true)
+            .strict(true);
+        assert!(!query.mixes_peek());
+        assert_eq!(query.build().unwrap(), "(UID rfc822.header BODY.PEEK[TEXT])");
+
+        let query = FetchQuery::new().item("BODY[]").item("rfc822").strict(true);
+        assert!(!query.mixes_peek());
+        assert_eq!(query.build().unwrap(), "(BODY[] rfc822)");
+    }
+
+    #[test]
+    fn header_fields() {
+        let query = FetchQuery::new()
+            .item("UID")
+            .header_fields(&["Subject", "From"], true)
+            .header_fields_not(&["Received"], true);
+        assert_eq!(
+            query.build().unwrap(),
+            "(UID BODY.PEEK[HEADER.FIELDS (SUBJECT FROM)] BODY.PEEK[HEADER.FIELDS.NOT (RECEIVED)])"
+        );
+        assert_eq!(
+            FetchQuery::new()
+                .header_fields_not(&["Received", "dkim-signature"], false)
+                .build()
+                .unwrap(),
+            "BODY[HEADER.FIELDS.NOT (RECEIVED DKIM-SIGNATURE)]"
+        );
+    }
+
+    #[test]
+    fn macros() {
+        assert_eq!(FetchQuery::fast().build().unwrap(), "FAST");
+        assert_eq!(FetchQuery::all().build().unwrap(), "ALL");
+        assert_eq!(
+            FetchQuery::full().item("UID").build().unwrap(),
+            "(FLAGS INTERNALDATE RFC822.SIZE ENVELOPE BODY UID)"
+        );
+        assert_eq!(
+            FetchQuery::new().item("UID").item("fast").build().unwrap(),
+            "(UID FLAGS INTERNALDATE RFC822.SIZE)"
+        );
+    }
+
+    #[test]
+    fn uid() {
+        assert_eq!(with_uid("FLAGS").unwrap(), "(UID FLAGS)");
+        assert_eq!(with_uid("(uid FLAGS)").unwrap(), "(uid FLAGS)");
+        assert_eq!(
+            with_uid("(FLAGS BODY.PEEK[HEADER.FIELDS (UID)])").unwrap(),
+            "(UID FLAGS BODY.PEEK[HEADER.FIELDS (UID)])"
+        );
+        assert_eq!(
+            with_uid("ALL").unwrap(),
+            "(UID FLAGS INTERNALDATE RFC822.SIZE ENVELOPE)"
+        );
+    }
+
+    #[test]
+    fn peek() {
+        assert_eq!(peek_query("BODY[]"), "BODY.PEEK[]");
+        assert_eq!(
+            peek_query("(UID FLAGS body[HEADER.FIELDS (SUBJECT)] BODY.PEEK[1] BODY[2]<0.10>)"),
+            "(UID FLAGS BODY.PEEK[HEADER.FIELDS (SUBJECT)] BODY.PEEK[1] BODY.PEEK[2]<0.10>)"
+        );
+        assert_eq!(
+            peek_query("(RFC822.HEADER RFC822 RFC822.TEXT BODYSTRUCTURE)"),
+            "(RFC822.HEADER BODY.PEEK[] BODY.PEEK[TEXT] BODYSTRUCTURE)"
+        );
+    }
+}
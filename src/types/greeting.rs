@@ -0,0 +1,40 @@
+use error::ResponseText;
+
+/// The greeting that the server sends when a client connects, such as `* OK [CAPABILITY IMAP4rev1
+/// ...] Dovecot ready.`
+///
+/// A server that turns the connection away greets with `* BYE`, which connecting reports as
+/// `Error::Bye` instead.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Greeting {
+    pub(crate) status: GreetingStatus,
+    pub(crate) text: ResponseText,
+}
+
+/// Whether the connection still needs to be authenticated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum GreetingStatus {
+    /// `* OK`: the client must log in (or authenticate) first.
+    Ok,
+    /// `* PREAUTH`: the connection is already authenticated, e.g. because the server was started
+    /// for a logged-in user. See `Client::preauth`.
+    PreAuth,
+}
+
+impl Greeting {
+    /// Whether the server greeted with `OK` or `PREAUTH`.
+    pub fn status(&self) -> GreetingStatus {
+        self.status
+    }
+
+    /// The text of the greeting, with its response code (often `CAPABILITY ...`) and the
+    /// human-readable part, which usually names the server software.
+    pub fn text(&self) -> &ResponseText {
+        &self.text
+    }
+
+    /// Whether the connection is already authenticated.
+    pub fn is_preauth(&self) -> bool {
+        self.status == GreetingStatus::PreAuth
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+
+/// A string argument of a command, in the form that [RFC
+/// 3501](https://tools.ietf.org/html/rfc3501#section-4) allows for its value: an atom, a quoted
+/// string, or a literal.
+///
+/// Commands are built as text, so an argument is rendered with `Display`. A literal is rendered
+/// as its announcement, a line break, and its data (e.g. `{3}\r\nfoo`); the session then sends
+/// the data once the server has asked for it, or right away if the server supports `LITERAL+`.
+/// Commands with literals cannot be sent with `Session::pipeline`.
+///
+/// ```
+/// # use imap::ImapArg;
+/// assert_eq!(ImapArg::new("INBOX").to_string(), "INBOX");
+/// assert_eq!(ImapArg::new("weekly report").to_string(), "\"weekly report\"");
+/// assert_eq!(ImapArg::string("INBOX").to_string(), "\"INBOX\"");
+/// assert_eq!(ImapArg::new("two\r\nlines").to_string(), "{10}\r\ntwo\r\nlines");
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ImapArg<'a> {
+    /// A value that is sent as is, since it only consists of characters that are allowed in an
+    /// atom.
+    Atom(&'a str),
+    /// A value that is sent between double quotes, with quotes and backslashes escaped.
+    Quoted(&'a str),
+    /// A value that contains line breaks (CR or LF), which quoted strings cannot hold.
+    Literal(&'a str),
+}
+
+impl<'a> ImapArg<'a> {
+    /// The most compact form of `value` where the grammar allows an atom or a string (an
+    /// `astring`), such as a header field name.
+    pub fn new(value: &'a str) -> Self {
+        if !value.is_empty() && value.chars().all(is_atom_char) {
+            ImapArg::Atom(value)
+        } else {
+            ImapArg::string(value)
+        }
+    }
+
+    /// The form of `value` where the grammar requires a string, or where a value should be
+    /// quoted even if it could be an atom: a quoted string, or a literal if it contains line
+    /// breaks.
+    pub fn string(value: &'a str) -> Self {
+        if value.contains(['\r', '\n']) {
+            ImapArg::Literal(value)
+        } else {
+            ImapArg::Quoted(value)
+        }
+    }
+}
+
+impl<'a> fmt::Display for ImapArg<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ImapArg::Atom(value) => f.write_str(value),
+            ImapArg::Quoted(value) => write!(
+                f,
+                "\"{}\"",
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            ImapArg::Literal(value) => write!(f, "{{{}}}\r\n{}", value.len(), value),
+        }
+    }
+}
+
+// Whether `c` may appear in an atom, i.e. it is neither a control character, a space, nor one of
+// the `atom-specials` of RFC 3501.
+fn is_atom_char(c: char) -> bool {
+    c.is_ascii_graphic() && !"(){%*\"\\]".contains(c)
+}
+
+// Split a command into lines for `run_command_with_literals`, taking out the literals rendered
+// by `ImapArg` (a `{<length>}` announcement that ends a line, followed by that many octets).
+pub(crate) fn split_literals(command: &str) -> (Vec<String>, Vec<Vec<u8>>) {
+    let mut lines = vec![String::new()];
+    let mut literals = Vec::new();
+    let mut rest = command;
+    while let Some(end) = rest.find("}\r\n") {
+        let start = rest[..end].rfind('{');
+        let len = start.and_then(|start| rest[start + 1..end].parse::<usize>().ok());
+        match (start, len) {
+            (Some(start), Some(len)) if rest.len() >= end + 3 + len => {
+                let data = &rest.as_bytes()[end + 3..end + 3 + len];
+                lines.last_mut().unwrap().push_str(&rest[..start]);
+                literals.push(data.to_vec());
+                lines.push(String::new());
+                rest = &rest[end + 3 + len..];
+            }
+            _ => {
+                lines.last_mut().unwrap().push_str(&rest[..end + 3]);
+                rest = &rest[end + 3..];
+            }
+        }
+    }
+    lines.last_mut().unwrap().push_str(rest);
+    (lines, literals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forms() {
+        assert_eq!(ImapArg::new("X-Spam"), ImapArg::Atom("X-Spam"));
+        assert_eq!(ImapArg::new(""), ImapArg::Quoted(""));
+        assert_eq!(ImapArg::new("a]b"), ImapArg::Quoted("a]b"));
+        assert_eq!(ImapArg::new("Gel\u{f6}scht"), ImapArg::Quoted("Gel\u{f6}scht"));
+        assert_eq!(ImapArg::new("a\nb"), ImapArg::Literal("a\nb"));
+        assert_eq!(
+            ImapArg::string("say \"hi\" to C:\\").to_string(),
+            "\"say \\\"hi\\\" to C:\\\\\""
+        );
+    }
+
+    #[test]
+    fn split() {
+        let (lines, literals) = split_literals("SEARCH SUBJECT {12}\r\nline1\r\nline2 UNSEEN");
+        assert_eq!(lines, vec!["SEARCH SUBJECT ", " UNSEEN"]);
+        assert_eq!(literals, vec![b"line1\r\nline2".to_vec()]);
+
+        // literals may contain what looks like an announcement
+        let (lines, literals) = split_literals("X {5}\r\n{0}\r\n {0}\r\n");
+        assert_eq!(lines, vec!["X ", " ", ""]);
+        assert_eq!(literals, vec![b"{0}\r\n".to_vec(), Vec::new()]);
+
+        let (lines, literals) = split_literals("NOOP");
+        assert_eq!(lines, vec!["NOOP"]);
+        assert!(literals.is_empty());
+    }
+}
@@ -1,43 +1,58 @@
 use std::fmt;
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
 pub struct Mailbox {
     pub flags: Vec<String>,
     pub exists: u32,
     pub recent: u32,
-    pub unseen: Option<u32>,
+    /// The sequence number of the first message without the `\Seen` flag, from `[UNSEEN n]` in
+    /// the response to `SELECT` or `EXAMINE`. Note that this is *not* the number of unseen
+    /// messages; that is `unseen_count`.
+    pub first_unseen: Option<u32>,
+    /// The number of messages without the `\Seen` flag. Servers only report this in response to
+    /// `STATUS` with the `UNSEEN` item, e.g. `session.status("INBOX", "(UNSEEN)")`, never when
+    /// selecting a mailbox; see also `Session::unread_counts`.
+    pub unseen_count: Option<u32>,
+    /// The flags that can be changed permanently, from `[PERMANENTFLAGS (...)]`. The special
+    /// `\*` is not included; see `can_create_keywords`.
     pub permanent_flags: Vec<String>,
     pub uid_next: Option<u32>,
     pub uid_validity: Option<u32>,
-}
-
-impl Default for Mailbox {
-    fn default() -> Mailbox {
-        Mailbox {
-            flags: Vec::new(),
-            exists: 0,
-            recent: 0,
-            unseen: None,
-            permanent_flags: Vec::new(),
-            uid_next: None,
-            uid_validity: None,
-        }
-    }
+    /// The highest mod-sequence of any message in the mailbox, from `[HIGHESTMODSEQ n]` (or the
+    /// `HIGHESTMODSEQ` item of `STATUS`), if the server supports CONDSTORE ([RFC
+    /// 7162](https://tools.ietf.org/html/rfc7162)).
+    pub highest_mod_seq: Option<u64>,
+    /// Whether the server said `[NOMODSEQ]`, i.e. that this mailbox does not keep mod-sequences,
+    /// so it has to be synchronized without CONDSTORE.
+    pub no_mod_seq: bool,
+    /// Whether the mailbox was opened read-only, because it was opened with `EXAMINE`, or
+    /// because the server said `[READ-ONLY]` (e.g. when the user may not modify it).
+    pub read_only: bool,
+    /// Whether new keywords can be created by storing them on a message, as indicated by `\*`
+    /// in `[PERMANENTFLAGS (...)]`.
+    pub can_create_keywords: bool,
 }
 
 impl fmt::Display for Mailbox {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "flags: {:?}, exists: {}, recent: {}, unseen: {:?}, permanent_flags: {:?},\
-             uid_next: {:?}, uid_validity: {:?}",
+            "flags: {:?}, exists: {}, recent: {}, first_unseen: {:?}, \
+             unseen_count: {:?}, permanent_flags: {:?},\
+             uid_next: {:?}, uid_validity: {:?}, highest_mod_seq: {:?}, no_mod_seq: {}, \
+             read_only: {}, can_create_keywords: {}",
             self.flags,
             self.exists,
             self.recent,
-            self.unseen,
+            self.first_unseen,
+            self.unseen_count,
             self.permanent_flags,
             self.uid_next,
-            self.uid_validity
+            self.uid_validity,
+            self.highest_mod_seq,
+            self.no_mod_seq,
+            self.read_only,
+            self.can_create_keywords
         )
     }
 }
@@ -0,0 +1,24 @@
+/// The status of a mailbox, as reported by an untagged `STATUS` response.
+///
+/// The server only reports the items that were asked for (e.g. through the `NOTIFY` command of
+/// [RFC 5465](https://tools.ietf.org/html/rfc5465)), so all of them are optional.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MailboxStatus {
+    /// The name of the mailbox.
+    pub mailbox: String,
+    /// The number of messages in the mailbox.
+    pub messages: Option<u32>,
+    /// The number of messages with the `\Recent` flag set.
+    pub recent: Option<u32>,
+    /// The next unique identifier value of the mailbox.
+    pub uid_next: Option<u32>,
+    /// The unique identifier validity value of the mailbox.
+    pub uid_validity: Option<u32>,
+    /// The number of messages which do not have the `\Seen` flag set.
+    pub unseen: Option<u32>,
+    /// The highest mod-sequence of any message in the mailbox, if the server supports CONDSTORE
+    /// ([RFC 7162](https://tools.ietf.org/html/rfc7162#section-3.1.6)). It changes whenever a
+    /// message in the mailbox changes, so comparing it to the last known value tells whether the
+    /// mailbox needs to be synchronized.
+    pub highest_mod_seq: Option<u64>,
+}
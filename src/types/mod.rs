@@ -1,15 +1,62 @@
 mod mailbox;
 pub use self::mailbox::Mailbox;
 
+mod mailbox_status;
+pub use self::mailbox_status::MailboxStatus;
+
+mod envelope;
+pub use self::envelope::{Address, Envelope};
+
 mod fetch;
 pub use self::fetch::Fetch;
 
+mod fetch_query;
+pub use self::fetch_query::FetchQuery;
+pub(crate) use self::fetch_query::{peek_query, with_uid};
+
+mod imap_arg;
+pub use self::imap_arg::ImapArg;
+pub(crate) use self::imap_arg::split_literals;
+
+mod greeting;
+pub use self::greeting::{Greeting, GreetingStatus};
+
 mod name;
-pub use self::name::Name;
+pub use self::name::{Name, SpecialUse};
+
+mod append;
+pub use self::append::{AppendMessage, AppendUid};
+
+mod annotation;
+pub use self::annotation::Annotation;
 
 mod capabilities;
 pub use self::capabilities::Capabilities;
 
+mod notify;
+pub use self::notify::NotifySpec;
+
+mod quota;
+pub use self::quota::Quota;
+
+mod resync;
+pub use self::resync::Resync;
+
+mod search_query;
+pub use self::search_query::{SearchDate, SearchKey, SearchQuery};
+
+mod search_result;
+pub use self::search_result::SearchResult;
+
+mod sequence_set;
+pub use self::sequence_set::SequenceSet;
+
+mod untagged_responses;
+pub use self::untagged_responses::UntaggedResponses;
+
+mod unsolicited_response;
+pub use self::unsolicited_response::UnsolicitedResponse;
+
 pub struct ZeroCopy<D> {
     owned: Box<[u8]>,
     derived: D,
@@ -48,6 +95,51 @@ impl<D> ZeroCopy<D> {
     }
 }
 
+impl<D: IntoOwned> ZeroCopy<D> {
+    /// Copy all the data borrowed from the underlying buffer, so that the result is no longer
+    /// tied to the lifetime of this `ZeroCopy`.
+    ///
+    /// This is useful for keeping parsed responses around (e.g., in a cache) after the response
+    /// buffer would otherwise have been dropped.
+    pub fn into_owned(self) -> D::Owned {
+        // `derived` may still point into `owned` here, so `owned` must outlive the conversion.
+        let ZeroCopy { owned, derived } = self;
+        let result = derived.into_owned();
+        drop(owned);
+        result
+    }
+}
+
+/// Parsed types that (may) borrow from a `ZeroCopy` buffer, and that can be converted into a
+/// version that owns all of its data.
+pub trait IntoOwned {
+    /// The fully owned version of this type.
+    type Owned: 'static;
+
+    /// Copy any borrowed data so that the result can outlive the buffer it was parsed from.
+    fn into_owned(self) -> Self::Owned;
+}
+
+impl<T: IntoOwned> IntoOwned for Vec<T> {
+    type Owned = Vec<T::Owned>;
+    fn into_owned(self) -> Self::Owned {
+        self.into_iter().map(IntoOwned::into_owned).collect()
+    }
+}
+
+// search results are numbers only
+impl IntoOwned for ::std::collections::HashSet<u32> {
+    type Owned = Self;
+    fn into_owned(self) -> Self {
+        self
+    }
+}
+
+use std::borrow::Cow;
+pub(crate) fn owned<B: ?Sized + ToOwned>(c: Cow<'static, B>) -> Cow<'static, B> {
+    Cow::Owned(c.into_owned())
+}
+
 use super::error::Error;
 pub type ZeroCopyResult<T> = Result<ZeroCopy<T>, Error>;
 
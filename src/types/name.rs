@@ -1,22 +1,179 @@
+use std::borrow::Cow;
+
+use super::{owned, IntoOwned};
+
 // Note that none of these fields are *actually* 'static.
 // Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Name`.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Name {
-    pub(crate) attributes: Vec<&'static str>,
-    pub(crate) delimiter: &'static str,
-    pub(crate) name: &'static str,
+    pub(crate) attributes: Vec<Cow<'static, str>>,
+    pub(crate) delimiter: Cow<'static, str>,
+    pub(crate) name: Cow<'static, str>,
+    // the name decoded from modified UTF-7, if it differs from the raw one
+    pub(crate) decoded: Option<String>,
+    pub(crate) child_info: Vec<Cow<'static, str>>,
 }
 
 impl Name {
-    pub fn attributes(&self) -> &[&str] {
+    pub fn attributes(&self) -> &[Cow<'_, str>] {
         &self.attributes[..]
     }
 
     pub fn delimiter(&self) -> &str {
-        self.delimiter
+        &self.delimiter
     }
 
+    /// The name of the mailbox, decoded from the modified UTF-7 that IMAP uses for mailbox names
+    /// (e.g. `Gelöschte` rather than `Gel&APY-schte`). If the name cannot be decoded, it is
+    /// returned as sent by the server. Once `UTF8=ACCEPT` is enabled (see `Session::enable`), the
+    /// server sends names in UTF-8, and they are returned as is.
     pub fn name(&self) -> &str {
-        self.name
+        self.decoded.as_deref().unwrap_or(&self.name)
+    }
+
+    /// The name of the mailbox as sent by the server, i.e. still encoded in modified UTF-7.
+    pub fn raw_name(&self) -> &str {
+        &self.name
+    }
+
+    /// The selection criteria (such as `SUBSCRIBED`) that some descendant of this mailbox
+    /// matches, as reported by the `CHILDINFO` extended data item of an RFC 5258 `LIST` with the
+    /// `RECURSIVEMATCH` selection option.
+    ///
+    /// This lets a mailbox that does not itself match the criteria (e.g. an unsubscribed parent
+    /// of a subscribed mailbox) be told apart from one that does.
+    pub fn child_info(&self) -> &[Cow<'_, str>] {
+        &self.child_info[..]
+    }
+
+    /// Whether this mailbox has child mailboxes, as reported by the `\HasChildren` and
+    /// `\HasNoChildren` attributes of the CHILDREN extension ([RFC
+    /// 3348](https://tools.ietf.org/html/rfc3348)). A mailbox marked `\NoInferiors` cannot have
+    /// children at all. `None` if the server did not say.
+    ///
+    /// This tells e.g. a folder tree whether to show an expander without having to list the
+    /// children first.
+    pub fn has_children(&self) -> Option<bool> {
+        self.attributes.iter().find_map(|a| {
+            if a.eq_ignore_ascii_case("\\HasChildren") {
+                Some(true)
+            } else if a.eq_ignore_ascii_case("\\HasNoChildren")
+                || a.eq_ignore_ascii_case("\\NoInferiors")
+            {
+                Some(false)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether this mailbox has been marked by the server as probably having new messages since
+    /// it was last selected (`\Marked`), or as not having any (`\Unmarked`). `None` if the
+    /// server did not say.
+    ///
+    /// Clients can use this to decide which mailboxes to sync first.
+    pub fn is_marked(&self) -> Option<bool> {
+        self.attributes.iter().find_map(|a| {
+            if a.eq_ignore_ascii_case("\\Marked") {
+                Some(true)
+            } else if a.eq_ignore_ascii_case("\\Unmarked") {
+                Some(false)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether this mailbox can be selected, i.e. it has neither the `\Noselect` attribute (a
+    /// node in the hierarchy that holds no messages) nor the `\NonExistent` attribute of [RFC
+    /// 5258](https://tools.ietf.org/html/rfc5258) (a name that is only listed because of its
+    /// children or its subscription).
+    ///
+    /// This saves a failing `SELECT` when walking a tree of mailboxes.
+    pub fn is_selectable(&self) -> bool {
+        !self.attributes.iter().any(|a| {
+            a.eq_ignore_ascii_case("\\Noselect") || a.eq_ignore_ascii_case("\\NonExistent")
+        })
+    }
+
+    /// The special use of this mailbox, as given by its attributes (see [RFC
+    /// 6154](https://tools.ietf.org/html/rfc6154)).
+    ///
+    /// This is the portable way to find e.g. the folder for sent messages, since folder names are
+    /// often localized.
+    pub fn special_use(&self) -> Option<SpecialUse> {
+        self.attributes
+            .iter()
+            .filter_map(|a| SpecialUse::from_attribute(a))
+            .next()
+    }
+
+    /// Whether this mailbox holds copies of messages that have been sent (`\Sent`).
+    pub fn is_sent(&self) -> bool {
+        self.special_use() == Some(SpecialUse::Sent)
+    }
+
+    /// Whether this mailbox holds messages that have been deleted (`\Trash`).
+    pub fn is_trash(&self) -> bool {
+        self.special_use() == Some(SpecialUse::Trash)
+    }
+
+    /// Whether this mailbox holds message drafts (`\Drafts`).
+    pub fn is_drafts(&self) -> bool {
+        self.special_use() == Some(SpecialUse::Drafts)
+    }
+
+    /// Whether this mailbox holds messages that were deemed to be spam (`\Junk`).
+    pub fn is_junk(&self) -> bool {
+        self.special_use() == Some(SpecialUse::Junk)
+    }
+}
+
+/// The special uses of a mailbox defined by [RFC 6154](https://tools.ietf.org/html/rfc6154).
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum SpecialUse {
+    /// `\All`: a virtual mailbox containing all messages in the message store.
+    All,
+    /// `\Archive`: the mailbox used to archive messages.
+    Archive,
+    /// `\Drafts`: the mailbox used to hold draft messages.
+    Drafts,
+    /// `\Flagged`: a virtual mailbox containing all messages with the `\Flagged` flag.
+    Flagged,
+    /// `\Junk`: the mailbox where messages deemed to be junk mail are held.
+    Junk,
+    /// `\Sent`: the mailbox used to hold copies of messages that have been sent.
+    Sent,
+    /// `\Trash`: the mailbox used to hold messages that have been deleted.
+    Trash,
+}
+
+impl SpecialUse {
+    /// The special use indicated by the given mailbox attribute (e.g. `\Sent`), if any.
+    pub fn from_attribute(attribute: &str) -> Option<SpecialUse> {
+        let attribute = attribute.to_lowercase();
+        Some(match &*attribute {
+            "\\all" => SpecialUse::All,
+            "\\archive" => SpecialUse::Archive,
+            "\\drafts" => SpecialUse::Drafts,
+            "\\flagged" => SpecialUse::Flagged,
+            "\\junk" => SpecialUse::Junk,
+            "\\sent" => SpecialUse::Sent,
+            "\\trash" => SpecialUse::Trash,
+            _ => return None,
+        })
+    }
+}
+
+impl IntoOwned for Name {
+    type Owned = Name;
+    fn into_owned(self) -> Name {
+        Name {
+            attributes: self.attributes.into_iter().map(owned).collect(),
+            delimiter: owned(self.delimiter),
+            name: owned(self.name),
+            decoded: self.decoded,
+            child_info: self.child_info.into_iter().map(owned).collect(),
+        }
     }
 }
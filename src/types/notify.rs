@@ -0,0 +1,123 @@
+/// The events that a server should report through `NOTIFY` ([RFC
+/// 5465](https://tools.ietf.org/html/rfc5465)), for use with `Session::notify`.
+///
+/// Each call adds a set of mailboxes and the events to report for them, such as `MessageNew`,
+/// `MessageExpunge`, `FlagChange`, `MailboxName`, or `SubscriptionChange`. An empty list of
+/// events asks for no events at all for those mailboxes.
+///
+/// ```
+/// # use imap::NotifySpec;
+/// let spec = NotifySpec::new()
+///     .selected(&["MessageNew", "MessageExpunge", "FlagChange"])
+///     .personal(&["MessageNew", "MessageExpunge"])
+///     .status(true);
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NotifySpec {
+    status: bool,
+    // the mailbox filter, the names of the mailboxes it lists (if any), and the events
+    groups: Vec<(String, Vec<String>, Vec<String>)>,
+}
+
+impl NotifySpec {
+    /// A specification without any mailboxes, to which they are added with the other methods.
+    pub fn new() -> Self {
+        NotifySpec::default()
+    }
+
+    /// Have the server send the `STATUS` of each of the mailboxes right away (except for the
+    /// selected one), so that the client starts out with their current state.
+    pub fn status(mut self, status: bool) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Report events for the selected mailbox, as they happen.
+    pub fn selected(self, events: &[&str]) -> Self {
+        self.group("SELECTED", &[], events)
+    }
+
+    /// Report events for the selected mailbox, but only once a command allows the server to
+    /// report expunges (i.e. not during `FETCH`, `STORE`, or `SEARCH`).
+    pub fn selected_delayed(self, events: &[&str]) -> Self {
+        self.group("SELECTED-DELAYED", &[], events)
+    }
+
+    /// Report events for all mailboxes that receive new messages, such as the `INBOX`.
+    pub fn inboxes(self, events: &[&str]) -> Self {
+        self.group("INBOXES", &[], events)
+    }
+
+    /// Report events for all mailboxes in the user's personal namespace.
+    pub fn personal(self, events: &[&str]) -> Self {
+        self.group("PERSONAL", &[], events)
+    }
+
+    /// Report events for all subscribed mailboxes.
+    pub fn subscribed(self, events: &[&str]) -> Self {
+        self.group("SUBSCRIBED", &[], events)
+    }
+
+    /// Report events for the given mailboxes.
+    pub fn mailboxes(self, mailboxes: &[&str], events: &[&str]) -> Self {
+        self.group("MAILBOXES", mailboxes, events)
+    }
+
+    /// Report events for the given mailboxes and all mailboxes below them.
+    pub fn subtree(self, mailboxes: &[&str], events: &[&str]) -> Self {
+        self.group("SUBTREE", mailboxes, events)
+    }
+
+    fn group(mut self, filter: &str, mailboxes: &[&str], events: &[&str]) -> Self {
+        self.groups.push((
+            filter.to_string(),
+            mailboxes.iter().map(|m| m.to_string()).collect(),
+            events.iter().map(|e| e.to_string()).collect(),
+        ));
+        self
+    }
+
+    // The arguments of `NOTIFY SET`, with mailbox names encoded by `quote`.
+    pub(crate) fn arguments<F: Fn(&str) -> String>(&self, quote: F) -> String {
+        let mut arguments = String::new();
+        if self.status {
+            arguments.push_str("STATUS");
+        }
+        for (filter, mailboxes, events) in &self.groups {
+            if !arguments.is_empty() {
+                arguments.push(' ');
+            }
+            arguments.push('(');
+            arguments.push_str(filter);
+            if !mailboxes.is_empty() {
+                let mailboxes: Vec<String> = mailboxes.iter().map(|m| quote(m)).collect();
+                arguments.push_str(&format!(" ({})", mailboxes.join(" ")));
+            }
+            if events.is_empty() {
+                arguments.push_str(" NONE)");
+            } else {
+                arguments.push_str(&format!(" ({}))", events.join(" ")));
+            }
+        }
+        arguments
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments() {
+        let spec = NotifySpec::new()
+            .status(true)
+            .selected(&["MessageNew", "MessageExpunge"])
+            .mailboxes(&["Lists", "Work"], &["MessageNew"])
+            .subscribed(&[]);
+        assert_eq!(
+            spec.arguments(|m| format!("\"{}\"", m)),
+            "STATUS (SELECTED (MessageNew MessageExpunge)) \
+             (MAILBOXES (\"Lists\" \"Work\") (MessageNew)) (SUBSCRIBED NONE)"
+        );
+    }
+}
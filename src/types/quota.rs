@@ -0,0 +1,49 @@
+use std::borrow::Cow;
+
+use super::{owned, IntoOwned};
+
+/// The usage and limits of a quota root, as returned by `GETQUOTA` and `GETQUOTAROOT` (see
+/// [`Session::get_quota`](../client/struct.Session.html#method.get_quota)).
+// Note that none of these fields are *actually* 'static.
+// Rather, they are tied to the lifetime of the `ZeroCopy` that contains this `Quota`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct Quota {
+    pub(crate) root: Cow<'static, str>,
+    pub(crate) resources: Vec<(Cow<'static, str>, u64, u64)>,
+}
+
+impl Quota {
+    /// The name of the quota root, which is often the empty string.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// The name (such as `STORAGE` or `MESSAGE`), usage, and limit of each resource that the
+    /// root limits. `STORAGE` is counted in units of 1024 octets, and `MESSAGE` in messages;
+    /// servers may define resources of their own.
+    pub fn resources(&self) -> &[(Cow<'_, str>, u64, u64)] {
+        &self.resources[..]
+    }
+
+    /// The usage and limit of the given resource, if the root limits it.
+    pub fn resource(&self, name: &str) -> Option<(u64, u64)> {
+        self.resources
+            .iter()
+            .find(|(n, _, _)| n.eq_ignore_ascii_case(name))
+            .map(|&(_, usage, limit)| (usage, limit))
+    }
+}
+
+impl IntoOwned for Quota {
+    type Owned = Quota;
+    fn into_owned(self) -> Quota {
+        Quota {
+            root: owned(self.root),
+            resources: self
+                .resources
+                .into_iter()
+                .map(|(name, usage, limit)| (owned(name), usage, limit))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,14 @@
+use super::{Fetch, Mailbox, SequenceSet};
+
+/// A mailbox selected with `Session::select_qresync`, along with what changed in it since the
+/// state the client already knew.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Resync {
+    /// The selected mailbox, as `Session::select` would return it.
+    pub mailbox: Mailbox,
+    /// The UIDs of the known messages that have been expunged since, from `* VANISHED (EARLIER)`.
+    pub vanished: SequenceSet,
+    /// The messages that were added or whose flags changed since, each with its UID, flags, and
+    /// mod-sequence.
+    pub changed: Vec<Fetch>,
+}
@@ -0,0 +1,344 @@
+use std::fmt;
+
+use super::{ImapArg, SequenceSet};
+
+/// A calendar date for the date-based search keys, rendered as IMAP expects (e.g. `1-Jan-2020`).
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SearchDate {
+    year: u16,
+    month: u8,
+    day: u8,
+}
+
+impl SearchDate {
+    /// A date from its year, month (1 to 12), and day of the month.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `month` is not between 1 and 12, or `day` is not a day of that month (e.g. 30
+    /// for February, or 29 outside of leap years).
+    pub fn new(year: u16, month: u8, day: u8) -> Self {
+        assert!((1..=12).contains(&month), "invalid month {}", month);
+        assert!(
+            day >= 1 && day <= days_in_month(year, month),
+            "invalid day {} of month {}",
+            day,
+            month
+        );
+        SearchDate { year, month, day }
+    }
+}
+
+fn days_in_month(year: u16, month: u8) -> u8 {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl fmt::Display for SearchDate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        write!(
+            f,
+            "{}-{}-{}",
+            self.day,
+            MONTHS[usize::from(self.month) - 1],
+            self.year
+        )
+    }
+}
+
+/// A search key of the `SEARCH` command, as defined in [section 6.4.4 of RFC
+/// 3501](https://tools.ietf.org/html/rfc3501#section-6.4.4).
+///
+/// String arguments are always sent quoted, so they may contain spaces and quotes, or as literals
+/// if they contain line breaks (see `ImapArg`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SearchKey {
+    /// All messages in the mailbox.
+    All,
+    /// Messages whose `From` header contains the given string.
+    From(String),
+    /// Messages whose `To` header contains the given string.
+    To(String),
+    /// Messages whose `Subject` header contains the given string.
+    Subject(String),
+    /// Messages whose body contains the given string.
+    Body(String),
+    /// Messages whose internal date is on or after the given date.
+    ///
+    /// The internal date is when the message arrived in the mailbox (or the date given to
+    /// `APPEND`), which can be long after it was sent, e.g. for mail that was delayed or
+    /// migrated from another server. See `SentSince` for the date the message was sent.
+    Since(SearchDate),
+    /// Messages whose internal date is before the given date.
+    Before(SearchDate),
+    /// Messages whose internal date is the given date.
+    On(SearchDate),
+    /// Messages whose `Date` header, i.e. the date they were sent, is on or after the given
+    /// date (ignoring its time and time zone). See `Since` for the date the message arrived.
+    SentSince(SearchDate),
+    /// Messages whose `Date` header is before the given date.
+    SentBefore(SearchDate),
+    /// Messages whose `Date` header is the given date.
+    SentOn(SearchDate),
+    /// Messages with the `\Seen` flag set.
+    Seen,
+    /// Messages without the `\Seen` flag set.
+    Unseen,
+    /// Messages with the given header field (first) containing the given string (second). An
+    /// empty string matches all messages that have the field.
+    Header(String, String),
+    /// Messages whose UIDs are in the given set.
+    Uid(SequenceSet),
+    /// Messages whose internal date is at least the given number of seconds ago. This needs the
+    /// `WITHIN` extension ([RFC 5032](https://tools.ietf.org/html/rfc5032)).
+    Older(u32),
+    /// Messages whose internal date is at most the given number of seconds ago. This needs the
+    /// `WITHIN` extension ([RFC 5032](https://tools.ietf.org/html/rfc5032)).
+    Younger(u32),
+    /// Messages that match all of the given keys.
+    And(Vec<SearchKey>),
+    /// Messages that match either of the given keys.
+    Or(Box<SearchKey>, Box<SearchKey>),
+    /// Messages that do not match the given key.
+    Not(Box<SearchKey>),
+}
+
+impl SearchKey {
+    // Whether this key, or any key within it, needs the `WITHIN` extension.
+    fn needs_within(&self) -> bool {
+        match *self {
+            SearchKey::Older(_) | SearchKey::Younger(_) => true,
+            SearchKey::And(ref keys) => keys.iter().any(SearchKey::needs_within),
+            SearchKey::Or(ref a, ref b) => a.needs_within() || b.needs_within(),
+            SearchKey::Not(ref key) => key.needs_within(),
+            _ => false,
+        }
+    }
+}
+
+fn quoted(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "{}", ImapArg::string(s))
+}
+
+impl fmt::Display for SearchKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SearchKey::All => f.write_str("ALL"),
+            SearchKey::From(ref s) => {
+                f.write_str("FROM ")?;
+                quoted(f, s)
+            }
+            SearchKey::To(ref s) => {
+                f.write_str("TO ")?;
+                quoted(f, s)
+            }
+            SearchKey::Subject(ref s) => {
+                f.write_str("SUBJECT ")?;
+                quoted(f, s)
+            }
+            SearchKey::Body(ref s) => {
+                f.write_str("BODY ")?;
+                quoted(f, s)
+            }
+            SearchKey::Since(date) => write!(f, "SINCE {}", date),
+            SearchKey::Before(date) => write!(f, "BEFORE {}", date),
+            SearchKey::On(date) => write!(f, "ON {}", date),
+            SearchKey::SentSince(date) => write!(f, "SENTSINCE {}", date),
+            SearchKey::SentBefore(date) => write!(f, "SENTBEFORE {}", date),
+            SearchKey::SentOn(date) => write!(f, "SENTON {}", date),
+            SearchKey::Seen => f.write_str("SEEN"),
+            SearchKey::Unseen => f.write_str("UNSEEN"),
+            SearchKey::Header(ref name, ref value) => {
+                f.write_str("HEADER ")?;
+                quoted(f, name)?;
+                f.write_str(" ")?;
+                quoted(f, value)
+            }
+            SearchKey::Uid(ref set) => write!(f, "UID {}", set),
+            SearchKey::Older(secs) => write!(f, "OLDER {}", secs),
+            SearchKey::Younger(secs) => write!(f, "YOUNGER {}", secs),
+            SearchKey::And(ref keys) if keys.is_empty() => f.write_str("ALL"),
+            SearchKey::And(ref keys) => {
+                f.write_str("(")?;
+                for (i, key) in keys.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", key)?;
+                }
+                f.write_str(")")
+            }
+            SearchKey::Or(ref a, ref b) => write!(f, "OR {} {}", a, b),
+            SearchKey::Not(ref key) => write!(f, "NOT {}", key),
+        }
+    }
+}
+
+/// The criteria of a `SEARCH` command, which `Session::search` and `Session::uid_search` accept.
+///
+/// A query is either built from `SearchKey`s, all of which a message must match, or converted
+/// from a raw string, which is sent as is.
+///
+/// ```
+/// # use imap::{SearchDate, SearchKey, SearchQuery};
+/// let query = SearchQuery::new()
+///     .key(SearchKey::Subject("weekly report".to_string()))
+///     .key(SearchKey::Since(SearchDate::new(2020, 1, 1)))
+///     .key(SearchKey::Unseen);
+/// assert_eq!(query.to_string(), "SUBJECT \"weekly report\" SINCE 1-Jan-2020 UNSEEN");
+///
+/// let query = SearchQuery::from("FROM alice UNSEEN");
+/// assert_eq!(query.to_string(), "FROM alice UNSEEN");
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchQuery {
+    criteria: String,
+    // whether any of the keys needs the `WITHIN` extension
+    within: bool,
+}
+
+impl SearchQuery {
+    /// Create an empty query, which matches all messages.
+    pub fn new() -> Self {
+        SearchQuery::default()
+    }
+
+    /// Also require messages to match the given key.
+    pub fn key(mut self, key: SearchKey) -> Self {
+        if !self.criteria.is_empty() {
+            self.criteria.push(' ');
+        }
+        self.criteria.push_str(&key.to_string());
+        self.within |= key.needs_within();
+        self
+    }
+
+    /// Whether the query uses `SearchKey::Older` or `SearchKey::Younger`, which need the server
+    /// to support the `WITHIN` extension. This is not known for raw strings.
+    pub fn needs_within(&self) -> bool {
+        self.within
+    }
+
+    /// The criteria as they are sent to the server.
+    pub fn as_str(&self) -> &str {
+        if self.criteria.is_empty() {
+            "ALL"
+        } else {
+            &self.criteria
+        }
+    }
+}
+
+impl fmt::Display for SearchQuery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl From<SearchKey> for SearchQuery {
+    fn from(key: SearchKey) -> Self {
+        SearchQuery::new().key(key)
+    }
+}
+
+impl<'a> From<&'a str> for SearchQuery {
+    fn from(criteria: &'a str) -> Self {
+        SearchQuery {
+            criteria: criteria.to_string(),
+            within: false,
+        }
+    }
+}
+
+impl From<String> for SearchQuery {
+    fn from(criteria: String) -> Self {
+        SearchQuery {
+            criteria,
+            within: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        let key = SearchKey::Subject("say \"hi\" to C:\\".to_string());
+        assert_eq!(key.to_string(), "SUBJECT \"say \\\"hi\\\" to C:\\\\\"");
+    }
+
+    #[test]
+    fn nested() {
+        let key = SearchKey::Or(
+            Box::new(SearchKey::From("alice".to_string())),
+            Box::new(SearchKey::And(vec![
+                SearchKey::Header("X-Spam".to_string(), String::new()),
+                SearchKey::Not(Box::new(SearchKey::Seen)),
+            ])),
+        );
+        assert_eq!(
+            key.to_string(),
+            "OR FROM \"alice\" (HEADER \"X-Spam\" \"\" NOT SEEN)"
+        );
+
+        let query = SearchQuery::from(SearchKey::Uid("1:5".parse().unwrap()))
+            .key(SearchKey::On(SearchDate::new(2019, 12, 24)));
+        assert_eq!(query.as_str(), "UID 1:5 ON 24-Dec-2019");
+        assert_eq!(SearchQuery::new().as_str(), "ALL");
+    }
+
+    #[test]
+    fn dates() {
+        let months = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        for (i, name) in months.iter().enumerate() {
+            let date = SearchDate::new(2020, i as u8 + 1, 7);
+            assert_eq!(date.to_string(), format!("7-{}-2020", name));
+        }
+        assert_eq!(SearchDate::new(1999, 12, 31).to_string(), "31-Dec-1999");
+        assert_eq!(
+            SearchKey::Before(SearchDate::new(2020, 2, 29)).to_string(),
+            "BEFORE 29-Feb-2020"
+        );
+        assert_eq!(SearchDate::new(2000, 2, 29).to_string(), "29-Feb-2000");
+    }
+
+    #[test]
+    fn sent_dates() {
+        let query = SearchQuery::from(SearchKey::SentSince(SearchDate::new(2020, 1, 1)))
+            .key(SearchKey::SentBefore(SearchDate::new(2020, 3, 5)))
+            .key(SearchKey::Not(Box::new(SearchKey::SentOn(SearchDate::new(2020, 2, 14)))))
+            .key(SearchKey::Since(SearchDate::new(2021, 6, 1)));
+        assert_eq!(
+            query.as_str(),
+            "SENTSINCE 1-Jan-2020 SENTBEFORE 5-Mar-2020 NOT SENTON 14-Feb-2020 SINCE 1-Jun-2021"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid day 29 of month 2")]
+    fn invalid_date() {
+        SearchDate::new(1900, 2, 29);
+    }
+
+    #[test]
+    fn within() {
+        let query = SearchQuery::from(SearchKey::Unseen);
+        assert!(!query.needs_within());
+        let query = query.key(SearchKey::Not(Box::new(SearchKey::Older(3600))));
+        assert!(query.needs_within());
+        assert_eq!(query.as_str(), "UNSEEN NOT OLDER 3600");
+        assert_eq!(SearchKey::Younger(60).to_string(), "YOUNGER 60");
+    }
+}
@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+/// The messages found by a search, which remembers whether they are identified by sequence
+/// number (`Session::search`) or by UID (`Session::uid_search`).
+///
+/// The two are easily confused, since both are plain numbers, but sequence numbers change as
+/// messages are expunged, and refer to different messages than UIDs do. `uids` and
+/// `sequence_numbers` only return the numbers if they are of the kind asked for, so that they
+/// are not passed to the wrong command by mistake.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SearchResult {
+    ids: HashSet<u32>,
+    uid: bool,
+}
+
+impl SearchResult {
+    pub(crate) fn new(ids: HashSet<u32>, uid: bool) -> Self {
+        SearchResult { ids, uid }
+    }
+
+    /// Whether the numbers are UIDs, i.e. the result of `UID SEARCH`.
+    pub fn is_uid(&self) -> bool {
+        self.uid
+    }
+
+    /// The UIDs of the messages found, or `None` if this holds sequence numbers.
+    pub fn uids(&self) -> Option<&HashSet<u32>> {
+        if self.uid {
+            Some(&self.ids)
+        } else {
+            None
+        }
+    }
+
+    /// The sequence numbers of the messages found, or `None` if this holds UIDs.
+    pub fn sequence_numbers(&self) -> Option<&HashSet<u32>> {
+        if self.uid {
+            None
+        } else {
+            Some(&self.ids)
+        }
+    }
+
+    /// The numbers as returned by the server, whichever kind they are.
+    pub fn ids(&self) -> &HashSet<u32> {
+        &self.ids
+    }
+
+    /// The numbers in ascending order, whichever kind they are.
+    pub fn to_vec(&self) -> Vec<u32> {
+        let mut ids: Vec<u32> = self.ids.iter().cloned().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// The number of messages found.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Whether no messages were found.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt;
+use std::mem;
+use std::str::FromStr;
+
+use error::{Error, ParseError};
+
+/// A set of message sequence numbers or UIDs, such as `1:50,60,70:*`.
+///
+/// `*` stands for the largest number in use in the mailbox. As RFC 3501 allows, a range may be
+/// written in either order (`50:1` is the same as `1:50`); ranges are normalized so that the
+/// smaller number comes first, and a range involving `*` always ends with it.
+///
+/// ```
+/// # use imap::SequenceSet;
+/// let set: SequenceSet = "1:50,60,70:*".parse().unwrap();
+/// assert_eq!(set.ranges(), vec![(1, Some(50)), (60, Some(60)), (70, None)]);
+/// assert_eq!(set.to_string(), "1:50,60,70:*");
+/// ```
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SequenceSet {
+    ranges: Vec<(u32, Option<u32>)>,
+}
+
+impl SequenceSet {
+    /// The ranges of this set as `(start, end)`, in the order they were given, where an `end` of
+    /// `None` means the range is open-ended (i.e. runs up to `*`). A single number `n` is the
+    /// range `(n, Some(n))`.
+    pub fn ranges(&self) -> Vec<(u32, Option<u32>)> {
+        self.ranges.clone()
+    }
+
+    /// Whether `n` is in this set, where an open-ended range `n:*` contains every number from
+    /// `n` on.
+    pub fn contains(&self, n: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|&(start, end)| start <= n && end.is_none_or(|end| n <= end))
+    }
+
+    /// Whether this set has no numbers at all, which only happens for the empty set that
+    /// `SequenceSet::default()` gives (an empty set cannot be written down in IMAP).
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    /// This set with `*` standing for `largest`, the largest number in use in the mailbox (such as
+    /// its number of messages, for sequence numbers), so that it has no open-ended ranges left.
+    /// As in IMAP, `n:*` with `n` greater than `largest` only contains `largest`.
+    pub fn resolve(&self, largest: u32) -> SequenceSet {
+        let ranges = self
+            .ranges
+            .iter()
+            .map(|&(start, end)| match end {
+                Some(end) => (start, Some(end)),
+                None => (start.min(largest), Some(largest)),
+            })
+            .collect();
+        SequenceSet { ranges }
+    }
+
+    // Add the ranges of `other` after those of this set.
+    pub(crate) fn append(&mut self, other: SequenceSet) {
+        self.ranges.extend(other.ranges);
+    }
+
+    /// Split this set into sets of at most `size` numbers each, keeping the order in which the
+    /// numbers were given but leaving out duplicates. Consecutive numbers are again written as
+    /// ranges.
+    ///
+    /// Open-ended ranges (`n:*`) cannot be split, since their end is not known. They are put
+    /// together in one last set (see `resolve`).
+    ///
+    /// Ranges are split without going through their numbers one by one, so that even
+    /// `1:4294967295` only takes as long as the number of sets it is split into.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `size` is zero.
+    pub fn split(&self, size: usize) -> Vec<SequenceSet> {
+        assert!(size > 0, "cannot split a sequence set into empty sets");
+        let size = size as u64;
+        let mut sets = Vec::new();
+        // the ranges taken so far, sorted and disjoint
+        let mut seen: Vec<(u32, u32)> = Vec::new();
+        let mut current: Vec<(u32, Option<u32>)> = Vec::new();
+        let mut count = 0u64;
+        let mut open = Vec::new();
+        for &(start, end) in &self.ranges {
+            let end = match end {
+                Some(end) => end,
+                None => {
+                    open.push((start, None));
+                    continue;
+                }
+            };
+            for (mut a, b) in unseen(&seen, start, end) {
+                loop {
+                    let take = (size - count).min(u64::from(b - a) + 1);
+                    let last = (u64::from(a) + take - 1) as u32;
+                    let follows = current
+                        .last()
+                        .is_some_and(|&(_, end)| end.and_then(|e| e.checked_add(1)) == Some(a));
+                    if follows {
+                        current.last_mut().unwrap().1 = Some(last);
+                    } else {
+                        current.push((a, Some(last)));
+                    }
+                    count += take;
+                    if count == size {
+                        sets.push(SequenceSet {
+                            ranges: mem::take(&mut current),
+                        });
+                        count = 0;
+                    }
+                    if last == b {
+                        break;
+                    }
+                    a = last + 1;
+                }
+            }
+            seen.push((start, end));
+            seen.sort_unstable();
+            seen = merge(seen);
+        }
+        if !current.is_empty() {
+            sets.push(SequenceSet { ranges: current });
+        }
+        if !open.is_empty() {
+            sets.push(SequenceSet { ranges: open });
+        }
+        sets
+    }
+}
+
+// The parts of `start..=end` that are not in `seen` (sorted and disjoint), in ascending order.
+fn unseen(seen: &[(u32, u32)], start: u32, end: u32) -> Vec<(u32, u32)> {
+    let mut parts = Vec::new();
+    let mut next = Some(start);
+    for &(s, e) in seen {
+        let from = match next {
+            Some(from) if from <= end => from,
+            _ => break,
+        };
+        if e < from {
+            continue;
+        }
+        if s > end {
+            break;
+        }
+        if s > from {
+            parts.push((from, s - 1));
+        }
+        next = e.checked_add(1);
+    }
+    if let Some(from) = next.filter(|&from| from <= end) {
+        parts.push((from, end));
+    }
+    parts
+}
+
+// Merge sorted ranges that overlap or follow each other.
+fn merge(ranges: Vec<(u32, u32)>) -> Vec<(u32, u32)> {
+    let mut merged: Vec<(u32, u32)> = Vec::with_capacity(ranges.len());
+    for (s, e) in ranges {
+        match merged.last_mut() {
+            Some(last) if u64::from(s) <= u64::from(last.1) + 1 => last.1 = last.1.max(e),
+            _ => merged.push((s, e)),
+        }
+    }
+    merged
+}
+
+impl From<&[u32]> for SequenceSet {
+    /// The set of the given numbers, in the given order, with duplicates left out. Consecutive
+    /// numbers are written as ranges.
+    fn from(numbers: &[u32]) -> Self {
+        let ranges: Vec<_> = numbers.iter().map(|&n| (n, Some(n))).collect();
+        SequenceSet { ranges }
+            .split(usize::MAX)
+            .pop()
+            .unwrap_or(SequenceSet { ranges: Vec::new() })
+    }
+}
+
+impl FromStr for SequenceSet {
+    type Err = Error;
+
+    /// Parse a sequence set. A lone `*`, which cannot be represented as a range with a known
+    /// start, is rejected.
+    fn from_str(set: &str) -> Result<Self, Error> {
+        let invalid = || Error::Parse(ParseError::Invalid(set.as_bytes().to_vec()));
+        let bound = |n: &str| -> Result<Option<u32>, Error> {
+            match n {
+                "*" => Ok(None),
+                n => n.parse().map(Some).map_err(|_| invalid()),
+            }
+        };
+
+        let mut ranges = Vec::new();
+        for range in set.split(',') {
+            let mut bounds = range.splitn(2, ':');
+            let first = bound(bounds.next().unwrap_or(""))?;
+            let second = match bounds.next() {
+                Some(n) => bound(n)?,
+                None => first,
+            };
+            ranges.push(match (first, second) {
+                (Some(a), Some(b)) if a <= b => (a, Some(b)),
+                (Some(a), Some(b)) => (b, Some(a)),
+                (Some(n), None) | (None, Some(n)) => (n, None),
+                (None, None) => return Err(invalid()),
+            });
+        }
+        Ok(SequenceSet { ranges })
+    }
+}
+
+impl fmt::Display for SequenceSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, &(start, end)) in self.ranges.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            match end {
+                Some(end) if end == start => write!(f, "{}", start)?,
+                Some(end) => write!(f, "{}:{}", start, end)?,
+                None => write!(f, "{}:*", start)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        let set: SequenceSet = "1:50,60,70:*".parse().unwrap();
+        assert_eq!(
+            set.ranges(),
+            vec![(1, Some(50)), (60, Some(60)), (70, None)]
+        );
+    }
+
+    #[test]
+    fn reversed_ranges() {
+        let set: SequenceSet = "9:4,*:7".parse().unwrap();
+        assert_eq!(set.ranges(), vec![(4, Some(9)), (7, None)]);
+        assert_eq!(set.to_string(), "4:9,7:*");
+    }
+
+    #[test]
+    fn contains() {
+        let set: SequenceSet = "4:9,12,20:*".parse().unwrap();
+        assert!(set.contains(4) && set.contains(9) && set.contains(12));
+        assert!(set.contains(20) && set.contains(u32::MAX));
+        assert!(!set.contains(3) && !set.contains(10) && !set.contains(19));
+        assert!(!SequenceSet::default().contains(1));
+        assert!(SequenceSet::default().is_empty());
+    }
+
+    #[test]
+    fn resolve() {
+        let set: SequenceSet = "2,5:*,9:7".parse().unwrap();
+        assert_eq!(set.resolve(20).to_string(), "2,5:20,7:9");
+        let set: SequenceSet = "4294967295:*".parse().unwrap();
+        assert_eq!(set.resolve(3).to_string(), "3");
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("".parse::<SequenceSet>().is_err());
+        assert!("*".parse::<SequenceSet>().is_err());
+        assert!("1,,2".parse::<SequenceSet>().is_err());
+        assert!("1:2:3".parse::<SequenceSet>().is_err());
+        assert!("a:3".parse::<SequenceSet>().is_err());
+    }
+
+    #[test]
+    fn split() {
+        let set: SequenceSet = "1:5,3,9,20:*,10:11".parse().unwrap();
+        let sets: Vec<String> = set.split(3).iter().map(|s| s.to_string()).collect();
+        assert_eq!(sets, vec!["1:3", "4:5,9", "10:11", "20:*"]);
+
+        let set: SequenceSet = "7".parse().unwrap();
+        assert_eq!(set.split(100), vec![set.clone()]);
+
+        // overlapping ranges only contribute what is new
+        let set: SequenceSet = "5:8,1:10,9".parse().unwrap();
+        let sets: Vec<String> = set.split(4).iter().map(|s| s.to_string()).collect();
+        assert_eq!(sets, vec!["5:8", "1:4", "9:10"]);
+
+        // wide ranges are split without being expanded
+        let set: SequenceSet = "1:4294967295".parse().unwrap();
+        let sets: Vec<String> = set.split(1 << 30).iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            sets,
+            vec![
+                "1:1073741824",
+                "1073741825:2147483648",
+                "2147483649:3221225472",
+                "3221225473:4294967295"
+            ]
+        );
+    }
+
+    #[test]
+    fn from_numbers() {
+        let set = SequenceSet::from(&[4, 5, 6, 1, 5, 9][..]);
+        assert_eq!(set.to_string(), "4:6,1,9");
+        assert_eq!(SequenceSet::from(&[][..]).ranges(), vec![]);
+    }
+}
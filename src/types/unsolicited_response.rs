@@ -0,0 +1,28 @@
+use super::{Fetch, MailboxStatus};
+
+/// Responses that the server sent without being asked for them, such as notifications that new
+/// messages have arrived in the selected mailbox.
+///
+/// These may be interleaved with the responses to any command (see [section 7 of RFC
+/// 3501](https://tools.ietf.org/html/rfc3501#section-7)), and are collected in the session's
+/// queue instead of being returned by the command. See
+/// [`Session::unsolicited_responses`](../client/struct.Session.html#method.unsolicited_responses).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UnsolicitedResponse {
+    /// The number of messages in the selected mailbox changed.
+    Exists(u32),
+    /// The number of messages with the `\Recent` flag changed.
+    Recent(u32),
+    /// The message with the given sequence number was expunged.
+    Expunge(u32),
+    /// The data of a message changed, typically its flags (e.g. because another client marked it
+    /// as `\Seen`).
+    Fetch(Fetch),
+    /// The set of flags that apply to the selected mailbox changed, e.g. because another client
+    /// created a new keyword.
+    Flags(Vec<String>),
+    /// The status of a mailbox, which need not be the selected one. Servers send these when
+    /// asked to through `NOTIFY` ([RFC 5465](https://tools.ietf.org/html/rfc5465)), e.g. when
+    /// new mail arrives in another mailbox.
+    Status(MailboxStatus),
+}
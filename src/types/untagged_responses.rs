@@ -0,0 +1,19 @@
+use imap_proto::Response;
+
+/// The untagged responses to a command run with `Session::run_command_and_parse`, as parsed by
+/// [imap-proto](https://docs.rs/imap-proto/0.4.1/imap_proto/) (which is re-exported as
+/// `imap::imap_proto`).
+// Note that the responses are not *actually* 'static.
+// Rather, they are tied to the lifetime of the `ZeroCopy` that contains them, which is why they
+// are only handed out with the lifetime of `&self`.
+#[derive(Debug, Eq, PartialEq)]
+pub struct UntaggedResponses {
+    pub(crate) responses: Vec<Response<'static>>,
+}
+
+impl UntaggedResponses {
+    /// The responses, in the order in which the server sent them.
+    pub fn responses(&self) -> &[Response<'_>] {
+        &self.responses[..]
+    }
+}
@@ -0,0 +1,128 @@
+//! The modified UTF-7 encoding used for mailbox names, as defined in [section 5.1.3 of RFC
+//! 3501](https://tools.ietf.org/html/rfc3501#section-5.1.3).
+//!
+//! It differs from the UTF-7 of RFC 2152 in that `&` (rather than `+`) starts a shifted sequence,
+//! a literal `&` is written as `&-`, and the base64 alphabet uses `,` in place of `/`. Characters
+//! outside the Basic Multilingual Plane, such as emoji, are encoded as UTF-16 surrogate pairs.
+
+use base64;
+
+use error::{Error, ParseError, Result};
+
+/// Encode a mailbox name in modified UTF-7.
+///
+/// ```
+/// # use imap::utf7;
+/// assert_eq!(utf7::encode("Entwürfe"), "Entw&APw-rfe");
+/// assert_eq!(utf7::encode("Q&A"), "Q&-A");
+/// ```
+pub fn encode(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    let mut shifted = Vec::new();
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut encoded, &mut shifted);
+            if c == '&' {
+                encoded.push_str("&-");
+            } else {
+                encoded.push(c);
+            }
+        } else {
+            let mut units = [0; 2];
+            for unit in c.encode_utf16(&mut units) {
+                shifted.push((*unit >> 8) as u8);
+                shifted.push(*unit as u8);
+            }
+        }
+    }
+    flush(&mut encoded, &mut shifted);
+    encoded
+}
+
+// Write the pending UTF-16 bytes as a shifted sequence.
+fn flush(encoded: &mut String, shifted: &mut Vec<u8>) {
+    if shifted.is_empty() {
+        return;
+    }
+    encoded.push('&');
+    encoded.push_str(&base64::encode_config(shifted, base64::STANDARD_NO_PAD).replace('/', ","));
+    encoded.push('-');
+    shifted.clear();
+}
+
+/// Decode a mailbox name from modified UTF-7.
+///
+/// Unterminated shifted sequences, malformed base64, and invalid UTF-16 (such as unpaired
+/// surrogates) are rejected.
+///
+/// ```
+/// # use imap::utf7;
+/// assert_eq!(utf7::decode("Entw&APw-rfe").unwrap(), "Entwürfe");
+/// ```
+pub fn decode(name: &str) -> Result<String> {
+    let invalid = || Error::Parse(ParseError::Invalid(name.as_bytes().to_vec()));
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let end = rest.find('-').ok_or_else(invalid)?;
+        if end == 0 {
+            decoded.push('&');
+        } else {
+            let shifted = rest[..end].replace(',', "/");
+            let bytes = base64::decode_config(&shifted, base64::STANDARD_NO_PAD)
+                .map_err(|_| invalid())?;
+            if bytes.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|pair| (u16::from(pair[0]) << 8) | u16::from(pair[1]))
+                .collect();
+            decoded.push_str(&String::from_utf16(&units).map_err(|_| invalid())?);
+        }
+        rest = &rest[end + 1..];
+    }
+    decoded.push_str(rest);
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ampersand() {
+        assert_eq!(encode("&"), "&-");
+        assert_eq!(encode("Tom & Jerry"), "Tom &- Jerry");
+        assert_eq!(decode("Tom &- Jerry").unwrap(), "Tom & Jerry");
+    }
+
+    #[test]
+    fn bmp() {
+        assert_eq!(encode("Entwürfe"), "Entw&APw-rfe");
+        assert_eq!(decode("Entw&APw-rfe").unwrap(), "Entwürfe");
+
+        // the example from RFC 3501, whose base64 needs the `,` of the modified alphabet
+        let name = "~peter/mail/\u{53F0}\u{5317}/\u{65E5}\u{672C}\u{8A9E}";
+        assert_eq!(encode(name), "~peter/mail/&U,BTFw-/&ZeVnLIqe-");
+        assert_eq!(decode("~peter/mail/&U,BTFw-/&ZeVnLIqe-").unwrap(), name);
+    }
+
+    #[test]
+    fn astral() {
+        let name = "Mail \u{1F4E7} & more";
+        let encoded = encode(name);
+        assert_eq!(encoded, "Mail &2D3c5w- &- more");
+        assert_eq!(decode(&encoded).unwrap(), name);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(decode("&APw").is_err());
+        assert!(decode("&A-").is_err());
+        // an unpaired high surrogate
+        assert!(decode("&2D0-").is_err());
+    }
+}